@@ -0,0 +1,79 @@
+// Backoff for the servers' accept loops. Errors such as EMFILE leave the pending
+// connection in the listen backlog, so retrying straight away fails again and spins
+// a core; the loops sleep for `next_delay()` instead and only stop on errors that
+// mean the listening socket itself is unusable.
+
+use crate::config;
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+
+const DEFAULT_INITIAL_MS: u64 = 10;
+const DEFAULT_MAX_MS: u64 = 1000;
+
+/// Config keys read by `AcceptBackoff::from_config`, shared by every server's schema.
+pub const INITIAL_KEY: config::ConfigKey = config::ConfigKey {
+    name: "ACCEPT_BACKOFF_MS",
+    default: Some("10"),
+    description: "Delay in milliseconds before retrying after a failed accept; doubles on each consecutive failure",
+};
+
+pub const MAX_KEY: config::ConfigKey = config::ConfigKey {
+    name: "ACCEPT_BACKOFF_MAX_MS",
+    default: Some("1000"),
+    description: "Upper bound in milliseconds for the accept retry delay",
+};
+
+#[derive(Clone, Debug)]
+pub struct AcceptBackoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl AcceptBackoff {
+    pub fn new(initial: Duration, max: Duration) -> AcceptBackoff {
+        // A zero delay would never grow, which is the spin this exists to prevent
+        let initial = initial.max(Duration::from_millis(1));
+        let max = max.max(initial);
+        AcceptBackoff {
+            initial,
+            max,
+            next: initial,
+        }
+    }
+
+    pub fn from_config(config: &HashMap<String, String>) -> AcceptBackoff {
+        let millis = |key: &config::ConfigKey, default: u64| {
+            config
+                .get(key.name)
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default)
+        };
+        AcceptBackoff::new(
+            Duration::from_millis(millis(&INITIAL_KEY, DEFAULT_INITIAL_MS)),
+            Duration::from_millis(millis(&MAX_KEY, DEFAULT_MAX_MS)),
+        )
+    }
+
+    /// Delay to sleep after a failed accept. Doubles on each consecutive call, up to the cap.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    /// Called after a successful accept so the next failure starts from the initial delay.
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
+
+/// True for accept errors that mean the listener itself is broken, so retrying cannot help.
+/// Everything else (fd exhaustion, aborted connections, low memory) is treated as transient.
+pub fn is_fatal(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EBADF | libc::EFAULT | libc::EINVAL | libc::ENOTSOCK | libc::EOPNOTSUPP)
+    )
+}
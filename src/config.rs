@@ -1,13 +1,36 @@
 use std::collections::HashMap;
 use std::fs;
+use std::path::Path;
+
+/// One supported configuration key. Each server publishes a schema of these so
+/// startup validation and the `--init` template are generated from the same list.
+pub struct ConfigKey {
+    pub name: &'static str,
+    /// `None` marks the key as required; `Some` is the value used when it is absent.
+    pub default: Option<&'static str>,
+    pub description: &'static str,
+}
+
+impl ConfigKey {
+    pub fn is_required(&self) -> bool {
+        self.default.is_none()
+    }
+}
 
 // Config loading uses expect() because configuration is a critical startup dependency
 // - If config files can't be read, the application cannot function
 // - This is not a recoverable runtime error but a setup/environment issue
 pub fn load_config(path: &str) -> HashMap<String, String> {
     let content = fs::read_to_string(path).expect("Failed to read config file");
+    parse_config(&content)
+}
+
+pub fn parse_config(content: &str) -> HashMap<String, String> {
     let mut map = HashMap::new();
     for line in content.lines() {
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
         if let Some(eq_pos) = line.find('=') {
             let key = line[..eq_pos].trim().to_string();
             let value = line[eq_pos + 1..].trim().to_string();
@@ -15,4 +38,68 @@ pub fn load_config(path: &str) -> HashMap<String, String> {
         }
     }
     map
-}
\ No newline at end of file
+}
+
+/// Checks that every required key in `schema` is present with a non-empty value.
+/// Returns the names of all missing keys so they can be reported together.
+pub fn validate(map: &HashMap<String, String>, schema: &[ConfigKey]) -> Result<(), Vec<String>> {
+    let missing: Vec<String> = schema
+        .iter()
+        .filter(|key| key.is_required())
+        .filter(|key| map.get(key.name).is_none_or(|v| v.is_empty()))
+        .map(|key| key.name.to_string())
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(missing)
+    }
+}
+
+/// Renders a commented config template listing every key in `schema`.
+/// `overrides` replaces the rendered value for individual keys (e.g. generated examples).
+pub fn render_template(title: &str, schema: &[ConfigKey], overrides: &[(&str, String)]) -> String {
+    let mut out = format!("# {} configuration\n# Generated by --init\n", title);
+    for key in schema {
+        out.push('\n');
+        if key.is_required() {
+            out.push_str(&format!("# REQUIRED: {}\n", key.description));
+        } else {
+            out.push_str(&format!("# {}\n", key.description));
+        }
+        let value = overrides
+            .iter()
+            .find(|(name, _)| *name == key.name)
+            .map(|(_, value)| value.as_str())
+            .or(key.default)
+            .unwrap_or("");
+        out.push_str(&format!("{}={}\n", key.name, value));
+    }
+    out
+}
+
+/// Handles `--init [path] [--force]`: writes `template` to `path` (or `default_path`)
+/// and returns the process exit code. Existing files are only replaced with `--force`.
+pub fn run_init(args: &[String], default_path: &str, template: &str) -> i32 {
+    let force = args.iter().any(|a| a == "--force");
+    let path = args
+        .iter()
+        .find(|a| !a.starts_with("--"))
+        .map(String::as_str)
+        .unwrap_or(default_path);
+
+    if Path::new(path).exists() && !force {
+        eprintln!("Refusing to overwrite existing {} (use --force)", path);
+        return 1;
+    }
+    match fs::write(path, template) {
+        Ok(()) => {
+            println!("Wrote config template to {}", path);
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", path, e);
+            1
+        }
+    }
+}
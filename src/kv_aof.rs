@@ -0,0 +1,415 @@
+// Append-only file for shrmpl-kv-srv. With AOF_PATH set, every change to the store is
+// appended to the file as the command that recreates the key's new state: `SET` or
+// `SETB` with its value, `DEL`, or `FLUSHALL`, prefixed with `@<unix ms>` when the key
+// expires. Lines are queued while the store's write lock is held, so the file has
+// changes in the order they were made, and one task owns the file. At startup the
+// server replays it through the command path; since expirations are absolute, a
+// replayed key expires when it would have, and a key that expired meanwhile is
+// replayed as a DEL. Only values and expirations are journaled: tags set by TAG (and
+// carried over by RENAME) live outside the store's entries and are gone after a
+// replay, as they are from EXPORT and DUMP.
+
+use crate::config;
+use crate::kv_protocol::{encode_bytes, Command, Limits, ParseError};
+use crate::kv_store::{self, Store, StoredValue, Value};
+use crate::shrmpl_log_client::Logger;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+
+/// Config keys read by `AofSettings::from_config`.
+pub const PATH_KEY: config::ConfigKey = config::ConfigKey {
+    name: "AOF_PATH",
+    default: Some(""),
+    description: "File every change to the store is appended to and replayed from at startup; empty disables it",
+};
+
+pub const SYNC_KEY: config::ConfigKey = config::ConfigKey {
+    name: "AOF_SYNC",
+    default: Some("everysec"),
+    description: "When AOF_PATH is fsynced: always (before each write is answered), everysec or no",
+};
+
+/// Prefix of a line's expiration, in unix milliseconds.
+pub const EXPIRY_PREFIX: char = '@';
+
+/// When the file is forced to disk. Every mode hands each batch of lines to the OS
+/// straight away, so only a machine crash, not a server crash, can lose them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AofSync {
+    /// fsync after each batch; writes are answered once their line is on disk.
+    Always,
+    /// fsync once a second.
+    EverySec,
+    /// Left to the OS.
+    No,
+}
+
+impl AofSync {
+    pub fn parse(value: &str) -> Option<AofSync> {
+        match value.to_ascii_lowercase().as_str() {
+            "always" => Some(AofSync::Always),
+            "everysec" => Some(AofSync::EverySec),
+            "no" => Some(AofSync::No),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AofSettings {
+    pub path: String,
+    pub sync: AofSync,
+}
+
+impl AofSettings {
+    /// None when AOF_PATH is empty or missing. An unknown AOF_SYNC means everysec.
+    pub fn from_config(config: &HashMap<String, String>) -> Option<AofSettings> {
+        let path = config.get(PATH_KEY.name).filter(|path| !path.is_empty())?;
+        let sync = config
+            .get(SYNC_KEY.name)
+            .and_then(|value| AofSync::parse(value))
+            .unwrap_or(AofSync::EverySec);
+        Some(AofSettings {
+            path: path.clone(),
+            sync,
+        })
+    }
+}
+
+/// The line that recreates `key` as `stored`.
+pub fn entry_line(key: &str, stored: &StoredValue) -> String {
+    let command = match &stored.value {
+        Value::Int(i) => format!("SET {} {}", key, i),
+        // `{:?}` keeps the `.0` of a whole number, so it replays as a float
+        Value::Float(f) => format!("SET {} {}", key, kv_store::format_float(*f)),
+        Value::Str(s) => set_str_line(key, s),
+        Value::Compressed(payload) => set_str_line(key, &kv_store::decompress(payload)),
+        Value::Bytes(b) => format!("SETB {} {}", key, encode_bytes(b)),
+    };
+    let expires_ms = stored
+        .expires_at
+        .and_then(|exp_time| exp_time.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis());
+    match expires_ms {
+        Some(ms) => format!("{}{} {}", EXPIRY_PREFIX, ms, command),
+        None => command,
+    }
+}
+
+// A string that looks like a number was stored with RAW, and needs it again to come
+// back as the same string
+fn set_str_line(key: &str, text: &str) -> String {
+    if Value::number(text).is_some() {
+        format!("SET {} {} RAW", key, text)
+    } else {
+        format!("SET {} {}", key, text)
+    }
+}
+
+pub fn del_line(key: &str) -> String {
+    format!("DEL {}", key)
+}
+
+pub const FLUSH_LINE: &str = "FLUSHALL";
+
+/// Limits lines are replayed under. Each line was held to the limits in force when it
+/// was written, which may have been higher than today's.
+pub const REPLAY_LIMITS: Limits = Limits {
+    max_key_len: usize::MAX,
+    max_value_len: usize::MAX,
+    max_bytes_len: usize::MAX,
+};
+
+/// The command a line replays as at `now`: a SET or SETB with the time left until its
+/// expiration, or a DEL if that has passed. Only the commands `entry_line`, `del_line`
+/// and FLUSH_LINE write are accepted.
+pub fn replay_command(line: &str, now: SystemTime) -> Result<Command, ParseError> {
+    let (expires_at, text) = match line.strip_prefix(EXPIRY_PREFIX) {
+        Some(rest) => {
+            let (ms, text) = rest.split_once(' ').ok_or(ParseError::InvalidArguments)?;
+            let ms: u64 = ms.parse().map_err(|_| ParseError::InvalidExpiration)?;
+            (Some(UNIX_EPOCH + Duration::from_millis(ms)), text)
+        }
+        None => (None, line),
+    };
+    let command = Command::parse_with(text, &REPLAY_LIMITS)?;
+    let ttl = match expires_at {
+        Some(exp_time) => match exp_time.duration_since(now) {
+            Ok(left) if !left.is_zero() => Some(left),
+            _ => {
+                return match command {
+                    Command::Set { key, .. } | Command::SetB { key, .. } => {
+                        Ok(Command::Del { key })
+                    }
+                    _ => Err(ParseError::InvalidArguments),
+                }
+            }
+        },
+        None => None,
+    };
+    match command {
+        Command::Set {
+            key, value, raw, ..
+        } => Ok(Command::Set {
+            key,
+            value,
+            ttl,
+            raw,
+        }),
+        Command::SetB { key, data, .. } => Ok(Command::SetB { key, data, ttl }),
+        Command::Del { .. } | Command::FlushAll if ttl.is_none() => Ok(command),
+        _ => Err(ParseError::UnknownCommand),
+    }
+}
+
+/// Lines recreating the store's live keys, as BGREWRITEAOF writes them.
+pub fn snapshot_lines(store: &Store) -> Vec<String> {
+    let now = SystemTime::now();
+    store
+        .iter()
+        .filter(|(_, stored)| !stored.is_expired(now))
+        .map(|(key, stored)| entry_line(key, stored))
+        .collect()
+}
+
+/// The complete lines of an AOF and the length they take up. A last line without its
+/// newline, cut short by a crash, is left out. A missing file reads as empty.
+pub fn read(path: &str) -> io::Result<(Vec<String>, u64)> {
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(e),
+    };
+    let complete = content
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |end| end + 1);
+    let text = std::str::from_utf8(&content[..complete])
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "AOF is not UTF-8"))?;
+    Ok((text.lines().map(String::from).collect(), complete as u64))
+}
+
+enum Message {
+    Line(String),
+    Rewrite {
+        lines: Vec<String>,
+        done: oneshot::Sender<io::Result<usize>>,
+    },
+}
+
+/// Queues lines for the writer task. Held by the store, which appends a line for
+/// every change it makes.
+#[derive(Clone, Debug)]
+pub struct Journal {
+    tx: mpsc::UnboundedSender<Message>,
+    sync: AofSync,
+    // Lines queued so far, and how many of them the writer has finished with
+    queued: Arc<AtomicU64>,
+    written: watch::Receiver<u64>,
+}
+
+impl Journal {
+    /// Call with the store's write lock held, so lines keep the order of the changes.
+    pub fn append(&self, line: String) {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        // Only fails once the writer has stopped at shutdown
+        let _ = self.tx.send(Message::Line(line));
+    }
+
+    /// Replaces the file with `lines` once everything queued before them is written.
+    /// Call with the store's lock held, so no change is lost between the two.
+    pub fn rewrite(&self, lines: Vec<String>) -> oneshot::Receiver<io::Result<usize>> {
+        let (done, result) = oneshot::channel();
+        if let Err(mpsc::error::SendError(Message::Rewrite { done, .. })) =
+            self.tx.send(Message::Rewrite { lines, done })
+        {
+            let _ = done.send(Err(io::Error::other("AOF writer stopped")));
+        }
+        result
+    }
+
+    /// With AOF_SYNC=always, waits until every line queued so far is on disk.
+    pub async fn wait_synced(&self) {
+        if self.sync != AofSync::Always {
+            return;
+        }
+        let target = self.queued.load(Ordering::SeqCst);
+        let mut written = self.written.clone();
+        // An error means the writer has stopped, and nothing more will be synced
+        let _ = written.wait_for(|&n| n >= target).await;
+    }
+
+    pub fn sync(&self) -> AofSync {
+        self.sync
+    }
+}
+
+/// Opens `settings.path` for appending, cut back to `len` bytes so a line torn by a
+/// crash is dropped, and starts the task that writes to it. The task stops, after
+/// writing what is queued and syncing, when a value arrives on `shutdown_rx`.
+pub async fn start(
+    settings: &AofSettings,
+    len: u64,
+    logger: Logger,
+    shutdown_rx: broadcast::Receiver<()>,
+) -> io::Result<(Journal, JoinHandle<()>)> {
+    let file = open(&settings.path).await?;
+    if file.metadata().await?.len() > len {
+        file.set_len(len).await?;
+    }
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (written_tx, written) = watch::channel(0);
+    let journal = Journal {
+        tx,
+        sync: settings.sync,
+        queued: Arc::new(AtomicU64::new(0)),
+        written,
+    };
+    let writer = Writer {
+        path: settings.path.clone(),
+        sync: settings.sync,
+        file,
+        written: 0,
+        written_tx,
+        dirty: false,
+        failing: false,
+        logger,
+    };
+    Ok((journal, tokio::spawn(writer.run(rx, shutdown_rx))))
+}
+
+async fn open(path: &str) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+struct Writer {
+    path: String,
+    sync: AofSync,
+    file: File,
+    // Lines finished with, whether written or lost to an error
+    written: u64,
+    written_tx: watch::Sender<u64>,
+    // Written since the last fsync
+    dirty: bool,
+    // Set after a failed write, so a broken disk is logged once until it recovers
+    failing: bool,
+    logger: Logger,
+}
+
+impl Writer {
+    async fn run(
+        mut self,
+        mut rx: mpsc::UnboundedReceiver<Message>,
+        mut shutdown_rx: broadcast::Receiver<()>,
+    ) {
+        let mut every_second = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                message = rx.recv() => match message {
+                    Some(message) => self.handle(message, &mut rx).await,
+                    None => break,
+                },
+                _ = every_second.tick(), if self.sync == AofSync::EverySec && self.dirty => {
+                    self.fsync().await;
+                }
+                _ = shutdown_rx.recv() => break,
+            }
+        }
+        while let Ok(message) = rx.try_recv() {
+            self.handle(message, &mut rx).await;
+        }
+        if self.dirty {
+            self.fsync().await;
+        }
+    }
+
+    // Writes `first` with whatever else is queued, up to the next rewrite, as one batch
+    async fn handle(&mut self, first: Message, rx: &mut mpsc::UnboundedReceiver<Message>) {
+        let mut batch = String::new();
+        let mut lines = 0;
+        let mut next = Some(first);
+        while let Some(message) = next.take() {
+            match message {
+                Message::Line(line) => {
+                    batch.push_str(&line);
+                    batch.push('\n');
+                    lines += 1;
+                    next = rx.try_recv().ok();
+                }
+                Message::Rewrite {
+                    lines: snapshot,
+                    done,
+                } => {
+                    self.write_batch(&batch, lines).await;
+                    (batch, lines) = (String::new(), 0);
+                    let _ = done.send(self.rewrite(snapshot).await);
+                    next = rx.try_recv().ok();
+                }
+            }
+        }
+        self.write_batch(&batch, lines).await;
+    }
+
+    async fn write_batch(&mut self, batch: &str, lines: u64) {
+        if lines == 0 {
+            return;
+        }
+        let result = self.file.write_all(batch.as_bytes()).await;
+        self.report("AOF write", result);
+        self.dirty = true;
+        if self.sync == AofSync::Always {
+            self.fsync().await;
+        }
+        self.written += lines;
+        self.written_tx.send_replace(self.written);
+    }
+
+    async fn fsync(&mut self) {
+        let result = self.file.sync_data().await;
+        self.report("AOF fsync", result);
+        self.dirty = false;
+    }
+
+    // Written to a temporary file, synced and renamed over the AOF, so a failed
+    // rewrite leaves the old file in place
+    async fn rewrite(&mut self, lines: Vec<String>) -> io::Result<usize> {
+        let tmp = format!("{}.tmp", self.path);
+        let mut content = lines.join("\n");
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        let mut file = File::create(&tmp).await?;
+        file.write_all(content.as_bytes()).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        self.file = open(&self.path).await?;
+        self.dirty = false;
+        Ok(lines.len())
+    }
+
+    fn report(&mut self, what: &str, result: io::Result<()>) {
+        match result {
+            Ok(()) => self.failing = false,
+            Err(e) if !self.failing => {
+                self.failing = true;
+                self.logger.log_detached(
+                    "ERRO",
+                    "KVAOFFAIL",
+                    &format!("{} to {} failed: {}", what, self.path, e),
+                );
+            }
+            Err(_) => {}
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// Per-key access counters for shrmpl-kv-srv, kept when KEY_STATS is on. Keys that
+// exist are tracked for as long as they do. GETs of keys that don't exist go to a
+// separate map capped at a fixed number of names: once full, a new name replaces the
+// least missed one and inherits its count plus one (the Space-Saving scheme), so the
+// most missed keys stay listed however many distinct names a client tries.
+
+use crate::kv_protocol::KeyCounters;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Counts {
+    hits: u64,
+    misses: u64,
+    sets: u64,
+    last_access: u64,
+}
+
+#[derive(Debug)]
+pub struct KeyStats {
+    present: HashMap<String, Counts>,
+    missing: HashMap<String, Counts>,
+    miss_capacity: usize,
+}
+
+impl KeyStats {
+    /// Tracks at most `miss_capacity` names of keys that don't exist (at least one).
+    pub fn new(miss_capacity: usize) -> KeyStats {
+        KeyStats {
+            present: HashMap::new(),
+            missing: HashMap::new(),
+            miss_capacity: miss_capacity.max(1),
+        }
+    }
+
+    /// A GET that found `key`.
+    pub fn hit(&mut self, key: &str, now: SystemTime) {
+        let counts = self.present_entry(key);
+        counts.hits += 1;
+        counts.last_access = millis(now);
+    }
+
+    /// A GET of `key` while it did not exist.
+    pub fn miss(&mut self, key: &str, now: SystemTime) {
+        let counts = match self.missing.remove(key) {
+            Some(counts) => counts,
+            None => {
+                let inherited = self.evict_least_missed();
+                // A tracked key that was deleted or expired keeps its history
+                self.present.remove(key).unwrap_or(inherited)
+            }
+        };
+        self.missing.insert(
+            key.to_string(),
+            Counts {
+                misses: counts.misses + 1,
+                last_access: millis(now),
+                ..counts
+            },
+        );
+    }
+
+    /// A write (SET, SETB or INCR) of `key`.
+    pub fn set(&mut self, key: &str, now: SystemTime) {
+        let counts = self.present_entry(key);
+        counts.sets += 1;
+        counts.last_access = millis(now);
+    }
+
+    /// Forgets `key`, e.g. once it is deleted. Its misses stay tracked.
+    pub fn remove(&mut self, key: &str) {
+        if let Some(counts) = self.present.remove(key) {
+            if counts.misses > 0 {
+                self.evict_least_missed();
+                self.missing.insert(key.to_string(), counts);
+            }
+        }
+    }
+
+    /// Forgets keys for which `exists` is false, such as ones the expiry sweep
+    /// removed, as `remove` does.
+    pub fn retain_present(&mut self, mut exists: impl FnMut(&str) -> bool) {
+        let gone: Vec<String> = self
+            .present
+            .keys()
+            .filter(|key| !exists(key))
+            .cloned()
+            .collect();
+        for key in gone {
+            self.remove(&key);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<KeyCounters> {
+        self.present
+            .get_key_value(key)
+            .or_else(|| self.missing.get_key_value(key))
+            .map(|(key, counts)| counters(key, counts))
+    }
+
+    /// The `n` most accessed keys, busiest first; ties go to the latest accessed.
+    pub fn top(&self, n: usize) -> Vec<KeyCounters> {
+        let mut all: Vec<KeyCounters> = self
+            .present
+            .iter()
+            .chain(&self.missing)
+            .map(|(key, counts)| counters(key, counts))
+            .collect();
+        all.sort_by_key(|c| std::cmp::Reverse((c.accesses(), c.last_access)));
+        all.truncate(n);
+        all
+    }
+
+    /// Names tracked for keys that don't exist; never above the capacity.
+    pub fn missing_tracked(&self) -> usize {
+        self.missing.len()
+    }
+
+    // Existing keys start from any misses recorded while they were absent
+    fn present_entry(&mut self, key: &str) -> &mut Counts {
+        if !self.present.contains_key(key) {
+            let counts = self.missing.remove(key).unwrap_or_default();
+            self.present.insert(key.to_string(), counts);
+        }
+        self.present.get_mut(key).unwrap()
+    }
+
+    // Makes room for one more missing name, returning the counts it starts from
+    fn evict_least_missed(&mut self) -> Counts {
+        if self.missing.len() < self.miss_capacity {
+            return Counts::default();
+        }
+        let least = self
+            .missing
+            .iter()
+            .min_by_key(|(_, counts)| (counts.misses, counts.last_access))
+            .map(|(key, _)| key.clone());
+        let evicted = least.and_then(|key| self.missing.remove(&key));
+        Counts {
+            misses: evicted.map_or(0, |counts| counts.misses),
+            ..Counts::default()
+        }
+    }
+}
+
+fn counters(key: &str, counts: &Counts) -> KeyCounters {
+    KeyCounters {
+        key: key.to_string(),
+        hits: counts.hits,
+        misses: counts.misses,
+        sets: counts.sets,
+        last_access: counts.last_access,
+    }
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
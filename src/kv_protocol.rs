@@ -0,0 +1,1767 @@
+// Shared shrmpl-kv wire protocol: the command grammar, response shapes, limits and
+// sentinels used by both shrmpl-kv-srv and KvClient. Adding a command means adding a
+// Command variant here, so the server and every client agree on its syntax.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use chrono::{DateTime, Utc};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::fmt;
+use std::io::{Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default key and value length limits; servers may configure others and report them
+/// with LIMITS.
+pub const MAX_KEY_LEN: usize = 100;
+pub const MAX_VALUE_LEN: usize = 100;
+/// Default limit on the decoded size of a SETB value; servers may configure another.
+pub const MAX_BYTES_LEN: usize = 100;
+pub const MAX_BATCH_COMMANDS: usize = 3;
+pub const MAX_MGET_KEYS: usize = 16;
+pub const MAX_MSET_PAIRS: usize = 32;
+/// Marks MSET's optional trailing expiration, e.g. `TTL=60s`.
+pub const MSET_TTL_PREFIX: &str = "TTL=";
+/// Entries per SCAN page without COUNT, and the most COUNT may ask for.
+pub const SCAN_DEFAULT_COUNT: usize = 100;
+pub const MAX_SCAN_COUNT: usize = 1000;
+/// SCAN cursor that starts a scan, and comes back once it is complete.
+pub const SCAN_START: &str = "0";
+/// Longest pause DEBUG SLEEP accepts.
+pub const MAX_DEBUG_SLEEP_MS: u64 = 60_000;
+
+/// Returned for GET/DEL on a missing or expired key.
+pub const KEY_NOT_FOUND: &str = "*KEY NOT FOUND*";
+/// Unsolicited heartbeat line the server may send at any time.
+pub const HEARTBEAT: &str = "UPONG";
+/// Sent by the server to every connection when it shuts down.
+pub const TERM: &str = "TERM";
+pub const NO_EXPIRATION: &str = "no-expiration";
+/// MGET's line for a key that is missing or expired.
+pub const NIL: &str = "*NIL*";
+/// Ends an MGET reply.
+pub const MGET_END: &str = "END";
+/// Joins a SELECTed namespace to the keys stored under it: `namespace:key`.
+pub const NAMESPACE_SEPARATOR: char = ':';
+/// Highest protocol revision this build speaks. HELLO settles on the lower of this
+/// and the client's revision.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Optional capabilities a server lists in its HELLO reply: `batch` (BATCH lines),
+/// `binary` (SETB/GETB), `status` (STATUS), `heartbeat` (UPONG), `compress`
+/// (COMPRESS ON), `limits` (LIMITS) and `admin` (EXPORT/IMPORT/DUMP/LOAD/BADCMDS,
+/// listed only when the server enables them).
+pub const FEATURE_BATCH: &str = "batch";
+pub const FEATURE_BINARY: &str = "binary";
+pub const FEATURE_STATUS: &str = "status";
+pub const FEATURE_HEARTBEAT: &str = "heartbeat";
+pub const FEATURE_COMPRESS: &str = "compress";
+pub const FEATURE_LIMITS: &str = "limits";
+pub const FEATURE_ADMIN: &str = "admin";
+
+/// Features every server built from this crate supports, in HELLO order.
+pub const FEATURES: &[&str] = &[
+    FEATURE_BATCH,
+    FEATURE_BINARY,
+    FEATURE_STATUS,
+    FEATURE_HEARTBEAT,
+    FEATURE_COMPRESS,
+    FEATURE_LIMITS,
+];
+
+/// After COMPRESS ON every line from the server starts with a flag byte: FRAME_PLAIN
+/// before a line sent as is, or FRAME_DEFLATE before `LEN\n` and LEN bytes of zlib
+/// data that inflate to one or more whole lines.
+pub const FRAME_PLAIN: u8 = b'=';
+pub const FRAME_DEFLATE: u8 = b'~';
+/// Replies shorter than this aren't worth deflating and go out as FRAME_PLAIN lines.
+pub const COMPRESS_MIN_BYTES: usize = 256;
+
+/// Frames a reply (one or more whole lines) for a connection with COMPRESS ON.
+pub fn frame_reply(wire: &str) -> Vec<u8> {
+    if wire.len() >= COMPRESS_MIN_BYTES {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        // Writing to a Vec can't fail
+        encoder.write_all(wire.as_bytes()).unwrap();
+        let data = encoder.finish().unwrap();
+        let mut frame = format!("{}{}\n", FRAME_DEFLATE as char, data.len()).into_bytes();
+        frame.extend(data);
+        return frame;
+    }
+    let mut frame = Vec::with_capacity(wire.len() + 4);
+    for line in wire.split_inclusive('\n') {
+        frame.push(FRAME_PLAIN);
+        frame.extend_from_slice(line.as_bytes());
+    }
+    frame
+}
+
+/// Inflates the data of a FRAME_DEFLATE frame.
+pub fn inflate_frame(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut lines = Vec::new();
+    ZlibDecoder::new(data).read_to_end(&mut lines)?;
+    Ok(lines)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownCommand,
+    InvalidArguments,
+    InvalidLength,
+    InvalidExpiration,
+    InvalidBase64,
+    /// INCR would leave the i64 range, or INCRBYFLOAT the finite f64 one.
+    Overflow,
+    /// INCR on a value that is neither an integer nor a numeric string.
+    NotAnInteger,
+    /// INCR on a float; INCRBYFLOAT works on those.
+    WrongType,
+    /// INCRBYFLOAT with an infinite or NaN delta, or on a value that isn't a number.
+    NotANumber,
+    /// SELECT with an empty or too long name, or one containing `:` or whitespace.
+    InvalidNamespace,
+    /// RENAME of a key that is missing or expired.
+    NoSuchKey,
+    /// A write that would add a key while the store holds MAX_KEYS live keys.
+    StoreFull,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ParseError::UnknownCommand => "unknown command",
+            ParseError::InvalidArguments => "invalid arguments",
+            ParseError::InvalidLength => "invalid length",
+            ParseError::InvalidExpiration => "invalid expiration",
+            ParseError::InvalidBase64 => "invalid base64",
+            ParseError::Overflow => "overflow",
+            ParseError::NotAnInteger => "not an integer",
+            ParseError::WrongType => "wrong type",
+            ParseError::NotANumber => "not a number",
+            ParseError::InvalidNamespace => "invalid namespace",
+            ParseError::NoSuchKey => "key not found",
+            ParseError::StoreFull => "store full",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses the TTL syntax accepted by SET/INCR: "30s", "5min" or "2h".
+pub fn parse_expiration(exp_str: &str) -> Option<Duration> {
+    if let Some(num_str) = exp_str.strip_suffix("min") {
+        num_str
+            .parse::<u64>()
+            .ok()
+            .map(|mins| Duration::from_secs(mins * 60))
+    } else if let Some(num_str) = exp_str.strip_suffix('s') {
+        num_str.parse::<u64>().ok().map(Duration::from_secs)
+    } else if let Some(num_str) = exp_str.strip_suffix('h') {
+        num_str
+            .parse::<u64>()
+            .ok()
+            .map(|hours| Duration::from_secs(hours * 3600))
+    } else {
+        None
+    }
+}
+
+/// TIME reply: unix seconds and the nanoseconds within that second, e.g.
+/// `1760605923 481000000`.
+pub fn time_line(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{} {}", since_epoch.as_secs(), since_epoch.subsec_nanos())
+}
+
+pub fn parse_time(line: &str) -> Option<SystemTime> {
+    let (secs, nanos) = line.split_once(' ')?;
+    let nanos: u32 = nanos.parse().ok().filter(|n| *n < 1_000_000_000)?;
+    UNIX_EPOCH.checked_add(Duration::new(secs.parse().ok()?, nanos))
+}
+
+/// Binary values travel as standard padded base64 in SETB/GETB and EXPORT.
+pub fn encode_bytes(data: &[u8]) -> String {
+    BASE64.encode(data)
+}
+
+pub fn decode_bytes(encoded: &str) -> Result<Vec<u8>, ParseError> {
+    BASE64
+        .decode(encoded)
+        .map_err(|_| ParseError::InvalidBase64)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// `HELLO [noheartbeat] [proto=1]`, only accepted as a connection's first line.
+    /// Without it a connection keeps the server defaults.
+    Hello {
+        heartbeat: bool,
+        proto: u32,
+    },
+    Ping,
+    Status,
+    /// `TIME`: the server's clock, answered with `time_line`.
+    Time,
+    /// `LIMITS`: the server's length limits, answered with `Limits::to_line`.
+    Limits,
+    Get {
+        key: String,
+    },
+    /// `PEEK key`: answers like GET but only reads, so an expired key is reported
+    /// missing without being removed.
+    Peek {
+        key: String,
+    },
+    /// `EXISTS key`: 1 if the key is present and not expired, otherwise 0.
+    Exists {
+        key: String,
+    },
+    /// `TYPE key`: the kind the value is stored as, `int`, `float`, `str` or `bytes`,
+    /// which decides whether INCR or INCRBYFLOAT works on it.
+    Type {
+        key: String,
+    },
+    /// `MGET key1 ... keyN`: GET of up to MAX_MGET_KEYS keys. Key lengths are checked
+    /// per key by the server, so one bad key only fails its own line.
+    MGet {
+        keys: Vec<String>,
+    },
+    /// `GETEX key [ttl|PERSIST]`: answers like GET and, when the key exists, sets or
+    /// clears its expiration under the same lock.
+    GetEx {
+        key: String,
+        expiry: ExpiryChange,
+    },
+    /// `GETDEL key`: answers like GET and removes the key under the same lock, so of
+    /// several clients racing for a one-shot value only one gets it.
+    GetDel {
+        key: String,
+    },
+    /// `SET key value [ttl] [RAW]`. A value that parses as i64 is stored as an
+    /// integer, so `007` reads back as `7`; with RAW it is kept as the exact string.
+    Set {
+        key: String,
+        value: String,
+        ttl: Option<Duration>,
+        raw: bool,
+    },
+    /// `GETSET key value [ttl]`: replaces the value and replies with the old one, or NIL
+    /// if the key was missing or expired. Without a ttl a live key keeps its expiration.
+    GetSet {
+        key: String,
+        value: String,
+        ttl: Option<Duration>,
+    },
+    /// `SETNX key value [ttl]`: SET only if the key is missing or expired. Replies 1 if
+    /// it set the key and 0 if the key was already there.
+    SetNx {
+        key: String,
+        value: String,
+        ttl: Option<Duration>,
+    },
+    /// `CAS key expected value [ttl]`: SET only if the key's current value reads
+    /// `expected`, as GET would show it. Replies 1 if it set the key and 0 if the value
+    /// differed or the key was missing. Without a ttl the key keeps its expiration.
+    Cas {
+        key: String,
+        expected: String,
+        value: String,
+        ttl: Option<Duration>,
+    },
+    /// `MSET key1 value1 ... keyN valueN [TTL=<duration>]`: SET of up to MAX_MSET_PAIRS
+    /// pairs under one lock, so other clients see all of them or none, each with the
+    /// same expiration. Nothing is written if any pair is rejected.
+    MSet {
+        pairs: Vec<(String, String)>,
+        ttl: Option<Duration>,
+    },
+    /// Adds one to the value. A missing or expired key counts as 0, and a string that
+    /// parses as i64 is incremented numerically. Any other string or binary value is
+    /// rejected with `ERROR not an integer`, and a result past i64::MAX with
+    /// `ERROR overflow`; in both cases the stored value is left unchanged.
+    Incr {
+        key: String,
+        ttl: Option<Duration>,
+    },
+    /// Subtracts one, otherwise exactly like INCR; a result below i64::MIN is
+    /// rejected with `ERROR overflow`.
+    Decr {
+        key: String,
+        ttl: Option<Duration>,
+    },
+    /// `INCRBY key amount [ttl]`: adds a non-zero `amount`, otherwise exactly like INCR.
+    IncrBy {
+        key: String,
+        amount: i64,
+        ttl: Option<Duration>,
+    },
+    /// `DECRBY key amount [ttl]`: subtracts a non-zero `amount`, otherwise exactly like
+    /// DECR.
+    DecrBy {
+        key: String,
+        amount: i64,
+        ttl: Option<Duration>,
+    },
+    /// `INCRBYFLOAT key delta [ttl]`: adds a finite, non-zero `delta` to a float, an
+    /// integer or a numeric string, storing the result as a float, and replies with it
+    /// as `f64::to_string` prints it. A missing or expired key counts as 0, and `ttl`
+    /// only applies to a key created here.
+    IncrByFloat {
+        key: String,
+        delta: f64,
+        ttl: Option<Duration>,
+    },
+    Del {
+        key: String,
+    },
+    /// `RENAME src dst`: moves the value and expiration of `src` to `dst` under one
+    /// lock, replacing anything stored at `dst`. `ERROR key not found` if `src` is
+    /// missing or expired; renaming a key to itself changes nothing.
+    Rename {
+        src: String,
+        dst: String,
+    },
+    /// `RENAMENX src dst`: RENAME only if `dst` is missing or expired. Replies 1 if the
+    /// key was moved, 0 if `dst` exists.
+    RenameNx {
+        src: String,
+        dst: String,
+    },
+    /// `PERSIST key`: removes the key's expiration, leaving the value as it is.
+    Persist {
+        key: String,
+    },
+    /// `TTL key`: whole seconds until the key expires, -1 if it never does and -2 if
+    /// it is missing or expired.
+    Ttl {
+        key: String,
+    },
+    /// `PTTL key`: TTL in whole milliseconds, with the same -1 and -2.
+    PTtl {
+        key: String,
+    },
+    /// `EXPIRE key ttl`: gives an existing key a new expiration without touching its
+    /// value. A missing or expired key gets KEY_NOT_FOUND.
+    Expire {
+        key: String,
+        ttl: Duration,
+    },
+    SetB {
+        key: String,
+        data: Vec<u8>,
+        ttl: Option<Duration>,
+    },
+    GetB {
+        key: String,
+    },
+    /// `LIST [pattern]`: every live entry, or those whose names match the glob as KEYS
+    /// matches them, in no particular order and ended by an empty line.
+    List {
+        pattern: Option<String>,
+    },
+    /// `LIST BYEXPIRY [LIMIT n]`: LIST's entries sorted by expiration, soonest first and
+    /// keys without one last, cut to the first `n` when a limit is given.
+    ListByExpiry {
+        limit: Option<usize>,
+    },
+    /// `COUNT [pattern]`: how many live keys there are, or how many match the glob, as
+    /// an integer.
+    Count {
+        pattern: Option<String>,
+    },
+    /// `KEYS pattern`: names of live keys matching a glob where `*` matches any run of
+    /// characters and `?` exactly one, one per line and ended by an empty line.
+    Keys {
+        pattern: String,
+    },
+    /// `SCAN cursor [COUNT n]`: up to `count` live entries whose names sort after
+    /// `after` (None for SCAN_START), in name order. The reply is the next cursor, then
+    /// LIST lines, ended by an empty line.
+    Scan {
+        after: Option<String>,
+        count: usize,
+    },
+    /// `TAG key tag`: adds `tag` to a live key. Tags stay through SET and RENAME and
+    /// go when the key is deleted or expires. KEY_NOT_FOUND if the key is missing.
+    Tag {
+        key: String,
+        tag: String,
+    },
+    /// `TAGGED tag`: names of live keys bearing `tag`, as KEYS lists them.
+    Tagged {
+        tag: String,
+    },
+    /// `EXPIRETAG tag ttl`: EXPIRE on every live key bearing `tag` at once. The reply
+    /// is the number of keys given the expiration.
+    ExpireTag {
+        tag: String,
+        ttl: Duration,
+    },
+    Export,
+    Import {
+        json: String,
+    },
+    /// `FLUSHALL`: removes every key from the whole store (admin command).
+    FlushAll,
+    /// `SELECT [namespace]`: later commands on the connection read and write keys as
+    /// `namespace:key`, and LIST shows only that namespace with the prefix removed.
+    /// Without a name it goes back to the global namespace.
+    Select {
+        namespace: Option<String>,
+    },
+    /// `BADCMDS` (admin): returns the server's recent samples of rejected command
+    /// lines and clears them.
+    BadCmds,
+    /// `DUMP <path>` (admin): writes the whole store as an EXPORT document to a file
+    /// on the server's host.
+    Dump {
+        path: String,
+    },
+    /// `STATS KEY <key>`: the per-key counters for one key, when KEY_STATS is on.
+    KeyStats {
+        key: String,
+    },
+    /// `STATS TOP <n>`: counters of the `n` most accessed keys, busiest first.
+    TopKeys {
+        n: usize,
+    },
+    /// `LOAD <path> [merge|replace]` (admin): reads a DUMP file on the server's host,
+    /// skipping expired entries.
+    Load {
+        path: String,
+        mode: LoadMode,
+    },
+    /// `BGREWRITEAOF` (admin): replaces the AOF with the lines that recreate the
+    /// current store. Writes carry on meanwhile and land in the new file.
+    BgRewriteAof,
+    /// `COMPRESS ON|OFF`: switches the connection's replies to and from framed wire
+    /// compression, starting after the OK. Not accepted inside a BATCH.
+    Compress {
+        on: bool,
+    },
+    /// `DEBUG SLEEP ms`: the server waits `ms` milliseconds before replying OK, to test
+    /// client timeouts. Rejected unless the server enables debug commands.
+    DebugSleep {
+        ms: u64,
+    },
+}
+
+/// How LOAD combines the file with the current store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadMode {
+    /// Loaded keys overwrite existing ones; other keys are kept. The default.
+    Merge,
+    /// The store is emptied first.
+    Replace,
+}
+
+/// What GETEX does to the expiration of the key it reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryChange {
+    /// Left as it is; GETEX then answers exactly like GET.
+    Keep,
+    /// Expires this long from now.
+    Ttl(Duration),
+    /// Never expires.
+    Persist,
+}
+
+impl LoadMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoadMode::Merge => "merge",
+            LoadMode::Replace => "replace",
+        }
+    }
+}
+
+impl Command {
+    /// Parses one command line (without BATCH framing) under the default limits. The
+    /// verb is case-insensitive.
+    pub fn parse(line: &str) -> Result<Command, ParseError> {
+        Command::parse_with(line, &Limits::default())
+    }
+
+    /// `parse` holding keys and values to `limits`, as a server configured with other
+    /// limits does.
+    pub fn parse_with(line: &str, limits: &Limits) -> Result<Command, ParseError> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        Command::parse_parts(&parts, limits)
+    }
+
+    pub fn parse_parts(parts: &[&str], limits: &Limits) -> Result<Command, ParseError> {
+        let verb = match parts.first() {
+            Some(verb) => verb.to_ascii_uppercase(),
+            None => return Err(ParseError::UnknownCommand),
+        };
+        let args = &parts[1..];
+        let ttl_arg = |arg: Option<&&str>| match arg {
+            Some(exp_str) => parse_expiration(exp_str)
+                .map(Some)
+                .ok_or(ParseError::InvalidExpiration),
+            None => Ok(None),
+        };
+
+        let command = match verb.as_str() {
+            "HELLO" => {
+                let mut heartbeat = true;
+                let mut proto = PROTOCOL_VERSION;
+                for arg in args {
+                    if arg.eq_ignore_ascii_case("noheartbeat") {
+                        heartbeat = false;
+                    } else if let Some(version) = arg.strip_prefix("proto=") {
+                        proto = version.parse().map_err(|_| ParseError::InvalidArguments)?;
+                    } else {
+                        return Err(ParseError::InvalidArguments);
+                    }
+                }
+                // Newer clients are answered with this build's revision
+                if proto == 0 {
+                    return Err(ParseError::InvalidArguments);
+                }
+                Command::Hello { heartbeat, proto }
+            }
+            "PING" => {
+                expect_args(args, 0, 0)?;
+                Command::Ping
+            }
+            "STATUS" => {
+                expect_args(args, 0, 0)?;
+                Command::Status
+            }
+            "TIME" => {
+                expect_args(args, 0, 0)?;
+                Command::Time
+            }
+            "LIMITS" => {
+                expect_args(args, 0, 0)?;
+                Command::Limits
+            }
+            "GET" => {
+                expect_args(args, 1, 1)?;
+                Command::Get {
+                    key: args[0].to_string(),
+                }
+            }
+            "PEEK" => {
+                expect_args(args, 1, 1)?;
+                Command::Peek {
+                    key: args[0].to_string(),
+                }
+            }
+            "GETEX" => {
+                expect_args(args, 1, 2)?;
+                let expiry = match args.get(1) {
+                    None => ExpiryChange::Keep,
+                    Some(arg) if arg.eq_ignore_ascii_case("PERSIST") => ExpiryChange::Persist,
+                    Some(arg) => ExpiryChange::Ttl(
+                        parse_expiration(arg).ok_or(ParseError::InvalidExpiration)?,
+                    ),
+                };
+                Command::GetEx {
+                    key: args[0].to_string(),
+                    expiry,
+                }
+            }
+            "EXISTS" => {
+                expect_args(args, 1, 1)?;
+                Command::Exists {
+                    key: args[0].to_string(),
+                }
+            }
+            "TYPE" => {
+                expect_args(args, 1, 1)?;
+                Command::Type {
+                    key: args[0].to_string(),
+                }
+            }
+            "MGET" => {
+                expect_args(args, 1, MAX_MGET_KEYS)?;
+                Command::MGet {
+                    keys: args.iter().map(|key| key.to_string()).collect(),
+                }
+            }
+            "SET" => {
+                // `SET key RAW` stores the word RAW
+                let (args, raw) = match args {
+                    [rest @ .., last] if rest.len() >= 2 && last.eq_ignore_ascii_case("RAW") => {
+                        (rest, true)
+                    }
+                    _ => (args, false),
+                };
+                expect_args(args, 2, 3)?;
+                check_key(limits, args[0])?;
+                check_value(limits, args[1])?;
+                Command::Set {
+                    key: args[0].to_string(),
+                    value: args[1].to_string(),
+                    ttl: ttl_arg(args.get(2))?,
+                    raw,
+                }
+            }
+            "GETSET" => {
+                expect_args(args, 2, 3)?;
+                Command::GetSet {
+                    key: args[0].to_string(),
+                    value: args[1].to_string(),
+                    ttl: ttl_arg(args.get(2))?,
+                }
+            }
+            "SETNX" => {
+                expect_args(args, 2, 3)?;
+                check_key(limits, args[0])?;
+                check_value(limits, args[1])?;
+                Command::SetNx {
+                    key: args[0].to_string(),
+                    value: args[1].to_string(),
+                    ttl: ttl_arg(args.get(2))?,
+                }
+            }
+            "CAS" => {
+                expect_args(args, 3, 4)?;
+                check_key(limits, args[0])?;
+                check_value(limits, args[1])?;
+                check_value(limits, args[2])?;
+                Command::Cas {
+                    key: args[0].to_string(),
+                    expected: args[1].to_string(),
+                    value: args[2].to_string(),
+                    ttl: ttl_arg(args.get(3))?,
+                }
+            }
+            "MSET" => {
+                expect_args(args, 2, 2 * MAX_MSET_PAIRS + 1)?;
+                // Only an odd trailing argument can be the TTL, so a value that happens
+                // to start with TTL= is still taken as a value
+                let (args, ttl) = match args.split_last() {
+                    Some((last, pairs)) if !args.len().is_multiple_of(2) => {
+                        match last.strip_prefix(MSET_TTL_PREFIX) {
+                            Some(exp_str) => (pairs, ttl_arg(Some(&exp_str))?),
+                            None => return Err(ParseError::InvalidArguments),
+                        }
+                    }
+                    _ => (args, None),
+                };
+                Command::MSet {
+                    pairs: args
+                        .chunks(2)
+                        .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                        .collect(),
+                    ttl,
+                }
+            }
+            "INCR" => {
+                expect_args(args, 1, 2)?;
+                check_key(limits, args[0])?;
+                Command::Incr {
+                    key: args[0].to_string(),
+                    ttl: ttl_arg(args.get(1))?,
+                }
+            }
+            "DECR" => {
+                expect_args(args, 1, 2)?;
+                check_key(limits, args[0])?;
+                Command::Decr {
+                    key: args[0].to_string(),
+                    ttl: ttl_arg(args.get(1))?,
+                }
+            }
+            "INCRBY" | "DECRBY" => {
+                expect_args(args, 2, 3)?;
+                check_key(limits, args[0])?;
+                let key = args[0].to_string();
+                // A zero step would be a GET that can create the key
+                let amount = match args[1].parse() {
+                    Ok(0) | Err(_) => return Err(ParseError::InvalidArguments),
+                    Ok(amount) => amount,
+                };
+                let ttl = ttl_arg(args.get(2))?;
+                if verb == "INCRBY" {
+                    Command::IncrBy { key, amount, ttl }
+                } else {
+                    Command::DecrBy { key, amount, ttl }
+                }
+            }
+            "INCRBYFLOAT" => {
+                expect_args(args, 2, 3)?;
+                check_key(limits, args[0])?;
+                let delta = match args[1].parse::<f64>() {
+                    Ok(delta) if !delta.is_finite() => return Err(ParseError::NotANumber),
+                    Ok(delta) if delta != 0.0 => delta,
+                    _ => return Err(ParseError::InvalidArguments),
+                };
+                Command::IncrByFloat {
+                    key: args[0].to_string(),
+                    delta,
+                    ttl: ttl_arg(args.get(2))?,
+                }
+            }
+            "TTL" => {
+                expect_args(args, 1, 1)?;
+                Command::Ttl {
+                    key: args[0].to_string(),
+                }
+            }
+            "PTTL" => {
+                expect_args(args, 1, 1)?;
+                Command::PTtl {
+                    key: args[0].to_string(),
+                }
+            }
+            "EXPIRE" => {
+                expect_args(args, 2, 2)?;
+                Command::Expire {
+                    key: args[0].to_string(),
+                    ttl: parse_expiration(args[1]).ok_or(ParseError::InvalidExpiration)?,
+                }
+            }
+            "RENAME" | "RENAMENX" => {
+                expect_args(args, 2, 2)?;
+                let (src, dst) = (args[0].to_string(), args[1].to_string());
+                if verb == "RENAME" {
+                    Command::Rename { src, dst }
+                } else {
+                    Command::RenameNx { src, dst }
+                }
+            }
+            "PERSIST" => {
+                expect_args(args, 1, 1)?;
+                Command::Persist {
+                    key: args[0].to_string(),
+                }
+            }
+            "DEL" => {
+                expect_args(args, 1, 1)?;
+                Command::Del {
+                    key: args[0].to_string(),
+                }
+            }
+            "GETDEL" => {
+                expect_args(args, 1, 1)?;
+                Command::GetDel {
+                    key: args[0].to_string(),
+                }
+            }
+            "SETB" => {
+                expect_args(args, 2, 3)?;
+                check_key(limits, args[0])?;
+                Command::SetB {
+                    key: args[0].to_string(),
+                    data: decode_bytes(args[1])?,
+                    ttl: ttl_arg(args.get(2))?,
+                }
+            }
+            "GETB" => {
+                expect_args(args, 1, 1)?;
+                Command::GetB {
+                    key: args[0].to_string(),
+                }
+            }
+            "LIST" => match args {
+                [word, rest @ ..] if word.eq_ignore_ascii_case("BYEXPIRY") => {
+                    let limit = match rest {
+                        [] => None,
+                        [word, n] if word.eq_ignore_ascii_case("LIMIT") => {
+                            Some(n.parse().map_err(|_| ParseError::InvalidArguments)?)
+                        }
+                        _ => return Err(ParseError::InvalidArguments),
+                    };
+                    Command::ListByExpiry { limit }
+                }
+                _ => {
+                    expect_args(args, 0, 1)?;
+                    Command::List {
+                        pattern: args.first().map(|pattern| pattern.to_string()),
+                    }
+                }
+            },
+            "COUNT" => {
+                expect_args(args, 0, 1)?;
+                Command::Count {
+                    pattern: args.first().map(|pattern| pattern.to_string()),
+                }
+            }
+            "KEYS" => {
+                expect_args(args, 1, 1)?;
+                Command::Keys {
+                    pattern: args[0].to_string(),
+                }
+            }
+            "SCAN" => {
+                let count = match args {
+                    [_] => SCAN_DEFAULT_COUNT,
+                    [_, word, n] if word.eq_ignore_ascii_case("COUNT") => {
+                        n.parse().map_err(|_| ParseError::InvalidArguments)?
+                    }
+                    _ => return Err(ParseError::InvalidArguments),
+                };
+                Command::Scan {
+                    after: scan_cursor_key(args[0])?,
+                    count,
+                }
+            }
+            "TAG" => {
+                expect_args(args, 2, 2)?;
+                Command::Tag {
+                    key: args[0].to_string(),
+                    tag: args[1].to_string(),
+                }
+            }
+            "TAGGED" => {
+                expect_args(args, 1, 1)?;
+                Command::Tagged {
+                    tag: args[0].to_string(),
+                }
+            }
+            "EXPIRETAG" => {
+                expect_args(args, 2, 2)?;
+                Command::ExpireTag {
+                    tag: args[0].to_string(),
+                    ttl: parse_expiration(args[1]).ok_or(ParseError::InvalidExpiration)?,
+                }
+            }
+            "EXPORT" => {
+                expect_args(args, 0, 0)?;
+                Command::Export
+            }
+            "BADCMDS" => {
+                expect_args(args, 0, 0)?;
+                Command::BadCmds
+            }
+            "FLUSHALL" => {
+                expect_args(args, 0, 0)?;
+                Command::FlushAll
+            }
+            "BGREWRITEAOF" => {
+                expect_args(args, 0, 0)?;
+                Command::BgRewriteAof
+            }
+            "STATS" => {
+                expect_args(args, 2, 2)?;
+                match args[0].to_ascii_uppercase().as_str() {
+                    "KEY" => Command::KeyStats {
+                        key: args[1].to_string(),
+                    },
+                    "TOP" => Command::TopKeys {
+                        n: args[1].parse().map_err(|_| ParseError::InvalidArguments)?,
+                    },
+                    _ => return Err(ParseError::InvalidArguments),
+                }
+            }
+            "DEBUG" => {
+                expect_args(args, 2, 2)?;
+                if !args[0].eq_ignore_ascii_case("SLEEP") {
+                    return Err(ParseError::InvalidArguments);
+                }
+                Command::DebugSleep {
+                    ms: args[1]
+                        .parse()
+                        .ok()
+                        .filter(|ms| *ms <= MAX_DEBUG_SLEEP_MS)
+                        .ok_or(ParseError::InvalidArguments)?,
+                }
+            }
+            "DUMP" => {
+                expect_args(args, 1, 1)?;
+                Command::Dump {
+                    path: args[0].to_string(),
+                }
+            }
+            "LOAD" => {
+                expect_args(args, 1, 2)?;
+                let mode = match args.get(1) {
+                    None => LoadMode::Merge,
+                    Some(mode) if mode.eq_ignore_ascii_case("merge") => LoadMode::Merge,
+                    Some(mode) if mode.eq_ignore_ascii_case("replace") => LoadMode::Replace,
+                    Some(_) => return Err(ParseError::InvalidArguments),
+                };
+                Command::Load {
+                    path: args[0].to_string(),
+                    mode,
+                }
+            }
+            "COMPRESS" => {
+                expect_args(args, 1, 1)?;
+                let on = match args[0].to_ascii_uppercase().as_str() {
+                    "ON" => true,
+                    "OFF" => false,
+                    _ => return Err(ParseError::InvalidArguments),
+                };
+                Command::Compress { on }
+            }
+            "SELECT" => {
+                expect_args(args, 0, 1)?;
+                Command::Select {
+                    namespace: args.first().map(|ns| ns.to_string()),
+                }
+            }
+            "IMPORT" => {
+                if args.is_empty() {
+                    return Err(ParseError::InvalidArguments);
+                }
+                // Keys and values never contain whitespace, so rejoining the split
+                // parts only affects insignificant whitespace in the JSON document
+                Command::Import {
+                    json: args.join(" "),
+                }
+            }
+            _ => return Err(ParseError::UnknownCommand),
+        };
+        command.validate_with(limits)?;
+        Ok(command)
+    }
+
+    /// Applies the default key/value length limits. Used by clients before sending.
+    /// SETB's decoded size limit is configurable, so only the server checks it.
+    pub fn validate(&self) -> Result<(), ParseError> {
+        self.validate_with(&Limits::default())
+    }
+
+    /// `validate` against the key and value limits of a server configured otherwise.
+    pub fn validate_with(&self, limits: &Limits) -> Result<(), ParseError> {
+        match self {
+            Command::Get { key }
+            | Command::Peek { key }
+            | Command::Exists { key }
+            | Command::Type { key }
+            | Command::GetEx { key, .. }
+            | Command::GetDel { key }
+            | Command::KeyStats { key }
+            | Command::Del { key }
+            | Command::Incr { key, .. }
+            | Command::Decr { key, .. }
+            | Command::IncrBy { key, .. }
+            | Command::DecrBy { key, .. }
+            | Command::IncrByFloat { key, .. }
+            | Command::Expire { key, .. }
+            | Command::Ttl { key }
+            | Command::PTtl { key }
+            | Command::Persist { key }
+            | Command::SetB { key, .. }
+            | Command::GetB { key } => check_key(limits, key),
+            // A pattern is held to the same limit as the keys it matches
+            Command::Keys { pattern }
+            | Command::List {
+                pattern: Some(pattern),
+            }
+            | Command::Count {
+                pattern: Some(pattern),
+            } => check_key(limits, pattern),
+            Command::Scan { count, .. } if *count == 0 || *count > MAX_SCAN_COUNT => {
+                Err(ParseError::InvalidArguments)
+            }
+            Command::ListByExpiry { limit: Some(0) } => Err(ParseError::InvalidArguments),
+            Command::Scan {
+                after: Some(after), ..
+            } => check_key(limits, after),
+            // Tags are held to the key limit too
+            Command::Tagged { tag } | Command::ExpireTag { tag, .. } => check_key(limits, tag),
+            Command::Tag { key, tag } => {
+                check_key(limits, key)?;
+                check_key(limits, tag)
+            }
+            Command::Rename { src, dst } | Command::RenameNx { src, dst } => {
+                check_key(limits, src)?;
+                check_key(limits, dst)
+            }
+            Command::Set { key, value, .. }
+            | Command::SetNx { key, value, .. }
+            | Command::GetSet { key, value, .. } => {
+                check_key(limits, key)?;
+                check_value(limits, value)
+            }
+            Command::Cas {
+                key,
+                expected,
+                value,
+                ..
+            } => {
+                check_key(limits, key)?;
+                check_value(limits, expected)?;
+                check_value(limits, value)
+            }
+            Command::MGet { keys } if keys.is_empty() || keys.len() > MAX_MGET_KEYS => {
+                Err(ParseError::InvalidArguments)
+            }
+            Command::MSet { pairs, .. } if pairs.is_empty() || pairs.len() > MAX_MSET_PAIRS => {
+                Err(ParseError::InvalidArguments)
+            }
+            Command::MSet { pairs, .. } => pairs.iter().try_for_each(|(key, value)| {
+                check_key(limits, key)?;
+                check_value(limits, value)
+            }),
+            Command::Select {
+                namespace: Some(namespace),
+            } => check_namespace(limits, namespace),
+            Command::Hello { .. }
+            | Command::Select { namespace: None }
+            | Command::Ping
+            | Command::Status
+            | Command::Time
+            | Command::Limits
+            | Command::List { pattern: None }
+            | Command::ListByExpiry { .. }
+            | Command::Count { pattern: None }
+            | Command::Scan { after: None, .. }
+            | Command::Export
+            | Command::FlushAll
+            | Command::BadCmds
+            | Command::TopKeys { .. }
+            | Command::Dump { .. }
+            | Command::Load { .. }
+            | Command::BgRewriteAof
+            | Command::Compress { .. }
+            | Command::DebugSleep { .. }
+            | Command::MGet { .. }
+            | Command::Import { .. } => Ok(()),
+        }
+    }
+
+    /// The command's first word on the wire, e.g. `STATS` for STATS KEY and STATS TOP.
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Command::Hello { .. } => "HELLO",
+            Command::Ping => "PING",
+            Command::Status => "STATUS",
+            Command::Time => "TIME",
+            Command::Limits => "LIMITS",
+            Command::Get { .. } => "GET",
+            Command::Peek { .. } => "PEEK",
+            Command::Exists { .. } => "EXISTS",
+            Command::Type { .. } => "TYPE",
+            Command::MGet { .. } => "MGET",
+            Command::GetEx { .. } => "GETEX",
+            Command::GetDel { .. } => "GETDEL",
+            Command::Set { .. } => "SET",
+            Command::SetNx { .. } => "SETNX",
+            Command::GetSet { .. } => "GETSET",
+            Command::Cas { .. } => "CAS",
+            Command::MSet { .. } => "MSET",
+            Command::Incr { .. } => "INCR",
+            Command::Decr { .. } => "DECR",
+            Command::IncrBy { .. } => "INCRBY",
+            Command::DecrBy { .. } => "DECRBY",
+            Command::IncrByFloat { .. } => "INCRBYFLOAT",
+            Command::Del { .. } => "DEL",
+            Command::Expire { .. } => "EXPIRE",
+            Command::Ttl { .. } => "TTL",
+            Command::PTtl { .. } => "PTTL",
+            Command::Persist { .. } => "PERSIST",
+            Command::Rename { .. } => "RENAME",
+            Command::RenameNx { .. } => "RENAMENX",
+            Command::SetB { .. } => "SETB",
+            Command::GetB { .. } => "GETB",
+            Command::List { .. } | Command::ListByExpiry { .. } => "LIST",
+            Command::Count { .. } => "COUNT",
+            Command::Keys { .. } => "KEYS",
+            Command::Scan { .. } => "SCAN",
+            Command::Tag { .. } => "TAG",
+            Command::Tagged { .. } => "TAGGED",
+            Command::ExpireTag { .. } => "EXPIRETAG",
+            Command::Export => "EXPORT",
+            Command::Import { .. } => "IMPORT",
+            Command::Select { .. } => "SELECT",
+            Command::BadCmds => "BADCMDS",
+            Command::FlushAll => "FLUSHALL",
+            Command::Dump { .. } => "DUMP",
+            Command::KeyStats { .. } | Command::TopKeys { .. } => "STATS",
+            Command::Load { .. } => "LOAD",
+            Command::BgRewriteAof => "BGREWRITEAOF",
+            Command::Compress { .. } => "COMPRESS",
+            Command::DebugSleep { .. } => "DEBUG",
+        }
+    }
+
+    /// The key the command reads or writes, if it names one.
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            Command::Get { key }
+            | Command::Peek { key }
+            | Command::Exists { key }
+            | Command::Type { key }
+            | Command::GetEx { key, .. }
+            | Command::GetDel { key }
+            | Command::Set { key, .. }
+            | Command::SetNx { key, .. }
+            | Command::GetSet { key, .. }
+            | Command::Cas { key, .. }
+            | Command::Incr { key, .. }
+            | Command::Decr { key, .. }
+            | Command::IncrBy { key, .. }
+            | Command::DecrBy { key, .. }
+            | Command::IncrByFloat { key, .. }
+            | Command::Expire { key, .. }
+            | Command::Ttl { key }
+            | Command::PTtl { key }
+            | Command::Persist { key }
+            | Command::Del { key }
+            | Command::SetB { key, .. }
+            | Command::GetB { key }
+            | Command::Tag { key, .. }
+            | Command::KeyStats { key } => Some(key),
+            // The key that has to exist for the command to succeed
+            Command::Rename { src, .. } | Command::RenameNx { src, .. } => Some(src),
+            _ => None,
+        }
+    }
+
+    /// Serializes the command as a wire line, without the trailing newline.
+    pub fn to_line(&self) -> String {
+        match self {
+            Command::Hello { heartbeat, proto } => {
+                if *heartbeat {
+                    format!("HELLO proto={}", proto)
+                } else {
+                    format!("HELLO noheartbeat proto={}", proto)
+                }
+            }
+            Command::Ping => "PING".to_string(),
+            Command::Status => "STATUS".to_string(),
+            Command::Time => "TIME".to_string(),
+            Command::Limits => "LIMITS".to_string(),
+            Command::Get { key } => format!("GET {}", key),
+            Command::Peek { key } => format!("PEEK {}", key),
+            Command::Exists { key } => format!("EXISTS {}", key),
+            Command::Type { key } => format!("TYPE {}", key),
+            Command::MGet { keys } => format!("MGET {}", keys.join(" ")),
+            Command::GetDel { key } => format!("GETDEL {}", key),
+            Command::GetEx { key, expiry } => match expiry {
+                ExpiryChange::Keep => format!("GETEX {}", key),
+                ExpiryChange::Ttl(ttl) => format!("GETEX {} {}s", key, ttl.as_secs()),
+                ExpiryChange::Persist => format!("GETEX {} PERSIST", key),
+            },
+            Command::Set {
+                key,
+                value,
+                ttl,
+                raw,
+            } => {
+                let line = match ttl {
+                    Some(ttl) => format!("SET {} {} {}s", key, value, ttl.as_secs()),
+                    None => format!("SET {} {}", key, value),
+                };
+                if *raw {
+                    line + " RAW"
+                } else {
+                    line
+                }
+            }
+            Command::SetNx { key, value, ttl } => match ttl {
+                Some(ttl) => format!("SETNX {} {} {}s", key, value, ttl.as_secs()),
+                None => format!("SETNX {} {}", key, value),
+            },
+            Command::GetSet { key, value, ttl } => match ttl {
+                Some(ttl) => format!("GETSET {} {} {}s", key, value, ttl.as_secs()),
+                None => format!("GETSET {} {}", key, value),
+            },
+            Command::Cas {
+                key,
+                expected,
+                value,
+                ttl,
+            } => match ttl {
+                Some(ttl) => format!("CAS {} {} {} {}s", key, expected, value, ttl.as_secs()),
+                None => format!("CAS {} {} {}", key, expected, value),
+            },
+            Command::MSet { pairs, ttl } => {
+                let mut line = "MSET".to_string();
+                for (key, value) in pairs {
+                    line.push_str(&format!(" {} {}", key, value));
+                }
+                if let Some(ttl) = ttl {
+                    line.push_str(&format!(" {}{}s", MSET_TTL_PREFIX, ttl.as_secs()));
+                }
+                line
+            }
+            Command::Incr { key, ttl } => match ttl {
+                Some(ttl) => format!("INCR {} {}s", key, ttl.as_secs()),
+                None => format!("INCR {}", key),
+            },
+            Command::Decr { key, ttl } => match ttl {
+                Some(ttl) => format!("DECR {} {}s", key, ttl.as_secs()),
+                None => format!("DECR {}", key),
+            },
+            Command::IncrBy { key, amount, ttl } => match ttl {
+                Some(ttl) => format!("INCRBY {} {} {}s", key, amount, ttl.as_secs()),
+                None => format!("INCRBY {} {}", key, amount),
+            },
+            Command::DecrBy { key, amount, ttl } => match ttl {
+                Some(ttl) => format!("DECRBY {} {} {}s", key, amount, ttl.as_secs()),
+                None => format!("DECRBY {} {}", key, amount),
+            },
+            Command::IncrByFloat { key, delta, ttl } => match ttl {
+                Some(ttl) => format!("INCRBYFLOAT {} {} {}s", key, delta, ttl.as_secs()),
+                None => format!("INCRBYFLOAT {} {}", key, delta),
+            },
+            Command::Del { key } => format!("DEL {}", key),
+            Command::Expire { key, ttl } => format!("EXPIRE {} {}s", key, ttl.as_secs()),
+            Command::Ttl { key } => format!("TTL {}", key),
+            Command::PTtl { key } => format!("PTTL {}", key),
+            Command::Persist { key } => format!("PERSIST {}", key),
+            Command::Rename { src, dst } => format!("RENAME {} {}", src, dst),
+            Command::RenameNx { src, dst } => format!("RENAMENX {} {}", src, dst),
+            Command::SetB { key, data, ttl } => match ttl {
+                Some(ttl) => format!("SETB {} {} {}s", key, encode_bytes(data), ttl.as_secs()),
+                None => format!("SETB {} {}", key, encode_bytes(data)),
+            },
+            Command::GetB { key } => format!("GETB {}", key),
+            Command::List { pattern } => match pattern {
+                Some(pattern) => format!("LIST {}", pattern),
+                None => "LIST".to_string(),
+            },
+            Command::ListByExpiry { limit } => match limit {
+                Some(limit) => format!("LIST BYEXPIRY LIMIT {}", limit),
+                None => "LIST BYEXPIRY".to_string(),
+            },
+            Command::Count { pattern } => match pattern {
+                Some(pattern) => format!("COUNT {}", pattern),
+                None => "COUNT".to_string(),
+            },
+            Command::Keys { pattern } => format!("KEYS {}", pattern),
+            Command::Scan { after, count } => {
+                format!("SCAN {} COUNT {}", scan_cursor(after.as_deref()), count)
+            }
+            Command::Tag { key, tag } => format!("TAG {} {}", key, tag),
+            Command::Tagged { tag } => format!("TAGGED {}", tag),
+            Command::ExpireTag { tag, ttl } => format!("EXPIRETAG {} {}s", tag, ttl.as_secs()),
+            Command::Export => "EXPORT".to_string(),
+            Command::BadCmds => "BADCMDS".to_string(),
+            Command::FlushAll => "FLUSHALL".to_string(),
+            Command::KeyStats { key } => format!("STATS KEY {}", key),
+            Command::TopKeys { n } => format!("STATS TOP {}", n),
+            Command::Dump { path } => format!("DUMP {}", path),
+            Command::Load { path, mode } => format!("LOAD {} {}", path, mode.as_str()),
+            Command::BgRewriteAof => "BGREWRITEAOF".to_string(),
+            Command::Import { json } => format!("IMPORT {}", json),
+            Command::Compress { on } => {
+                format!("COMPRESS {}", if *on { "ON" } else { "OFF" })
+            }
+            Command::Select { namespace } => match namespace {
+                Some(namespace) => format!("SELECT {}", namespace),
+                None => "SELECT".to_string(),
+            },
+            Command::DebugSleep { ms } => format!("DEBUG SLEEP {}", ms),
+        }
+    }
+}
+
+fn expect_args(args: &[&str], min: usize, max: usize) -> Result<(), ParseError> {
+    if args.len() < min || args.len() > max {
+        Err(ParseError::InvalidArguments)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_key(limits: &Limits, key: &str) -> Result<(), ParseError> {
+    if key.is_empty() || key.len() > limits.max_key_len || key.contains(char::is_whitespace) {
+        Err(ParseError::InvalidLength)
+    } else {
+        Ok(())
+    }
+}
+
+// A namespace must leave room for at least a one-byte key after `namespace:`
+fn check_namespace(limits: &Limits, namespace: &str) -> Result<(), ParseError> {
+    if namespace.is_empty()
+        || namespace.len() + 2 > limits.max_key_len
+        || namespace.contains(|c: char| c == NAMESPACE_SEPARATOR || c.is_whitespace())
+    {
+        Err(ParseError::InvalidNamespace)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_value(limits: &Limits, value: &str) -> Result<(), ParseError> {
+    if value.is_empty() || value.len() > limits.max_value_len || value.contains(char::is_whitespace)
+    {
+        Err(ParseError::InvalidLength)
+    } else {
+        Ok(())
+    }
+}
+
+/// The SCAN cursor that resumes after the name `after`: the name hex-encoded, so no
+/// key can be mistaken for SCAN_START. None gives SCAN_START.
+pub fn scan_cursor(after: Option<&str>) -> String {
+    match after {
+        Some(name) => name.bytes().map(|b| format!("{:02x}", b)).collect(),
+        None => SCAN_START.to_string(),
+    }
+}
+
+/// The name a SCAN cursor resumes after; None for SCAN_START.
+pub fn scan_cursor_key(cursor: &str) -> Result<Option<String>, ParseError> {
+    if cursor == SCAN_START {
+        return Ok(None);
+    }
+    // The decoded name is held to the key limit by validate
+    if cursor.is_empty() || !cursor.len().is_multiple_of(2) {
+        return Err(ParseError::InvalidArguments);
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(cursor.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()
+        .ok_or(ParseError::InvalidArguments)?;
+    String::from_utf8(bytes)
+        .map(Some)
+        .map_err(|_| ParseError::InvalidArguments)
+}
+
+/// One LIST line: `key=value,type,expiration` where type is as TYPE reports it and
+/// expiration is unix seconds or `no-expiration`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListEntry {
+    pub key: String,
+    pub value: String,
+    pub value_type: String,
+    pub expires_at: Option<u64>,
+}
+
+impl ListEntry {
+    pub fn parse(line: &str) -> Option<ListEntry> {
+        let (key, rest) = line.split_once('=')?;
+        let (rest, expiration) = rest.rsplit_once(',')?;
+        let (value, value_type) = rest.rsplit_once(',')?;
+        let expires_at = if expiration == NO_EXPIRATION {
+            None
+        } else {
+            Some(expiration.parse::<u64>().ok()?)
+        };
+        Some(ListEntry {
+            key: key.to_string(),
+            value: value.to_string(),
+            value_type: value_type.to_string(),
+            expires_at,
+        })
+    }
+
+    /// The expiration as a UTC date and time; None for `no-expiration`.
+    pub fn expires_at_utc(&self) -> Option<DateTime<Utc>> {
+        self.expires_at
+            .and_then(|secs| DateTime::from_timestamp(i64::try_from(secs).ok()?, 0))
+    }
+
+    pub fn to_line(&self) -> String {
+        let expiration = match self.expires_at {
+            Some(ts) => ts.to_string(),
+            None => NO_EXPIRATION.to_string(),
+        };
+        format!(
+            "{}={},{},{}",
+            self.key, self.value, self.value_type, expiration
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    Ok,
+    Pong,
+    Value(String),
+    Integer(i64),
+    NotFound,
+    Error(String),
+    /// LIST body: one line per entry followed by an empty line.
+    List(Vec<ListEntry>),
+    /// EXPORT body: byte length line, then the JSON document.
+    Export(String),
+    /// Free-form single-line status such as IMPORT's `OK imported=N skipped=M`,
+    /// DUMP's `OK dumped=N` or LOAD's `OK loaded=N skipped=M`.
+    Status(String),
+    /// BADCMDS body: one line per sample followed by an empty line.
+    Lines(Vec<String>),
+    /// MGET body: one Value, NotFound (written as NIL) or Error line per key, then
+    /// MGET_END.
+    Values(Vec<Response>),
+}
+
+impl Response {
+    pub fn error(err: ParseError) -> Response {
+        Response::Error(err.to_string())
+    }
+
+    /// Serializes the response including its trailing newline(s).
+    pub fn to_wire(&self) -> String {
+        match self {
+            Response::Ok => "OK\n".to_string(),
+            Response::Pong => "PONG\n".to_string(),
+            Response::Value(v) => format!("{}\n", v),
+            Response::Integer(i) => format!("{}\n", i),
+            Response::NotFound => format!("{}\n", KEY_NOT_FOUND),
+            Response::Error(msg) => format!("ERROR {}\n", msg),
+            Response::List(entries) => {
+                let mut out = String::new();
+                for entry in entries {
+                    out.push_str(&entry.to_line());
+                    out.push('\n');
+                }
+                out.push('\n'); // Empty line indicates end
+                out
+            }
+            Response::Export(json) => format!("{}\n{}\n", json.len(), json),
+            Response::Status(s) => format!("{}\n", s),
+            Response::Lines(lines) => {
+                let mut out = String::new();
+                for line in lines {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+                out.push('\n'); // Empty line indicates end
+                out
+            }
+            Response::Values(slots) => {
+                let mut out = String::new();
+                for slot in slots {
+                    match slot {
+                        Response::NotFound => out.push_str(NIL),
+                        slot => out.push_str(slot.to_wire().trim_end_matches('\n')),
+                    }
+                    out.push('\n');
+                }
+                out.push_str(MGET_END);
+                out.push('\n');
+                out
+            }
+        }
+    }
+
+    /// Classifies a single response line according to the shape `command` expects.
+    /// Multi-line bodies (LIST entries, the EXPORT document, BADCMDS samples, MGET values) are read
+    /// by the caller; for those commands only an error line is classified here.
+    pub fn parse(command: &Command, line: &str) -> Response {
+        if let Some(msg) = line.strip_prefix("ERROR ") {
+            return Response::Error(msg.to_string());
+        }
+        if line == KEY_NOT_FOUND {
+            return Response::NotFound;
+        }
+        let unexpected = || Response::Error(format!("unexpected response: {}", line));
+        match command {
+            Command::Ping if line == "PONG" => Response::Pong,
+            Command::Get { .. }
+            | Command::Peek { .. }
+            | Command::Type { .. }
+            | Command::GetEx { .. }
+            | Command::GetDel { .. }
+            | Command::GetB { .. }
+            | Command::IncrByFloat { .. } => Response::Value(line.to_string()),
+            Command::GetSet { .. } if line == NIL => Response::NotFound,
+            Command::GetSet { .. } => Response::Value(line.to_string()),
+            Command::Set { .. }
+            | Command::MSet { .. }
+            | Command::Del { .. }
+            | Command::Expire { .. }
+            | Command::Persist { .. }
+            | Command::Rename { .. }
+            | Command::Tag { .. }
+            | Command::FlushAll
+            | Command::SetB { .. }
+            | Command::Select { .. }
+            | Command::Compress { .. }
+            | Command::DebugSleep { .. }
+                if line == "OK" =>
+            {
+                Response::Ok
+            }
+            Command::Incr { .. }
+            | Command::Decr { .. }
+            | Command::IncrBy { .. }
+            | Command::DecrBy { .. }
+            | Command::Ttl { .. }
+            | Command::PTtl { .. }
+            | Command::Exists { .. }
+            | Command::RenameNx { .. }
+            | Command::ExpireTag { .. }
+            | Command::Count { .. }
+            | Command::SetNx { .. }
+            | Command::Cas { .. } => line
+                .parse()
+                .map(Response::Integer)
+                .unwrap_or_else(|_| unexpected()),
+            Command::Export => line
+                .parse()
+                .map(Response::Integer)
+                .unwrap_or_else(|_| unexpected()),
+            Command::Import { .. }
+            | Command::Dump { .. }
+            | Command::Load { .. }
+            | Command::BgRewriteAof
+                if line.starts_with("OK") =>
+            {
+                Response::Status(line.to_string())
+            }
+            Command::Status | Command::Time | Command::Limits | Command::KeyStats { .. } => {
+                Response::Status(line.to_string())
+            }
+            Command::Hello { .. } if line.starts_with("HELLO ") => {
+                Response::Status(line.to_string())
+            }
+            _ => unexpected(),
+        }
+    }
+}
+
+/// The LIMITS reply: `max_key_len=100 max_value_len=100 max_bytes_len=100`, the
+/// longest key (namespace prefix included), text value and decoded SETB value the
+/// server accepts. Unknown fields are ignored when parsing, and missing ones keep
+/// their defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_key_len: usize,
+    pub max_value_len: usize,
+    pub max_bytes_len: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_key_len: MAX_KEY_LEN,
+            max_value_len: MAX_VALUE_LEN,
+            max_bytes_len: MAX_BYTES_LEN,
+        }
+    }
+}
+
+impl Limits {
+    pub fn to_line(&self) -> String {
+        format!(
+            "max_key_len={} max_value_len={} max_bytes_len={}",
+            self.max_key_len, self.max_value_len, self.max_bytes_len
+        )
+    }
+
+    pub fn parse(line: &str) -> Option<Limits> {
+        let mut limits = Limits::default();
+        for field in line.split_whitespace() {
+            let (name, value) = field.split_once('=')?;
+            let slot = match name {
+                "max_key_len" => &mut limits.max_key_len,
+                "max_value_len" => &mut limits.max_value_len,
+                "max_bytes_len" => &mut limits.max_bytes_len,
+                _ => continue,
+            };
+            *slot = value.parse().ok()?;
+        }
+        Some(limits)
+    }
+}
+
+/// The HELLO reply: `HELLO version=0.1.1 proto=1 heartbeat=120s features=batch,binary`, or
+/// `heartbeat=off` when the connection gets no UPONG lines. Unknown fields are ignored
+/// when parsing, and a reply without `features=` (older servers) parses with none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HelloReply {
+    pub version: String,
+    /// Protocol revision in use on this connection.
+    pub proto: u32,
+    pub heartbeat: Option<Duration>,
+    pub features: Vec<String>,
+}
+
+impl HelloReply {
+    pub fn to_line(&self) -> String {
+        let heartbeat = match self.heartbeat {
+            Some(interval) => format!("{}s", interval.as_secs()),
+            None => "off".to_string(),
+        };
+        let mut line = format!(
+            "HELLO version={} proto={} heartbeat={}",
+            self.version, self.proto, heartbeat
+        );
+        if !self.features.is_empty() {
+            line.push_str(&format!(" features={}", self.features.join(",")));
+        }
+        line
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    pub fn parse(line: &str) -> Option<HelloReply> {
+        let mut fields = line.split_whitespace();
+        if fields.next() != Some("HELLO") {
+            return None;
+        }
+        let mut version = None;
+        let mut proto = None;
+        let mut heartbeat = None;
+        let mut features = Vec::new();
+        for field in fields {
+            match field.split_once('=') {
+                Some(("version", v)) => version = Some(v.to_string()),
+                Some(("proto", v)) => proto = v.parse().ok(),
+                Some(("heartbeat", "off")) => heartbeat = Some(None),
+                Some(("heartbeat", v)) => heartbeat = parse_expiration(v).map(Some),
+                Some(("features", v)) => {
+                    features = v
+                        .split(',')
+                        .filter(|f| !f.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                _ => {}
+            }
+        }
+        Some(HelloReply {
+            version: version?,
+            proto: proto?,
+            heartbeat: heartbeat?,
+            features,
+        })
+    }
+}
+
+/// The STATUS line: `uptime=3600s keys=1523 expiring=410 mem_bytes~=204800 version=0.1.1 connections=12 saved_bytes~=0 bytes_read=5210 bytes_written=88104 commands=310`.
+/// mem_bytes is an estimate. Unknown fields are ignored when parsing so the line can grow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerStatus {
+    pub uptime: Duration,
+    pub keys: u64,
+    pub expiring: u64,
+    pub mem_bytes: u64,
+    pub version: String,
+    pub connections: u64,
+    /// Bytes saved by value compression; 0 when reported by servers without it.
+    pub saved_bytes: u64,
+    /// Traffic of every connection since startup, open or closed; 0 when reported by
+    /// servers that don't count it.
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Command lines read, a BATCH counting once.
+    pub commands: u64,
+}
+
+impl ServerStatus {
+    pub fn to_line(&self) -> String {
+        format!(
+            "uptime={}s keys={} expiring={} mem_bytes~={} version={} connections={} saved_bytes~={} bytes_read={} bytes_written={} commands={}",
+            self.uptime.as_secs(),
+            self.keys,
+            self.expiring,
+            self.mem_bytes,
+            self.version,
+            self.connections,
+            self.saved_bytes,
+            self.bytes_read,
+            self.bytes_written,
+            self.commands
+        )
+    }
+
+    pub fn parse(line: &str) -> Option<ServerStatus> {
+        let mut uptime = None;
+        let mut keys = None;
+        let mut expiring = None;
+        let mut mem_bytes = None;
+        let mut version = None;
+        let mut connections = None;
+        let mut saved_bytes = 0;
+        let mut bytes_read = 0;
+        let mut bytes_written = 0;
+        let mut commands = 0;
+        for field in line.split_whitespace() {
+            let (name, value) = field.split_once('=')?;
+            match name {
+                "uptime" => {
+                    uptime = value
+                        .strip_suffix('s')?
+                        .parse()
+                        .ok()
+                        .map(Duration::from_secs)
+                }
+                "keys" => keys = value.parse().ok(),
+                "expiring" => expiring = value.parse().ok(),
+                "mem_bytes~" => mem_bytes = value.parse().ok(),
+                "version" => version = Some(value.to_string()),
+                "connections" => connections = value.parse().ok(),
+                "saved_bytes~" => saved_bytes = value.parse().ok()?,
+                "bytes_read" => bytes_read = value.parse().ok()?,
+                "bytes_written" => bytes_written = value.parse().ok()?,
+                "commands" => commands = value.parse().ok()?,
+                _ => {}
+            }
+        }
+        Some(ServerStatus {
+            uptime: uptime?,
+            keys: keys?,
+            expiring: expiring?,
+            mem_bytes: mem_bytes?,
+            version: version?,
+            connections: connections?,
+            saved_bytes,
+            bytes_read,
+            bytes_written,
+            commands,
+        })
+    }
+}
+
+/// One key's counters from STATS KEY and STATS TOP:
+/// `key=motd hits=12 misses=0 sets=1 last_access=1760605923481`, last_access in unix
+/// milliseconds. `misses` counts GETs of the key while it did not exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyCounters {
+    pub key: String,
+    pub hits: u64,
+    pub misses: u64,
+    pub sets: u64,
+    pub last_access: u64,
+}
+
+impl KeyCounters {
+    /// Hits, misses and sets together; STATS TOP orders by this.
+    pub fn accesses(&self) -> u64 {
+        self.hits + self.misses + self.sets
+    }
+
+    pub fn to_line(&self) -> String {
+        format!(
+            "key={} hits={} misses={} sets={} last_access={}",
+            self.key, self.hits, self.misses, self.sets, self.last_access
+        )
+    }
+
+    pub fn parse(line: &str) -> Option<KeyCounters> {
+        let mut counters = KeyCounters {
+            key: String::new(),
+            hits: 0,
+            misses: 0,
+            sets: 0,
+            last_access: 0,
+        };
+        for field in line.split_whitespace() {
+            let (name, value) = field.split_once('=')?;
+            match name {
+                "key" => counters.key = value.to_string(),
+                "hits" => counters.hits = value.parse().ok()?,
+                "misses" => counters.misses = value.parse().ok()?,
+                "sets" => counters.sets = value.parse().ok()?,
+                "last_access" => counters.last_access = value.parse().ok()?,
+                _ => {}
+            }
+        }
+        (!counters.key.is_empty()).then_some(counters)
+    }
+}
+
+/// Splits a `BATCH cmd1;cmd2;...` line into its sub-commands, skipping empty ones.
+/// Returns None if the line is not a batch.
+pub fn split_batch(line: &str) -> Option<Vec<&str>> {
+    let body = line.strip_prefix("BATCH ")?;
+    Some(
+        body.split(';')
+            .map(str::trim)
+            .filter(|cmd| !cmd.is_empty())
+            .collect(),
+    )
+}
+
+pub fn batch_line(commands: &[&str]) -> String {
+    format!("BATCH {}", commands.join(";"))
+}
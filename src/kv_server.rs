@@ -0,0 +1,2678 @@
+// shrmpl-kv server core: socket setup, the accept loop and command execution. The
+// shrmpl-kv-srv binary is a thin wrapper that loads config and builds the Logger;
+// tests run the same code in-process against an ephemeral port.
+
+use crate::accept_backoff::{self, AcceptBackoff};
+use crate::config;
+use crate::kv_aof::{self, AofSettings, AofSync};
+use crate::kv_key_stats::KeyStats;
+use crate::kv_protocol::{
+    self, decode_bytes, encode_bytes, Command, ExpiryChange, HelloReply, KeyCounters, Limits,
+    ListEntry, LoadMode, ParseError, Response, ServerStatus, KEY_NOT_FOUND, MAX_BYTES_LEN,
+    MAX_KEY_LEN, MAX_VALUE_LEN,
+};
+use crate::kv_statsd::{self, Sample, Statsd, StatsdSettings};
+use crate::kv_store::{self, Store, StoredValue, Value};
+use crate::proxy_protocol;
+use crate::sd_notify::{self, Notifier};
+use crate::shrmpl_kv_client::KvClient;
+use crate::shrmpl_log_client::{LogLevel, Logger};
+use socket2::{Socket, TcpKeepalive};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, Notify, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, interval_at, Duration as TokioDuration, Interval};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+type KvStore = Arc<RwLock<Store>>;
+
+// LIST entries formatted per read-lock acquisition while streaming
+const LIST_CHUNK_KEYS: usize = 1000;
+
+// Replies longer than this are written in pieces of this size, yielding in between
+const WRITE_CHUNK_BYTES: usize = 64 * 1024;
+
+// Replies to pipelined commands are held back up to this many bytes and written together
+const WRITE_BUFFER_BYTES: usize = 64 * 1024;
+
+// Rejected lines are cut to this many bytes in the BADCMDS samples
+const BAD_COMMAND_SAMPLE_BYTES: usize = 200;
+
+// Server-wide counters updated by every connection
+#[derive(Debug)]
+struct Stats {
+    started: Instant,
+    connections: AtomicU64,
+    // Traffic totals over every connection, for STATUS
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    commands: AtomicU64,
+    // Reads that found or missed their key, for StatsD
+    hits: AtomicU64,
+    misses: AtomicU64,
+    encoding_errors: AtomicU64,
+    // The most recent rejected command lines for BADCMDS, oldest first
+    bad_commands: std::sync::Mutex<VecDeque<String>>,
+    bad_command_samples: usize,
+    // Per-key counters for STATS KEY/TOP, when KEY_STATS is on
+    key_stats: Option<std::sync::Mutex<KeyStats>>,
+}
+
+impl Stats {
+    fn new(settings: &Settings) -> Stats {
+        Stats {
+            started: Instant::now(),
+            connections: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            commands: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            encoding_errors: AtomicU64::new(0),
+            bad_commands: std::sync::Mutex::new(VecDeque::new()),
+            bad_command_samples: settings.bad_command_samples,
+            key_stats: settings
+                .key_stats
+                .map(|miss_keys| std::sync::Mutex::new(KeyStats::new(miss_keys))),
+        }
+    }
+
+    fn record_get(&self, key: &str, found: bool) {
+        let total = if found { &self.hits } else { &self.misses };
+        total.fetch_add(1, Ordering::Relaxed);
+        if let Some(key_stats) = &self.key_stats {
+            let mut key_stats = key_stats.lock().unwrap();
+            if found {
+                key_stats.hit(key, SystemTime::now());
+            } else {
+                key_stats.miss(key, SystemTime::now());
+            }
+        }
+    }
+
+    fn record_set(&self, key: &str) {
+        if let Some(key_stats) = &self.key_stats {
+            key_stats.lock().unwrap().set(key, SystemTime::now());
+        }
+    }
+
+    fn forget_key(&self, key: &str) {
+        if let Some(key_stats) = &self.key_stats {
+            key_stats.lock().unwrap().remove(key);
+        }
+    }
+
+    // Keeps `TIMESTAMP PEER [error] LINE`, dropping the oldest sample when full
+    fn record_bad_command(&self, peer: &str, error: &str, line: &str) {
+        if self.bad_command_samples == 0 {
+            return;
+        }
+        let mut end = line.len().min(BAD_COMMAND_SAMPLE_BYTES);
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        let sample = format!(
+            "{} {} [{}] {}",
+            chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            peer,
+            error,
+            &line[..end]
+        );
+        let mut samples = self.bad_commands.lock().unwrap();
+        if samples.len() == self.bad_command_samples {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    fn take_bad_commands(&self) -> Vec<String> {
+        self.bad_commands.lock().unwrap().drain(..).collect()
+    }
+}
+
+// Keeps the open-connection count right however handle_connection returns
+struct ConnectionGuard(Arc<Stats>);
+
+impl ConnectionGuard {
+    fn new(stats: Arc<Stats>) -> ConnectionGuard {
+        stats.connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(stats)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Counts the bytes that actually reach the socket, for the connection and the server
+// totals, so framing, heartbeats and TERM are included
+struct CountingWriter<W> {
+    inner: W,
+    written: u64,
+    stats: Arc<Stats>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CountingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.written += n as u64;
+            self.stats
+                .bytes_written
+                .fetch_add(n as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// Startup settings, shared read-only by every connection
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub bind_addr: String,
+    pub admin_commands: bool,
+    /// Longest key, text value and decoded SETB value accepted, as LIMITS reports them.
+    pub limits: Limits,
+    /// Keys loaded into the store at startup, before the listener is bound.
+    pub seed_file: Option<String>,
+    /// UPONG interval for connections that don't opt out with HELLO; None disables it.
+    pub heartbeat: Option<Duration>,
+    /// String values longer than this are stored compressed; None disables compression.
+    pub compress_threshold: Option<usize>,
+    /// Live keys the store may hold before writes of new keys are refused; None for no limit.
+    pub max_keys: Option<usize>,
+    /// Most names one KEYS reply returns, the first in sorted order.
+    pub keys_reply_limit: usize,
+    /// File the store is loaded from at startup and written to on shutdown, on each
+    /// `snapshot_now` and every `snapshot_interval`; None disables snapshots.
+    pub snapshot_path: Option<String>,
+    pub snapshot_interval: Option<Duration>,
+    /// Notified to write a snapshot straight away; the binary does so on SIGUSR1.
+    pub snapshot_now: Arc<Notify>,
+    /// File every change is journaled to and replayed from at startup; None when
+    /// AOF_PATH is unset.
+    pub aof: Option<AofSettings>,
+    /// Rejected command lines kept for BADCMDS; 0 keeps none.
+    pub bad_command_samples: usize,
+    /// Per-key counters for STATS KEY/TOP, with the number of missing key names
+    /// tracked; None when KEY_STATS is off.
+    pub key_stats: Option<usize>,
+    /// One KVACCESS record per command, for auditing.
+    pub access_log: bool,
+    /// DEBUG SLEEP for testing clients; never meant for production.
+    pub debug_commands: bool,
+    /// FLUSHALL, which empties the store; off unless ALLOW_FLUSH=true.
+    pub allow_flush: bool,
+    /// Upper-case verbs rejected with "ERROR command disabled".
+    pub disabled_commands: Vec<String>,
+    pub accept_backoff: AcceptBackoff,
+    /// Whether connections must open with a PROXY protocol header naming the client.
+    pub proxy_protocol: bool,
+    /// Where and how often metrics are pushed; None when STATSD_ADDR is unset.
+    pub statsd: Option<StatsdSettings>,
+    /// systemd notifications; from_config leaves them off and the binary reads them from the environment.
+    pub notify: Notifier,
+}
+
+impl Settings {
+    pub fn from_config(config: &HashMap<String, String>) -> Settings {
+        Settings {
+            bind_addr: config.get("BIND_ADDR").cloned().unwrap_or_default(),
+            admin_commands: config
+                .get("ADMIN_COMMANDS")
+                .map(|s| s == "true")
+                .unwrap_or(false),
+            limits: Limits {
+                max_key_len: config
+                    .get("MAX_KEY_LEN")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(MAX_KEY_LEN),
+                max_value_len: config
+                    .get("MAX_VALUE_LEN")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(MAX_VALUE_LEN),
+                max_bytes_len: config
+                    .get("MAX_BYTES_LEN")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(MAX_BYTES_LEN),
+            },
+            seed_file: config
+                .get("SEED_FILE")
+                .filter(|path| !path.is_empty())
+                .cloned(),
+            heartbeat: config
+                .get("HEARTBEAT_SECS")
+                .and_then(|s| s.parse::<u64>().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            compress_threshold: config
+                .get("COMPRESS_THRESHOLD_BYTES")
+                .and_then(|s| s.parse::<usize>().ok())
+                .filter(|bytes| *bytes > 0),
+            max_keys: config
+                .get("MAX_KEYS")
+                .and_then(|s| s.parse::<usize>().ok())
+                .filter(|keys| *keys > 0),
+            keys_reply_limit: config
+                .get("KEYS_REPLY_LIMIT")
+                .and_then(|s| s.parse::<usize>().ok())
+                .filter(|names| *names > 0)
+                .unwrap_or(10_000),
+            snapshot_path: config
+                .get("SNAPSHOT_PATH")
+                .filter(|path| !path.is_empty())
+                .cloned(),
+            snapshot_interval: config
+                .get("SNAPSHOT_INTERVAL_SECS")
+                .and_then(|s| s.parse::<u64>().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            snapshot_now: Arc::new(Notify::new()),
+            aof: AofSettings::from_config(config),
+            bad_command_samples: config
+                .get("BAD_COMMAND_SAMPLES")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(32),
+            key_stats: config.get("KEY_STATS").filter(|s| *s == "true").map(|_| {
+                config
+                    .get("KEY_STATS_MISS_KEYS")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(1000)
+            }),
+            access_log: config
+                .get("ACCESS_LOG")
+                .map(|s| s == "true")
+                .unwrap_or(false),
+            debug_commands: config
+                .get("DEBUG_COMMANDS_ENABLED")
+                .map(|s| s == "true")
+                .unwrap_or(false),
+            allow_flush: config
+                .get("ALLOW_FLUSH")
+                .map(|s| s == "true")
+                .unwrap_or(false),
+            disabled_commands: config
+                .get("DISABLED_COMMANDS")
+                .map(|list| {
+                    list.split(',')
+                        .map(|verb| verb.trim().to_uppercase())
+                        .filter(|verb| !verb.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            accept_backoff: AcceptBackoff::from_config(config),
+            proxy_protocol: proxy_protocol::enabled(config),
+            statsd: StatsdSettings::from_config(config),
+            notify: Notifier::disabled(),
+        }
+    }
+}
+
+pub const CONFIG_SCHEMA: &[config::ConfigKey] = &[
+    config::ConfigKey {
+        name: "BIND_ADDR",
+        default: None,
+        description: "Address and port to listen on (e.g. 0.0.0.0:7171)",
+    },
+    config::ConfigKey {
+        name: "SLOG_DEST",
+        default: Some(""),
+        description: "host:port of the shrmpl-log server; empty disables remote logging",
+    },
+    config::ConfigKey {
+        name: "SERVER_NAME",
+        default: Some("auto"),
+        description: "Host name reported in log records; auto uses the OS hostname",
+    },
+    config::ConfigKey {
+        name: "SEND_LOG",
+        default: Some("true"),
+        description: "Send log records to SLOG_DEST (true/false)",
+    },
+    config::ConfigKey {
+        name: "LOG_LEVEL",
+        default: Some("INFO"),
+        description: "Minimum level to log: DEBUG, INFO, WARN or ERROR",
+    },
+    config::ConfigKey {
+        name: "LOG_CONSOLE",
+        default: Some("true"),
+        description: "Print log messages to stdout (true/false)",
+    },
+    config::ConfigKey {
+        name: "SEND_ACTV",
+        default: Some("false"),
+        description: "Also send ACTV (activity) records to SLOG_DEST (true/false)",
+    },
+    config::ConfigKey {
+        name: "LOG_FILE",
+        default: Some(""),
+        description: "Also append records to PREFIX-YYYYMMDD.log locally; empty disables it",
+    },
+    config::ConfigKey {
+        name: "SLOG_BATCH_MAX",
+        default: Some("1"),
+        description: "Records sent to SLOG in one write over a kept-open connection; 1 sends each on its own",
+    },
+    config::ConfigKey {
+        name: "SLOG_BATCH_DELAY_MS",
+        default: Some("5"),
+        description: "Longest a queued record waits for a batch to fill when SLOG_BATCH_MAX is above 1",
+    },
+    config::ConfigKey {
+        name: "ADMIN_COMMANDS",
+        default: Some("false"),
+        description: "Enable admin-only commands such as EXPORT and IMPORT (true/false)",
+    },
+    config::ConfigKey {
+        name: "BAD_COMMAND_SAMPLES",
+        default: Some("32"),
+        description: "Most recent rejected command lines kept for the BADCMDS admin command; 0 keeps none",
+    },
+    config::ConfigKey {
+        name: "DISABLED_COMMANDS",
+        default: Some(""),
+        description: "Comma-separated command verbs to reject, e.g. LIST,EXPORT",
+    },
+    config::ConfigKey {
+        name: "DEBUG_COMMANDS_ENABLED",
+        default: Some("false"),
+        description: "Accept DEBUG SLEEP, which stalls a connection, for testing clients only (true/false)",
+    },
+    config::ConfigKey {
+        name: "ALLOW_FLUSH",
+        default: Some("false"),
+        description: "Accept FLUSHALL, which empties the whole store, e.g. between integration test runs (true/false)",
+    },
+    config::ConfigKey {
+        name: "ACCESS_LOG",
+        default: Some("false"),
+        description: "Log one KVACCESS record per command at INFO (true/false)",
+    },
+    config::ConfigKey {
+        name: "KEY_STATS",
+        default: Some("false"),
+        description: "Count GET hits/misses and sets per key for STATS KEY and STATS TOP (true/false)",
+    },
+    config::ConfigKey {
+        name: "KEY_STATS_MISS_KEYS",
+        default: Some("1000"),
+        description: "Names of missing keys whose GET misses are counted; the least missed make way",
+    },
+    config::ConfigKey {
+        name: "MAX_KEY_LEN",
+        default: Some("100"),
+        description: "Maximum key length in bytes, including a SELECT namespace prefix",
+    },
+    config::ConfigKey {
+        name: "MAX_VALUE_LEN",
+        default: Some("100"),
+        description: "Maximum length in bytes of a text value stored with SET, MSET and the like",
+    },
+    config::ConfigKey {
+        name: "MAX_BYTES_LEN",
+        default: Some("100"),
+        description: "Maximum decoded size in bytes of a binary value stored with SETB",
+    },
+    config::ConfigKey {
+        name: "SEED_FILE",
+        default: Some(""),
+        description: "File of SET lines or an EXPORT document loaded before accepting clients; empty disables",
+    },
+    config::ConfigKey {
+        name: "HEARTBEAT_SECS",
+        default: Some("0"),
+        description: "Seconds between unsolicited UPONG lines on each connection; 0 disables",
+    },
+    config::ConfigKey {
+        name: "COMPRESS_THRESHOLD_BYTES",
+        default: Some("0"),
+        description: "Store string values longer than this many bytes compressed; 0 disables",
+    },
+    config::ConfigKey {
+        name: "MAX_KEYS",
+        default: Some("0"),
+        description: "Live keys the store may hold; writes of new keys beyond it get ERROR store full. 0 is unlimited",
+    },
+    config::ConfigKey {
+        name: "KEYS_REPLY_LIMIT",
+        default: Some("10000"),
+        description: "Most names returned by one KEYS, the first in sorted order; SCAN pages through the rest",
+    },
+    config::ConfigKey {
+        name: "SNAPSHOT_PATH",
+        default: Some(""),
+        description: "File the store is loaded from at startup and saved to on shutdown and SIGUSR1; empty disables snapshots",
+    },
+    config::ConfigKey {
+        name: "SNAPSHOT_INTERVAL_SECS",
+        default: Some("0"),
+        description: "Seconds between periodic snapshots to SNAPSHOT_PATH; 0 saves only on shutdown and SIGUSR1",
+    },
+    accept_backoff::INITIAL_KEY,
+    accept_backoff::MAX_KEY,
+    proxy_protocol::KEY,
+    kv_statsd::ADDR_KEY,
+    kv_statsd::INTERVAL_KEY,
+    kv_aof::PATH_KEY,
+    kv_aof::SYNC_KEY,
+];
+
+// Binds the listener with keepalive enabled. Port 0 picks an ephemeral port.
+fn bind(addr: &str) -> std::io::Result<TcpListener> {
+    let addr_parsed: SocketAddr = addr
+        .parse()
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid address"))?;
+    let socket = Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None)?;
+    socket.set_keepalive(true)?;
+    socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(Duration::from_secs(60)))?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr_parsed.into())?;
+    socket.listen(128)?;
+    let std_listener: StdTcpListener = socket.into();
+    TcpListener::from_std(std_listener)
+}
+
+/// Binds `settings.bind_addr` and serves connections until a value arrives on `shutdown_rx`.
+/// Returns the bound address and the handle of the accept loop, which finishes after shutdown.
+pub async fn run(
+    settings: Settings,
+    logger: Logger,
+    shutdown_rx: broadcast::Receiver<()>,
+) -> std::io::Result<(SocketAddr, JoinHandle<()>)> {
+    let mut seeded = Store::new();
+    if let Some(path) = &settings.seed_file {
+        load_seed(path, &mut seeded, &settings, &logger).await;
+    }
+    // Loaded over the seed keys, so what was saved last wins
+    if let Some(path) = &settings.snapshot_path {
+        load_snapshot(path, &mut seeded, &settings, &logger).await?;
+    }
+    let store: KvStore = Arc::new(RwLock::new(seeded));
+    let stats = Arc::new(Stats::new(&settings));
+    // Replayed over the snapshot; changes from then on are journaled
+    let aof_writer = match &settings.aof {
+        Some(aof) => Some(
+            open_aof(
+                aof,
+                &store,
+                &settings,
+                &stats,
+                &logger,
+                shutdown_rx.resubscribe(),
+            )
+            .await?,
+        ),
+        None => None,
+    };
+
+    let listener = bind(&settings.bind_addr)?;
+    let local_addr = listener.local_addr()?;
+    logger.log_detached(
+        "INFO",
+        "KVSERVERLIST",
+        &format!(
+            "shrmpl-kv-srv version {} listening on {}",
+            VERSION, local_addr
+        ),
+    );
+
+    if settings.debug_commands {
+        logger.log_detached(
+            "WARN",
+            "KVDEBUGCMDS",
+            "DEBUG_COMMANDS_ENABLED is on: clients can stall connections with DEBUG SLEEP",
+        );
+    }
+
+    // Spawn cleanup task for expired keys
+    let store_for_cleanup = store.clone();
+    let stats_for_cleanup = stats.clone();
+    let mut cleanup_shutdown_rx = shutdown_rx.resubscribe();
+    let cleanup_notify = settings.notify.clone();
+    let cleanup_logger = logger.clone();
+    let max_keys = settings.max_keys;
+    tokio::spawn(async move {
+        let mut cleanup_interval = interval(TokioDuration::from_secs(60));
+        let mut watchdog = cleanup_notify.watchdog_timer();
+        // Warns once each time the store climbs past 90% of MAX_KEYS
+        let mut near_full = false;
+        loop {
+            tokio::select! {
+                _ = cleanup_interval.tick() => {
+                    let mut store_write = store_for_cleanup.write().await;
+                    store_write.remove_expired(SystemTime::now());
+                    // Expired keys keep only their miss counts, as after a DEL
+                    if let Some(key_stats) = &stats_for_cleanup.key_stats {
+                        key_stats.lock().unwrap().retain_present(|key| store_write.get(key).is_some());
+                    }
+                    if let Some(max_keys) = max_keys {
+                        let keys = store_write.len();
+                        let over = keys.saturating_mul(10) > max_keys.saturating_mul(9);
+                        let was_near_full = std::mem::replace(&mut near_full, over);
+                        if near_full && !was_near_full {
+                            cleanup_logger.log_detached(
+                                "WARN",
+                                "KVKEYSHIGH",
+                                &format!("Store holds {} of MAX_KEYS {} keys", keys, max_keys),
+                            );
+                        }
+                    }
+                }
+                // Only pings once the store lock is free, so a deadlocked store gets restarted
+                _ = sd_notify::next_watchdog(&mut watchdog) => {
+                    let _store_read = store_for_cleanup.read().await;
+                    cleanup_notify.watchdog();
+                }
+                _ = cleanup_shutdown_rx.recv() => {
+                    break;
+                }
+            }
+        }
+    });
+
+    if let Some(statsd) = settings.statsd.clone() {
+        tokio::spawn(push_statsd(
+            statsd,
+            store.clone(),
+            stats.clone(),
+            shutdown_rx.resubscribe(),
+            logger.clone(),
+        ));
+    }
+
+    let snapshots = settings.snapshot_path.clone().map(|path| {
+        tokio::spawn(take_snapshots(
+            path,
+            settings.snapshot_interval,
+            settings.snapshot_now.clone(),
+            store.clone(),
+            shutdown_rx.resubscribe(),
+            logger.clone(),
+        ))
+    });
+
+    settings.notify.ready();
+
+    let handle = tokio::spawn(async move {
+        let mut shutdown_rx = shutdown_rx;
+        let mut backoff = settings.accept_backoff.clone();
+        loop {
+            tokio::select! {
+                accept_result = listener.accept() => {
+                    let socket = match accept_result {
+                        Ok((socket, _)) => {
+                            backoff.reset();
+                            socket
+                        }
+                        Err(e) if accept_backoff::is_fatal(&e) => {
+                            logger
+                                .error("KVACCEPTFAIL", &format!("Listener failed, stopping server: {}", e))
+                                .await;
+                            break;
+                        }
+                        Err(e) => {
+                            // A pending shutdown stays queued on shutdown_rx through the sleep
+                            let delay = backoff.next_delay();
+                            logger
+                                .warn(
+                                    "KVACCEPTERR",
+                                    &format!("Accept failed: {}; retrying in {}ms", e, delay.as_millis()),
+                                )
+                                .await;
+                            tokio::time::sleep(delay).await;
+                            continue;
+                        }
+                    };
+                    let store = store.clone();
+                    let settings = settings.clone();
+                    let stats = stats.clone();
+                    let conn_shutdown_rx = shutdown_rx.resubscribe();
+                    let logger_clone = logger.clone();
+                    tokio::spawn(async move {
+                        handle_connection(socket, store, settings, stats, conn_shutdown_rx, logger_clone).await;
+                    });
+                }
+                _ = shutdown_rx.recv() => {
+                    logger.info("KVSERVERDOWN", "Shutting down server...").await;
+                    break;
+                }
+            }
+        }
+        // The snapshot task writes its last snapshot on shutdown; wait for it to land
+        if let Some(snapshots) = snapshots {
+            let _ = snapshots.await;
+        }
+        // The AOF writer syncs whatever is queued as it stops
+        if let Some(aof_writer) = aof_writer {
+            let _ = aof_writer.await;
+        }
+        logger.flush().await;
+        settings.notify.stopping();
+    });
+
+    Ok((local_addr, handle))
+}
+
+// Sends a StatsD packet every interval until shutdown. Send failures are logged once
+// per run of failures, since a missing daemon would otherwise warn on every push.
+async fn push_statsd(
+    settings: StatsdSettings,
+    store: KvStore,
+    stats: Arc<Stats>,
+    mut shutdown_rx: broadcast::Receiver<()>,
+    logger: Logger,
+) {
+    let statsd = match Statsd::connect(&settings.addr).await {
+        Ok(statsd) => statsd,
+        Err(e) => {
+            let message = format!(
+                "StatsD push disabled, cannot reach {}: {}",
+                settings.addr, e
+            );
+            logger.warn("KVSTATSDFAIL", &message).await;
+            return;
+        }
+    };
+    let mut push_interval = interval(settings.interval);
+    let mut previous = Sample::default();
+    let mut failing = false;
+    loop {
+        tokio::select! {
+            _ = push_interval.tick() => {}
+            _ = shutdown_rx.recv() => break,
+        }
+        let sample = Sample {
+            commands: stats.commands.load(Ordering::Relaxed),
+            hits: stats.hits.load(Ordering::Relaxed),
+            misses: stats.misses.load(Ordering::Relaxed),
+            keys: store.read().await.len() as u64,
+            connections: stats.connections.load(Ordering::Relaxed),
+        };
+        match statsd.send(&sample.packet(&previous)).await {
+            Ok(()) => failing = false,
+            Err(e) if !failing => {
+                failing = true;
+                let message = format!("StatsD push to {} failed: {}", settings.addr, e);
+                logger.warn("KVSTATSDERR", &message).await;
+            }
+            Err(_) => {}
+        }
+        previous = sample;
+    }
+}
+
+// Writes a snapshot on each request and interval, and a last one on shutdown. One task
+// does all the writing, so two snapshots never share the temporary file.
+async fn take_snapshots(
+    path: String,
+    every: Option<Duration>,
+    now: Arc<Notify>,
+    store: KvStore,
+    mut shutdown_rx: broadcast::Receiver<()>,
+    logger: Logger,
+) {
+    let mut periodic = every.map(|every| interval_at(tokio::time::Instant::now() + every, every));
+    loop {
+        let reason = tokio::select! {
+            _ = now.notified() => "requested",
+            _ = next_tick(&mut periodic) => "periodic",
+            _ = shutdown_rx.recv() => break,
+        };
+        save_snapshot(&path, &store, reason, &logger).await;
+    }
+    save_snapshot(&path, &store, "shutdown", &logger).await;
+}
+
+async fn save_snapshot(path: &str, store: &KvStore, reason: &str, logger: &Logger) {
+    let (json, saved) = export_json(store).await;
+    match write_dump(path, &json).await {
+        Ok(()) => {
+            let message = format!("Saved {} keys to snapshot {} ({})", saved, path, reason);
+            logger.info("KVSNAPSAVE", &message).await;
+        }
+        Err(e) => {
+            let message = format!("Cannot write snapshot {}: {}", path, e);
+            logger.error("KVSNAPFAIL", &message).await;
+        }
+    }
+}
+
+// A missing snapshot is a first start; one that can't be read or parsed stops startup,
+// since running on would overwrite it with a near-empty store at the next snapshot
+async fn load_snapshot(
+    path: &str,
+    store: &mut Store,
+    settings: &Settings,
+    logger: &Logger,
+) -> std::io::Result<()> {
+    if !std::path::Path::new(path).exists() {
+        logger.log_detached(
+            "INFO",
+            "KVSNAPLOAD",
+            &format!("No snapshot at {} yet, starting without it", path),
+        );
+        return Ok(());
+    }
+    // The entries were accepted when saved, under limits that may have been higher
+    let mut load_settings = settings.clone();
+    load_settings.limits = kv_aof::REPLAY_LIMITS;
+    let entries = read_dump(path, &load_settings)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let (loaded, skipped) = insert_live(store, entries);
+    logger.log_detached(
+        "INFO",
+        "KVSNAPLOAD",
+        &format!(
+            "Loaded {} keys from snapshot {} ({} expired skipped)",
+            loaded, path, skipped
+        ),
+    );
+    Ok(())
+}
+
+// Replays the AOF through process_single_command, then has the store journal every
+// later change to it. A line that can't be replayed stops the server, as a corrupt
+// snapshot does; a last line cut short by a crash is dropped.
+async fn open_aof(
+    aof: &AofSettings,
+    store: &KvStore,
+    settings: &Settings,
+    stats: &Stats,
+    logger: &Logger,
+    shutdown_rx: broadcast::Receiver<()>,
+) -> std::io::Result<JoinHandle<()>> {
+    let (lines, len) = kv_aof::read(&aof.path)?;
+    if std::fs::metadata(&aof.path).is_ok_and(|meta| meta.len() > len) {
+        logger.log_detached(
+            "WARN",
+            "KVAOFTORN",
+            &format!("Dropping an incomplete last line from {}", aof.path),
+        );
+    }
+    // The lines were accepted when written, so limits and DISABLED_COMMANDS don't apply
+    let mut replay_settings = settings.clone();
+    replay_settings.disabled_commands.clear();
+    replay_settings.max_keys = None;
+    replay_settings.limits = kv_aof::REPLAY_LIMITS;
+    replay_settings.admin_commands = true;
+    replay_settings.allow_flush = true;
+    let now = SystemTime::now();
+    for (n, line) in lines.iter().enumerate() {
+        let command = kv_aof::replay_command(line, now).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} line {}: {}", aof.path, n + 1, e),
+            )
+        })?;
+        process_single_command(
+            command,
+            "aof",
+            &mut None,
+            store,
+            &replay_settings,
+            stats,
+            logger,
+        )
+        .await;
+    }
+    logger.log_detached(
+        "INFO",
+        "KVAOFLOAD",
+        &format!("Replayed {} lines from {}", lines.len(), aof.path),
+    );
+    let (journal, writer) = kv_aof::start(aof, len, logger.clone(), shutdown_rx).await?;
+    store.write().await.set_journal(journal);
+    Ok(writer)
+}
+
+/// Starts the server as configured on an ephemeral loopback port and runs a SET, GET
+/// and DEL round trip against it, for `--selftest`. DISABLED_COMMANDS is ignored,
+/// nothing is sent to SLOG and the snapshot and AOF files are left alone.
+pub async fn selftest(settings: &Settings) -> Result<String, String> {
+    let mut settings = settings.clone();
+    settings.bind_addr = "127.0.0.1:0".to_string();
+    settings.disabled_commands.clear();
+    settings.snapshot_path = None;
+    settings.aof = None;
+    settings.notify = Notifier::disabled();
+    let logger = Logger::new(
+        String::new(),
+        "selftest".to_string(),
+        LogLevel::Error,
+        false,
+        false,
+        false,
+    );
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    let (addr, handle) = run(settings, logger, shutdown_rx)
+        .await
+        .map_err(|e| format!("cannot bind: {}", e))?;
+    let result = round_trip(&addr.to_string()).await;
+    let _ = shutdown_tx.send(());
+    let _ = handle.await;
+    result.map(|()| format!("SET/GET/DEL round trip on {}", addr))
+}
+
+async fn round_trip(addr: &str) -> Result<(), String> {
+    let key = format!("selftest-{}", std::process::id());
+    let mut client = KvClient::connect_without_heartbeat(addr)
+        .await
+        .map_err(|e| format!("cannot connect: {}", e))?;
+    client
+        .set(&key, "ok")
+        .await
+        .map_err(|e| format!("SET failed: {}", e))?;
+    match client.get(&key).await {
+        Ok(Some(value)) if value == "ok" => {}
+        Ok(other) => return Err(format!("GET returned {:?}", other)),
+        Err(e) => return Err(format!("GET failed: {}", e)),
+    }
+    match client.delete(&key).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("DEL found nothing to delete".to_string()),
+        Err(e) => Err(format!("DEL failed: {}", e)),
+    }
+}
+
+async fn handle_connection(
+    mut socket: TcpStream,
+    store: KvStore,
+    settings: Settings,
+    stats: Arc<Stats>,
+    mut shutdown_rx: broadcast::Receiver<()>,
+    logger: Logger,
+) {
+    let _connection = ConnectionGuard::new(stats.clone());
+
+    // Set TCP_NODELAY
+    socket.set_nodelay(true).unwrap_or_default();
+    let mut peer = socket
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    // Behind a load balancer the peer is the balancer; the header names the client
+    if settings.proxy_protocol {
+        match proxy_protocol::read_header(&mut socket).await {
+            Ok(Some(client)) => peer = client.to_string(),
+            Ok(None) => {}
+            Err(e) => {
+                logger
+                    .warn(
+                        "KVPROXYERR",
+                        &format!("Dropped connection from {}: {}", peer, e),
+                    )
+                    .await;
+                return;
+            }
+        }
+    }
+
+    let (reader, writer) = socket.split();
+    let mut reader = BufReader::new(reader);
+    let writer = CountingWriter {
+        inner: writer,
+        written: 0,
+        stats: stats.clone(),
+    };
+    let mut writer = BufWriter::with_capacity(WRITE_BUFFER_BYTES, writer);
+    // This connection's share of the STATUS totals, logged when it closes
+    let mut bytes_read = 0;
+    let mut commands = 0;
+    // Read raw bytes so a line with invalid UTF-8 can be rejected without dropping the connection
+    let mut line = Vec::new();
+
+    // The first tick is one period out; a HELLO noheartbeat as the first line drops it
+    let mut heartbeat = settings
+        .heartbeat
+        .map(|period| interval_at(tokio::time::Instant::now() + period, period));
+    let mut first_line = true;
+    // Set by SELECT; keys on this connection are stored as `namespace:key`
+    let mut namespace: Option<String> = None;
+    // Set by COMPRESS ON; every line written is then framed by kv_protocol::frame_reply
+    let mut compress = false;
+    // With AOF_SYNC=always each reply waits until the changes before it are on disk
+    let journal = store
+        .read()
+        .await
+        .journal()
+        .filter(|journal| journal.sync() == AofSync::Always)
+        .cloned();
+
+    loop {
+        line.clear();
+        tokio::select! {
+            _ = next_tick(&mut heartbeat) => {
+                let beat = format!("{}\n", kv_protocol::HEARTBEAT);
+                if write_wire(&mut writer, &beat, compress).await.is_err() || writer.flush().await.is_err() {
+                    break; // Connection closed
+                }
+            }
+            result = reader.read_until(b'\n', &mut line) => {
+                match result {
+                    Ok(0) => break, // EOF
+                    Ok(n) => {
+                        bytes_read += n as u64;
+                        commands += 1;
+                        stats.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+                        stats.commands.fetch_add(1, Ordering::Relaxed);
+                        let text = match std::str::from_utf8(&line) {
+                            Ok(text) => text,
+                            Err(_) => {
+                                let count = stats.encoding_errors.fetch_add(1, Ordering::Relaxed) + 1;
+                                let lossy = String::from_utf8_lossy(&line);
+                                stats.record_bad_command(&peer, "invalid encoding", lossy.trim_end());
+                                logger
+                                    .warn(
+                                        "KVBADENCODE",
+                                        &format!("Rejected command with invalid UTF-8 (total {})", count),
+                                    )
+                                    .await;
+                                if write_wire(&mut writer, "ERROR invalid encoding\n", compress).await.is_err()
+                                    || flush_unless_pipelined(&mut writer, &reader).await.is_err()
+                                {
+                                    break;
+                                }
+                                continue;
+                            }
+                        };
+                        // Every line gets exactly one reply, a blank one included, so a
+                        // client counting replies never waits on a line the server skipped
+                        let trimmed = text.trim_end();
+                        logger.debug("KVCMDRECV", &format!("Received command: {}", trimmed)).await;
+                        // Connection-level commands are parsed here; the rest go to process_command
+                        let direct = first_line || is_compress(trimmed, &settings);
+                        let mut switch_compress = None;
+                        let reply = match direct.then(|| Command::parse(trimmed)) {
+                            Some(Ok(Command::Compress { on })) => {
+                                switch_compress = Some(on);
+                                Reply::from(Response::Ok)
+                            }
+                            Some(Ok(Command::Hello { heartbeat: wanted, proto })) if first_line => {
+                                if !wanted {
+                                    heartbeat = None;
+                                }
+                                let reply = HelloReply {
+                                    version: VERSION.to_string(),
+                                    proto: proto.min(kv_protocol::PROTOCOL_VERSION),
+                                    heartbeat: heartbeat.as_ref().map(|interval| interval.period()),
+                                    features: features(&settings),
+                                };
+                                Reply::Immediate(Response::Status(reply.to_line()).to_wire())
+                            }
+                            _ => {
+                                process_command(trimmed, &peer, &mut namespace, &store, &settings, &stats, &logger).await
+                            }
+                        };
+                        first_line = false;
+                        if let Some(journal) = &journal {
+                            journal.wait_synced().await;
+                        }
+                        if write_reply(&mut writer, reply, compress).await.is_err() {
+                            break;
+                        }
+                        // The OK itself goes out in the mode it switches from
+                        compress = switch_compress.unwrap_or(compress);
+                        if flush_unless_pipelined(&mut writer, &reader).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+            _ = shutdown_rx.recv() => {
+                let _ = write_wire(&mut writer, "TERM\n", compress).await;
+                let _ = writer.flush().await;
+                break;
+            }
+        }
+    }
+    logger
+        .info(
+            "KVCONNCLOSE",
+            &format!(
+                "{} bytes_read={} bytes_written={} commands={}",
+                peer,
+                bytes_read,
+                writer.get_ref().written,
+                commands
+            ),
+        )
+        .await;
+}
+
+// Replies stay buffered while another complete command is already waiting in `reader`,
+// so a pipelined burst is answered with one write; an interactive client, whose next
+// line hasn't arrived, gets each reply straight away.
+async fn flush_unless_pipelined<W: AsyncWriteExt + Unpin, R: AsyncRead>(
+    writer: &mut W,
+    reader: &BufReader<R>,
+) -> std::io::Result<()> {
+    if reader.buffer().contains(&b'\n') {
+        Ok(())
+    } else {
+        writer.flush().await
+    }
+}
+
+// COMPRESS changes how the connection writes, so handle_connection answers it itself.
+// When disabled it goes on to process_command to be rejected like any other verb.
+fn is_compress(line: &str, settings: &Settings) -> bool {
+    line.split_whitespace()
+        .next()
+        .is_some_and(|verb| verb.eq_ignore_ascii_case("COMPRESS"))
+        && !settings
+            .disabled_commands
+            .iter()
+            .any(|verb| verb == "COMPRESS")
+}
+
+// Features listed in HELLO replies
+fn features(settings: &Settings) -> Vec<String> {
+    let mut features: Vec<String> = kv_protocol::FEATURES
+        .iter()
+        .map(|f| f.to_string())
+        .collect();
+    if settings.admin_commands {
+        features.push(kv_protocol::FEATURE_ADMIN.to_string());
+    }
+    features
+}
+
+// Streamed replies go out chunk by chunk, with the store unlocked while each is written
+async fn write_reply<W: AsyncWriteExt + Unpin>(
+    writer: &mut W,
+    reply: Reply,
+    compress: bool,
+) -> std::io::Result<()> {
+    match reply {
+        // A LIST is deflated as a whole, so the snapshot is formatted up front
+        Reply::Streamed(list) if compress => write_wire(writer, &list.collect().await, true).await,
+        Reply::Immediate(response) if compress => write_wire(writer, &response, true).await,
+        Reply::Immediate(response) => write_chunked(writer, response.as_bytes()).await,
+        Reply::Streamed(mut list) => {
+            while let Some(chunk) = list.next_chunk().await {
+                writer.write_all(chunk.as_bytes()).await?;
+                // A fast reader never makes the write wait, so let other connections run
+                tokio::task::yield_now().await;
+            }
+            writer.write_all(b"\n").await // Empty line indicates end
+        }
+    }
+}
+
+// Lines for the client, framed when the connection has COMPRESS ON
+async fn write_wire<W: AsyncWriteExt + Unpin>(
+    writer: &mut W,
+    wire: &str,
+    compress: bool,
+) -> std::io::Result<()> {
+    if compress {
+        write_chunked(writer, &kv_protocol::frame_reply(wire)).await
+    } else {
+        write_chunked(writer, wire.as_bytes()).await
+    }
+}
+
+// A client that reads as fast as we write never makes write_all wait, so a
+// multi-megabyte GET would otherwise hold the worker thread for the whole transfer
+async fn write_chunked<W: AsyncWriteExt + Unpin>(
+    writer: &mut W,
+    bytes: &[u8],
+) -> std::io::Result<()> {
+    if bytes.len() <= WRITE_CHUNK_BYTES {
+        return writer.write_all(bytes).await;
+    }
+    for chunk in bytes.chunks(WRITE_CHUNK_BYTES) {
+        writer.write_all(chunk).await?;
+        tokio::task::yield_now().await;
+    }
+    Ok(())
+}
+
+// Waits for the next tick, or forever without an interval, e.g. on connections
+// without a heartbeat
+async fn next_tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::Int(i) => i.to_string(),
+        Value::Float(f) => kv_store::format_float(*f),
+        Value::Str(s) => s.clone(),
+        // Binary values read back through GET/LIST in their base64 form
+        Value::Bytes(b) => encode_bytes(b),
+        Value::Compressed(payload) => kv_store::decompress(payload),
+    }
+}
+
+// Compresses a string above the configured threshold, keeping the text when
+// deflate doesn't actually make it smaller
+fn compact(value: Value, settings: &Settings) -> Value {
+    match (value, settings.compress_threshold) {
+        (Value::Str(text), Some(threshold)) if text.len() > threshold => {
+            let payload = kv_store::compress(&text);
+            if payload.len() < text.len() {
+                Value::Compressed(payload)
+            } else {
+                Value::Str(text)
+            }
+        }
+        (value, _) => value,
+    }
+}
+
+// SET stores numbers as integers or floats (see Value::number) so INCR and
+// INCRBYFLOAT can work on them, unless `raw` asks for the exact string
+fn stored_from_set(
+    value: String,
+    ttl: Option<Duration>,
+    raw: bool,
+    settings: &Settings,
+) -> StoredValue {
+    let expires_at = ttl.map(|duration| SystemTime::now() + duration);
+    let value = match Value::number(&value) {
+        Some(number) if !raw => number,
+        _ => compact(Value::Str(value), settings),
+    };
+    StoredValue { value, expires_at }
+}
+
+// Applies the operator's seed file: either an EXPORT document or one `SET key value [ttl]`
+// per line, with blank lines and # comments ignored. Problems are logged and skipped
+// rather than stopping the server, since the seed only warms the cache.
+async fn load_seed(path: &str, store: &mut Store, settings: &Settings, logger: &Logger) {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            logger.log_detached(
+                "ERRO",
+                "KVSEEDFAIL",
+                &format!("Cannot read seed file {}: {}", path, e),
+            );
+            return;
+        }
+    };
+
+    let mut loaded = 0;
+    let mut skipped = 0;
+    if content.trim_start().starts_with('[') {
+        match parse_import_json(&content, settings) {
+            Ok(entries) => {
+                for (key, stored) in entries {
+                    store.insert(key, stored);
+                    loaded += 1;
+                }
+            }
+            Err(e) => {
+                logger.log_detached(
+                    "WARN",
+                    "KVSEEDSKIP",
+                    &format!("{}: invalid seed document: {}", path, e),
+                );
+            }
+        }
+    } else {
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let reason = match Command::parse_with(line, &settings.limits) {
+                Ok(Command::Set {
+                    key,
+                    value,
+                    ttl,
+                    raw,
+                }) => {
+                    store.insert(key, stored_from_set(value, ttl, raw, settings));
+                    loaded += 1;
+                    continue;
+                }
+                Ok(_) => "only SET lines are allowed".to_string(),
+                Err(e) => e.to_string(),
+            };
+            skipped += 1;
+            logger.log_detached(
+                "WARN",
+                "KVSEEDSKIP",
+                &format!("{} line {}: {}: {}", path, index + 1, reason, line),
+            );
+        }
+    }
+
+    logger.log_detached(
+        "INFO",
+        "KVSEEDLOAD",
+        &format!(
+            "Loaded {} keys from seed file {} ({} lines skipped)",
+            loaded, path, skipped
+        ),
+    );
+}
+
+// Returns the key's value, removing it first if it has expired
+fn get_live(store: &mut Store, key: &str) -> Option<Value> {
+    let stored = store.get(key)?;
+    if stored.is_expired(SystemTime::now()) {
+        store.remove(key);
+        None
+    } else {
+        Some(stored.value.clone())
+    }
+}
+
+// TTL and PTTL: the time left in the caller's unit, -1 without an expiration and -2
+// for a missing key. Takes the write lock's store so an expired key is removed, as GET does.
+fn time_to_live(store: &mut Store, key: &str, in_unit: fn(Duration) -> i64) -> Response {
+    let now = SystemTime::now();
+    match store.get(key) {
+        Some(stored) if !stored.is_expired(now) => match stored.expires_at {
+            Some(exp_time) => {
+                Response::Integer(in_unit(exp_time.duration_since(now).unwrap_or_default()))
+            }
+            None => Response::Integer(-1),
+        },
+        Some(_) => {
+            store.remove(key);
+            Response::Integer(-2)
+        }
+        None => Response::Integer(-2),
+    }
+}
+
+// EXPIRE and PERSIST: only a live key changes; an expired one is removed as GET would
+fn change_expiration(
+    store: &mut Store,
+    key: &str,
+    expires_at: Option<SystemTime>,
+    now: SystemTime,
+) -> Response {
+    match store.get(key) {
+        Some(stored) if !stored.is_expired(now) => {
+            store.set_expiration(key, expires_at);
+            Response::Ok
+        }
+        Some(_) => {
+            store.remove(key);
+            Response::NotFound
+        }
+        None => Response::NotFound,
+    }
+}
+
+/// What a command sends back: a response formatted up front, or a LIST body that
+/// handle_connection writes in chunks so the store isn't locked for the whole of it.
+enum Reply {
+    Immediate(String),
+    Streamed(ListStream),
+}
+
+impl From<Response> for Reply {
+    fn from(response: Response) -> Reply {
+        Reply::Immediate(response.to_wire())
+    }
+}
+
+/// LIST body over a snapshot of the key names. Values are looked up chunk by chunk,
+/// and keys deleted or expired since the snapshot are skipped.
+struct ListStream {
+    store: KvStore,
+    keys: std::vec::IntoIter<String>,
+    // `namespace:` of the connection's SELECT, stripped from the listed keys
+    prefix: String,
+}
+
+impl ListStream {
+    // Only the names of live, matching keys are cloned while the read lock is held
+    async fn new(store: &KvStore, namespace: Option<&str>, pattern: Option<&str>) -> ListStream {
+        let prefix = namespace_prefix(namespace);
+        let now = SystemTime::now();
+        let keys: Vec<String> = store
+            .read()
+            .await
+            .iter()
+            .filter(|(key, stored)| key.starts_with(&prefix) && !stored.is_expired(now))
+            .filter(|(key, _)| {
+                pattern.is_none_or(|pattern| glob_match(pattern, &key[prefix.len()..]))
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        ListStream {
+            store: store.clone(),
+            keys: keys.into_iter(),
+            prefix,
+        }
+    }
+
+    // LIST BYEXPIRY: the snapshot also copies each expiration, to sort by
+    async fn by_expiry(
+        store: &KvStore,
+        namespace: Option<&str>,
+        limit: Option<usize>,
+    ) -> ListStream {
+        let prefix = namespace_prefix(namespace);
+        let now = SystemTime::now();
+        let mut entries: Vec<(Option<SystemTime>, String)> = store
+            .read()
+            .await
+            .iter()
+            .filter(|(key, stored)| key.starts_with(&prefix) && !stored.is_expired(now))
+            .map(|(key, stored)| (stored.expires_at, key.clone()))
+            .collect();
+        // Names break ties so equal expirations list the same way every time
+        entries.sort_unstable_by(|(a_exp, a_key), (b_exp, b_key)| {
+            (a_exp.is_none(), a_exp, a_key).cmp(&(b_exp.is_none(), b_exp, b_key))
+        });
+        entries.truncate(limit.unwrap_or(usize::MAX));
+        let keys: Vec<String> = entries.into_iter().map(|(_, key)| key).collect();
+        ListStream {
+            store: store.clone(),
+            keys: keys.into_iter(),
+            prefix,
+        }
+    }
+
+    /// The next LIST_CHUNK_KEYS entries as wire lines, or None once all keys are done.
+    async fn next_chunk(&mut self) -> Option<String> {
+        if self.keys.as_slice().is_empty() {
+            return None;
+        }
+        let store_read = self.store.read().await;
+        let now = SystemTime::now();
+        let mut chunk = String::new();
+        for key in self.keys.by_ref().take(LIST_CHUNK_KEYS) {
+            // A key that expired since the snapshot is skipped like a deleted one
+            if let Some(stored_value) = store_read.get(&key).filter(|s| !s.is_expired(now)) {
+                let entry = list_entry(&key[self.prefix.len()..], stored_value);
+                chunk.push_str(&entry.to_line());
+                chunk.push('\n');
+            }
+        }
+        Some(chunk)
+    }
+
+    // Batches join single-line results, and COMPRESS ON deflates whole replies, so
+    // a LIST is then built in full
+    async fn collect(mut self) -> String {
+        let mut body = String::new();
+        while let Some(chunk) = self.next_chunk().await {
+            body.push_str(&chunk);
+        }
+        body.push('\n');
+        body
+    }
+}
+
+// A LIST or SCAN line for `name`, which has any SELECT prefix already removed
+fn list_entry(name: &str, stored: &StoredValue) -> ListEntry {
+    ListEntry {
+        key: name.to_string(),
+        value: value_to_string(&stored.value),
+        value_type: stored.value.type_name().to_string(),
+        expires_at: stored.expires_at.map(|exp_time| {
+            exp_time
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        }),
+    }
+}
+
+// `namespace:` for a connection's SELECT, or nothing in the global namespace
+fn namespace_prefix(namespace: Option<&str>) -> String {
+    namespace
+        .map(|ns| format!("{}{}", ns, kv_protocol::NAMESPACE_SEPARATOR))
+        .unwrap_or_default()
+}
+
+// Moves a key command into the connection's namespace. The prefixed key has to fit
+// the key length limit like any other.
+fn scoped(command: Command, namespace: &str, max_key_len: usize) -> Result<Command, ParseError> {
+    let scope = |key: String| {
+        let key = format!("{}{}{}", namespace, kv_protocol::NAMESPACE_SEPARATOR, key);
+        if key.len() > max_key_len {
+            Err(ParseError::InvalidLength)
+        } else {
+            Ok(key)
+        }
+    };
+    Ok(match command {
+        Command::Get { key } => Command::Get { key: scope(key)? },
+        Command::Peek { key } => Command::Peek { key: scope(key)? },
+        Command::Exists { key } => Command::Exists { key: scope(key)? },
+        Command::Type { key } => Command::Type { key: scope(key)? },
+        // Each key's length is checked on its own when the MGET runs
+        Command::MGet { keys } => Command::MGet {
+            keys: keys
+                .into_iter()
+                .map(|key| format!("{}{}{}", namespace, kv_protocol::NAMESPACE_SEPARATOR, key))
+                .collect(),
+        },
+        Command::GetEx { key, expiry } => Command::GetEx {
+            key: scope(key)?,
+            expiry,
+        },
+        Command::GetB { key } => Command::GetB { key: scope(key)? },
+        Command::Del { key } => Command::Del { key: scope(key)? },
+        Command::GetDel { key } => Command::GetDel { key: scope(key)? },
+        Command::KeyStats { key } => Command::KeyStats { key: scope(key)? },
+        Command::Set {
+            key,
+            value,
+            ttl,
+            raw,
+        } => Command::Set {
+            key: scope(key)?,
+            value,
+            ttl,
+            raw,
+        },
+        Command::GetSet { key, value, ttl } => Command::GetSet {
+            key: scope(key)?,
+            value,
+            ttl,
+        },
+        Command::SetNx { key, value, ttl } => Command::SetNx {
+            key: scope(key)?,
+            value,
+            ttl,
+        },
+        Command::Cas {
+            key,
+            expected,
+            value,
+            ttl,
+        } => Command::Cas {
+            key: scope(key)?,
+            expected,
+            value,
+            ttl,
+        },
+        Command::MSet { pairs, ttl } => Command::MSet {
+            pairs: pairs
+                .into_iter()
+                .map(|(key, value)| Ok((scope(key)?, value)))
+                .collect::<Result<_, ParseError>>()?,
+            ttl,
+        },
+        Command::SetB { key, data, ttl } => Command::SetB {
+            key: scope(key)?,
+            data,
+            ttl,
+        },
+        Command::Incr { key, ttl } => Command::Incr {
+            key: scope(key)?,
+            ttl,
+        },
+        Command::Decr { key, ttl } => Command::Decr {
+            key: scope(key)?,
+            ttl,
+        },
+        Command::IncrBy { key, amount, ttl } => Command::IncrBy {
+            key: scope(key)?,
+            amount,
+            ttl,
+        },
+        Command::DecrBy { key, amount, ttl } => Command::DecrBy {
+            key: scope(key)?,
+            amount,
+            ttl,
+        },
+        Command::IncrByFloat { key, delta, ttl } => Command::IncrByFloat {
+            key: scope(key)?,
+            delta,
+            ttl,
+        },
+        Command::Expire { key, ttl } => Command::Expire {
+            key: scope(key)?,
+            ttl,
+        },
+        Command::Ttl { key } => Command::Ttl { key: scope(key)? },
+        Command::PTtl { key } => Command::PTtl { key: scope(key)? },
+        Command::Persist { key } => Command::Persist { key: scope(key)? },
+        Command::Tag { key, tag } => Command::Tag {
+            key: scope(key)?,
+            tag,
+        },
+        Command::Rename { src, dst } => Command::Rename {
+            src: scope(src)?,
+            dst: scope(dst)?,
+        },
+        Command::RenameNx { src, dst } => Command::RenameNx {
+            src: scope(src)?,
+            dst: scope(dst)?,
+        },
+        command => command,
+    })
+}
+
+async fn process_single_command(
+    command: Command,
+    peer: &str,
+    namespace: &mut Option<String>,
+    store: &KvStore,
+    settings: &Settings,
+    stats: &Stats,
+    logger: &Logger,
+) -> Reply {
+    if settings
+        .disabled_commands
+        .iter()
+        .any(|verb| verb == command.verb())
+    {
+        return Reply::from(Response::Error("command disabled".to_string()));
+    }
+    // EXPORT, IMPORT, DUMP and LOAD always work on the whole store
+    let command = match namespace.as_deref() {
+        Some(ns) => match scoped(command, ns, settings.limits.max_key_len) {
+            Ok(command) => command,
+            Err(e) => return Reply::from(Response::error(e)),
+        },
+        None => command,
+    };
+    let response = match command {
+        // Handled by handle_connection when it is the first line
+        Command::Hello { .. } => Response::Error("HELLO must be the first command".to_string()),
+        Command::Ping => Response::Pong,
+        Command::Time => Response::Status(kv_protocol::time_line(SystemTime::now())),
+        Command::Limits => Response::Status(settings.limits.to_line()),
+        Command::Status => {
+            let store_read = store.read().await;
+            let status = ServerStatus {
+                uptime: stats.started.elapsed(),
+                keys: store_read.len() as u64,
+                expiring: store_read.expiring() as u64,
+                mem_bytes: store_read.mem_bytes() as u64,
+                version: VERSION.to_string(),
+                connections: stats.connections.load(Ordering::Relaxed),
+                saved_bytes: store_read.saved_bytes() as u64,
+                bytes_read: stats.bytes_read.load(Ordering::Relaxed),
+                bytes_written: stats.bytes_written.load(Ordering::Relaxed),
+                commands: stats.commands.load(Ordering::Relaxed),
+            };
+            Response::Status(status.to_line())
+        }
+        Command::Get { key } => {
+            let value = get_live(&mut *store.write().await, &key);
+            stats.record_get(&key, value.is_some());
+            match value {
+                Some(value) => Response::Value(value_to_string(&value)),
+                None => Response::NotFound,
+            }
+        }
+        // Read lock only: an expired key is left for the sweep or the next GET
+        Command::Peek { key } => match store.read().await.get(&key) {
+            Some(stored) if !stored.is_expired(SystemTime::now()) => {
+                Response::Value(value_to_string(&stored.value))
+            }
+            _ => Response::NotFound,
+        },
+        // Lazy expiry as for GET, but no value goes back and KEY_STATS counts nothing
+        Command::Exists { key } => {
+            let live = get_live(&mut *store.write().await, &key).is_some();
+            Response::Integer(i64::from(live))
+        }
+        // Read lock only, as for PEEK
+        Command::Type { key } => match store.read().await.get(&key) {
+            Some(stored) if !stored.is_expired(SystemTime::now()) => {
+                Response::Value(stored.value.type_name().to_string())
+            }
+            _ => Response::NotFound,
+        },
+        Command::MGet { keys } => {
+            let mut store_write = store.write().await;
+            let mut slots = Vec::with_capacity(keys.len());
+            for key in &keys {
+                if key.len() > settings.limits.max_key_len {
+                    slots.push(Response::error(ParseError::InvalidLength));
+                    continue;
+                }
+                let value = get_live(&mut store_write, key);
+                stats.record_get(key, value.is_some());
+                slots.push(match value {
+                    Some(value) => Response::Value(value_to_string(&value)),
+                    None => Response::NotFound,
+                });
+            }
+            Response::Values(slots)
+        }
+        // Counts as a GET, and a found key then loses its counters as with DEL
+        Command::GetDel { key } => {
+            let mut store_write = store.write().await;
+            let value = get_live(&mut store_write, &key);
+            stats.record_get(&key, value.is_some());
+            match value {
+                Some(value) => {
+                    store_write.remove(&key);
+                    stats.forget_key(&key);
+                    Response::Value(value_to_string(&value))
+                }
+                None => Response::NotFound,
+            }
+        }
+        Command::GetEx { key, expiry } => {
+            let mut store_write = store.write().await;
+            let value = get_live(&mut store_write, &key);
+            stats.record_get(&key, value.is_some());
+            match value {
+                Some(value) => {
+                    match expiry {
+                        ExpiryChange::Keep => {}
+                        ExpiryChange::Ttl(ttl) => {
+                            store_write.set_expiration(&key, Some(SystemTime::now() + ttl));
+                        }
+                        ExpiryChange::Persist => {
+                            store_write.set_expiration(&key, None);
+                        }
+                    }
+                    Response::Value(value_to_string(&value))
+                }
+                None => Response::NotFound,
+            }
+        }
+        Command::GetB { key } => {
+            let value = get_live(&mut *store.write().await, &key);
+            stats.record_get(&key, value.is_some());
+            match value {
+                Some(Value::Bytes(b)) => Response::Value(encode_bytes(&b)),
+                // Text values come back as their UTF-8 bytes
+                Some(value) => Response::Value(encode_bytes(value_to_string(&value).as_bytes())),
+                None => Response::NotFound,
+            }
+        }
+        Command::SetB { key, data, ttl } => {
+            if data.len() > settings.limits.max_bytes_len {
+                return Reply::from(Response::error(ParseError::InvalidLength));
+            }
+            let stored_value = StoredValue {
+                value: Value::Bytes(data),
+                expires_at: ttl.map(|duration| SystemTime::now() + duration),
+            };
+            let mut store_write = store.write().await;
+            if !has_room(&store_write, settings, [key.as_str()]) {
+                return Reply::from(Response::error(ParseError::StoreFull));
+            }
+            store_write.insert(key.clone(), stored_value);
+            stats.record_set(&key);
+            Response::Ok
+        }
+        Command::Set {
+            key,
+            value,
+            ttl,
+            raw,
+        } => {
+            let stored_value = stored_from_set(value, ttl, raw, settings);
+            let mut store_write = store.write().await;
+            if !has_room(&store_write, settings, [key.as_str()]) {
+                return Reply::from(Response::error(ParseError::StoreFull));
+            }
+            store_write.insert(key.clone(), stored_value);
+            stats.record_set(&key);
+            Response::Ok
+        }
+        // Counts as a GET and a SET; an expired key is simply overwritten
+        Command::GetSet { key, value, ttl } => {
+            let mut stored_value = stored_from_set(value, ttl, false, settings);
+            let mut store_write = store.write().await;
+            let now = SystemTime::now();
+            let old = store_write
+                .get(&key)
+                .filter(|stored| !stored.is_expired(now))
+                .map(|stored| (value_to_string(&stored.value), stored.expires_at));
+            if old.is_none() && !has_room(&store_write, settings, [key.as_str()]) {
+                return Reply::from(Response::error(ParseError::StoreFull));
+            }
+            stats.record_get(&key, old.is_some());
+            stats.record_set(&key);
+            let response = match old {
+                Some((old_value, expires_at)) => {
+                    if ttl.is_none() {
+                        stored_value.expires_at = expires_at;
+                    }
+                    Response::Value(old_value)
+                }
+                None => Response::Value(kv_protocol::NIL.to_string()),
+            };
+            store_write.insert(key, stored_value);
+            response
+        }
+        // The existence check and the insert share one write lock, so only one of two
+        // racing clients gets 1
+        Command::SetNx { key, value, ttl } => {
+            let stored_value = stored_from_set(value, ttl, false, settings);
+            let mut store_write = store.write().await;
+            if get_live(&mut store_write, &key).is_some() {
+                Response::Integer(0)
+            } else if !has_room(&store_write, settings, [key.as_str()]) {
+                Response::error(ParseError::StoreFull)
+            } else {
+                store_write.insert(key.clone(), stored_value);
+                stats.record_set(&key);
+                Response::Integer(1)
+            }
+        }
+        // The comparison and the write share one write lock, as with SETNX
+        Command::Cas {
+            key,
+            expected,
+            value,
+            ttl,
+        } => {
+            let mut stored_value = stored_from_set(value, ttl, false, settings);
+            let mut store_write = store.write().await;
+            let now = SystemTime::now();
+            let current = store_write
+                .get(&key)
+                .filter(|stored| !stored.is_expired(now))
+                .map(|stored| (value_to_string(&stored.value), stored.expires_at));
+            stats.record_get(&key, current.is_some());
+            match current {
+                Some((current, expires_at)) if current == expected => {
+                    if ttl.is_none() {
+                        stored_value.expires_at = expires_at;
+                    }
+                    store_write.insert(key.clone(), stored_value);
+                    stats.record_set(&key);
+                    Response::Integer(1)
+                }
+                _ => Response::Integer(0),
+            }
+        }
+        // Values are prepared before taking the lock, which is then held for every insert
+        Command::MSet { pairs, ttl } => {
+            let stored: Vec<(String, StoredValue)> = pairs
+                .into_iter()
+                .map(|(key, value)| (key, stored_from_set(value, ttl, false, settings)))
+                .collect();
+            let mut store_write = store.write().await;
+            if !has_room(
+                &store_write,
+                settings,
+                stored.iter().map(|(key, _)| key.as_str()),
+            ) {
+                return Reply::from(Response::error(ParseError::StoreFull));
+            }
+            for (key, stored_value) in stored {
+                stats.record_set(&key);
+                store_write.insert(key, stored_value);
+            }
+            Response::Ok
+        }
+        Command::Incr { key, ttl } => {
+            increment(&mut *store.write().await, stats, settings, &key, 1, ttl)
+        }
+        Command::Decr { key, ttl } => {
+            increment(&mut *store.write().await, stats, settings, &key, -1, ttl)
+        }
+        Command::IncrBy { key, amount, ttl } => increment(
+            &mut *store.write().await,
+            stats,
+            settings,
+            &key,
+            amount.into(),
+            ttl,
+        ),
+        Command::DecrBy { key, amount, ttl } => increment(
+            &mut *store.write().await,
+            stats,
+            settings,
+            &key,
+            -i128::from(amount),
+            ttl,
+        ),
+        Command::IncrByFloat { key, delta, ttl } => {
+            increment_float(&mut *store.write().await, stats, settings, &key, delta, ttl)
+        }
+        Command::Ttl { key } => time_to_live(&mut *store.write().await, &key, |left| {
+            left.as_secs() as i64
+        }),
+        Command::PTtl { key } => time_to_live(&mut *store.write().await, &key, |left| {
+            left.as_millis() as i64
+        }),
+        Command::Expire { key, ttl } => {
+            let now = SystemTime::now();
+            change_expiration(&mut *store.write().await, &key, Some(now + ttl), now)
+        }
+        Command::Persist { key } => {
+            change_expiration(&mut *store.write().await, &key, None, SystemTime::now())
+        }
+        Command::Rename { src, dst } => rename(&mut *store.write().await, stats, &src, &dst, false),
+        Command::RenameNx { src, dst } => {
+            rename(&mut *store.write().await, stats, &src, &dst, true)
+        }
+        Command::Del { key } => {
+            let mut store_write = store.write().await;
+            stats.forget_key(&key);
+            match store_write.remove(&key) {
+                Some(stored) => {
+                    if stored
+                        .expires_at
+                        .is_some_and(|exp_time| exp_time <= SystemTime::now())
+                    {
+                        Response::NotFound
+                    } else {
+                        Response::Ok
+                    }
+                }
+                None => Response::NotFound,
+            }
+        }
+        Command::KeyStats { key } => match &stats.key_stats {
+            Some(key_stats) => match key_stats.lock().unwrap().get(&key) {
+                Some(counters) => Response::Status(counters.to_line()),
+                None => Response::NotFound,
+            },
+            None => Response::Error("key stats disabled".to_string()),
+        },
+        Command::TopKeys { n } => match &stats.key_stats {
+            Some(key_stats) => Response::Lines(
+                key_stats
+                    .lock()
+                    .unwrap()
+                    .top(n)
+                    .iter()
+                    .map(KeyCounters::to_line)
+                    .collect(),
+            ),
+            None => Response::Error("key stats disabled".to_string()),
+        },
+        Command::List { pattern } => {
+            return Reply::Streamed(
+                ListStream::new(store, namespace.as_deref(), pattern.as_deref()).await,
+            )
+        }
+        Command::ListByExpiry { limit } => {
+            return Reply::Streamed(ListStream::by_expiry(store, namespace.as_deref(), limit).await)
+        }
+        // Counted like LIST would list them, so SELECT's prefix is left out of the match
+        Command::Count { pattern } => {
+            let prefix = namespace_prefix(namespace.as_deref());
+            let now = SystemTime::now();
+            let count = store
+                .read()
+                .await
+                .iter()
+                .filter(|(key, stored)| key.starts_with(&prefix) && !stored.is_expired(now))
+                .filter(|(key, _)| {
+                    pattern
+                        .as_deref()
+                        .is_none_or(|pattern| glob_match(pattern, &key[prefix.len()..]))
+                })
+                .count();
+            Response::Integer(count as i64)
+        }
+        // Matched against the names LIST would show, so SELECT's prefix is left out
+        Command::Keys { pattern } => {
+            let prefix = namespace_prefix(namespace.as_deref());
+            let now = SystemTime::now();
+            let mut names: Vec<String> = store
+                .read()
+                .await
+                .iter()
+                .filter(|(_, stored)| !stored.is_expired(now))
+                .filter_map(|(key, _)| key.strip_prefix(&prefix))
+                .filter(|name| glob_match(&pattern, name))
+                .map(str::to_string)
+                .collect();
+            // Only the names kept are sorted when the match is larger than the limit
+            let limit = settings.keys_reply_limit;
+            if names.len() > limit {
+                names.select_nth_unstable(limit);
+                names.truncate(limit);
+            }
+            names.sort_unstable();
+            Response::Lines(names)
+        }
+        // Each page takes the read lock once and sorts only the names it returns. Keys
+        // stored for the whole scan are returned exactly once; others may or may not be.
+        Command::Scan { after, count } => {
+            let prefix = namespace_prefix(namespace.as_deref());
+            let now = SystemTime::now();
+            let store_read = store.read().await;
+            let mut page: Vec<(&str, &StoredValue)> = store_read
+                .iter()
+                .filter(|(_, stored)| !stored.is_expired(now))
+                .filter_map(|(key, stored)| Some((key.strip_prefix(&prefix)?, stored)))
+                .filter(|(name, _)| after.as_deref().is_none_or(|after| *name > after))
+                .collect();
+            let more = page.len() > count;
+            if more {
+                page.select_nth_unstable_by(count, |a, b| a.0.cmp(b.0));
+                page.truncate(count);
+            }
+            page.sort_unstable_by(|a, b| a.0.cmp(b.0));
+            let next = match page.last() {
+                Some((name, _)) if more => kv_protocol::scan_cursor(Some(name)),
+                _ => kv_protocol::SCAN_START.to_string(),
+            };
+            let mut lines = vec![next];
+            lines.extend(
+                page.into_iter()
+                    .map(|(name, stored)| list_entry(name, stored).to_line()),
+            );
+            Response::Lines(lines)
+        }
+        Command::Tag { key, tag } => {
+            let mut store_write = store.write().await;
+            if get_live(&mut store_write, &key).is_some() && store_write.tag(&key, &tag) {
+                Response::Ok
+            } else {
+                Response::NotFound
+            }
+        }
+        // Tags are global, but under SELECT only the namespace's keys are listed, as KEYS does
+        Command::Tagged { tag } => {
+            let prefix = namespace_prefix(namespace.as_deref());
+            let now = SystemTime::now();
+            let store_read = store.read().await;
+            let mut names: Vec<String> = store_read
+                .tagged(&tag)
+                .filter(|key| store_read.get(key).is_some_and(|s| !s.is_expired(now)))
+                .filter_map(|key| key.strip_prefix(&prefix))
+                .map(str::to_string)
+                .collect();
+            names.sort_unstable();
+            Response::Lines(names)
+        }
+        // Scoped like TAGGED: under SELECT only the namespace's keys are expired
+        Command::ExpireTag { tag, ttl } => {
+            let prefix = namespace_prefix(namespace.as_deref());
+            let now = SystemTime::now();
+            let mut store_write = store.write().await;
+            let keys: Vec<String> = store_write
+                .tagged(&tag)
+                .filter(|key| key.starts_with(&prefix))
+                .filter(|key| store_write.get(key).is_some_and(|s| !s.is_expired(now)))
+                .cloned()
+                .collect();
+            for key in &keys {
+                store_write.set_expiration(key, Some(now + ttl));
+            }
+            Response::Integer(keys.len() as i64)
+        }
+        // Only valid as a line of its own, where handle_connection answers it
+        Command::Compress { .. } => Response::Error("COMPRESS must be sent on its own".to_string()),
+        Command::Select {
+            namespace: selected,
+        } => {
+            *namespace = selected;
+            Response::Ok
+        }
+        Command::Export => {
+            if !settings.admin_commands {
+                return Reply::from(Response::Error("admin commands disabled".to_string()));
+            }
+            // Length-prefixed so clients can read the whole document in one go
+            Response::Export(export_json(store).await.0)
+        }
+        // Sleeps inside the connection's loop, so heartbeats pause too, as on a stuck server
+        Command::DebugSleep { ms } => {
+            if !settings.debug_commands {
+                return Reply::from(Response::Error("debug commands disabled".to_string()));
+            }
+            tokio::time::sleep(TokioDuration::from_millis(ms)).await;
+            Response::Ok
+        }
+        Command::BadCmds => {
+            if !settings.admin_commands {
+                return Reply::from(Response::Error("admin commands disabled".to_string()));
+            }
+            Response::Lines(stats.take_bad_commands())
+        }
+        Command::Import { json } => {
+            if !settings.admin_commands {
+                return Reply::from(Response::Error("admin commands disabled".to_string()));
+            }
+            let entries = match parse_import_json(&json, settings) {
+                Ok(entries) => entries,
+                Err(e) => return Reply::from(Response::Error(format!("invalid import: {}", e))),
+            };
+            let (imported, skipped) = insert_live(&mut *store.write().await, entries);
+            Response::Status(format!("OK imported={} skipped={}", imported, skipped))
+        }
+        // Covers the whole store whatever the connection's SELECT, like LOAD REPLACE
+        Command::FlushAll => {
+            if !settings.allow_flush {
+                return Reply::from(Response::Error("command disabled".to_string()));
+            }
+            let flushed = {
+                let mut store_write = store.write().await;
+                let flushed = store_write.len();
+                store_write.clear();
+                flushed
+            };
+            logger
+                .activity(
+                    "KVFLUSH",
+                    &format!("FLUSHALL from {}: removed {} keys", peer, flushed),
+                )
+                .await;
+            Response::Ok
+        }
+        // The lines are taken under the read lock, which queues the rewrite behind every
+        // change already journaled; the file is written once the lock is released
+        Command::BgRewriteAof => {
+            if !settings.admin_commands {
+                return Reply::from(Response::Error("admin commands disabled".to_string()));
+            }
+            let rewrite = {
+                let store_read = store.read().await;
+                store_read
+                    .journal()
+                    .map(|journal| journal.rewrite(kv_aof::snapshot_lines(&store_read)))
+            };
+            let response = match rewrite {
+                None => return Reply::from(Response::Error("aof disabled".to_string())),
+                Some(done) => match done.await {
+                    Ok(Ok(lines)) => Response::Status(format!("OK rewritten={}", lines)),
+                    Ok(Err(e)) => Response::Error(format!("rewrite failed: {}", e)),
+                    Err(_) => Response::Error("rewrite failed: AOF writer stopped".to_string()),
+                },
+            };
+            logger
+                .activity(
+                    "KVAOFREWRITE",
+                    &format!(
+                        "BGREWRITEAOF from {}: {}",
+                        peer,
+                        response.to_wire().trim_end()
+                    ),
+                )
+                .await;
+            response
+        }
+        Command::Dump { path } => {
+            if !settings.admin_commands {
+                return Reply::from(Response::Error("admin commands disabled".to_string()));
+            }
+            let (json, dumped) = export_json(store).await;
+            let response = match write_dump(&path, &json).await {
+                Ok(()) => Response::Status(format!("OK dumped={}", dumped)),
+                Err(e) => Response::Error(format!("dump failed: {}", e)),
+            };
+            logger
+                .activity(
+                    "KVDUMP",
+                    &format!(
+                        "DUMP {} from {}: {}",
+                        path,
+                        peer,
+                        response.to_wire().trim_end()
+                    ),
+                )
+                .await;
+            response
+        }
+        Command::Load { path, mode } => {
+            if !settings.admin_commands {
+                return Reply::from(Response::Error("admin commands disabled".to_string()));
+            }
+            let response = match read_dump(&path, settings).await {
+                Ok(entries) => {
+                    let mut store_write = store.write().await;
+                    if mode == LoadMode::Replace {
+                        store_write.clear();
+                    }
+                    let (loaded, skipped) = insert_live(&mut store_write, entries);
+                    Response::Status(format!("OK loaded={} skipped={}", loaded, skipped))
+                }
+                Err(e) => Response::Error(format!("load failed: {}", e)),
+            };
+            logger
+                .activity(
+                    "KVLOAD",
+                    &format!(
+                        "LOAD {} {} from {}: {}",
+                        path,
+                        mode.as_str(),
+                        peer,
+                        response.to_wire().trim_end()
+                    ),
+                )
+                .await;
+            response
+        }
+    };
+    Reply::from(response)
+}
+
+// KEYS and LIST patterns: `*` matches any run of characters, `?` exactly one, `[abc]`
+// one of a set, which may hold ranges such as `[a-z]`, and anything else itself; a `[`
+// without a closing `]` matches itself. Only the latest `*` is backtracked to, so a
+// match costs at most pattern × name steps.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = glob_tokens(pattern);
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Pattern position after the latest `*`, and the name position it resumes from
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(Glob::Star) => {
+                p += 1;
+                star = Some((p, n));
+            }
+            Some(token) if token.matches(name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((after_star, from)) => {
+                    p = after_star;
+                    n = from + 1;
+                    star = Some((after_star, n));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|token| *token == Glob::Star)
+}
+
+#[derive(PartialEq)]
+enum Glob {
+    Star,
+    Any,
+    Char(char),
+    // Inclusive ranges, a lone character being a range of one
+    Class(Vec<(char, char)>),
+}
+
+impl Glob {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Glob::Star => false,
+            Glob::Any => true,
+            Glob::Char(expected) => *expected == c,
+            Glob::Class(ranges) => ranges.iter().any(|&(low, high)| low <= c && c <= high),
+        }
+    }
+}
+
+fn glob_tokens(pattern: &str) -> Vec<Glob> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let token = match chars[i] {
+            '*' => Glob::Star,
+            '?' => Glob::Any,
+            // `[]` is no set, so its `[` is taken literally
+            '[' => match chars[i + 1..].iter().position(|&c| c == ']') {
+                Some(len) if len > 0 => {
+                    let class = &chars[i + 1..i + 1 + len];
+                    i += len + 1;
+                    Glob::Class(class_ranges(class))
+                }
+                _ => Glob::Char('['),
+            },
+            c => Glob::Char(c),
+        };
+        tokens.push(token);
+        i += 1;
+    }
+    tokens
+}
+
+// `a-z0` is the ranges a..=z and 0..=0; a `-` at either end is itself
+fn class_ranges(class: &[char]) -> Vec<(char, char)> {
+    let mut ranges = Vec::new();
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            ranges.push((class[i], class[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((class[i], class[i]));
+            i += 1;
+        }
+    }
+    ranges
+}
+
+// RENAME and RENAMENX: the value keeps its expiration and tags, and KEY_STATS sees a DEL of
+// `src` and a SET of `dst`. With `nx` a live `dst`, including `src` itself, is left alone
+fn rename(store: &mut Store, stats: &Stats, src: &str, dst: &str, nx: bool) -> Response {
+    if get_live(store, src).is_none() {
+        return Response::error(ParseError::NoSuchKey);
+    }
+    if nx && get_live(store, dst).is_some() {
+        return Response::Integer(0);
+    }
+    if src != dst && store.rename(src, dst) {
+        stats.forget_key(src);
+        stats.record_set(dst);
+    }
+    if nx {
+        Response::Integer(1)
+    } else {
+        Response::Ok
+    }
+}
+
+// INCR and DECR: adds `delta` to the key's integer value, which starts from 0 when
+// the key is missing or expired. `ttl` only applies to a key created here.
+fn increment(
+    store: &mut Store,
+    stats: &Stats,
+    settings: &Settings,
+    key: &str,
+    delta: i128,
+    ttl: Option<Duration>,
+) -> Response {
+    let current = store.get(key).filter(|stored| {
+        stored
+            .expires_at
+            .is_none_or(|exp_time| exp_time > SystemTime::now())
+    });
+    let current_val = match current.map(|stored| &stored.value) {
+        Some(Value::Int(i)) => *i,
+        Some(value @ (Value::Str(_) | Value::Compressed(_))) => {
+            match value_to_string(value).parse::<i64>() {
+                Ok(i) => i,
+                Err(_) => return Response::error(ParseError::NotAnInteger),
+            }
+        }
+        Some(Value::Bytes(_)) => return Response::error(ParseError::NotAnInteger),
+        Some(Value::Float(_)) => return Response::error(ParseError::WrongType),
+        None if !has_room(store, settings, [key]) => return Response::error(ParseError::StoreFull),
+        None => 0, // New or expired key
+    };
+    // Wide enough that DECRBY of i64::MIN can't overflow before the range check
+    let new_val = match i64::try_from(i128::from(current_val) + delta) {
+        Ok(new_val) => new_val,
+        Err(_) => return Response::error(ParseError::Overflow),
+    };
+
+    // Only set expiration if the key is new; otherwise keep the existing one
+    let expires_at = match current {
+        Some(stored) => stored.expires_at,
+        None => ttl.map(|duration| SystemTime::now() + duration),
+    };
+
+    let stored_value = StoredValue {
+        value: Value::Int(new_val),
+        expires_at,
+    };
+    store.insert(key.to_string(), stored_value);
+    stats.record_set(key);
+    Response::Integer(new_val)
+}
+
+// INCRBYFLOAT: as `increment`, but an integer or numeric string is promoted and the
+// result is stored as a float
+fn increment_float(
+    store: &mut Store,
+    stats: &Stats,
+    settings: &Settings,
+    key: &str,
+    delta: f64,
+    ttl: Option<Duration>,
+) -> Response {
+    let current = store.get(key).filter(|stored| {
+        stored
+            .expires_at
+            .is_none_or(|exp_time| exp_time > SystemTime::now())
+    });
+    let current_val = match current.map(|stored| &stored.value) {
+        Some(Value::Float(f)) => *f,
+        Some(Value::Int(i)) => *i as f64,
+        Some(value @ (Value::Str(_) | Value::Compressed(_))) => {
+            match value_to_string(value).parse::<f64>() {
+                Ok(f) if f.is_finite() => f,
+                _ => return Response::error(ParseError::NotANumber),
+            }
+        }
+        Some(Value::Bytes(_)) => return Response::error(ParseError::NotANumber),
+        None if !has_room(store, settings, [key]) => return Response::error(ParseError::StoreFull),
+        None => 0.0,
+    };
+    let new_val = current_val + delta;
+    if !new_val.is_finite() {
+        return Response::error(ParseError::Overflow);
+    }
+
+    let expires_at = match current {
+        Some(stored) => stored.expires_at,
+        None => ttl.map(|duration| SystemTime::now() + duration),
+    };
+    let stored_value = StoredValue {
+        value: Value::Float(new_val),
+        expires_at,
+    };
+    store.insert(key.to_string(), stored_value);
+    stats.record_set(key);
+    Response::Value(kv_store::format_float(new_val))
+}
+
+// Whether writing `keys` stays within MAX_KEYS. Live keys among them are overwritten
+// and need no room; expired keys not yet swept don't count, but are only walked once
+// the store looks full.
+fn has_room<'a>(
+    store: &Store,
+    settings: &Settings,
+    keys: impl IntoIterator<Item = &'a str>,
+) -> bool {
+    let Some(max_keys) = settings.max_keys else {
+        return true;
+    };
+    let now = SystemTime::now();
+    let new_keys: HashSet<&str> = keys
+        .into_iter()
+        .filter(|key| store.get(key).is_none_or(|stored| stored.is_expired(now)))
+        .collect();
+    if new_keys.is_empty() || store.len() + new_keys.len() <= max_keys {
+        return true;
+    }
+    let live = store
+        .iter()
+        .filter(|(_, stored)| !stored.is_expired(now))
+        .count();
+    live + new_keys.len() <= max_keys
+}
+
+// Inserts the entries that have not expired yet, returning (inserted, skipped) counts
+fn insert_live(store: &mut Store, entries: Vec<(String, StoredValue)>) -> (u64, u64) {
+    let now = SystemTime::now();
+    let mut inserted = 0;
+    let mut skipped = 0;
+    for (key, stored_value) in entries {
+        if stored_value.is_expired(now) {
+            skipped += 1;
+            continue;
+        }
+        store.insert(key, stored_value);
+        inserted += 1;
+    }
+    (inserted, skipped)
+}
+
+// Written to a temporary file and renamed into place, so a failed DUMP never leaves a
+// truncated file where a good one was
+async fn write_dump(path: &str, json: &str) -> std::io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    tokio::fs::write(&tmp, json).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+async fn read_dump(path: &str, settings: &Settings) -> Result<Vec<(String, StoredValue)>, String> {
+    let json = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("{}: {}", path, e))?;
+    parse_import_json(&json, settings).map_err(|e| format!("{}: {}", path, e))
+}
+
+// EXPORT document: a JSON array of {key, value, type, expires_at} objects where
+// expires_at is absolute unix milliseconds (or null) so it survives the move between
+// hosts.
+// As for LIST, only the key names are copied under one read lock; entries are then
+// serialized LIST_CHUNK_KEYS at a time, taking the lock again for each chunk, so
+// writers get in between chunks. Each entry is the key as it was when its chunk was
+// written. Also returns the number of entries written.
+async fn export_json(store: &KvStore) -> (String, usize) {
+    let keys: Vec<String> = store
+        .read()
+        .await
+        .iter()
+        .map(|(key, _)| key.clone())
+        .collect();
+    let mut json = String::from("[");
+    let mut count = 0;
+    for chunk in keys.chunks(LIST_CHUNK_KEYS) {
+        let store_read = store.read().await;
+        let now = SystemTime::now();
+        for key in chunk {
+            // Deleted or expired since the names were copied
+            let Some(stored) = store_read.get(key).filter(|s| !s.is_expired(now)) else {
+                continue;
+            };
+            if count > 0 {
+                json.push(',');
+            }
+            json.push_str(&export_entry(key, stored).to_string());
+            count += 1;
+        }
+    }
+    json.push(']');
+    (json, count)
+}
+
+fn export_entry(key: &str, stored: &StoredValue) -> serde_json::Value {
+    let value = match &stored.value {
+        Value::Int(i) => serde_json::json!(i),
+        Value::Float(f) => serde_json::json!(f),
+        Value::Str(s) => serde_json::json!(s),
+        Value::Bytes(b) => serde_json::json!(encode_bytes(b)),
+        Value::Compressed(payload) => serde_json::json!(kv_store::decompress(payload)),
+    };
+    let expires_at = stored
+        .expires_at
+        .and_then(|exp_time| exp_time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64);
+    serde_json::json!({
+        "key": key,
+        "value": value,
+        "type": stored.value.type_name(),
+        "expires_at": expires_at,
+    })
+}
+
+fn parse_import_json(
+    json: &str,
+    settings: &Settings,
+) -> Result<Vec<(String, StoredValue)>, String> {
+    let doc: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let items = doc.as_array().ok_or("expected a JSON array")?;
+    let mut entries = Vec::with_capacity(items.len());
+    for item in items {
+        let key = item["key"].as_str().ok_or("entry missing key")?;
+        if key.is_empty()
+            || key.len() > settings.limits.max_key_len
+            || key.contains(char::is_whitespace)
+        {
+            return Err(format!("invalid key {:?}", key));
+        }
+        let value = match (item["type"].as_str(), &item["value"]) {
+            (Some("int"), v) => Value::Int(v.as_i64().ok_or("int entry without integer value")?),
+            (Some("float"), v) => Value::Float(
+                v.as_f64()
+                    .filter(|f| f.is_finite())
+                    .ok_or("float entry without number value")?,
+            ),
+            (Some("str"), v) => {
+                let s = v.as_str().ok_or("str entry without string value")?;
+                // An empty value couldn't be written as a SET line, or journaled
+                if s.is_empty()
+                    || s.len() > settings.limits.max_value_len
+                    || s.contains(char::is_whitespace)
+                {
+                    return Err(format!("invalid value for key {}", key));
+                }
+                compact(Value::Str(s.to_string()), settings)
+            }
+            (Some("bytes"), v) => {
+                let s = v.as_str().ok_or("bytes entry without base64 value")?;
+                let data = decode_bytes(s).map_err(|e| format!("{} for key {}", e, key))?;
+                if data.is_empty() || data.len() > settings.limits.max_bytes_len {
+                    return Err(format!("invalid value for key {}", key));
+                }
+                Value::Bytes(data)
+            }
+            _ => return Err(format!("unknown type for key {}", key)),
+        };
+        let expires_at = match &item["expires_at"] {
+            serde_json::Value::Null => None,
+            v => {
+                let ms = v
+                    .as_u64()
+                    .ok_or("expires_at must be unix milliseconds or null")?;
+                Some(std::time::UNIX_EPOCH + Duration::from_millis(ms))
+            }
+        };
+        entries.push((key.to_string(), StoredValue { value, expires_at }));
+    }
+    Ok(entries)
+}
+
+/// One executed command, logged as
+/// `TIMESTAMP PEER VERB KEY RESULT BYTES DURATION_US` with `-` for a missing field.
+struct AccessEntry<'a> {
+    time: chrono::DateTime<chrono::Utc>,
+    peer: &'a str,
+    verb: &'static str,
+    /// As stored, i.e. with the connection's namespace prefix.
+    key: Option<String>,
+    /// `ok`, `miss` (key not found) or `error`.
+    result: &'static str,
+    /// Size of the reply; unknown for a streamed LIST.
+    bytes: Option<usize>,
+    duration: Duration,
+}
+
+impl AccessEntry<'_> {
+    fn line(&self) -> String {
+        format!(
+            "{} {} {} {} {} {} {}",
+            self.time.format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            self.peer,
+            self.verb,
+            self.key.as_deref().unwrap_or("-"),
+            self.result,
+            self.bytes.map_or("-".to_string(), |b| b.to_string()),
+            self.duration.as_micros()
+        )
+    }
+}
+
+// process_single_command plus the KVACCESS record when ACCESS_LOG is on. A LIST's
+// duration covers taking the snapshot, not streaming it.
+async fn run_command(
+    command: Command,
+    peer: &str,
+    namespace: &mut Option<String>,
+    store: &KvStore,
+    settings: &Settings,
+    stats: &Stats,
+    logger: &Logger,
+) -> Reply {
+    if !settings.access_log {
+        return process_single_command(command, peer, namespace, store, settings, stats, logger)
+            .await;
+    }
+    let time = chrono::Utc::now();
+    let started = Instant::now();
+    let verb = command.verb();
+    let key = command.key().map(|key| match namespace.as_deref() {
+        Some(ns) => format!("{}{}{}", ns, kv_protocol::NAMESPACE_SEPARATOR, key),
+        None => key.to_string(),
+    });
+    let reply =
+        process_single_command(command, peer, namespace, store, settings, stats, logger).await;
+    let (result, bytes) = match &reply {
+        Reply::Immediate(wire) if wire.starts_with("ERROR") => ("error", Some(wire.len())),
+        Reply::Immediate(wire) if wire.trim_end() == KEY_NOT_FOUND => ("miss", Some(wire.len())),
+        Reply::Immediate(wire) => ("ok", Some(wire.len())),
+        Reply::Streamed(_) => ("ok", None),
+    };
+    let entry = AccessEntry {
+        time,
+        peer,
+        verb,
+        key,
+        result,
+        bytes,
+        duration: started.elapsed(),
+    };
+    logger.info("KVACCESS", &entry.line()).await;
+    reply
+}
+
+async fn process_command(
+    line: &str,
+    peer: &str,
+    namespace: &mut Option<String>,
+    store: &KvStore,
+    settings: &Settings,
+    stats: &Stats,
+    logger: &Logger,
+) -> Reply {
+    let reply = if let Some(commands) = kv_protocol::split_batch(line) {
+        if commands.len() > kv_protocol::MAX_BATCH_COMMANDS {
+            stats.record_bad_command(peer, "too many commands", line);
+            Reply::Immediate("ERROR too many commands\n".to_string())
+        } else {
+            let mut results = Vec::new();
+            for cmd in commands {
+                let wire = match Command::parse_with(cmd, &settings.limits) {
+                    Ok(command) => {
+                        match run_command(command, peer, namespace, store, settings, stats, logger)
+                            .await
+                        {
+                            Reply::Immediate(wire) => wire,
+                            Reply::Streamed(list) => list.collect().await,
+                        }
+                    }
+                    Err(e) => {
+                        stats.record_bad_command(peer, &e.to_string(), cmd);
+                        Response::error(e).to_wire()
+                    }
+                };
+                results.push(wire.trim_end().to_string());
+            }
+            Reply::Immediate(results.join(";") + "\n")
+        }
+    } else {
+        match Command::parse_with(line, &settings.limits) {
+            Ok(command) => {
+                run_command(command, peer, namespace, store, settings, stats, logger).await
+            }
+            Err(e) => {
+                stats.record_bad_command(peer, &e.to_string(), line);
+                Reply::from(Response::error(e))
+            }
+        }
+    };
+
+    let result = match &reply {
+        Reply::Immediate(wire) => wire.trim(),
+        Reply::Streamed(_) => "(streamed)",
+    };
+    logger
+        .debug(
+            "KVCMDPROC",
+            &format!("Processing command: {} = {}", line.trim(), result),
+        )
+        .await;
+    reply
+}
@@ -0,0 +1,110 @@
+// StatsD push for shrmpl-kv-srv. With STATSD_ADDR set the server sends one UDP packet
+// of metrics every STATSD_INTERVAL_SECS. Totals that only grow go out as counters of
+// the change since the previous packet, so a lost packet loses only its own share;
+// current levels go out as gauges.
+
+use crate::config;
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const DEFAULT_INTERVAL_SECS: u64 = 10;
+
+/// Prefix of every metric name, e.g. `shrmpl.kv.commands`.
+pub const PREFIX: &str = "shrmpl.kv.";
+
+/// Config keys read by `StatsdSettings::from_config`.
+pub const ADDR_KEY: config::ConfigKey = config::ConfigKey {
+    name: "STATSD_ADDR",
+    default: Some(""),
+    description:
+        "host:port of a StatsD daemon that metrics are pushed to over UDP; empty disables it",
+};
+
+pub const INTERVAL_KEY: config::ConfigKey = config::ConfigKey {
+    name: "STATSD_INTERVAL_SECS",
+    default: Some("10"),
+    description: "Seconds between two StatsD pushes when STATSD_ADDR is set",
+};
+
+#[derive(Clone, Debug)]
+pub struct StatsdSettings {
+    pub addr: String,
+    pub interval: Duration,
+}
+
+impl StatsdSettings {
+    /// None when STATSD_ADDR is empty or missing. The interval is at least a second.
+    pub fn from_config(config: &HashMap<String, String>) -> Option<StatsdSettings> {
+        let addr = config.get(ADDR_KEY.name).filter(|addr| !addr.is_empty())?;
+        let secs = config
+            .get(INTERVAL_KEY.name)
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_INTERVAL_SECS);
+        Some(StatsdSettings {
+            addr: addr.clone(),
+            interval: Duration::from_secs(secs.max(1)),
+        })
+    }
+}
+
+/// The server's counters at one push.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sample {
+    /// Commands processed since startup.
+    pub commands: u64,
+    /// Reads that found their key, and those that didn't, since startup.
+    pub hits: u64,
+    pub misses: u64,
+    /// Keys stored, including expired ones not yet swept, as STATUS counts them.
+    pub keys: u64,
+    /// Open connections.
+    pub connections: u64,
+}
+
+impl Sample {
+    /// Newline-separated StatsD lines: commands, hits and misses as counters of the
+    /// change since `previous`, keys and connections as gauges.
+    pub fn packet(&self, previous: &Sample) -> String {
+        let counter = |name: &str, now: u64, before: u64| {
+            format!("{}{}:{}|c", PREFIX, name, now.saturating_sub(before))
+        };
+        let gauge = |name: &str, now: u64| format!("{}{}:{}|g", PREFIX, name, now);
+        [
+            counter("commands", self.commands, previous.commands),
+            counter("hits", self.hits, previous.hits),
+            counter("misses", self.misses, previous.misses),
+            gauge("keys", self.keys),
+            gauge("connections", self.connections),
+        ]
+        .join("\n")
+    }
+}
+
+/// A UDP socket connected to the StatsD daemon.
+pub struct Statsd {
+    socket: UdpSocket,
+}
+
+impl Statsd {
+    /// Resolves `addr` and binds an ephemeral local port of the same address family.
+    pub async fn connect(addr: &str) -> io::Result<Statsd> {
+        let target = tokio::net::lookup_host(addr)
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))?;
+        let local = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(target).await?;
+        Ok(Statsd { socket })
+    }
+
+    pub async fn send(&self, packet: &str) -> io::Result<()> {
+        self.socket.send(packet.as_bytes()).await.map(|_| ())
+    }
+}
@@ -0,0 +1,331 @@
+// In-memory storage behind shrmpl-kv-srv. Besides the entries it keeps a memory
+// estimate, the number of keys with a TTL and the bytes saved by compression,
+// updated on every insert and removal, so STATUS can report them without walking
+// the map. Tags set by TAG are indexed both ways here too, so whichever path removes
+// a key (DEL, the expiry sweep, RENAME) also drops its tags. For the same reason the
+// AOF journal, when there is one, is fed from here.
+
+use crate::kv_aof::{self, Journal};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::time::SystemTime;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    /// Always finite; INCRBYFLOAT rejects a result that isn't.
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    /// A large `Str` kept deflated; see `compress`. Clients only ever see the text.
+    Compressed(Vec<u8>),
+}
+
+impl Value {
+    /// How SET stores `text` unless told RAW: as an integer if it parses as i64, as a
+    /// float if it is a finite f64 spelled exactly the way `{:?}` prints it (`4.2`,
+    /// `2.0`, `1e-7`, `1e300`), and otherwise, with None, as the string itself. GET
+    /// shows a float in that same spelling, so a float reads back as written; others
+    /// such as `4.20`, `0.0000001` or `12345678901234567890` stay strings.
+    pub fn number(text: &str) -> Option<Value> {
+        if let Ok(i) = text.parse::<i64>() {
+            return Some(Value::Int(i));
+        }
+        let f = text.parse::<f64>().ok().filter(|f| f.is_finite())?;
+        (format_float(f) == text).then_some(Value::Float(f))
+    }
+
+    /// The name TYPE, LIST and EXPORT give the kind: `int`, `float`, `str` or `bytes`.
+    /// A compressed value is a `str` to clients.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) | Value::Compressed(_) => "str",
+            Value::Bytes(_) => "bytes",
+        }
+    }
+}
+
+/// How a float is shown wherever a value is read or compared (GET, LIST, CAS, the
+/// INCRBYFLOAT reply) and written (the AOF, `KvClient::set_float`): the shortest
+/// spelling that parses back to the same f64, always with a `.` or an exponent.
+pub fn format_float(f: f64) -> String {
+    format!("{:?}", f)
+}
+
+/// Deflates `text` into a `Value::Compressed` payload: the original length as 4
+/// little-endian bytes followed by raw deflate data.
+pub fn compress(text: &str) -> Vec<u8> {
+    let mut payload = (text.len() as u32).to_le_bytes().to_vec();
+    let mut encoder = DeflateEncoder::new(payload, Compression::default());
+    // Writing into a Vec cannot fail
+    encoder
+        .write_all(text.as_bytes())
+        .expect("deflate into memory");
+    payload = encoder.finish().expect("deflate into memory");
+    payload
+}
+
+pub fn decompress(payload: &[u8]) -> String {
+    let mut text = String::with_capacity(original_len(payload));
+    // Payloads are only built by compress() from valid UTF-8, so failure means memory corruption
+    DeflateDecoder::new(&payload[4..])
+        .read_to_string(&mut text)
+        .expect("stored compressed value is valid deflate");
+    text
+}
+
+/// Length of the text a `Value::Compressed` payload expands to.
+pub fn original_len(payload: &[u8]) -> usize {
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&payload[..4]);
+    u32::from_le_bytes(len) as usize
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredValue {
+    pub value: Value,
+    pub expires_at: Option<SystemTime>,
+}
+
+impl StoredValue {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|exp_time| exp_time <= now)
+    }
+}
+
+// Rough fixed cost per entry: the hash bucket, String/Vec headers and the expiration
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Estimated bytes used by one entry.
+pub fn entry_size(key: &str, stored: &StoredValue) -> usize {
+    let value_len = match &stored.value {
+        Value::Int(_) => std::mem::size_of::<i64>(),
+        Value::Float(_) => std::mem::size_of::<f64>(),
+        Value::Str(s) => s.len(),
+        Value::Bytes(b) | Value::Compressed(b) => b.len(),
+    };
+    ENTRY_OVERHEAD + key.len() + value_len
+}
+
+#[derive(Debug, Default)]
+pub struct Store {
+    entries: HashMap<String, StoredValue>,
+    mem_bytes: usize,
+    expiring: usize,
+    saved_bytes: usize,
+    // Tag -> keys bearing it, and key -> its tags; both only hold stored keys
+    tagged: HashMap<String, HashSet<String>>,
+    key_tags: HashMap<String, HashSet<String>>,
+    journal: Option<Journal>,
+}
+
+impl Store {
+    pub fn new() -> Store {
+        Store::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&StoredValue> {
+        self.entries.get(key)
+    }
+
+    /// Appends every later change to `journal`. Expiry sweeps aren't journaled, since
+    /// the lines carry their keys' expirations.
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Removes every entry, keeping the journal.
+    pub fn clear(&mut self) {
+        let journal = self.journal.take();
+        *self = Store::default();
+        if let Some(journal) = &journal {
+            journal.append(kv_aof::FLUSH_LINE.to_string());
+        }
+        self.journal = journal;
+    }
+
+    /// Inserts or overwrites `key`, returning the previous value.
+    pub fn insert(&mut self, key: String, stored: StoredValue) -> Option<StoredValue> {
+        if let Some(journal) = &self.journal {
+            journal.append(kv_aof::entry_line(&key, &stored));
+        }
+        self.account_added(&key, &stored);
+        let previous = self.entries.insert(key.clone(), stored);
+        if let Some(old) = &previous {
+            self.account_removed(&key, old);
+        }
+        previous
+    }
+
+    /// Removes `key` along with its tags.
+    pub fn remove(&mut self, key: &str) -> Option<StoredValue> {
+        let removed = self.entries.remove(key);
+        if let Some(old) = &removed {
+            self.account_removed(key, old);
+            self.untag(key);
+            if let Some(journal) = &self.journal {
+                journal.append(kv_aof::del_line(key));
+            }
+        }
+        removed
+    }
+
+    /// Moves `src` and its tags to `dst`, replacing `dst` and dropping its tags.
+    /// Returns false if there is no `src`.
+    pub fn rename(&mut self, src: &str, dst: &str) -> bool {
+        if src == dst {
+            return self.entries.contains_key(src);
+        }
+        let tags = self.key_tags.get(src).cloned().unwrap_or_default();
+        let Some(stored) = self.remove(src) else {
+            return false;
+        };
+        self.remove(dst);
+        self.insert(dst.to_string(), stored);
+        for tag in tags {
+            self.tag(dst, &tag);
+        }
+        true
+    }
+
+    /// Adds `tag` to `key`, returning false if there is no such key. Expired entries
+    /// are not checked for.
+    pub fn tag(&mut self, key: &str, tag: &str) -> bool {
+        if !self.entries.contains_key(key) {
+            return false;
+        }
+        self.tagged
+            .entry(tag.to_string())
+            .or_default()
+            .insert(key.to_string());
+        self.key_tags
+            .entry(key.to_string())
+            .or_default()
+            .insert(tag.to_string());
+        true
+    }
+
+    /// Keys bearing `tag`, in no particular order, expired ones included.
+    pub fn tagged(&self, tag: &str) -> impl Iterator<Item = &String> {
+        self.tagged.get(tag).into_iter().flatten()
+    }
+
+    /// Replaces the expiration of `key` without touching its value, returning false
+    /// if there is no such key. Expired entries are not checked for.
+    pub fn set_expiration(&mut self, key: &str, expires_at: Option<SystemTime>) -> bool {
+        let Some(stored) = self.entries.get_mut(key) else {
+            return false;
+        };
+        match (stored.expires_at.is_some(), expires_at.is_some()) {
+            (false, true) => self.expiring += 1,
+            (true, false) => self.expiring -= 1,
+            _ => {}
+        }
+        stored.expires_at = expires_at;
+        if let Some(journal) = &self.journal {
+            journal.append(kv_aof::entry_line(key, stored));
+        }
+        true
+    }
+
+    /// Drops every entry that has expired by `now`, returning how many were removed.
+    pub fn remove_expired(&mut self, now: SystemTime) -> usize {
+        let mut freed = 0;
+        let mut expiring = 0;
+        let mut saved = 0;
+        let before = self.entries.len();
+        let mut untagged = Vec::new();
+        self.entries.retain(|key, stored| {
+            if stored.is_expired(now) {
+                freed += entry_size(key, stored);
+                expiring += 1;
+                saved += compression_saving(&stored.value);
+                if self.key_tags.contains_key(key) {
+                    untagged.push(key.clone());
+                }
+                false
+            } else {
+                true
+            }
+        });
+        for key in untagged {
+            self.untag(&key);
+        }
+        self.mem_bytes -= freed;
+        self.expiring -= expiring;
+        self.saved_bytes -= saved;
+        before - self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &StoredValue)> {
+        self.entries.iter()
+    }
+
+    /// Number of stored keys, including expired ones not yet swept.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn mem_bytes(&self) -> usize {
+        self.mem_bytes
+    }
+
+    /// Number of stored keys that have an expiration.
+    pub fn expiring(&self) -> usize {
+        self.expiring
+    }
+
+    /// Bytes saved by keeping values compressed, relative to storing them as text.
+    pub fn saved_bytes(&self) -> usize {
+        self.saved_bytes
+    }
+
+    // Drops every tag of `key`, and tags no key bears any more
+    fn untag(&mut self, key: &str) {
+        for tag in self.key_tags.remove(key).unwrap_or_default() {
+            if let Some(keys) = self.tagged.get_mut(&tag) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.tagged.remove(&tag);
+                }
+            }
+        }
+    }
+
+    fn account_added(&mut self, key: &str, stored: &StoredValue) {
+        self.mem_bytes += entry_size(key, stored);
+        self.saved_bytes += compression_saving(&stored.value);
+        if stored.expires_at.is_some() {
+            self.expiring += 1;
+        }
+    }
+
+    fn account_removed(&mut self, key: &str, stored: &StoredValue) {
+        self.mem_bytes -= entry_size(key, stored);
+        self.saved_bytes -= compression_saving(&stored.value);
+        if stored.expires_at.is_some() {
+            self.expiring -= 1;
+        }
+    }
+}
+
+fn compression_saving(value: &Value) -> usize {
+    match value {
+        // The server only keeps a compressed value when it is smaller than the text
+        Value::Compressed(payload) => original_len(payload).saturating_sub(payload.len()),
+        _ => 0,
+    }
+}
@@ -1,3 +1,23 @@
+pub mod accept_backoff;
 pub mod config;
+pub mod kv_aof;
+pub mod kv_key_stats;
+pub mod kv_protocol;
+pub mod kv_server;
+pub mod kv_statsd;
+pub mod kv_store;
+pub mod loadtest;
+pub mod loadtest_progress;
+pub mod loadtest_slow;
+pub mod log_alert;
+pub mod log_server;
+pub mod proxy_protocol;
+pub mod sd_notify;
+pub mod shrmpl_kv_client;
 pub mod shrmpl_log_client;
-pub mod shrmpl_kv_client;
\ No newline at end of file
+pub mod shutdown;
+pub mod vault_access_log;
+pub mod vault_cache;
+pub mod vault_nonce;
+pub mod vault_rate_limit;
+pub mod vault_server;
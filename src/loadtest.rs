@@ -0,0 +1,768 @@
+// shrmpl-kv-loadtest engine: runs concurrent client tasks against a server and
+// collects per-operation results. The binary handles arguments and the report.
+
+use crate::loadtest_progress::Progress;
+use crate::loadtest_slow::{SlowLog, SlowOp};
+use crate::shrmpl_kv_client::KvClient;
+use crate::shrmpl_log_client::{LogLevel, Logger};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{timeout, Duration, Instant};
+
+#[derive(Clone)]
+pub struct TestConfig {
+    /// Connections are assigned to these addresses round-robin by task id.
+    pub server_addrs: Vec<String>,
+    pub num_users: usize,
+    pub operations_per_user: usize,
+    pub shared_connection: bool,
+    pub full_test: bool,
+    /// Write increasing tokens to a per-task key set and check the final values at the end.
+    pub verify: bool,
+    /// Pause between a task's operations; not counted in the recorded latency.
+    pub think_time: Duration,
+    /// Upper bound of a random extra pause added to each think time.
+    pub think_jitter: Duration,
+    pub progress: Arc<Progress>,
+    /// Operations at or over its threshold are logged as they happen.
+    pub slow_log: Option<SlowLog>,
+}
+
+/// Number of keys each task cycles through in verify mode, so every key is overwritten
+/// many times over a run.
+const VERIFY_SLOTS: usize = 50;
+
+/// Output of a whole run: per-operation results plus any consistency violations.
+#[derive(Debug, Clone, Default)]
+pub struct TestRun {
+    pub results: Vec<TestResult>,
+    pub violations: Vec<Violation>,
+}
+
+/// A verify-mode key whose final value isn't the last token acknowledged for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub key: String,
+    pub expected: String,
+    pub actual: Option<String>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.actual {
+            Some(actual) => write!(
+                f,
+                "{}: expected {}, found stale {}",
+                self.key, self.expected, actual
+            ),
+            None => write!(f, "{}: expected {}, key missing", self.key, self.expected),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    /// Index into `TestConfig::server_addrs` of the server that handled the operation.
+    pub server: usize,
+    pub duration: Duration,
+    pub success: bool,
+    pub error_type: Option<String>,
+}
+
+pub async fn run_test(config: TestConfig) -> Result<TestRun, String> {
+    let mut run = TestRun::default();
+
+    if config.server_addrs.is_empty() {
+        return Err("No server addresses configured".to_string());
+    }
+
+    if config.shared_connection {
+        // Shared connection mode: one connection per server, shared by its tasks
+        let mut clients = Vec::new();
+        for addr in &config.server_addrs {
+            let client = KvClient::connect(addr).await.map_err(|e| e.to_string())?;
+            clients.push(Arc::new(Mutex::new(client)));
+        }
+
+        let mut handles = vec![];
+        for task_id in 0..config.num_users {
+            let server = task_id % clients.len();
+            let client = Arc::clone(&clients[server]);
+            let config = config.clone();
+            let handle =
+                tokio::spawn(
+                    async move { run_task_operations(client, config, task_id, server).await },
+                );
+            handles.push(handle);
+        }
+
+        for handle in handles {
+            match handle.await {
+                Ok(Ok(task_run)) => {
+                    run.results.extend(task_run.results);
+                    run.violations.extend(task_run.violations);
+                }
+                Ok(Err(e)) => return Err(format!("Task error: {}", e)),
+                Err(e) => return Err(format!("Join error: {}", e)),
+            }
+        }
+    } else {
+        // Multi-connection mode
+        let mut handles = vec![];
+        for task_id in 0..config.num_users {
+            let config = config.clone();
+            let server = task_id % config.server_addrs.len();
+            let handle = tokio::spawn(async move {
+                let client = KvClient::connect(&config.server_addrs[server])
+                    .await
+                    .map_err(|e| e.to_string())?;
+                run_task_operations(Arc::new(Mutex::new(client)), config, task_id, server).await
+            });
+            handles.push(handle);
+        }
+
+        for handle in handles {
+            match handle.await {
+                Ok(Ok(task_run)) => {
+                    run.results.extend(task_run.results);
+                    run.violations.extend(task_run.violations);
+                }
+                Ok(Err(e)) => return Err(format!("Task error: {}", e)),
+                Err(e) => return Err(format!("Join error: {}", e)),
+            }
+        }
+    }
+
+    Ok(run)
+}
+
+async fn run_task_operations(
+    client: Arc<Mutex<KvClient>>,
+    config: TestConfig,
+    task_id: usize,
+    server: usize,
+) -> Result<TestRun, String> {
+    let mut local_results = Vec::new();
+    let mut counter_value = 0i64;
+    // Last acknowledged token per verify key; a key whose SET failed is dropped since
+    // the write may or may not have been applied
+    let mut verify_expected: HashMap<String, String> = HashMap::new();
+    let mut jitter_rng = JitterRng::new(task_id);
+
+    for op_num in 0..config.operations_per_user {
+        if op_num > 0 && (!config.think_time.is_zero() || !config.think_jitter.is_zero()) {
+            let jitter = jitter_rng.below(config.think_jitter);
+            tokio::time::sleep(config.think_time + jitter).await;
+        }
+
+        let start = Instant::now();
+        let mut client_lock = client.lock().await;
+
+        let mut operation_success = true;
+        let mut operation_error = None;
+
+        if config.full_test {
+            // Comprehensive test operations
+            let set_key = format!("test_key_{}_{}", task_id, op_num);
+            let set_value = format!("{}", task_id);
+
+            // SET operation
+            if let Err(e) = client_lock.set(&set_key, &set_value).await {
+                operation_success = false;
+                operation_error = Some(format!("SET failed: {}", e));
+            }
+
+            // GET and verify
+            if operation_success {
+                match client_lock.get(&set_key).await {
+                    Ok(Some(val)) if val == set_value => {} // OK
+                    Ok(Some(val)) => {
+                        operation_success = false;
+                        operation_error = Some(format!(
+                            "GET verification failed: expected {}, got {}",
+                            set_value, val
+                        ));
+                    }
+                    Ok(None) => {
+                        operation_success = false;
+                        operation_error = Some("GET returned None".to_string());
+                    }
+                    Err(e) => {
+                        operation_success = false;
+                        operation_error = Some(format!("GET failed: {}", e));
+                    }
+                }
+            }
+
+            // INCR and verify
+            if operation_success {
+                let counter_key = format!("counter_{}", task_id);
+                match client_lock.incr(&counter_key).await {
+                    Ok(val) => {
+                        counter_value += 1;
+                        if val != counter_value {
+                            operation_success = false;
+                            operation_error = Some(format!(
+                                "INCR verification failed: expected {}, got {}",
+                                counter_value, val
+                            ));
+                        }
+                    }
+                    Err(e) => {
+                        operation_success = false;
+                        operation_error = Some(format!("INCR failed: {}", e));
+                    }
+                }
+            }
+
+            // SET with TTL
+            if operation_success {
+                let ttl_key = format!("ttl_key_{}_{}", task_id, op_num);
+                if let Err(e) = client_lock.set_with_ttl(&ttl_key, "ttl_value", "60s").await {
+                    operation_success = false;
+                    operation_error = Some(format!("SET with TTL failed: {}", e));
+                }
+            }
+
+            // MSET with TTL, then read one key back
+            if operation_success {
+                let mset_a = format!("mset_a_{}_{}", task_id, op_num);
+                let mset_b = format!("mset_b_{}_{}", task_id, op_num);
+                let pairs = [
+                    (mset_a.as_str(), set_value.as_str()),
+                    (mset_b.as_str(), "b"),
+                ];
+                if let Err(e) = client_lock.mset_with_ttl(&pairs, "60s").await {
+                    operation_success = false;
+                    operation_error = Some(format!("MSET failed: {}", e));
+                }
+            }
+            if operation_success {
+                let mset_a = format!("mset_a_{}_{}", task_id, op_num);
+                match client_lock.get(&mset_a).await {
+                    Ok(Some(val)) if val == set_value => {}
+                    Ok(val) => {
+                        operation_success = false;
+                        operation_error = Some(format!(
+                            "MSET verification failed: expected {}, got {:?}",
+                            set_value, val
+                        ));
+                    }
+                    Err(e) => {
+                        operation_success = false;
+                        operation_error = Some(format!("GET after MSET failed: {}", e));
+                    }
+                }
+            }
+        }
+
+        if config.verify && operation_success {
+            let key = format!("verify:{}:{}", task_id, op_num % VERIFY_SLOTS);
+            let token = format!("t{}-{}", task_id, op_num + 1);
+            match client_lock.set(&key, &token).await {
+                Ok(()) => {
+                    verify_expected.insert(key, token);
+                }
+                Err(e) => {
+                    verify_expected.remove(&key);
+                    operation_success = false;
+                    operation_error = Some(format!("Verify SET failed: {}", e));
+                }
+            }
+        }
+
+        // Always do the batch GET (the original test)
+        let batch_result = timeout(
+            Duration::from_secs(3),
+            client_lock.batch(&["GET loginlock-ip-123", "GET loginlock-user-abc"]),
+        )
+        .await;
+
+        drop(client_lock); // Release lock
+
+        let duration = start.elapsed();
+
+        let final_success = match batch_result {
+            Ok(Ok(_)) => operation_success,
+            Ok(Err(e)) => {
+                operation_error = Some(format!("Batch GET failed: {}", e));
+                false
+            }
+            Err(_) => {
+                operation_error = Some("Batch GET timeout".to_string());
+                false
+            }
+        };
+
+        config.progress.record(final_success);
+        if let Some(slow_log) = config.slow_log.as_ref().filter(|log| log.is_slow(duration)) {
+            let (op_type, key) = describe_operation(&config, task_id, op_num);
+            slow_log.record(SlowOp {
+                started: chrono::Utc::now()
+                    - chrono::Duration::from_std(duration).unwrap_or_default(),
+                task_id,
+                op_type,
+                key,
+                duration,
+                error: operation_error.clone(),
+            });
+        }
+        local_results.push(TestResult {
+            server,
+            duration,
+            success: final_success,
+            error_type: operation_error,
+        });
+    }
+
+    let mut violations = Vec::new();
+    if config.verify {
+        let mut client_lock = client.lock().await;
+        let mut keys: Vec<&String> = verify_expected.keys().collect();
+        keys.sort();
+        for key in keys {
+            let expected = &verify_expected[key];
+            let actual = client_lock
+                .get(key)
+                .await
+                .map_err(|e| format!("Verify GET {} failed: {}", key, e))?;
+            if actual.as_ref() != Some(expected) {
+                violations.push(Violation {
+                    key: key.clone(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+            let _ = client_lock.delete(key).await; // Ignore errors
+        }
+    }
+
+    // Cleanup: delete test keys
+    if config.full_test {
+        let mut client_lock = client.lock().await;
+        for op_num in 0..config.operations_per_user {
+            let set_key = format!("test_key_{}_{}", task_id, op_num);
+            let ttl_key = format!("ttl_key_{}_{}", task_id, op_num);
+            let _ = client_lock.delete(&set_key).await; // Ignore errors
+            let _ = client_lock.delete(&ttl_key).await; // Ignore errors
+            let _ = client_lock
+                .delete(&format!("mset_a_{}_{}", task_id, op_num))
+                .await;
+            let _ = client_lock
+                .delete(&format!("mset_b_{}_{}", task_id, op_num))
+                .await;
+        }
+        let counter_key = format!("counter_{}", task_id);
+        let _ = client_lock.delete(&counter_key).await; // Ignore errors
+    }
+
+    Ok(TestRun {
+        results: local_results,
+        violations,
+    })
+}
+
+// Test mode and first key of an operation, worked out only for slow operations
+fn describe_operation(
+    config: &TestConfig,
+    task_id: usize,
+    op_num: usize,
+) -> (&'static str, String) {
+    if config.full_test {
+        ("full", format!("test_key_{}_{}", task_id, op_num))
+    } else if config.verify {
+        (
+            "verify",
+            format!("verify:{}:{}", task_id, op_num % VERIFY_SLOTS),
+        )
+    } else {
+        ("batch", "loginlock-ip-123".to_string())
+    }
+}
+
+/// Per-server totals for the report when several servers are targeted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerSummary {
+    pub operations: usize,
+    pub successful: usize,
+    pub total_latency: Duration,
+}
+
+impl ServerSummary {
+    pub fn average_latency(&self) -> Duration {
+        if self.operations == 0 {
+            Duration::ZERO
+        } else {
+            self.total_latency / self.operations as u32
+        }
+    }
+}
+
+pub fn summarize_by_server(results: &[TestResult], servers: usize) -> Vec<ServerSummary> {
+    let mut summaries = vec![ServerSummary::default(); servers];
+    for result in results {
+        if let Some(summary) = summaries.get_mut(result.server) {
+            summary.operations += 1;
+            summary.total_latency += result.duration;
+            if result.success {
+                summary.successful += 1;
+            }
+        }
+    }
+    summaries
+}
+
+/// Benchmark for SET latency while LIST runs over a large store: SETs are timed on
+/// an idle server, then again while another connection issues LIST back to back.
+#[derive(Clone, Debug)]
+pub struct ListContentionConfig {
+    pub server_addr: String,
+    /// Keys written before measuring, and deleted again at the end.
+    pub keys: usize,
+    /// SETs timed on the idle server.
+    pub idle_sets: usize,
+    /// The contended phase keeps timing SETs until this many LISTs have completed.
+    pub lists: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ListContentionRun {
+    pub idle: Vec<Duration>,
+    pub during_list: Vec<Duration>,
+    /// Average time for one LIST of the whole store.
+    pub list_duration: Duration,
+}
+
+/// Connections used to write and delete the benchmark keys.
+const LIST_SEED_CONNECTIONS: usize = 8;
+
+pub async fn run_list_contention(
+    config: &ListContentionConfig,
+) -> Result<ListContentionRun, String> {
+    for_each_list_key(config, "SET", Some("listbench-value")).await?;
+    let mut client = KvClient::connect(&config.server_addr)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut run = ListContentionRun::default();
+    for i in 0..config.idle_sets {
+        run.idle.push(timed_set(&mut client, i).await?);
+    }
+
+    let completed = Arc::new(AtomicUsize::new(0));
+    let lister = {
+        let completed = Arc::clone(&completed);
+        let addr = config.server_addr.clone();
+        let lists = config.lists;
+        tokio::spawn(async move {
+            let mut client = KvClient::connect(&addr).await.map_err(|e| e.to_string())?;
+            let start = Instant::now();
+            for _ in 0..lists {
+                client.list().await.map_err(|e| e.to_string())?;
+                completed.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok::<Duration, String>(start.elapsed())
+        })
+    };
+    let mut i = 0;
+    while completed.load(Ordering::Relaxed) < config.lists && !lister.is_finished() {
+        run.during_list.push(timed_set(&mut client, i).await?);
+        i += 1;
+    }
+    let elapsed = lister.await.map_err(|e| e.to_string())??;
+    run.list_duration = elapsed / config.lists.max(1) as u32;
+
+    for_each_list_key(config, "DEL", None).await?;
+    let _ = client.delete("listbench:probe").await; // Ignore errors
+    Ok(run)
+}
+
+async fn timed_set(client: &mut KvClient, i: usize) -> Result<Duration, String> {
+    let start = Instant::now();
+    client
+        .set("listbench:probe", &i.to_string())
+        .await
+        .map_err(|e| format!("SET failed: {}", e))?;
+    Ok(start.elapsed())
+}
+
+// Sends `verb listbench:N [value]` for every benchmark key, in batches spread across
+// several connections
+async fn for_each_list_key(
+    config: &ListContentionConfig,
+    verb: &'static str,
+    value: Option<&'static str>,
+) -> Result<(), String> {
+    let mut handles = vec![];
+    for conn in 0..LIST_SEED_CONNECTIONS {
+        let addr = config.server_addr.clone();
+        let keys = config.keys;
+        handles.push(tokio::spawn(async move {
+            let mut client = KvClient::connect(&addr).await.map_err(|e| e.to_string())?;
+            let commands: Vec<String> = (conn..keys)
+                .step_by(LIST_SEED_CONNECTIONS)
+                .map(|n| match value {
+                    Some(value) => format!("{} listbench:{} {}", verb, n, value),
+                    None => format!("{} listbench:{}", verb, n),
+                })
+                .collect();
+            for batch in commands.chunks(crate::kv_protocol::MAX_BATCH_COMMANDS) {
+                let batch: Vec<&str> = batch.iter().map(|c| c.as_str()).collect();
+                client
+                    .batch(&batch)
+                    .await
+                    .map_err(|e| format!("{} failed: {}", verb, e))?;
+            }
+            Ok::<(), String>(())
+        }));
+    }
+    for handle in handles {
+        handle.await.map_err(|e| format!("Join error: {}", e))??;
+    }
+    Ok(())
+}
+
+/// Lock scenario for SETNX: every round, `tasks` connections try to take the same lock
+/// key at once with a `ttl` expiration, and exactly one of them should get it. The
+/// next round starts once the lock has expired, so each round is one TTL window.
+#[derive(Clone, Debug)]
+pub struct LockContentionConfig {
+    pub server_addr: String,
+    pub tasks: usize,
+    pub rounds: usize,
+    /// Whole seconds, as SETNX expirations are.
+    pub ttl: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LockContentionRun {
+    /// Tasks that took the lock in each round; anything but 1 is a violation.
+    pub winners: Vec<usize>,
+    /// Every SETNX of every round that got an answer.
+    pub latencies: Vec<Duration>,
+    /// Why each failed attempt failed; a failed attempt counts as losing the round.
+    pub errors: Vec<String>,
+}
+
+impl LockContentionRun {
+    /// Rounds in which no task or more than one took the lock.
+    pub fn violations(&self) -> usize {
+        self.winners.iter().filter(|&&winners| winners != 1).count()
+    }
+}
+
+const LOCK_KEY: &str = "lockbench:lock";
+
+// Margin past the TTL before the next round, so the lock has certainly expired
+const LOCK_EXPIRY_MARGIN: Duration = Duration::from_millis(100);
+
+pub async fn run_lock_contention(
+    config: &LockContentionConfig,
+) -> Result<LockContentionRun, String> {
+    if config.tasks == 0 {
+        return Err("lock contention needs at least one task".to_string());
+    }
+    let mut client = KvClient::connect(&config.server_addr)
+        .await
+        .map_err(|e| e.to_string())?;
+    let _ = client.delete(LOCK_KEY).await; // Ignore errors
+    let ttl = format!("{}s", config.ttl.as_secs().max(1));
+
+    // Tasks line up before each round so their SETNXs arrive together
+    let barrier = Arc::new(tokio::sync::Barrier::new(config.tasks));
+    let mut handles = vec![];
+    for task in 0..config.tasks {
+        let barrier = Arc::clone(&barrier);
+        let addr = config.server_addr.clone();
+        let (rounds, ttl, wait) = (config.rounds, ttl.clone(), config.ttl + LOCK_EXPIRY_MARGIN);
+        // A task whose connection or SETNX fails still waits at every barrier;
+        // leaving early would hold the other tasks at the next one forever
+        handles.push(tokio::spawn(async move {
+            let mut client = KvClient::connect(&addr)
+                .await
+                .map_err(|e| format!("Connect failed: {}", e));
+            let mut attempts = Vec::with_capacity(rounds);
+            for round in 0..rounds {
+                barrier.wait().await;
+                let attempt = match &mut client {
+                    Ok(client) => {
+                        let start = Instant::now();
+                        client
+                            .setnx_with_ttl(LOCK_KEY, &format!("task-{}", task), &ttl)
+                            .await
+                            .map(|won| (won, start.elapsed()))
+                            .map_err(|e| format!("SETNX failed: {}", e))
+                    }
+                    Err(e) => Err(e.clone()),
+                };
+                attempts.push(attempt);
+                if round + 1 < rounds {
+                    tokio::time::sleep(wait).await;
+                }
+            }
+            attempts
+        }));
+    }
+
+    let mut run = LockContentionRun {
+        winners: vec![0; config.rounds],
+        latencies: Vec::new(),
+        errors: Vec::new(),
+    };
+    for handle in handles {
+        let attempts = handle.await.map_err(|e| format!("Join error: {}", e))?;
+        for (round, attempt) in attempts.into_iter().enumerate() {
+            match attempt {
+                Ok((won, duration)) => {
+                    run.winners[round] += usize::from(won);
+                    run.latencies.push(duration);
+                }
+                Err(e) => run.errors.push(e),
+            }
+        }
+    }
+    let _ = client.delete(LOCK_KEY).await; // Ignore errors
+    Ok(run)
+}
+
+/// Benchmark for the Logger's SLOG sending: records are logged back to back to a
+/// local sink that only counts lines, with or without batching.
+#[derive(Clone, Debug)]
+pub struct LogThroughputConfig {
+    pub records: usize,
+    /// SLOG_BATCH_MAX for the run; 1 sends each record on its own connection.
+    pub batch_max: usize,
+    pub batch_delay: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogThroughputRun {
+    /// Lines the sink received, each checked to be one whole record.
+    pub received: usize,
+    /// From the first record logged until the sink had them all.
+    pub elapsed: Duration,
+}
+
+impl LogThroughputRun {
+    pub fn records_per_sec(&self) -> f64 {
+        self.received as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+pub async fn run_log_throughput(config: &LogThroughputConfig) -> Result<LogThroughputRun, String> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| e.to_string())?;
+    let dest = listener.local_addr().map_err(|e| e.to_string())?;
+    let received = Arc::new(AtomicUsize::new(0));
+    let malformed = Arc::new(AtomicUsize::new(0));
+    let sink = {
+        let (received, malformed) = (Arc::clone(&received), Arc::clone(&malformed));
+        tokio::spawn(async move {
+            use tokio::io::AsyncBufReadExt;
+            while let Ok((socket, _)) = listener.accept().await {
+                let (received, malformed) = (Arc::clone(&received), Arc::clone(&malformed));
+                tokio::spawn(async move {
+                    let mut lines = tokio::io::BufReader::new(socket).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let counter = if is_whole_record(&line) {
+                            &received
+                        } else {
+                            &malformed
+                        };
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        })
+    };
+
+    let logger = Logger::new(
+        dest.to_string(),
+        "loadtest".to_string(),
+        LogLevel::Info,
+        false,
+        false,
+        true,
+    )
+    .with_batching(config.batch_max, config.batch_delay);
+    let start = Instant::now();
+    for i in 0..config.records {
+        logger
+            .info("LOADTEST", &format!("benchmark record {}", i))
+            .await;
+    }
+    logger.flush().await;
+    let all_received = async {
+        while received.load(Ordering::Relaxed) + malformed.load(Ordering::Relaxed) < config.records
+        {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    };
+    let result = timeout(Duration::from_secs(30), all_received).await;
+    let elapsed = start.elapsed();
+    sink.abort();
+    if malformed.load(Ordering::Relaxed) > 0 {
+        return Err(format!(
+            "{} records arrived split or merged",
+            malformed.load(Ordering::Relaxed)
+        ));
+    }
+    result.map_err(|_| {
+        format!(
+            "sink received {} of {} records",
+            received.load(Ordering::Relaxed),
+            config.records
+        )
+    })?;
+    Ok(LogThroughputRun {
+        received: received.load(Ordering::Relaxed),
+        elapsed,
+    })
+}
+
+// `LVL HOST(32) CODE(12) LEN(5): MSG` with LEN matching the message
+fn is_whole_record(line: &str) -> bool {
+    line.get(50..)
+        .and_then(|rest| rest.trim_start().split_once(": "))
+        .and_then(|(len, msg)| Some(len.parse::<usize>().ok()? == msg.len()))
+        .unwrap_or(false)
+}
+
+/// The latency at percentile `pct` (0-100) of `latencies`, or zero when empty.
+pub fn percentile(latencies: &[Duration], pct: f64) -> Duration {
+    let mut sorted = latencies.to_vec();
+    sorted.sort();
+    match sorted.len() {
+        0 => Duration::ZERO,
+        len => sorted[((len - 1) as f64 * pct / 100.0).round() as usize],
+    }
+}
+
+// Small xorshift for think-time jitter; quality doesn't matter, only spread
+struct JitterRng(u64);
+
+impl JitterRng {
+    fn new(task_id: usize) -> JitterRng {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() as u64)
+            .unwrap_or(0);
+        // Never zero, or xorshift gets stuck
+        JitterRng((nanos ^ ((task_id as u64 + 1) << 32)) | 1)
+    }
+
+    fn below(&mut self, max: Duration) -> Duration {
+        let max_micros = max.as_micros() as u64;
+        if max_micros == 0 {
+            return Duration::ZERO;
+        }
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        Duration::from_micros(self.0 % (max_micros + 1))
+    }
+}
@@ -0,0 +1,66 @@
+// Live progress counters for shrmpl-kv-loadtest. Every task records into the same
+// Progress through atomics, and a reporter task samples it once a second.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[derive(Debug, Default)]
+pub struct Progress {
+    completed: AtomicU64,
+    succeeded: AtomicU64,
+}
+
+/// Point-in-time copy of the counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Snapshot {
+    pub completed: u64,
+    pub succeeded: u64,
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress::default()
+    }
+
+    /// Records one finished operation.
+    pub fn record(&self, success: bool) {
+        if success {
+            self.succeeded.fetch_add(1, Ordering::Relaxed);
+        }
+        // Bump completed last so a concurrent snapshot never sees more successes than ops
+        self.completed.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let completed = self.completed.load(Ordering::Acquire);
+        let succeeded = self.succeeded.load(Ordering::Relaxed);
+        Snapshot {
+            completed,
+            succeeded: succeeded.min(completed),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn success_rate(&self) -> f64 {
+        if self.completed == 0 {
+            100.0
+        } else {
+            (self.succeeded as f64 / self.completed as f64) * 100.0
+        }
+    }
+
+    /// Formats a progress line; the rate is the ops completed since `previous`
+    /// spread over `interval`.
+    pub fn progress_line(&self, previous: &Snapshot, interval: Duration) -> String {
+        let delta = self.completed.saturating_sub(previous.completed);
+        let secs = interval.as_secs_f64();
+        let rps = if secs > 0.0 { delta as f64 / secs } else { 0.0 };
+        format!(
+            "Progress: {} ops, {:.1}% success, ~{:.0} ops/s",
+            self.completed,
+            self.success_rate(),
+            rps
+        )
+    }
+}
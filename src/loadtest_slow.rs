@@ -0,0 +1,121 @@
+// Slow-operation log for shrmpl-kv-loadtest. Tasks hand each operation over the
+// threshold to a channel without formatting or I/O of their own; a single writer task
+// prints it straight away and keeps it for the slowest-operations report.
+
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// One operation that took at least the slow threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowOp {
+    /// Wall-clock time the operation started.
+    pub started: DateTime<Utc>,
+    pub task_id: usize,
+    /// `full`, `verify` or `batch`, after the test mode that ran it.
+    pub op_type: &'static str,
+    pub key: String,
+    pub duration: Duration,
+    pub error: Option<String>,
+}
+
+impl fmt::Display for SlowOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} task={} op={} key={} duration={:.1}ms",
+            self.started.format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            self.task_id,
+            self.op_type,
+            self.key,
+            self.duration.as_secs_f64() * 1000.0
+        )?;
+        if let Some(error) = &self.error {
+            write!(f, " error={}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Handle the tasks record into; clones share the writer.
+#[derive(Clone, Debug)]
+pub struct SlowLog {
+    threshold: Duration,
+    tx: mpsc::UnboundedSender<SlowOp>,
+}
+
+impl SlowLog {
+    /// Starts the writer, printing each slow operation as a line to `out`. The handle
+    /// resolves to every recorded operation once all `SlowLog` clones are dropped.
+    pub fn start<W>(threshold: Duration, out: W) -> (SlowLog, JoinHandle<Vec<SlowOp>>)
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let writer = tokio::spawn(write_slow_ops(rx, out));
+        (SlowLog { threshold, tx }, writer)
+    }
+
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    /// Whether an operation taking `duration` should be recorded.
+    pub fn is_slow(&self, duration: Duration) -> bool {
+        duration >= self.threshold
+    }
+
+    pub fn record(&self, op: SlowOp) {
+        let _ = self.tx.send(op); // The writer only stops once every sender is gone
+    }
+}
+
+// Write errors end the printing but not the collection, so the report stays complete
+async fn write_slow_ops<W: AsyncWrite + Unpin>(
+    mut rx: mpsc::UnboundedReceiver<SlowOp>,
+    mut out: W,
+) -> Vec<SlowOp> {
+    let mut ops = Vec::new();
+    let mut writable = true;
+    while let Some(op) = rx.recv().await {
+        if writable {
+            writable = out
+                .write_all(format!("SLOW {}\n", op).as_bytes())
+                .await
+                .is_ok();
+            // Flush once the queue is drained so each line shows up promptly
+            if writable && rx.is_empty() {
+                writable = out.flush().await.is_ok();
+            }
+        }
+        ops.push(op);
+    }
+    let _ = out.flush().await;
+    ops
+}
+
+/// The `n` slowest of `ops`, slowest first.
+pub fn slowest(ops: &[SlowOp], n: usize) -> Vec<SlowOp> {
+    let mut sorted = ops.to_vec();
+    sorted.sort_by_key(|op| std::cmp::Reverse(op.duration));
+    sorted.truncate(n);
+    sorted
+}
+
+/// Parses a threshold like `250ms`, `2s` or a bare number of milliseconds.
+pub fn parse_threshold(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    if let Some(ms) = text.strip_suffix("ms") {
+        ms.parse().ok().map(Duration::from_millis)
+    } else if let Some(secs) = text.strip_suffix('s') {
+        secs.parse::<f64>()
+            .ok()
+            .filter(|s| s.is_finite() && *s >= 0.0)
+            .map(Duration::from_secs_f64)
+    } else {
+        text.parse().ok().map(Duration::from_millis)
+    }
+}
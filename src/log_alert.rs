@@ -0,0 +1,341 @@
+// Error-rate alerts for shrmpl-log-srv. ERRO records are counted per host over a
+// sliding minute; a host reaching its threshold gets an ALRT record in the activity
+// stream, and an optional webhook POST, then an ALRTCLR record once its rate drops
+// back. A host alerts at most once per cooldown, however often it crosses.
+
+use crate::config;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Uri};
+use hyper_rustls::HttpsConnector;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+// Latest messages kept per host for the alert, each cut to SAMPLE_BYTES
+const SAMPLES: usize = 3;
+const SAMPLE_BYTES: usize = 200;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Config keys read by `AlertSettings::from_config`.
+pub const ERRO_PER_MIN_KEY: config::ConfigKey = config::ConfigKey {
+    name: "ALERT_ERRO_PER_MIN",
+    default: Some("0"),
+    description: "ERRO records per host per minute that raise an ALRT record; 0 disables alerts",
+};
+
+pub const HOST_THRESHOLDS_KEY: config::ConfigKey = config::ConfigKey {
+    name: "ALERT_ERRO_PER_MIN_HOSTS",
+    default: Some(""),
+    description: "Per-host overrides of ALERT_ERRO_PER_MIN as host:N pairs; 0 silences a host",
+};
+
+pub const COOLDOWN_KEY: config::ConfigKey = config::ConfigKey {
+    name: "ALERT_COOLDOWN_SECS",
+    default: Some("600"),
+    description: "Minimum seconds between two alerts for the same host",
+};
+
+pub const WEBHOOK_KEY: config::ConfigKey = config::ConfigKey {
+    name: "ALERT_WEBHOOK_URL",
+    default: Some(""),
+    description: "http(s) URL each alert is also POSTed to as JSON; empty disables it",
+};
+
+#[derive(Clone, Debug)]
+pub struct AlertSettings {
+    /// Threshold for hosts without an override; 0 for none.
+    pub erro_per_min: u32,
+    pub host_thresholds: Vec<(String, u32)>,
+    pub cooldown: Duration,
+    pub webhook_url: String,
+}
+
+impl AlertSettings {
+    pub fn from_config(config: &HashMap<String, String>) -> AlertSettings {
+        let get = |key: &config::ConfigKey| {
+            config
+                .get(key.name)
+                .map(String::as_str)
+                .or(key.default)
+                .unwrap_or_default()
+        };
+        AlertSettings {
+            erro_per_min: get(&ERRO_PER_MIN_KEY).parse().unwrap_or(0),
+            host_thresholds: parse_host_thresholds(get(&HOST_THRESHOLDS_KEY)),
+            cooldown: Duration::from_secs(get(&COOLDOWN_KEY).parse().unwrap_or(600)),
+            webhook_url: get(&WEBHOOK_KEY).to_string(),
+        }
+    }
+
+    /// Whether any host can raise an alert.
+    pub fn enabled(&self) -> bool {
+        self.erro_per_min > 0 || self.host_thresholds.iter().any(|(_, n)| *n > 0)
+    }
+
+    /// Threshold for `host`; 0 means it never alerts.
+    pub fn threshold(&self, host: &str) -> u32 {
+        self.host_thresholds
+            .iter()
+            .find(|(name, _)| name == host)
+            .map_or(self.erro_per_min, |(_, n)| *n)
+    }
+}
+
+// Entries that aren't `host:N` are ignored
+fn parse_host_thresholds(spec: &str) -> Vec<(String, u32)> {
+    spec.split(',')
+        .filter_map(|entry| entry.trim().rsplit_once(':'))
+        .filter(|(host, _)| !host.is_empty())
+        .filter_map(|(host, n)| Some((host.to_string(), n.parse().ok()?)))
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AlertEvent {
+    /// `host` reached `threshold` ERRO records within the last minute.
+    Alert {
+        host: String,
+        erro_per_min: usize,
+        threshold: u32,
+        /// The host's latest ERRO messages, oldest first.
+        samples: Vec<String>,
+    },
+    /// `host`'s rate dropped back below `threshold` after an alert.
+    Clear {
+        host: String,
+        erro_per_min: usize,
+        threshold: u32,
+    },
+}
+
+impl AlertEvent {
+    /// Code of the activity record written for the event.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AlertEvent::Alert { .. } => "ALRT",
+            AlertEvent::Clear { .. } => "ALRTCLR",
+        }
+    }
+
+    /// Message of the activity record written for the event.
+    pub fn message(&self) -> String {
+        match self {
+            AlertEvent::Alert {
+                host,
+                erro_per_min,
+                threshold,
+                samples,
+            } => format!(
+                "host={} erro_per_min={} threshold={} latest: {}",
+                host,
+                erro_per_min,
+                threshold,
+                samples.join(" | ")
+            ),
+            AlertEvent::Clear {
+                host,
+                erro_per_min,
+                threshold,
+            } => format!(
+                "host={} erro_per_min={} threshold={} cleared",
+                host, erro_per_min, threshold
+            ),
+        }
+    }
+
+    /// Webhook payload: `{"event", "host", "erro_per_min", "threshold", "samples"}`.
+    pub fn to_json(&self) -> String {
+        let value = match self {
+            AlertEvent::Alert {
+                host,
+                erro_per_min,
+                threshold,
+                samples,
+            } => serde_json::json!({
+                "event": "alert",
+                "host": host,
+                "erro_per_min": erro_per_min,
+                "threshold": threshold,
+                "samples": samples,
+            }),
+            AlertEvent::Clear {
+                host,
+                erro_per_min,
+                threshold,
+            } => serde_json::json!({
+                "event": "clear",
+                "host": host,
+                "erro_per_min": erro_per_min,
+                "threshold": threshold,
+                "samples": [],
+            }),
+        };
+        value.to_string()
+    }
+}
+
+#[derive(Default)]
+struct HostRate {
+    errors: VecDeque<Instant>,
+    samples: VecDeque<String>,
+    alerting: bool,
+    last_alert: Option<Instant>,
+}
+
+impl HostRate {
+    fn trim(&mut self, now: Instant) {
+        while self
+            .errors
+            .front()
+            .is_some_and(|&at| now.duration_since(at) >= WINDOW)
+        {
+            self.errors.pop_front();
+        }
+    }
+}
+
+/// Per-host ERRO counts. Time is passed in so callers (and tests) control the clock.
+pub struct ErrorRates {
+    settings: AlertSettings,
+    hosts: HashMap<String, HostRate>,
+}
+
+impl ErrorRates {
+    pub fn new(settings: AlertSettings) -> ErrorRates {
+        ErrorRates {
+            settings,
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// Counts an ERRO record from `host`, returning an alert when it takes the host to
+    /// its threshold outside the cooldown.
+    pub fn record(&mut self, host: &str, message: &str, now: Instant) -> Option<AlertEvent> {
+        let threshold = self.settings.threshold(host);
+        if threshold == 0 {
+            return None;
+        }
+        let rate = self.hosts.entry(host.to_string()).or_default();
+        rate.trim(now);
+        rate.errors.push_back(now);
+        if rate.samples.len() == SAMPLES {
+            rate.samples.pop_front();
+        }
+        rate.samples.push_back(sample(message));
+
+        let cooled_down = rate
+            .last_alert
+            .is_none_or(|at| now.duration_since(at) >= self.settings.cooldown);
+        if rate.alerting || rate.errors.len() < threshold as usize || !cooled_down {
+            return None;
+        }
+        rate.alerting = true;
+        rate.last_alert = Some(now);
+        Some(AlertEvent::Alert {
+            host: host.to_string(),
+            erro_per_min: rate.errors.len(),
+            threshold,
+            samples: rate.samples.iter().cloned().collect(),
+        })
+    }
+
+    /// Clears for alerting hosts whose rate has dropped below the threshold. Hosts
+    /// with nothing left to track are forgotten.
+    pub fn sweep(&mut self, now: Instant) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+        for (host, rate) in self.hosts.iter_mut() {
+            rate.trim(now);
+            let threshold = self.settings.threshold(host);
+            if rate.alerting && rate.errors.len() < threshold as usize {
+                rate.alerting = false;
+                events.push(AlertEvent::Clear {
+                    host: host.clone(),
+                    erro_per_min: rate.errors.len(),
+                    threshold,
+                });
+            }
+        }
+        let cooldown = self.settings.cooldown;
+        self.hosts.retain(|_, rate| {
+            !rate.errors.is_empty()
+                || rate.alerting
+                || rate
+                    .last_alert
+                    .is_some_and(|at| now.duration_since(at) < cooldown)
+        });
+        events
+    }
+
+    /// Number of hosts currently tracked.
+    pub fn tracked(&self) -> usize {
+        self.hosts.len()
+    }
+}
+
+fn sample(message: &str) -> String {
+    let mut end = message.len().min(SAMPLE_BYTES);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    message[..end].to_string()
+}
+
+/// Posts alert payloads to ALERT_WEBHOOK_URL.
+#[derive(Clone)]
+pub struct Webhook {
+    uri: Uri,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl Webhook {
+    /// HTTPS URLs are verified against the system's root certificates.
+    pub fn new(url: &str) -> Result<Webhook, String> {
+        let uri: Uri = url.parse().map_err(|e| format!("{}: {}", url, e))?;
+        if !matches!(uri.scheme_str(), Some("http" | "https")) {
+            return Err(format!("{}: not an http(s) URL", url));
+        }
+        let mut roots = rustls::RootCertStore::empty();
+        if uri.scheme_str() == Some("https") {
+            let certs = rustls_native_certs::load_native_certs()
+                .map_err(|e| format!("cannot load root certificates: {}", e))?;
+            for cert in certs {
+                let _ = roots.add(&rustls::Certificate(cert.0));
+            }
+            if roots.is_empty() {
+                return Err("no root certificates found for https".to_string());
+            }
+        }
+        let tls = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(tls)
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Webhook {
+            uri,
+            client: Client::builder().build(connector),
+        })
+    }
+
+    /// POSTs the event's JSON, failing on errors, timeouts and non-2xx replies.
+    pub async fn post(&self, event: &AlertEvent) -> Result<(), String> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.uri.clone())
+            .header("Content-Type", "application/json")
+            .body(Body::from(event.to_json()))
+            .map_err(|e| e.to_string())?;
+        let response = tokio::time::timeout(WEBHOOK_TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| "timed out".to_string())?
+            .map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("status {}", response.status()))
+        }
+    }
+}
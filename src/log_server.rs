@@ -0,0 +1,799 @@
+// shrmpl-log server core: the accept loop, line parsing and the per-file writer
+// threads. The shrmpl-log-srv binary loads config and forwards shutdown signals;
+// tests run the same code in-process against an ephemeral port.
+
+use crate::accept_backoff::{self, AcceptBackoff};
+use crate::config;
+use crate::log_alert::{self, AlertEvent, AlertSettings, ErrorRates, Webhook};
+use crate::sd_notify::{self, Notifier};
+use crate::shrmpl_log_client;
+use chrono::{DateTime, SecondsFormat, Utc};
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Clone)]
+struct Record {
+    lvl: [u8; 4],
+    host: [u8; 32],
+    code: [u8; 12],
+    len: u16,
+    msg: Vec<u8>,
+    recv_ts: [u8; 24],
+}
+
+impl Record {
+    /// A record stamped with the current time as its receive timestamp.
+    fn new(lvl: [u8; 4], host: [u8; 32], code: [u8; 12], msg: Vec<u8>) -> Record {
+        let mut recv_ts = [0u8; 24];
+        let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        recv_ts.copy_from_slice(&now.as_bytes()[..24]);
+        Record {
+            lvl,
+            host,
+            code,
+            len: msg.len() as u16,
+            msg,
+            recv_ts,
+        }
+    }
+
+    /// Bytes the record holds while queued, counted against QUEUE_MAX_BYTES.
+    fn queued_bytes(&self) -> u64 {
+        (std::mem::size_of::<Record>() + self.msg.len()) as u64
+    }
+}
+
+// Left-aligned, space-padded fixed-width field, cut at a char boundary if too long
+fn field<const N: usize>(text: &str) -> [u8; N] {
+    let mut end = text.len().min(N);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut out = [b' '; N];
+    out[..end].copy_from_slice(&text.as_bytes()[..end]);
+    out
+}
+
+// Startup settings read from the config file
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub data_dir: String,
+    pub bind_addr: String,
+    pub dev_mode: bool,
+    pub queue_capacity: usize,
+    /// Cap on the bytes held by all queues together; 0 leaves only `queue_capacity`.
+    pub queue_max_bytes: u64,
+    /// Host field of the records the server writes about itself, such as LOGSTATS.
+    pub server_name: String,
+    /// Levels written to their own daily file, as (LVL, file prefix). Every other
+    /// level goes to `misc`.
+    pub level_files: Vec<([u8; 4], String)>,
+    pub accept_backoff: AcceptBackoff,
+    /// ERRO-rate alerting; off unless a threshold is configured.
+    pub alerts: AlertSettings,
+    /// systemd notifications; from_config leaves them off and the binary reads them from the environment.
+    pub notify: Notifier,
+}
+
+impl Settings {
+    pub fn from_config(config: &HashMap<String, String>) -> Settings {
+        Settings {
+            data_dir: config.get("DATA_DIR").cloned().unwrap_or_default(),
+            bind_addr: config.get("BIND_ADDR").cloned().unwrap_or_default(),
+            dev_mode: config
+                .get("DEV_MODE")
+                .map(|s| s.parse().unwrap_or(false))
+                .unwrap_or(false),
+            queue_capacity: config
+                .get("QUEUE_CAPACITY")
+                .map(|s| s.parse().unwrap_or(10000))
+                .unwrap_or(10000),
+            queue_max_bytes: config
+                .get("QUEUE_MAX_BYTES")
+                .map(|s| s.parse().unwrap_or(0))
+                .unwrap_or(0),
+            server_name: shrmpl_log_client::resolve_host(
+                config.get("SERVER_NAME").map(String::as_str),
+                "server.local",
+            ),
+            level_files: parse_level_files(
+                config
+                    .get("LEVEL_FILES")
+                    .map(String::as_str)
+                    .unwrap_or(DEFAULT_LEVEL_FILES),
+            ),
+            accept_backoff: AcceptBackoff::from_config(config),
+            alerts: AlertSettings::from_config(config),
+            notify: Notifier::disabled(),
+        }
+    }
+}
+
+const DEFAULT_LEVEL_FILES: &str = "ACTV:activity,ERRO:error,WARN:warn,DEBG:debug";
+const MISC_FILE: &str = "misc";
+
+// Entries that aren't `LVL:prefix` with a four-byte level are ignored
+fn parse_level_files(spec: &str) -> Vec<([u8; 4], String)> {
+    spec.split(',')
+        .filter_map(|entry| entry.trim().split_once(':'))
+        .filter(|(_, prefix)| !prefix.is_empty())
+        .filter_map(|(lvl, prefix)| Some((lvl.as_bytes().try_into().ok()?, prefix.to_string())))
+        .collect()
+}
+
+// The file prefixes, one queue and writer thread each, with misc always present.
+// Levels mapped to the same prefix share its writer.
+struct Layout {
+    files: Vec<String>,
+    routes: Vec<([u8; 4], usize)>,
+    misc: usize,
+}
+
+impl Layout {
+    fn new(level_files: &[([u8; 4], String)]) -> Layout {
+        let mut files: Vec<String> = Vec::new();
+        let mut index = |prefix: &str| match files.iter().position(|f| f == prefix) {
+            Some(i) => i,
+            None => {
+                files.push(prefix.to_string());
+                files.len() - 1
+            }
+        };
+        let routes = level_files
+            .iter()
+            .map(|(lvl, prefix)| (*lvl, index(prefix)))
+            .collect();
+        let misc = index(MISC_FILE);
+        Layout {
+            files,
+            routes,
+            misc,
+        }
+    }
+}
+
+// Hands each record to the queue of its level's file
+#[derive(Clone)]
+struct Router {
+    routes: Vec<([u8; 4], usize)>,
+    misc: usize,
+    senders: Vec<Sender<Record>>,
+    counters: Arc<Counters>,
+    max_bytes: u64,
+}
+
+impl Router {
+    /// False when the queue is full and the record was dropped.
+    fn send(&self, record: Record) -> bool {
+        let queue = self
+            .routes
+            .iter()
+            .find(|(lvl, _)| *lvl == record.lvl)
+            .map_or(self.misc, |(_, queue)| *queue);
+        self.send_to(queue, record)
+    }
+
+    // The bytes are reserved before the record is queued, so a writer taking it off
+    // straight away never sees the total go negative
+    fn send_to(&self, queue: usize, record: Record) -> bool {
+        let size = record.queued_bytes();
+        let queued = &self.counters.queued_bytes;
+        let total = queued.fetch_add(size, Ordering::Relaxed) + size;
+        if (self.max_bytes > 0 && total > self.max_bytes)
+            || self.senders[queue].try_send(record).is_err()
+        {
+            queued.fetch_sub(size, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+}
+
+struct Counters {
+    received: AtomicU64,
+    dropped: AtomicU64,
+    oversize: AtomicU64,
+    /// Bytes of the records waiting in the queues, by `Record::queued_bytes`.
+    queued_bytes: AtomicU64,
+    /// Records written per file, in `files` order.
+    written: Vec<AtomicU64>,
+    files: Vec<String>,
+    protocol_errors: AtomicU64,
+    /// Unix millis each writer thread last went round its loop.
+    writer_beats: Vec<AtomicU64>,
+    started: DateTime<Utc>,
+}
+
+// Idle writers still wake this often to record a heartbeat
+const WRITER_BEAT_INTERVAL: Duration = Duration::from_secs(1);
+// A writer silent for longer is wedged (e.g. blocked on a hung disk) or has panicked.
+// A slow fsync only skips a ping or two; systemd waits the full WatchdogSec.
+const WRITER_STALE_AFTER: Duration = Duration::from_secs(3);
+
+// A client line of just this asks for the stats on that connection instead of logging
+const STAT_QUERY: &str = "STAT";
+
+impl Counters {
+    fn new(files: &[String]) -> Counters {
+        Counters {
+            received: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            oversize: AtomicU64::new(0),
+            queued_bytes: AtomicU64::new(0),
+            written: files.iter().map(|_| AtomicU64::new(0)).collect(),
+            files: files.to_vec(),
+            protocol_errors: AtomicU64::new(0),
+            writer_beats: files.iter().map(|_| AtomicU64::new(now_millis())).collect(),
+            started: Utc::now(),
+        }
+    }
+
+    // `activity_written=N error_written=N ...` for every file
+    fn written_fields(&self, separator: &str) -> String {
+        self.files
+            .iter()
+            .zip(&self.written)
+            .map(|(file, written)| format!("{}_written={}", file, written.load(Ordering::Relaxed)))
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    // Shared by the periodic LOGSTATS record and STAT replies
+    fn stats_line(&self) -> String {
+        let uptime = Utc::now().signed_duration_since(self.started).num_seconds();
+        format!("recv={} dropped={} oversize={} {} protocol_errors={} queued_bytes={} uptime={:.2}h start_time={} uptime_seconds={}",
+            self.received.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+            self.oversize.load(Ordering::Relaxed),
+            self.written_fields(" "),
+            self.protocol_errors.load(Ordering::Relaxed),
+            self.queued_bytes.load(Ordering::Relaxed),
+            uptime as f64 / 3600.0,
+            self.started.to_rfc3339_opts(SecondsFormat::Millis, true),
+            uptime
+        )
+    }
+
+    fn writers_alive(&self) -> bool {
+        let oldest = now_millis().saturating_sub(WRITER_STALE_AFTER.as_millis() as u64);
+        self.writer_beats
+            .iter()
+            .all(|beat| beat.load(Ordering::Relaxed) >= oldest)
+    }
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+pub const CONFIG_SCHEMA: &[config::ConfigKey] = &[
+    config::ConfigKey {
+        name: "DATA_DIR",
+        default: None,
+        description: "Directory the daily log files are written to",
+    },
+    config::ConfigKey {
+        name: "BIND_ADDR",
+        default: None,
+        description: "Address and port to listen on (e.g. 0.0.0.0:7379)",
+    },
+    config::ConfigKey {
+        name: "DEV_MODE",
+        default: Some("false"),
+        description: "Enable development mode (true/false)",
+    },
+    config::ConfigKey {
+        name: "QUEUE_CAPACITY",
+        default: Some("10000"),
+        description: "Total records buffered, split evenly across the per-file queues",
+    },
+    config::ConfigKey {
+        name: "QUEUE_MAX_BYTES",
+        default: Some("0"),
+        description:
+            "Total bytes buffered across the queues before records are dropped; 0 = no cap",
+    },
+    config::ConfigKey {
+        name: "SERVER_NAME",
+        default: Some(shrmpl_log_client::AUTO_HOST),
+        description: "Host name on the server's own records (LOGSTATS); auto uses the OS hostname",
+    },
+    config::ConfigKey {
+        name: "LEVEL_FILES",
+        default: Some(DEFAULT_LEVEL_FILES),
+        description:
+            "Levels with their own daily file as LVL:prefix pairs; other levels go to misc",
+    },
+    accept_backoff::INITIAL_KEY,
+    accept_backoff::MAX_KEY,
+    log_alert::ERRO_PER_MIN_KEY,
+    log_alert::HOST_THRESHOLDS_KEY,
+    log_alert::COOLDOWN_KEY,
+    log_alert::WEBHOOK_KEY,
+];
+
+// ERRO records waiting for the alert task; beyond this they go uncounted
+const ALERT_QUEUE: usize = 1024;
+// How often alerting hosts are checked for a rate back under the threshold
+const ALERT_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+enum ParseError {
+    Invalid,
+    Oversize,
+}
+
+// Protocol parsing uses custom error types for precise error categorization
+// (Invalid vs Oversize) to enable different handling strategies in calling code
+fn parse_line(line: &[u8]) -> Result<Record, ParseError> {
+    if line.len() < 59 || line.last() != Some(&b'\n') {
+        return Err(ParseError::Invalid);
+    }
+    let lvl: [u8; 4] = line[0..4].try_into().map_err(|_| ParseError::Invalid)?;
+    let host: [u8; 32] = line[5..37].try_into().map_err(|_| ParseError::Invalid)?;
+    let code: [u8; 12] = line[38..50].try_into().map_err(|_| ParseError::Invalid)?;
+    let len_str = std::str::from_utf8(&line[51..56]).map_err(|_| ParseError::Invalid)?;
+    let len: u16 = len_str.parse().map_err(|_| ParseError::Invalid)?;
+    if len > 4096 {
+        return Err(ParseError::Oversize);
+    }
+    if line.len() != 58 + len as usize + 1 {
+        return Err(ParseError::Invalid);
+    }
+    let msg = line[58..58 + len as usize].to_vec();
+    Ok(Record::new(lvl, host, code, msg))
+}
+
+async fn handle_connection(
+    socket: TcpStream,
+    router: Router,
+    counters: Arc<Counters>,
+    alerts: Option<mpsc::Sender<(String, String)>>,
+    _dev_mode: bool,
+    mut keepalive_rx: broadcast::Receiver<String>,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) {
+    let mut reader = BufReader::new(socket);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        tokio::select! {
+            result = reader.read_line(&mut line) => {
+                match result {
+                    Ok(0) => return,
+                    Ok(_) => {
+                        if line.trim_end() == STAT_QUERY {
+                            let reply = format!("STAT {}\n", counters.stats_line());
+                            let _ = reader.get_mut().write_all(reply.as_bytes()).await;
+                            continue;
+                        }
+                        let line_bytes = line.as_bytes();
+                        match parse_line(line_bytes) {
+                            Ok(record) => {
+                                println!("Received message: lvl={}, host={}, code={}, msg={}", String::from_utf8_lossy(&record.lvl), String::from_utf8_lossy(&record.host), String::from_utf8_lossy(&record.code),String::from_utf8_lossy(&record.msg));
+                                counters.received.fetch_add(1, Ordering::Relaxed);
+                                if let (Some(alerts), b"ERRO") = (&alerts, &record.lvl) {
+                                    let host = String::from_utf8_lossy(&record.host).trim_end().to_string();
+                                    let _ = alerts.try_send((host, String::from_utf8_lossy(&record.msg).to_string()));
+                                }
+                                if !router.send(record) {
+                                    counters.dropped.fetch_add(1, Ordering::Relaxed);
+                                }
+                            }
+                            Err(ParseError::Invalid) => {
+                                println!("Protocol error: invalid log message format");
+                                counters.protocol_errors.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(ParseError::Oversize) => {
+                                println!("Protocol error: log message too large (>4096 bytes)");
+                                counters.oversize.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                    Err(_) => return,
+                }
+            }
+            msg = keepalive_rx.recv() => {
+                if let Ok(msg) = msg {
+                    let _ = reader.get_mut().write_all(msg.as_bytes()).await;
+                }
+            }
+            // Dropping the senders on return lets the writers drain and exit
+            _ = shutdown_rx.recv() => return,
+        }
+    }
+}
+
+// Counts ERRO records from the connections and writes ALRT/ALRTCLR records to the
+// activity stream, posting each event to the webhook too when one is configured
+async fn alert_loop(
+    mut errors: mpsc::Receiver<(String, String)>,
+    settings: AlertSettings,
+    router: Router,
+    own_host: [u8; 32],
+    mut shutdown_rx: broadcast::Receiver<()>,
+) {
+    let webhook = match settings.webhook_url.as_str() {
+        "" => None,
+        url => match Webhook::new(url) {
+            Ok(webhook) => Some(webhook),
+            Err(e) => {
+                eprintln!("Alert webhook disabled: {}", e);
+                None
+            }
+        },
+    };
+    let mut rates = ErrorRates::new(settings);
+    let mut sweep = interval(ALERT_SWEEP_INTERVAL);
+    loop {
+        let events = tokio::select! {
+            error = errors.recv() => match error {
+                Some((host, msg)) => rates.record(&host, &msg, std::time::Instant::now()).into_iter().collect(),
+                None => break,
+            },
+            _ = sweep.tick() => rates.sweep(std::time::Instant::now()),
+            _ = shutdown_rx.recv() => break,
+        };
+        for event in events {
+            emit_alert(&event, &router, own_host);
+            if let Some(webhook) = webhook.clone() {
+                tokio::spawn(async move {
+                    if let Err(e) = webhook.post(&event).await {
+                        eprintln!("Alert webhook failed for {}: {}", event.code(), e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+fn emit_alert(event: &AlertEvent, router: &Router, own_host: [u8; 32]) {
+    let msg = event.message();
+    println!("{}: {}", event.code(), msg);
+    let mut msg = msg.into_bytes();
+    msg.truncate(4096);
+    let record = Record::new(*b"ACTV", own_host, field(event.code()), msg);
+    let _ = router.send(record);
+}
+
+fn start_writers(
+    receivers: Vec<Receiver<Record>>,
+    data_dir: String,
+    counters: Arc<Counters>,
+) -> Vec<std::thread::JoinHandle<()>> {
+    receivers
+        .into_iter()
+        .enumerate()
+        .map(|(i, rx)| {
+            let data_dir = data_dir.clone();
+            let counters = counters.clone();
+            std::thread::spawn(move || {
+                writer_loop(
+                    rx,
+                    &counters.files[i],
+                    &data_dir,
+                    &counters.written[i],
+                    &counters.writer_beats[i],
+                    &counters.queued_bytes,
+                )
+            })
+        })
+        .collect()
+}
+
+// Runs until every sender is dropped, then flushes whatever is still buffered.
+// `beat` is refreshed on every pass so the systemd watchdog can spot a stuck writer.
+fn writer_loop(
+    rx: Receiver<Record>,
+    file_prefix: &str,
+    data_dir: &str,
+    counter: &AtomicU64,
+    beat: &AtomicU64,
+    queued_bytes: &AtomicU64,
+) {
+    let mut current_date = String::new();
+    let mut writer: Option<BufWriter<fs::File>> = None;
+    let mut last_flush = std::time::Instant::now();
+    loop {
+        beat.store(now_millis(), Ordering::Relaxed);
+        let record = match rx.recv_timeout(WRITER_BEAT_INTERVAL) {
+            Ok(record) => record,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        queued_bytes.fetch_sub(record.queued_bytes(), Ordering::Relaxed);
+        let date = std::str::from_utf8(&record.recv_ts[..10])
+            .unwrap()
+            .replace("-", "");
+        if date != current_date {
+            writer = Some(open_file(data_dir, file_prefix, &date));
+            current_date = date.clone();
+        }
+        if let Some(ref mut w) = writer {
+            // High-frequency log writing uses unwrap() for performance:
+            // - These operations should never fail in normal operation
+            // - If they do fail, it indicates serious disk/system issues
+            // - Panicking is appropriate since the log writer cannot recover
+            w.write_all(&record.recv_ts).unwrap();
+            w.write_all(b" ").unwrap();
+            w.write_all(&record.lvl).unwrap();
+            w.write_all(b" ").unwrap();
+            w.write_all(&record.host).unwrap();
+            w.write_all(b" ").unwrap();
+            w.write_all(&record.code).unwrap();
+            w.write_all(b" ").unwrap();
+            write!(w, "{:04}", record.len).unwrap();
+            w.write_all(b": ").unwrap();
+            w.write_all(&record.msg).unwrap();
+            w.write_all(b"\n").unwrap();
+            counter.fetch_add(1, Ordering::Relaxed);
+            if last_flush.elapsed() > Duration::from_secs(2) {
+                // Flush operations use unwrap() - failure to flush indicates
+                // serious disk issues that should cause the writer thread to panic
+                w.flush().unwrap();
+                w.get_ref().sync_data().unwrap();
+                last_flush = std::time::Instant::now();
+            }
+        }
+    }
+    if let Some(mut w) = writer {
+        w.flush().unwrap();
+        w.get_ref().sync_data().unwrap();
+    }
+}
+
+fn open_file(data_dir: &str, prefix: &str, date: &str) -> BufWriter<fs::File> {
+    let path = format!("{}/{}-{}.log", data_dir, prefix, date);
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .unwrap();
+    BufWriter::new(file)
+}
+
+async fn signal_handler(counters: Arc<Counters>) {
+    let mut sigusr1 =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1()).unwrap();
+    loop {
+        sigusr1.recv().await;
+        println!(
+            "Counters: received={}, dropped={}, oversize={}, {}, protocol_errors={}, queued_bytes={}",
+            counters.received.load(Ordering::Relaxed),
+            counters.dropped.load(Ordering::Relaxed),
+            counters.oversize.load(Ordering::Relaxed),
+            counters.written_fields(", "),
+            counters.protocol_errors.load(Ordering::Relaxed),
+            counters.queued_bytes.load(Ordering::Relaxed),
+        );
+    }
+}
+
+/// Binds `settings.bind_addr` and writes received records until a value arrives on
+/// `shutdown_rx`. Returns the bound address and a handle that finishes once every
+/// queued record has been written and flushed.
+pub async fn run(
+    settings: Settings,
+    shutdown_rx: broadcast::Receiver<()>,
+) -> std::io::Result<(SocketAddr, JoinHandle<()>)> {
+    fs::create_dir_all(&settings.data_dir)?;
+
+    let layout = Layout::new(&settings.level_files);
+    let counters = Arc::new(Counters::new(&layout.files));
+    let (senders, receivers): (Vec<_>, Vec<_>) = layout
+        .files
+        .iter()
+        .map(|_| bounded((settings.queue_capacity / layout.files.len()).max(1)))
+        .unzip();
+    let router = Router {
+        routes: layout.routes,
+        misc: layout.misc,
+        senders,
+        counters: counters.clone(),
+        max_bytes: settings.queue_max_bytes,
+    };
+    let (keepalive_tx, _) = broadcast::channel::<String>(10);
+
+    let listener = TcpListener::bind(&settings.bind_addr).await?;
+    let local_addr = listener.local_addr()?;
+    println!(
+        "shrmpl-log server version {} Listening on {}",
+        VERSION, local_addr
+    );
+
+    let writers = start_writers(receivers, settings.data_dir.clone(), counters.clone());
+
+    let signals = tokio::spawn(signal_handler(counters.clone()));
+
+    let own_host: [u8; 32] = field(&settings.server_name);
+    let alerts_tx = if settings.alerts.enabled() {
+        let (tx, rx) = mpsc::channel(ALERT_QUEUE);
+        tokio::spawn(alert_loop(
+            rx,
+            settings.alerts.clone(),
+            router.clone(),
+            own_host,
+            shutdown_rx.resubscribe(),
+        ));
+        Some(tx)
+    } else {
+        None
+    };
+
+    let counters_clone = counters.clone();
+    let stats_router = router.clone();
+    let keepalive_tx_clone = keepalive_tx.clone();
+    let mut stats_shutdown_rx = shutdown_rx.resubscribe();
+    let stats_notify = settings.notify.clone();
+    tokio::spawn(async move {
+        let mut interval = interval(Duration::from_secs(60));
+        let mut watchdog = stats_notify.watchdog_timer();
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                // Withholding the ping lets systemd restart a server whose writers are stuck
+                _ = sd_notify::next_watchdog(&mut watchdog) => {
+                    if counters_clone.writers_alive() {
+                        stats_notify.watchdog();
+                    }
+                    continue;
+                }
+                _ = stats_shutdown_rx.recv() => break,
+            }
+            let unix_millis = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis();
+            let msg = format!("UPONG {}\n", unix_millis);
+            let _ = keepalive_tx_clone.send(msg);
+
+            let stats_msg = counters_clone.stats_line();
+            println!("Stats: {}", stats_msg);
+            let record = Record::new(
+                *b"INFO",
+                own_host,
+                field("LOGSTATS"),
+                stats_msg.into_bytes(),
+            );
+            let _ = stats_router.send_to(stats_router.misc, record);
+        }
+    });
+
+    settings.notify.ready();
+
+    let handle = tokio::spawn(async move {
+        let mut shutdown_rx = shutdown_rx;
+        let mut backoff = settings.accept_backoff.clone();
+        loop {
+            tokio::select! {
+                accept_result = listener.accept() => {
+                    let socket = match accept_result {
+                        Ok((socket, _)) => {
+                            backoff.reset();
+                            socket
+                        }
+                        // Falls through to the writer drain below, so queued records still land
+                        Err(e) if accept_backoff::is_fatal(&e) => {
+                            eprintln!("Listener failed, stopping shrmpl-log server: {}", e);
+                            break;
+                        }
+                        Err(e) => {
+                            let delay = backoff.next_delay();
+                            eprintln!("Accept failed: {}; retrying in {}ms", e, delay.as_millis());
+                            tokio::time::sleep(delay).await;
+                            continue;
+                        }
+                    };
+                    let router = router.clone();
+                    let counters = counters.clone();
+                    let alerts_tx = alerts_tx.clone();
+                    let dev_mode = settings.dev_mode;
+                    let keepalive_rx = keepalive_tx.subscribe();
+                    let conn_shutdown_rx = shutdown_rx.resubscribe();
+                    tokio::spawn(async move {
+                        handle_connection(
+                            socket,
+                            router,
+                            counters,
+                            alerts_tx,
+                            dev_mode,
+                            keepalive_rx,
+                            conn_shutdown_rx,
+                        )
+                        .await;
+                    });
+                }
+                _ = shutdown_rx.recv() => {
+                    println!("shrmpl-log server shutting down, flushing writers");
+                    break;
+                }
+            }
+        }
+
+        // Stop accepting, then wait for the writers to drain the queues. They exit once
+        // the connection and stats tasks have dropped their senders too.
+        settings.notify.stopping();
+        drop(listener);
+        drop(router);
+        signals.abort();
+        let _ = tokio::task::spawn_blocking(move || {
+            for writer in writers {
+                let _ = writer.join();
+            }
+        })
+        .await;
+        println!("shrmpl-log server stopped");
+    });
+
+    Ok((local_addr, handle))
+}
+
+/// Starts the server on an ephemeral loopback port, writing into a scratch directory
+/// under DATA_DIR, sends it one record and reads the record back from disk, for
+/// `--selftest`. Alerts are off and the scratch directory is removed afterwards.
+pub async fn selftest(settings: &Settings) -> Result<String, String> {
+    let scratch = format!(
+        "{}/selftest-{}",
+        settings.data_dir.trim_end_matches('/'),
+        std::process::id()
+    );
+    let mut settings = settings.clone();
+    settings.data_dir = scratch.clone();
+    settings.bind_addr = "127.0.0.1:0".to_string();
+    settings.alerts.erro_per_min = 0;
+    settings.alerts.host_thresholds.clear();
+    settings.notify = Notifier::disabled();
+
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    let (addr, handle) = run(settings, shutdown_rx)
+        .await
+        .map_err(|e| format!("cannot start in {}: {}", scratch, e))?;
+    let message = format!("selftest record {}", now_millis());
+    let logger = shrmpl_log_client::Logger::new(
+        addr.to_string(),
+        "selftest".to_string(),
+        shrmpl_log_client::LogLevel::Info,
+        false,
+        true,
+        true,
+    );
+    logger.info("SELFTEST", &message).await;
+    // Give the connection task time to queue the record before the writers drain
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let _ = shutdown_tx.send(());
+    let _ = handle.await;
+
+    let written = read_dir_text(&scratch);
+    let _ = fs::remove_dir_all(&scratch);
+    let written = written.map_err(|e| format!("cannot read {}: {}", scratch, e))?;
+    if written.contains(&message) {
+        Ok(format!("record written and read back via {}", addr))
+    } else {
+        Err(format!(
+            "record sent to {} was not written to {}",
+            addr, scratch
+        ))
+    }
+}
+
+fn read_dir_text(dir: &str) -> std::io::Result<String> {
+    let mut text = String::new();
+    for entry in fs::read_dir(dir)? {
+        text.push_str(&String::from_utf8_lossy(&fs::read(entry?.path())?));
+    }
+    Ok(text)
+}
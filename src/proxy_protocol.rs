@@ -0,0 +1,131 @@
+// PROXY protocol (v1 text and v2 binary) for servers behind a TCP load balancer. With
+// PROXY_PROTOCOL=true every accepted connection must open with a header in which the
+// balancer states the client's address, and the server uses that address instead of
+// the balancer's. The header is read without consuming anything after it, so the
+// caller can hand the stream on to its line reader or TLS handshake.
+
+use crate::config;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Config key read by `enabled`.
+pub const KEY: config::ConfigKey = config::ConfigKey {
+    name: "PROXY_PROTOCOL",
+    default: Some("false"),
+    description:
+        "Require a PROXY protocol v1/v2 header on every connection and take the client address from it (true/false)",
+};
+
+/// How long a connection has to send its header.
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Longest v1 header, CRLF included, per the specification
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+pub fn enabled(config: &HashMap<String, String>) -> bool {
+    config.get(KEY.name).is_some_and(|value| value == "true")
+}
+
+/// Reads the header at the start of `stream` and returns the client address it
+/// carries. None means the header names no client (v1 UNKNOWN, a v2 LOCAL health
+/// check, or a non-IP family) and the connection's own peer stands. A missing,
+/// malformed or incomplete header, or none within HEADER_TIMEOUT, is an error.
+pub async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    match tokio::time::timeout(HEADER_TIMEOUT, read(stream)).await {
+        Ok(Err(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            Err(invalid("connection closed before the PROXY header ended"))
+        }
+        Ok(result) => result,
+        Err(_) => Err(invalid("no PROXY header in time")),
+    }
+}
+
+async fn read<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    // Both versions are longer than this, and it tells them apart
+    let mut start = [0u8; 8];
+    stream.read_exact(&mut start).await?;
+    if start.starts_with(b"PROXY ") {
+        read_v1(stream, &start).await
+    } else if start == V2_SIGNATURE[..8] {
+        read_v2(stream).await
+    } else {
+        Err(invalid("missing PROXY header"))
+    }
+}
+
+// `PROXY TCP4 src dst sport dport\r\n`; a byte at a time so the line ends exactly at
+// the CRLF
+async fn read_v1<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    start: &[u8],
+) -> io::Result<Option<SocketAddr>> {
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            return Err(invalid("PROXY v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("malformed PROXY v1 header"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family @ ("TCP4" | "TCP6"), src, dst, sport, dport] => {
+            let ip: IpAddr = src
+                .parse()
+                .map_err(|_| invalid("bad PROXY v1 source address"))?;
+            let ports = (sport.parse::<u16>(), dport.parse::<u16>());
+            if ip.is_ipv4() != (*family == "TCP4") || dst.parse::<IpAddr>().is_err() {
+                return Err(invalid("bad PROXY v1 addresses"));
+            }
+            match ports {
+                (Ok(port), Ok(_)) => Ok(Some(SocketAddr::new(ip, port))),
+                _ => Err(invalid("bad PROXY v1 ports")),
+            }
+        }
+        _ => Err(invalid("malformed PROXY v1 header")),
+    }
+}
+
+// The rest of the 16-byte fixed part, then an address block of the length it gives
+async fn read_v2<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut fixed = [0u8; 8];
+    stream.read_exact(&mut fixed).await?;
+    if fixed[..4] != V2_SIGNATURE[8..] {
+        return Err(invalid("missing PROXY header"));
+    }
+    let (version_command, family) = (fixed[4], fixed[5]);
+    let mut block = vec![0u8; u16::from_be_bytes([fixed[6], fixed[7]]) as usize];
+    stream.read_exact(&mut block).await?;
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY version"));
+    }
+    match version_command & 0x0f {
+        0 => return Ok(None), // LOCAL: the balancer's own connection
+        1 => {}
+        _ => return Err(invalid("unknown PROXY v2 command")),
+    }
+    let port = |at: usize| u16::from_be_bytes([block[at], block[at + 1]]);
+    // The high nibble is the address family, the low one the transport
+    match family >> 4 {
+        1 if block.len() >= 12 => {
+            let ip: [u8; 4] = block[..4].try_into().unwrap();
+            Ok(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port(8))))
+        }
+        2 if block.len() >= 36 => {
+            let ip: [u8; 16] = block[..16].try_into().unwrap();
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port(32))))
+        }
+        1 | 2 => Err(invalid("short PROXY v2 address block")),
+        _ => Ok(None), // Unspecified or a Unix socket
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,114 @@
+// systemd service notifications (sd_notify) for Type=notify units. The protocol is
+// a single datagram of `KEY=VALUE` lines sent to the unix socket systemd names in
+// NOTIFY_SOCKET, so it is written directly rather than pulling in libsystemd.
+// Without NOTIFY_SOCKET every call is a no-op.
+
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+use tokio::time::{interval_at, Instant, Interval};
+
+/// Where to send notifications, read from the environment by each binary.
+#[derive(Clone, Debug)]
+pub struct Notifier {
+    socket: Option<String>,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Notifications disabled, the default for servers started outside systemd.
+    pub fn disabled() -> Notifier {
+        Notifier {
+            socket: None,
+            watchdog: None,
+        }
+    }
+
+    /// Sends to `socket` (a path, or `@name` for an abstract socket) with an
+    /// optional watchdog timeout, as systemd would pass in the environment.
+    pub fn new(socket: &str, watchdog: Option<Duration>) -> Notifier {
+        Notifier {
+            socket: Some(socket.to_string()),
+            watchdog,
+        }
+    }
+
+    /// Reads NOTIFY_SOCKET and WATCHDOG_USEC/WATCHDOG_PID as set by systemd.
+    pub fn from_env() -> Notifier {
+        let socket = env::var("NOTIFY_SOCKET").ok().filter(|s| !s.is_empty());
+        // WATCHDOG_PID is absent or ours unless the variables leaked from a parent
+        let for_us = env::var("WATCHDOG_PID")
+            .ok()
+            .is_none_or(|pid| pid.parse() == Ok(std::process::id()));
+        let watchdog = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|usec| *usec > 0 && for_us)
+            .map(Duration::from_micros);
+        Notifier { socket, watchdog }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Timer for WATCHDOG=1 pings at half the watchdog timeout, as systemd recommends.
+    /// None when notifications or the watchdog are disabled.
+    pub fn watchdog_timer(&self) -> Option<Interval> {
+        let period = self.watchdog.filter(|_| self.is_enabled())? / 2;
+        Some(interval_at(Instant::now() + period, period))
+    }
+
+    /// The listener is bound and the server is accepting connections.
+    pub fn ready(&self) {
+        self.notify("READY=1");
+    }
+
+    pub fn watchdog(&self) {
+        self.notify("WATCHDOG=1");
+    }
+
+    /// Graceful shutdown has begun.
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+
+    // Best-effort like sd_notify(3): a missing or full socket must not affect serving
+    fn notify(&self, state: &str) {
+        if let Some(socket) = &self.socket {
+            let _ = send(socket, state);
+        }
+    }
+}
+
+/// Waits for the next watchdog tick, or forever when there is no timer.
+pub async fn next_watchdog(timer: &mut Option<Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+fn send(socket: &str, state: &str) -> io::Result<usize> {
+    let datagram = UnixDatagram::unbound()?;
+    match socket.strip_prefix('@') {
+        Some(name) => send_abstract(&datagram, name, state),
+        None => datagram.send_to(state.as_bytes(), socket),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(datagram: &UnixDatagram, name: &str, state: &str) -> io::Result<usize> {
+    use std::os::linux::net::SocketAddrExt;
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    datagram.send_to_addr(state.as_bytes(), &addr)
+}
+
+// Abstract socket names only exist on Linux, and so does systemd
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_datagram: &UnixDatagram, _name: &str, _state: &str) -> io::Result<usize> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
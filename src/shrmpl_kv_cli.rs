@@ -1,6 +1,7 @@
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 use tokio::io::{AsyncBufReadExt, BufReader};
+use shrmpl::kv_protocol::{Command, ListEntry, LoadMode, MSET_TTL_PREFIX, SCAN_DEFAULT_COUNT};
 use shrmpl::shrmpl_kv_client::KvClient;
 
 // Client application uses proper error propagation to provide user-friendly error messages
@@ -65,9 +66,160 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             }
                         }
                     }
-                    "SET" => {
+                    "MGET" => {
+                        match client.mget(&parts[1..]).await {
+                            Ok(values) => {
+                                for (key, value) in parts[1..].iter().zip(values) {
+                                    println!("{}={}", key, value.as_deref().unwrap_or("*NIL*"));
+                                }
+                            }
+                            Err(e) => println!("ERROR: {}", e),
+                        }
+                    }
+                    "GETSET" => {
                         if parts.len() < 3 || parts.len() > 4 {
                             println!("ERROR invalid arguments");
+                        } else {
+                            let result = if parts.len() == 3 {
+                                client.getset(parts[1], parts[2]).await
+                            } else {
+                                client.getset_with_ttl(parts[1], parts[2], parts[3]).await
+                            };
+                            match result {
+                                Ok(old) => println!("{}", old.as_deref().unwrap_or("*NIL*")),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "SETNX" => {
+                        if parts.len() < 3 || parts.len() > 4 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            let result = if parts.len() == 3 {
+                                client.setnx(parts[1], parts[2]).await
+                            } else {
+                                client.setnx_with_ttl(parts[1], parts[2], parts[3]).await
+                            };
+                            match result {
+                                Ok(set) => println!("{}", if set { 1 } else { 0 }),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "CAS" => {
+                        if parts.len() < 4 || parts.len() > 5 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            let result = if parts.len() == 4 {
+                                client.cas(parts[1], parts[2], parts[3]).await
+                            } else {
+                                client.cas_with_ttl(parts[1], parts[2], parts[3], parts[4]).await
+                            };
+                            match result {
+                                Ok(set) => println!("{}", if set { 1 } else { 0 }),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "MSET" => {
+                        // A trailing TTL=<duration> applies to every pair
+                        let (args, ttl) = match parts[1..].split_last() {
+                            Some((last, rest))
+                                if parts.len().is_multiple_of(2)
+                                    && last.starts_with(MSET_TTL_PREFIX) =>
+                            {
+                                (rest, Some(&last[MSET_TTL_PREFIX.len()..]))
+                            }
+                            _ => (&parts[1..], None),
+                        };
+                        if args.len() < 2 || !args.len().is_multiple_of(2) {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            let pairs: Vec<(&str, &str)> = args
+                                .chunks(2)
+                                .map(|pair| (pair[0], pair[1]))
+                                .collect();
+                            let result = match ttl {
+                                Some(ttl) => client.mset_with_ttl(&pairs, ttl).await,
+                                None => client.mset(&pairs).await,
+                            };
+                            match result {
+                                Ok(_) => println!("OK"),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "RENAME" => {
+                        if parts.len() != 3 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.rename(parts[1], parts[2]).await {
+                                Ok(_) => println!("OK"),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "RENAMENX" => {
+                        if parts.len() != 3 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.renamenx(parts[1], parts[2]).await {
+                                Ok(moved) => println!("{}", i64::from(moved)),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "EXISTS" => {
+                        if parts.len() != 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.exists(parts[1]).await {
+                                Ok(found) => println!("{}", i64::from(found)),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "TYPE" => {
+                        if parts.len() != 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.key_type(parts[1]).await {
+                                Ok(Some(value_type)) => println!("{}", value_type),
+                                Ok(None) => println!("ERROR key not found"),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "PEEK" => {
+                        if parts.len() != 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.peek(parts[1]).await {
+                                Ok(Some(value)) => println!("{}", value),
+                                Ok(None) => println!("ERROR key not found"),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "GETEX" => match Command::parse(&command) {
+                        Ok(Command::GetEx { key, expiry }) => match client.get_ex(&key, expiry).await {
+                            Ok(Some(value)) => println!("{}", value),
+                            Ok(None) => println!("ERROR key not found"),
+                            Err(e) => println!("ERROR: {}", e),
+                        },
+                        Ok(_) => println!("ERROR invalid arguments"),
+                        Err(e) => println!("ERROR {}", e),
+                    },
+                    "SET" => {
+                        let raw = parts.len() > 3 && parts[parts.len() - 1].eq_ignore_ascii_case("RAW");
+                        if parts.len() < 3 || parts.len() > 4 + raw as usize {
+                            println!("ERROR invalid arguments");
+                        } else if raw {
+                            let ttl = if parts.len() == 5 { Some(parts[3]) } else { None };
+                            match client.set_raw(parts[1], parts[2], ttl).await {
+                                Ok(_) => println!("OK"),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
                         } else if parts.len() == 3 {
                             match client.set(parts[1], parts[2]).await {
                                 Ok(_) => println!("OK"),
@@ -95,6 +247,102 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             }
                         }
                     }
+                    "DECR" => {
+                        if parts.len() < 2 || parts.len() > 3 {
+                            println!("ERROR invalid arguments");
+                        } else if parts.len() == 2 {
+                            match client.decr(parts[1]).await {
+                                Ok(value) => println!("{}", value),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        } else {
+                            match client.decr_with_ttl(parts[1], parts[2]).await {
+                                Ok(value) => println!("{}", value),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "INCRBY" | "DECRBY" => {
+                        let amount = parts.get(2).and_then(|amount| amount.parse::<i64>().ok());
+                        match amount {
+                            Some(amount) if parts.len() == 3 || parts.len() == 4 => {
+                                let result = match (cmd.as_str(), parts.get(3)) {
+                                    ("INCRBY", None) => client.incrby(parts[1], amount).await,
+                                    ("INCRBY", Some(ttl)) => client.incrby_with_ttl(parts[1], amount, ttl).await,
+                                    (_, None) => client.decrby(parts[1], amount).await,
+                                    (_, Some(ttl)) => client.decrby_with_ttl(parts[1], amount, ttl).await,
+                                };
+                                match result {
+                                    Ok(value) => println!("{}", value),
+                                    Err(e) => println!("ERROR: {}", e),
+                                }
+                            }
+                            _ => println!("ERROR invalid arguments"),
+                        }
+                    }
+                    "INCRBYFLOAT" => {
+                        let delta = parts.get(2).and_then(|delta| delta.parse::<f64>().ok());
+                        match delta {
+                            Some(delta) if parts.len() == 3 => match client.incr_float(parts[1], delta).await {
+                                Ok(value) => println!("{:?}", value),
+                                Err(e) => println!("ERROR: {}", e),
+                            },
+                            _ => println!("ERROR invalid arguments"),
+                        }
+                    }
+                    "TTL" => {
+                        if parts.len() != 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.ttl(parts[1]).await {
+                                Ok(secs) => println!("{}", secs),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "PTTL" => {
+                        if parts.len() != 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.pttl(parts[1]).await {
+                                Ok(millis) => println!("{}", millis),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "EXPIRE" => {
+                        if parts.len() != 3 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.expire(parts[1], parts[2]).await {
+                                Ok(true) => println!("OK"),
+                                Ok(false) => println!("ERROR key not found"),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "PERSIST" => {
+                        if parts.len() != 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.persist(parts[1]).await {
+                                Ok(true) => println!("OK"),
+                                Ok(false) => println!("ERROR key not found"),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "GETDEL" => {
+                        if parts.len() != 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.getdel(parts[1]).await {
+                                Ok(Some(value)) => println!("{}", value),
+                                Ok(None) => println!("ERROR key not found"),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
                     "DEL" => {
                         if parts.len() != 2 {
                             println!("ERROR invalid arguments");
@@ -117,23 +365,98 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             Err(e) => println!("ERROR: {}", e),
                         }
                     }
-                    "LIST" => {
+                    "LIMITS" => {
                         if parts.len() != 1 {
                             println!("ERROR invalid arguments");
                         } else {
-                            match client.list().await {
+                            println!("{}", client.limits().to_line());
+                        }
+                    }
+                    "TIME" => {
+                        if parts.len() != 1 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.time().await {
+                                Ok(server_time) => {
+                                    let server: chrono::DateTime<chrono::Utc> = server_time.into();
+                                    let skew = chrono::Utc::now().signed_duration_since(server);
+                                    println!(
+                                        "{} (local clock {:+.3}s)",
+                                        server.format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+                                        skew.num_milliseconds() as f64 / 1000.0
+                                    );
+                                }
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "STATUS" => {
+                        if parts.len() != 1 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.status().await {
+                                Ok(status) => {
+                                    println!("Version:     {}", status.version);
+                                    println!("Uptime:      {}s", status.uptime.as_secs());
+                                    println!("Keys:        {} ({} with expiration)", status.keys, status.expiring);
+                                    println!("Memory:      ~{} bytes", status.mem_bytes);
+                                    if status.saved_bytes > 0 {
+                                        println!("Compressed:  ~{} bytes saved", status.saved_bytes);
+                                    }
+                                    println!("Connections: {}", status.connections);
+                                    println!(
+                                        "Traffic:     {} bytes read, {} bytes written, {} commands",
+                                        status.bytes_read, status.bytes_written, status.commands
+                                    );
+                                }
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "LIST" => {
+                        // LIST BYEXPIRY [LIMIT n] or LIST [pattern]
+                        let by_expiry = parts.get(1).is_some_and(|word| word.eq_ignore_ascii_case("BYEXPIRY"));
+                        let limit = match parts.get(2..) {
+                            Some([word, n]) if word.eq_ignore_ascii_case("LIMIT") => n.parse::<usize>().ok(),
+                            _ => None,
+                        };
+                        if (by_expiry && parts.len() != 2 && limit.is_none()) || (!by_expiry && parts.len() > 2) {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            let result = match parts.get(1) {
+                                _ if by_expiry => client.list_by_expiry(limit).await.map(|entries| {
+                                    entries
+                                        .into_iter()
+                                        .map(|entry| {
+                                            let datetime = entry.expires_at_utc();
+                                            (entry.key, entry.value, entry.value_type, datetime)
+                                        })
+                                        .collect()
+                                }),
+                                Some(pattern) => client.list_matching(pattern).await.map(|items| {
+                                    items
+                                        .into_iter()
+                                        .map(|(key, value, value_type, expires_at)| {
+                                            let entry = ListEntry { key, value, value_type, expires_at };
+                                            let datetime = entry.expires_at_utc();
+                                            (entry.key, entry.value, entry.value_type, datetime)
+                                        })
+                                        .collect()
+                                }),
+                                None => client.list_with_datetimes().await,
+                            };
+                            match result {
                                 Ok(items) => {
                                     if items.is_empty() {
                                         println!("(no keys)");
                                     } else {
-                                        for (key, value, expiration) in items {
+                                        for (key, value, value_type, expiration) in items {
                                             match expiration {
-                                                Some(timestamp) => {
-                                                    let datetime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(timestamp);
-                                                    println!("{} = {} (expires: {:?})", key, value, datetime);
+                                                Some(datetime) => {
+                                                    println!("{} = {} [{}] (expires: {})", key, value, value_type, datetime.format("%Y-%m-%dT%H:%M:%SZ"));
                                                 }
                                                 None => {
-                                                    println!("{} = {} (no expiration)", key, value);
+                                                    println!("{} = {} [{}] (no expiration)", key, value, value_type);
                                                 }
                                             }
                                         }
@@ -143,6 +466,238 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             }
                         }
                     }
+                    "COUNT" => {
+                        if parts.len() > 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            let result = match parts.get(1) {
+                                Some(pattern) => client.count_matching(pattern).await,
+                                None => client.count().await,
+                            };
+                            match result {
+                                Ok(n) => println!("{}", n),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "SCAN" => {
+                        let count = match parts.len() {
+                            2 => Some(SCAN_DEFAULT_COUNT),
+                            4 if parts[2].eq_ignore_ascii_case("COUNT") => parts[3].parse().ok(),
+                            _ => None,
+                        };
+                        match count {
+                            None => println!("ERROR invalid arguments"),
+                            Some(count) => match client.scan_page(parts[1], count).await {
+                                Ok((next, entries)) => {
+                                    println!("next cursor: {}", next);
+                                    for entry in entries {
+                                        match entry.expires_at_utc() {
+                                            Some(datetime) => {
+                                                println!("{} = {} [{}] (expires: {})", entry.key, entry.value, entry.value_type, datetime.format("%Y-%m-%dT%H:%M:%SZ"));
+                                            }
+                                            None => {
+                                                println!("{} = {} [{}] (no expiration)", entry.key, entry.value, entry.value_type);
+                                            }
+                                        }
+                                    }
+                                }
+                                Err(e) => println!("ERROR: {}", e),
+                            },
+                        }
+                    }
+                    "COMPRESS" => {
+                        let on = match parts.get(1).map(|arg| arg.to_ascii_uppercase()) {
+                            Some(arg) if parts.len() == 2 && arg == "ON" => Some(true),
+                            Some(arg) if parts.len() == 2 && arg == "OFF" => Some(false),
+                            _ => None,
+                        };
+                        match on {
+                            Some(on) => match client.set_compression(on).await {
+                                Ok(()) => println!("OK"),
+                                Err(e) => println!("ERROR: {}", e),
+                            },
+                            None => println!("ERROR invalid arguments"),
+                        }
+                    }
+                    "SELECT" => {
+                        if parts.len() > 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.select(parts.get(1).copied()).await {
+                                Ok(()) => println!("OK"),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "EXPORT" => {
+                        if parts.len() != 1 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.export().await {
+                                Ok(json) => println!("{}", json),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "FLUSHALL" => {
+                        if parts.len() != 1 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.flushall().await {
+                                Ok(()) => println!("OK"),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "STATS" => {
+                        if parts.len() != 3 {
+                            println!("ERROR invalid arguments");
+                        } else if parts[1].eq_ignore_ascii_case("KEY") {
+                            match client.key_stats(parts[2]).await {
+                                Ok(Some(counters)) => println!("{}", counters.to_line()),
+                                Ok(None) => println!("ERROR key not found"),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        } else if parts[1].eq_ignore_ascii_case("TOP") {
+                            match parts[2].parse() {
+                                Ok(n) => match client.top_keys(n).await {
+                                    Ok(keys) => {
+                                        if keys.is_empty() {
+                                            println!("(no keys counted)");
+                                        }
+                                        for counters in keys {
+                                            println!("{}", counters.to_line());
+                                        }
+                                    }
+                                    Err(e) => println!("ERROR: {}", e),
+                                },
+                                Err(_) => println!("ERROR invalid arguments"),
+                            }
+                        } else {
+                            println!("ERROR invalid arguments");
+                        }
+                    }
+                    "KEYS" => {
+                        if parts.len() != 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.keys(parts[1]).await {
+                                Ok(names) => {
+                                    if names.is_empty() {
+                                        println!("(no matching keys)");
+                                    }
+                                    for name in names {
+                                        println!("{}", name);
+                                    }
+                                }
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "TAG" => {
+                        if parts.len() != 3 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.tag(parts[1], parts[2]).await {
+                                Ok(true) => println!("OK"),
+                                Ok(false) => println!("ERROR key not found"),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "TAGGED" => {
+                        if parts.len() != 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.tagged(parts[1]).await {
+                                Ok(names) => {
+                                    if names.is_empty() {
+                                        println!("(no tagged keys)");
+                                    }
+                                    for name in names {
+                                        println!("{}", name);
+                                    }
+                                }
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "EXPIRETAG" => {
+                        if parts.len() != 3 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.expire_tag(parts[1], parts[2]).await {
+                                Ok(n) => println!("{}", n),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "BADCMDS" => {
+                        if parts.len() != 1 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.bad_commands().await {
+                                Ok(samples) => {
+                                    if samples.is_empty() {
+                                        println!("(no rejected commands)");
+                                    }
+                                    for sample in samples {
+                                        println!("{}", sample);
+                                    }
+                                }
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "IMPORT" => {
+                        if parts.len() < 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.import(&parts[1..].join(" ")).await {
+                                Ok((imported, skipped)) => {
+                                    println!("OK imported={} skipped={}", imported, skipped)
+                                }
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "DUMP" => {
+                        if parts.len() != 2 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.dump(parts[1]).await {
+                                Ok(dumped) => println!("OK dumped={}", dumped),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "BGREWRITEAOF" => {
+                        if parts.len() != 1 {
+                            println!("ERROR invalid arguments");
+                        } else {
+                            match client.bgrewriteaof().await {
+                                Ok(rewritten) => println!("OK rewritten={}", rewritten),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    "LOAD" => {
+                        let mode = match parts.get(2).map(|m| m.to_ascii_lowercase()).as_deref() {
+                            None | Some("merge") => Some(LoadMode::Merge),
+                            Some("replace") => Some(LoadMode::Replace),
+                            Some(_) => None,
+                        };
+                        match (parts.len(), mode) {
+                            (2 | 3, Some(mode)) => match client.load(parts[1], mode).await {
+                                Ok((loaded, skipped)) => {
+                                    println!("OK loaded={} skipped={}", loaded, skipped)
+                                }
+                                Err(e) => println!("ERROR: {}", e),
+                            },
+                            _ => println!("ERROR invalid arguments"),
+                        }
+                    }
                     _ => {
                         println!("ERROR unknown command");
                     }
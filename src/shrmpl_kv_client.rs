@@ -1,14 +1,98 @@
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use chrono::{DateTime, Utc};
+use std::time::SystemTime;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
-use tokio::time::{timeout, Duration};
+use tokio::time::{timeout, timeout_at, Duration, Instant};
+
+use crate::kv_protocol::{
+    batch_line, decode_bytes, inflate_frame, parse_expiration, parse_time, scan_cursor_key,
+    Command, ExpiryChange, HelloReply, KeyCounters, Limits, ListEntry, LoadMode, ParseError,
+    Response, ServerStatus, FEATURE_LIMITS, FRAME_DEFLATE, FRAME_PLAIN, HEARTBEAT,
+    MAX_BATCH_COMMANDS, MGET_END, NIL, PROTOCOL_VERSION, SCAN_START, TERM,
+};
+use crate::kv_store;
+
+/// Server errors callers may want to match on, returned boxed from `KvClient` methods.
+/// Other failures are plain messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvError {
+    /// INCR or DECR would leave the i64 range; the stored value is unchanged.
+    Overflow,
+    /// INCR or DECR on a value that isn't an integer; the stored value is unchanged.
+    NotAnInteger,
+    /// The write would add a key to a store already holding MAX_KEYS live keys; nothing
+    /// was written.
+    StoreFull,
+    /// Nothing arrived from the server, not even a heartbeat, for the dead-server
+    /// threshold. The connection is given up and every later command fails the same way.
+    ServerUnresponsive,
+}
+
+impl KvError {
+    fn from_message(msg: &str) -> Option<KvError> {
+        if msg == ParseError::Overflow.to_string() {
+            Some(KvError::Overflow)
+        } else if msg == ParseError::NotAnInteger.to_string() {
+            Some(KvError::NotAnInteger)
+        } else if msg == ParseError::StoreFull.to_string() {
+            Some(KvError::StoreFull)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for KvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KvError::Overflow => write!(f, "ERROR {}", ParseError::Overflow),
+            KvError::NotAnInteger => write!(f, "ERROR {}", ParseError::NotAnInteger),
+            KvError::StoreFull => write!(f, "ERROR {}", ParseError::StoreFull),
+            KvError::ServerUnresponsive => write!(f, "Server stopped responding"),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+/// Heartbeat intervals of silence after which a connection is declared dead, unless
+/// changed with `set_dead_after_heartbeats`.
+pub const DEAD_AFTER_HEARTBEATS: u32 = 3;
 
 pub struct KvClient {
     reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
     writer: tokio::net::tcp::OwnedWriteHalf,
+    hello: Option<HelloReply>,
+    // From LIMITS at connect time; commands are checked against them before sending
+    limits: Limits,
+    // Start of a line not yet terminated, kept when a read gives up at its deadline
+    pending: Vec<u8>,
+    // Whole lines already read but not yet consumed: inflated from a compressed frame,
+    // or put back by drain_heartbeats
+    decoded: Vec<u8>,
+    // Set by COMPRESS ON; every line from the server is then framed
+    compressed: bool,
+    bytes_read: u64,
+    last_contact: Instant,
+    dead_after_heartbeats: u32,
+    unresponsive: bool,
 }
 #[allow(dead_code)]
 impl KvClient {
+    /// Connects and negotiates with HELLO, keeping the server's heartbeat setting.
     pub async fn connect(addr: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        KvClient::connect_with_heartbeat(addr, true).await
+    }
+
+    /// Connects and asks the server not to send UPONG lines on this connection.
+    pub async fn connect_without_heartbeat(addr: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        KvClient::connect_with_heartbeat(addr, false).await
+    }
+
+    async fn connect_with_heartbeat(
+        addr: &str,
+        heartbeat: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let stream = match timeout(Duration::from_secs(5), TcpStream::connect(addr)).await {
             Ok(Ok(stream)) => stream,
             Ok(Err(e)) => {
@@ -26,13 +110,97 @@ impl KvClient {
         stream.set_nodelay(true)?;
         let (reader, writer) = stream.into_split();
 
-        Ok(KvClient {
+        let mut client = KvClient {
             reader: BufReader::new(reader),
             writer,
-        })
+            hello: None,
+            limits: Limits::default(),
+            pending: Vec::new(),
+            decoded: Vec::new(),
+            compressed: false,
+            bytes_read: 0,
+            last_contact: Instant::now(),
+            dead_after_heartbeats: DEAD_AFTER_HEARTBEATS,
+            unresponsive: false,
+        };
+        // Servers that predate HELLO answer with an error; the connection then simply
+        // keeps their defaults
+        let command = Command::Hello {
+            heartbeat,
+            proto: PROTOCOL_VERSION,
+        };
+        if let Ok(Response::Status(line)) = client.request(&command).await {
+            client.hello = HelloReply::parse(&line);
+        }
+        // Servers from before LIMITS use the default limits
+        if client.supports(FEATURE_LIMITS) {
+            if let Ok(Response::Status(line)) = client.request(&Command::Limits).await {
+                client.limits = Limits::parse(&line).unwrap_or_default();
+            }
+        }
+        Ok(client)
+    }
+
+    /// The server's key and value length limits, as LIMITS reported them when the
+    /// client connected, or the defaults if it couldn't say.
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// What the server agreed to in reply to HELLO, or None if it doesn't support HELLO.
+    pub fn negotiated(&self) -> Option<&HelloReply> {
+        self.hello.as_ref()
+    }
+
+    /// Whether the server listed `feature` (one of the `kv_protocol::FEATURE_*` names)
+    /// in its HELLO reply. Always false for servers without HELLO or a feature list.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.hello
+            .as_ref()
+            .is_some_and(|hello| hello.supports(feature))
+    }
+
+    /// Asks the server to compress its replies on this connection (`COMPRESS ON`), or
+    /// to stop. Servers list `compress` among their HELLO features when they can.
+    pub async fn set_compression(&mut self, on: bool) -> Result<(), Box<dyn std::error::Error>> {
+        match self.request(&Command::Compress { on }).await? {
+            Response::Ok => {
+                self.compressed = on;
+                Ok(())
+            }
+            other => Err(format!("unexpected response: {:?}", other).into()),
+        }
+    }
+
+    /// Bytes received from the server so far, as they came over the wire.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// When a line (heartbeats included) was last read from the server.
+    pub fn last_server_contact(&self) -> Instant {
+        self.last_contact
+    }
+
+    /// Whether the connection was declared dead; see `KvError::ServerUnresponsive`.
+    pub fn is_unresponsive(&self) -> bool {
+        self.unresponsive
+    }
+
+    /// Declares the server dead after `n` of its heartbeat intervals without a line
+    /// from it; 0 turns detection off. Only applies when HELLO negotiated heartbeats,
+    /// since otherwise an idle server is legitimately silent.
+    pub fn set_dead_after_heartbeats(&mut self, n: u32) {
+        self.dead_after_heartbeats = n;
+    }
+
+    fn dead_after(&self) -> Option<Duration> {
+        let interval = self.hello.as_ref()?.heartbeat?;
+        Some(interval * self.dead_after_heartbeats).filter(|limit| !limit.is_zero())
     }
 
     async fn send_command(&mut self, cmd: &str) -> Result<String, Box<dyn std::error::Error>> {
+        self.drain_heartbeats().await?;
         if self
             .writer
             .write_all(format!("{}\n", cmd).as_bytes())
@@ -41,93 +209,434 @@ impl KvClient {
         {
             return Err("Failed to send command".into());
         }
+        self.read_response_line().await
+    }
+
+    // Consumes the heartbeats that arrived while the client was idle, without waiting
+    // for more. A connection that has been silent past the threshold fails here, before
+    // the command is sent.
+    async fn drain_heartbeats(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        while let Ok(line) = timeout(Duration::ZERO, self.read_line()).await {
+            let line = line?;
+            if std::str::from_utf8(&line).map(str::trim) != Ok(HEARTBEAT) {
+                // Anything else (TERM) is left for the command's own read
+                let rest = std::mem::replace(&mut self.decoded, line);
+                self.decoded.extend(rest);
+                break;
+            }
+        }
+        Ok(())
+    }
 
-        let mut response = String::new();
+    // Reads one line of the reply stream, unwrapping the framing once COMPRESS is on
+    async fn read_line(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
         loop {
-            response.clear();
-            match self.reader.read_line(&mut response).await {
-                Ok(0) => return Err("Connection closed by server".into()),
-                Ok(_) => {
-                    let resp = response.trim().to_string();
-                    // Ignore UPONG heartbeats, return everything else
-                    if resp == "UPONG" {
+            if let Some(end) = self.decoded.iter().position(|&b| b == b'\n') {
+                let rest = self.decoded.split_off(end + 1);
+                return Ok(std::mem::replace(&mut self.decoded, rest));
+            }
+            let line = self.read_raw_line().await?;
+            if !self.compressed {
+                return Ok(line);
+            }
+            match line.first() {
+                Some(&FRAME_PLAIN) => return Ok(line[1..].to_vec()),
+                Some(&FRAME_DEFLATE) => {
+                    let len: usize = std::str::from_utf8(&line[1..])
+                        .ok()
+                        .and_then(|len| len.trim().parse().ok())
+                        .ok_or("Invalid compressed frame from server")?;
+                    let data = self.read_raw_exact(len).await?;
+                    let lines = inflate_frame(&data)
+                        .map_err(|e| format!("Invalid compressed frame from server: {}", e))?;
+                    self.decoded.extend(lines);
+                }
+                _ => return Err("Unframed line from server after COMPRESS ON".into()),
+            }
+        }
+    }
+
+    // The data of a compressed frame, which follows its header line
+    async fn read_raw_exact(&mut self, len: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut data = std::mem::take(&mut self.pending);
+        let have = data.len().min(len);
+        self.pending = data.split_off(have);
+        data.resize(len, 0);
+        if self.reader.read_exact(&mut data[have..]).await.is_err() {
+            return Err("Error reading from server".into());
+        }
+        self.bytes_read += (len - have) as u64;
+        self.last_contact = Instant::now();
+        Ok(data)
+    }
+
+    // Reads one raw line, giving up with ServerUnresponsive once nothing has arrived
+    // for the dead-server threshold. Cancel safe: a partial line stays in `pending`.
+    async fn read_raw_line(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if self.unresponsive {
+            return Err(KvError::ServerUnresponsive.into());
+        }
+        loop {
+            if let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+                let rest = self.pending.split_off(end + 1);
+                return Ok(std::mem::replace(&mut self.pending, rest));
+            }
+            let seen = self.pending.len();
+            let deadline = self.dead_after().map(|limit| self.last_contact + limit);
+            let read = self.reader.read_until(b'\n', &mut self.pending);
+            let result = match deadline {
+                Some(deadline) => match timeout_at(deadline, read).await {
+                    Ok(result) => result,
+                    // Part of a long line still counts as hearing from the server
+                    Err(_) if self.pending.len() > seen => {
+                        self.bytes_read += (self.pending.len() - seen) as u64;
+                        self.last_contact = Instant::now();
                         continue;
-                    } else if resp == "TERM" {
-                        return Err("Server shutting down".into());
-                    } else {
-                        return Ok(resp);
                     }
+                    Err(_) => {
+                        self.unresponsive = true;
+                        return Err(KvError::ServerUnresponsive.into());
+                    }
+                },
+                None => read.await,
+            };
+            match result {
+                Ok(0) => return Err("Connection closed by server".into()),
+                Ok(_) => {
+                    self.bytes_read += (self.pending.len() - seen) as u64;
+                    self.last_contact = Instant::now();
+                    return Ok(std::mem::take(&mut self.pending));
                 }
                 Err(_) => return Err("Error reading from server".into()),
             }
         }
     }
 
-    pub async fn get(&mut self, key: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
-        if key.len() > 100 {
-            return Err("Key length exceeds 100 characters".into());
+    // Reads the next response line, skipping heartbeats
+    async fn read_response_line(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+        // Read bytes rather than a String so a corrupted line is reported as such; the
+        // whole line is consumed, so the connection stays usable for the next request
+        loop {
+            let response = self.read_line().await?;
+            let resp = match std::str::from_utf8(&response) {
+                Ok(text) => text.trim().to_string(),
+                Err(_) => return Err("Invalid UTF-8 in server response".into()),
+            };
+            if resp == HEARTBEAT {
+                continue;
+            } else if resp == TERM {
+                return Err("Server shutting down".into());
+            } else {
+                return Ok(resp);
+            }
         }
+    }
 
-        let response = self.send_command(&format!("GET {}", key)).await?;
+    async fn request(&mut self, command: &Command) -> Result<Response, Box<dyn std::error::Error>> {
+        command.validate_with(&self.limits)?;
+        let line = self.send_command(&command.to_line()).await?;
+        match Response::parse(command, &line) {
+            Response::Error(msg) => match KvError::from_message(&msg) {
+                Some(err) => Err(err.into()),
+                None => Err(format!("ERROR {}", msg).into()),
+            },
+            response => Ok(response),
+        }
+    }
 
-        if response.starts_with("ERROR") {
-            if response.contains("key not found") {
-                Ok(None)
+    pub async fn get(&mut self, key: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let command = Command::Get {
+            key: key.to_string(),
+        };
+        match self.request(&command).await? {
+            Response::Value(value) => Ok(Some(value)),
+            _ => Ok(None),
+        }
+    }
+
+    /// GETs up to MAX_MGET_KEYS keys in one round trip, in order. Missing and expired
+    /// keys come back as None, and so do keys too long to be stored.
+    pub async fn mget(
+        &mut self,
+        keys: &[&str],
+    ) -> Result<Vec<Option<String>>, Box<dyn std::error::Error>> {
+        let command = Command::MGet {
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+        };
+        command.validate_with(&self.limits)?;
+        let slot_error = format!("ERROR {}", ParseError::InvalidLength);
+        let mut line = self.send_command(&command.to_line()).await?;
+        // The server only rejects a whole MGET for its arguments, never for a key's length
+        if line.starts_with("ERROR ") && line != slot_error {
+            return Err(line.into());
+        }
+        let mut values = Vec::with_capacity(keys.len());
+        while line != MGET_END {
+            values.push(if line == NIL || line == slot_error {
+                None
             } else {
-                Err(response.into())
-            }
-        } else {
-            Ok(Some(response))
+                Some(line)
+            });
+            line = self.read_response_line().await?;
         }
+        Ok(values)
     }
 
-    pub async fn set(&mut self, key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
-        if key.len() > 100 || value.len() > 100 {
-            return Err("Key or value length exceeds 100 characters".into());
+    /// Whether `key` is present and not expired, without fetching its value.
+    pub async fn exists(&mut self, key: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let command = Command::Exists {
+            key: key.to_string(),
+        };
+        Ok(expect_integer(self.request(&command).await?)? == 1)
+    }
+
+    /// What `key` is stored as, `int`, `float`, `str` or `bytes`, or None if it is
+    /// missing or expired. INCR and DECR only work on `int`.
+    pub async fn key_type(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let command = Command::Type {
+            key: key.to_string(),
+        };
+        match self.request(&command).await? {
+            Response::Value(value_type) => Ok(Some(value_type)),
+            Response::NotFound => Ok(None),
+            Response::Error(e) => Err(e.into()),
+            other => Err(format!("unexpected response: {:?}", other).into()),
         }
+    }
 
-        let response = self.send_command(&format!("SET {} {}", key, value)).await?;
+    /// Like `get`, but leaves the server's store untouched: an expired key reads as
+    /// missing without being removed.
+    pub async fn peek(&mut self, key: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let command = Command::Peek {
+            key: key.to_string(),
+        };
+        match self.request(&command).await? {
+            Response::Value(value) => Ok(Some(value)),
+            _ => Ok(None),
+        }
+    }
 
-        if response == "OK" {
-            Ok(())
-        } else {
-            Err(response.into())
+    /// Reads `key` and, if it exists, applies `expiry` to it in the same round trip,
+    /// e.g. to renew a session only while it is still there.
+    pub async fn get_ex(
+        &mut self,
+        key: &str,
+        expiry: ExpiryChange,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let command = Command::GetEx {
+            key: key.to_string(),
+            expiry,
+        };
+        match self.request(&command).await? {
+            Response::Value(value) => Ok(Some(value)),
+            _ => Ok(None),
         }
     }
 
-    pub async fn set_with_ttl(
+    pub async fn set(&mut self, key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let command = Command::Set {
+            key: key.to_string(),
+            value: value.to_string(),
+            ttl: None,
+            raw: false,
+        };
+        self.request(&command).await?;
+        Ok(())
+    }
+
+    /// SET with RAW: the value is stored as the exact string even if it looks like an
+    /// integer, so `007` reads back as `007` rather than `7`.
+    pub async fn set_raw(
         &mut self,
         key: &str,
         value: &str,
-        ttl: &str,
+        ttl: Option<&str>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        if key.len() > 100 || value.len() > 100 {
-            return Err("Key or value length exceeds 100 characters".into());
-        }
+        let ttl = match ttl {
+            Some(ttl) => Some(parse_expiration(ttl).ok_or(ParseError::InvalidExpiration)?),
+            None => None,
+        };
+        let command = Command::Set {
+            key: key.to_string(),
+            value: value.to_string(),
+            ttl,
+            raw: true,
+        };
+        self.request(&command).await?;
+        Ok(())
+    }
 
-        let response = self
-            .send_command(&format!("SET {} {} {}", key, value, ttl))
-            .await?;
+    /// GETDEL: returns `key`'s value and deletes it in one step, or None if the key was
+    /// missing or expired. Of several clients racing for the same key only one gets it.
+    pub async fn getdel(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let command = Command::GetDel {
+            key: key.to_string(),
+        };
+        match self.request(&command).await? {
+            Response::Value(value) => Ok(Some(value)),
+            _ => Ok(None),
+        }
+    }
 
-        if response == "OK" {
-            Ok(())
-        } else {
-            Err(response.into())
+    /// Replaces `key`'s value and returns the old one, or None if the key was missing or
+    /// expired. A live key keeps its expiration.
+    pub async fn getset(
+        &mut self,
+        key: &str,
+        value: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let command = Command::GetSet {
+            key: key.to_string(),
+            value: value.to_string(),
+            ttl: None,
+        };
+        match self.request(&command).await? {
+            Response::Value(old) => Ok(Some(old)),
+            _ => Ok(None),
         }
     }
 
-    pub async fn incr(&mut self, key: &str) -> Result<i64, Box<dyn std::error::Error>> {
-        if key.len() > 100 {
-            return Err("Key length exceeds 100 characters".into());
+    /// Like `getset`, giving the new value the expiration `ttl` (e.g. `30s`) instead.
+    pub async fn getset_with_ttl(
+        &mut self,
+        key: &str,
+        value: &str,
+        ttl: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let command = Command::GetSet {
+            key: key.to_string(),
+            value: value.to_string(),
+            ttl: Some(parse_expiration(ttl).ok_or(ParseError::InvalidExpiration)?),
+        };
+        match self.request(&command).await? {
+            Response::Value(old) => Ok(Some(old)),
+            _ => Ok(None),
         }
+    }
 
-        let response = self.send_command(&format!("INCR {}", key)).await?;
+    /// SETs `key` only if it is missing or expired, returning whether it did. Two
+    /// clients racing for the same key can't both get true, which makes it a lock.
+    pub async fn setnx(
+        &mut self,
+        key: &str,
+        value: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let command = Command::SetNx {
+            key: key.to_string(),
+            value: value.to_string(),
+            ttl: None,
+        };
+        Ok(expect_integer(self.request(&command).await?)? == 1)
+    }
 
-        if response.starts_with("ERROR") {
-            Err(response.into())
-        } else {
-            response.parse::<i64>().map_err(|e| e.into())
-        }
+    /// Like `setnx`, with an expiration (e.g. `30s`, `5min`) so an abandoned lock lapses.
+    pub async fn setnx_with_ttl(
+        &mut self,
+        key: &str,
+        value: &str,
+        ttl: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let command = Command::SetNx {
+            key: key.to_string(),
+            value: value.to_string(),
+            ttl: Some(parse_expiration(ttl).ok_or(ParseError::InvalidExpiration)?),
+        };
+        Ok(expect_integer(self.request(&command).await?)? == 1)
+    }
+
+    /// SETs `key` to `value` only if it currently reads `expected`, returning whether it
+    /// did. A missing or expired key never matches. The key keeps its expiration.
+    pub async fn cas(
+        &mut self,
+        key: &str,
+        expected: &str,
+        value: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let command = Command::Cas {
+            key: key.to_string(),
+            expected: expected.to_string(),
+            value: value.to_string(),
+            ttl: None,
+        };
+        Ok(expect_integer(self.request(&command).await?)? == 1)
+    }
+
+    /// Like `cas`, replacing the key's expiration with `ttl` (e.g. `30s`) when it sets.
+    pub async fn cas_with_ttl(
+        &mut self,
+        key: &str,
+        expected: &str,
+        value: &str,
+        ttl: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let command = Command::Cas {
+            key: key.to_string(),
+            expected: expected.to_string(),
+            value: value.to_string(),
+            ttl: Some(parse_expiration(ttl).ok_or(ParseError::InvalidExpiration)?),
+        };
+        Ok(expect_integer(self.request(&command).await?)? == 1)
+    }
+
+    /// SETs up to MAX_MSET_PAIRS keys at once. Other clients see either all of them or
+    /// none, and an invalid pair fails the whole call without writing anything.
+    pub async fn mset(&mut self, pairs: &[(&str, &str)]) -> Result<(), Box<dyn std::error::Error>> {
+        let command = Command::MSet {
+            pairs: pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            ttl: None,
+        };
+        self.request(&command).await?;
+        Ok(())
+    }
+
+    /// `mset` with one expiration, such as "60s", for every key.
+    pub async fn mset_with_ttl(
+        &mut self,
+        pairs: &[(&str, &str)],
+        ttl: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let command = Command::MSet {
+            pairs: pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            ttl: Some(parse_expiration(ttl).ok_or(ParseError::InvalidExpiration)?),
+        };
+        self.request(&command).await?;
+        Ok(())
+    }
+
+    pub async fn set_with_ttl(
+        &mut self,
+        key: &str,
+        value: &str,
+        ttl: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let command = Command::Set {
+            key: key.to_string(),
+            value: value.to_string(),
+            ttl: Some(parse_expiration(ttl).ok_or(ParseError::InvalidExpiration)?),
+            raw: false,
+        };
+        self.request(&command).await?;
+        Ok(())
+    }
+
+    pub async fn incr(&mut self, key: &str) -> Result<i64, Box<dyn std::error::Error>> {
+        let command = Command::Incr {
+            key: key.to_string(),
+            ttl: None,
+        };
+        expect_integer(self.request(&command).await?)
     }
 
     pub async fn incr_with_ttl(
@@ -135,57 +644,342 @@ impl KvClient {
         key: &str,
         ttl: &str,
     ) -> Result<i64, Box<dyn std::error::Error>> {
-        if key.len() > 100 {
-            return Err("Key length exceeds 100 characters".into());
+        let command = Command::Incr {
+            key: key.to_string(),
+            ttl: Some(parse_expiration(ttl).ok_or(ParseError::InvalidExpiration)?),
+        };
+        expect_integer(self.request(&command).await?)
+    }
+
+    pub async fn decr(&mut self, key: &str) -> Result<i64, Box<dyn std::error::Error>> {
+        let command = Command::Decr {
+            key: key.to_string(),
+            ttl: None,
+        };
+        expect_integer(self.request(&command).await?)
+    }
+
+    pub async fn decr_with_ttl(
+        &mut self,
+        key: &str,
+        ttl: &str,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        let command = Command::Decr {
+            key: key.to_string(),
+            ttl: Some(parse_expiration(ttl).ok_or(ParseError::InvalidExpiration)?),
+        };
+        expect_integer(self.request(&command).await?)
+    }
+
+    /// Adds `amount` to `key` like `incr`, returning the new value.
+    pub async fn incrby(
+        &mut self,
+        key: &str,
+        amount: i64,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        let command = Command::IncrBy {
+            key: key.to_string(),
+            amount,
+            ttl: None,
+        };
+        expect_integer(self.request(&command).await?)
+    }
+
+    /// `incrby` that gives the key `ttl` if this call creates it.
+    pub async fn incrby_with_ttl(
+        &mut self,
+        key: &str,
+        amount: i64,
+        ttl: &str,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        let command = Command::IncrBy {
+            key: key.to_string(),
+            amount,
+            ttl: Some(parse_expiration(ttl).ok_or(ParseError::InvalidExpiration)?),
+        };
+        expect_integer(self.request(&command).await?)
+    }
+
+    /// Subtracts `amount` from `key` like `decr`, returning the new value.
+    pub async fn decrby(
+        &mut self,
+        key: &str,
+        amount: i64,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        let command = Command::DecrBy {
+            key: key.to_string(),
+            amount,
+            ttl: None,
+        };
+        expect_integer(self.request(&command).await?)
+    }
+
+    /// `decrby` that gives the key `ttl` if this call creates it.
+    pub async fn decrby_with_ttl(
+        &mut self,
+        key: &str,
+        amount: i64,
+        ttl: &str,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        let command = Command::DecrBy {
+            key: key.to_string(),
+            amount,
+            ttl: Some(parse_expiration(ttl).ok_or(ParseError::InvalidExpiration)?),
+        };
+        expect_integer(self.request(&command).await?)
+    }
+
+    /// Stores `value` as a float. It is sent as `kv_store::format_float` spells it, so
+    /// a whole number such as 2.0 is stored as a float rather than an integer, and GET
+    /// returns the same text.
+    pub async fn set_float(
+        &mut self,
+        key: &str,
+        value: f64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !value.is_finite() {
+            return Err(ParseError::NotANumber.into());
+        }
+        let command = Command::Set {
+            key: key.to_string(),
+            value: kv_store::format_float(value),
+            ttl: None,
+            raw: false,
+        };
+        self.request(&command).await?;
+        Ok(())
+    }
+
+    /// GETs `key` as a number: floats, integers and numeric strings all parse. A value
+    /// that isn't a number is an error.
+    pub async fn get_float(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+        match self.get(key).await? {
+            Some(value) => match value.parse::<f64>() {
+                Ok(f) if f.is_finite() => Ok(Some(f)),
+                _ => Err(ParseError::NotANumber.into()),
+            },
+            None => Ok(None),
         }
+    }
 
-        let response = self.send_command(&format!("INCR {} {}", key, ttl)).await?;
+    /// INCRBYFLOAT: adds `delta` and returns the new value, which is then stored as a
+    /// float. An infinite or NaN delta gets "ERROR not a number".
+    pub async fn incr_float(
+        &mut self,
+        key: &str,
+        delta: f64,
+    ) -> Result<f64, Box<dyn std::error::Error>> {
+        let command = Command::IncrByFloat {
+            key: key.to_string(),
+            delta,
+            ttl: None,
+        };
+        match self.request(&command).await? {
+            Response::Value(value) => Ok(value.parse()?),
+            other => Err(format!("unexpected response: {:?}", other).into()),
+        }
+    }
 
-        if response.starts_with("ERROR") {
-            Err(response.into())
-        } else {
-            response.parse::<i64>().map_err(|e| e.into())
+    /// Gives `key` a new expiration (e.g. `30s`, `5min`) without changing its value.
+    /// Returns false if the key doesn't exist or has already expired.
+    pub async fn expire(
+        &mut self,
+        key: &str,
+        ttl: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let command = Command::Expire {
+            key: key.to_string(),
+            ttl: parse_expiration(ttl).ok_or(ParseError::InvalidExpiration)?,
+        };
+        match self.request(&command).await? {
+            Response::Ok => Ok(true),
+            _ => Ok(false),
         }
     }
 
-    pub async fn delete(&mut self, key: &str) -> Result<bool, Box<dyn std::error::Error>> {
-        if key.len() > 100 {
-            return Err("Key length exceeds 100 characters".into());
+    /// Removes `key`'s expiration so it is kept until deleted. Returns false if the key
+    /// doesn't exist or has already expired.
+    pub async fn persist(&mut self, key: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let command = Command::Persist {
+            key: key.to_string(),
+        };
+        match self.request(&command).await? {
+            Response::Ok => Ok(true),
+            _ => Ok(false),
         }
+    }
+
+    /// Whole seconds until `key` expires, -1 if it has no expiration and -2 if it is
+    /// missing or expired.
+    pub async fn ttl(&mut self, key: &str) -> Result<i64, Box<dyn std::error::Error>> {
+        let command = Command::Ttl {
+            key: key.to_string(),
+        };
+        expect_integer(self.request(&command).await?)
+    }
 
-        let response = self.send_command(&format!("DEL {}", key)).await?;
+    /// `ttl` in whole milliseconds.
+    pub async fn pttl(&mut self, key: &str) -> Result<i64, Box<dyn std::error::Error>> {
+        let command = Command::PTtl {
+            key: key.to_string(),
+        };
+        expect_integer(self.request(&command).await?)
+    }
 
-        if response == "OK" {
-            Ok(true)
-        } else if response.contains("key not found") {
-            Ok(false)
-        } else {
-            Err(response.into())
+    pub async fn delete(&mut self, key: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let command = Command::Del {
+            key: key.to_string(),
+        };
+        Ok(self.request(&command).await? == Response::Ok)
+    }
+
+    /// Moves `src` to `dst` with its expiration, replacing any value at `dst`. Fails
+    /// with `ERROR key not found` if `src` is missing or expired.
+    pub async fn rename(&mut self, src: &str, dst: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let command = Command::Rename {
+            src: src.to_string(),
+            dst: dst.to_string(),
+        };
+        self.request(&command).await?;
+        Ok(())
+    }
+
+    /// `rename` unless `dst` exists; false when it does and nothing was moved.
+    pub async fn renamenx(
+        &mut self,
+        src: &str,
+        dst: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let command = Command::RenameNx {
+            src: src.to_string(),
+            dst: dst.to_string(),
+        };
+        Ok(expect_integer(self.request(&command).await?)? == 1)
+    }
+
+    /// Scopes the keys of every later command on this connection to `namespace`, or
+    /// returns to the global namespace with None.
+    pub async fn select(
+        &mut self,
+        namespace: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let command = Command::Select {
+            namespace: namespace.map(str::to_string),
+        };
+        self.request(&command).await?;
+        Ok(())
+    }
+
+    /// Stores arbitrary bytes; the base64 wire encoding is handled here.
+    pub async fn set_bytes(
+        &mut self,
+        key: &str,
+        data: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let command = Command::SetB {
+            key: key.to_string(),
+            data: data.to_vec(),
+            ttl: None,
+        };
+        self.request(&command).await?;
+        Ok(())
+    }
+
+    pub async fn get_bytes(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        let command = Command::GetB {
+            key: key.to_string(),
+        };
+        match self.request(&command).await? {
+            Response::Value(encoded) => Ok(Some(decode_bytes(&encoded)?)),
+            _ => Ok(None),
         }
     }
 
     pub async fn ping(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let response = self.send_command("PING").await?;
+        self.request(&Command::Ping).await?;
+        Ok(())
+    }
 
-        if response == "PONG" {
-            Ok(())
-        } else {
-            Err(response.into())
+    /// Has the server wait `ms` milliseconds before replying, to exercise timeouts in
+    /// tests. Fails unless the server runs with DEBUG_COMMANDS_ENABLED=true.
+    pub async fn debug_sleep(&mut self, ms: u64) -> Result<(), Box<dyn std::error::Error>> {
+        self.request(&Command::DebugSleep { ms }).await?;
+        Ok(())
+    }
+
+    pub async fn status(&mut self) -> Result<ServerStatus, Box<dyn std::error::Error>> {
+        match self.request(&Command::Status).await? {
+            Response::Status(line) => ServerStatus::parse(&line)
+                .ok_or_else(|| format!("unexpected STATUS response: {}", line).into()),
+            other => Err(format!("unexpected response: {:?}", other).into()),
+        }
+    }
+
+    /// The server's clock, to compare with the local one before relying on TTLs.
+    pub async fn time(&mut self) -> Result<SystemTime, Box<dyn std::error::Error>> {
+        match self.request(&Command::Time).await? {
+            Response::Status(line) => parse_time(&line)
+                .ok_or_else(|| format!("unexpected TIME response: {}", line).into()),
+            other => Err(format!("unexpected response: {:?}", other).into()),
+        }
+    }
+
+    /// Hit/miss/set counters for `key` (needs KEY_STATS on the server); None if the
+    /// key has never been counted.
+    pub async fn key_stats(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<KeyCounters>, Box<dyn std::error::Error>> {
+        let command = Command::KeyStats {
+            key: key.to_string(),
+        };
+        match self.request(&command).await? {
+            Response::Status(line) => KeyCounters::parse(&line)
+                .map(Some)
+                .ok_or_else(|| format!("unexpected STATS KEY response: {}", line).into()),
+            Response::NotFound => Ok(None),
+            other => Err(format!("unexpected response: {:?}", other).into()),
+        }
+    }
+
+    /// The `n` most accessed keys, busiest first (needs KEY_STATS on the server).
+    pub async fn top_keys(
+        &mut self,
+        n: usize,
+    ) -> Result<Vec<KeyCounters>, Box<dyn std::error::Error>> {
+        let mut line = self.send_command(&Command::TopKeys { n }.to_line()).await?;
+        if let Some(msg) = line.strip_prefix("ERROR ") {
+            return Err(format!("ERROR {}", msg).into());
         }
+        let mut keys = Vec::new();
+        while !line.is_empty() {
+            keys.push(
+                KeyCounters::parse(&line)
+                    .ok_or_else(|| format!("unexpected STATS TOP line: {}", line))?,
+            );
+            line = self.read_response_line().await?;
+        }
+        Ok(keys)
     }
 
     pub async fn batch(
         &mut self,
         commands: &[&str],
     ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-        if commands.len() > 3 {
-            return Err("Too many commands in batch (max 3)".into());
+        if commands.len() > MAX_BATCH_COMMANDS {
+            return Err(format!("Too many commands in batch (max {})", MAX_BATCH_COMMANDS).into());
         }
         if commands.is_empty() {
             return Err("No commands in batch".into());
         }
 
-        let batch_cmd = format!("BATCH {}", commands.join(";"));
+        let batch_cmd = batch_line(commands);
         let response = timeout(Duration::from_secs(3), self.send_command(&batch_cmd))
             .await
             .map_err(|_| "Batch command timed out after 3 seconds")??;
@@ -197,56 +991,329 @@ impl KvClient {
         }
     }
 
-    pub async fn list(
+    /// Dumps the whole store as the server's JSON export document (admin command).
+    pub async fn export(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+        let len = expect_integer(self.request(&Command::Export).await?)? as usize;
+        // Payload is followed by a trailing newline
+        let mut payload = vec![0u8; len + 1];
+        if self.compressed {
+            // Framed like any other line; the JSON document has no raw newlines
+            payload.clear();
+            while payload.len() <= len {
+                payload.extend(self.read_line().await?);
+            }
+        } else if self.reader.read_exact(&mut payload).await.is_err() {
+            return Err("Error reading from server".into());
+        } else {
+            self.bytes_read += payload.len() as u64;
+        }
+        payload.truncate(len);
+        Ok(String::from_utf8(payload)?)
+    }
+
+    /// Loads a JSON export document, returning (imported, skipped) counts.
+    pub async fn import(&mut self, json: &str) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+        let command = Command::Import {
+            json: json.to_string(),
+        };
+        let status = expect_status(self.request(&command).await?)?;
+        Ok((count(&status, "imported")?, count(&status, "skipped")?))
+    }
+
+    /// Removes every key from the server's store, whatever the connection's SELECT.
+    /// Fails unless the server runs with ALLOW_FLUSH=true.
+    pub async fn flushall(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.request(&Command::FlushAll).await?;
+        Ok(())
+    }
+
+    /// Has the server write its whole store to `path` on the server's host (admin
+    /// command), returning the number of keys written.
+    pub async fn dump(&mut self, path: &str) -> Result<u64, Box<dyn std::error::Error>> {
+        let command = Command::Dump {
+            path: path.to_string(),
+        };
+        let status = expect_status(self.request(&command).await?)?;
+        count(&status, "dumped")
+    }
+
+    /// Has the server rewrite its AOF to just the lines recreating the current store
+    /// (admin command), returning the number of lines written.
+    pub async fn bgrewriteaof(&mut self) -> Result<u64, Box<dyn std::error::Error>> {
+        let status = expect_status(self.request(&Command::BgRewriteAof).await?)?;
+        count(&status, "rewritten")
+    }
+
+    /// Has the server load a DUMP file from `path` on the server's host (admin
+    /// command), returning (loaded, skipped) counts. Expired entries are skipped.
+    pub async fn load(
         &mut self,
-    ) -> Result<Vec<(String, String, Option<u64>)>, Box<dyn std::error::Error>> {
-        // Send LIST command
-        if self.writer.write_all(b"LIST\n").await.is_err() {
-            return Err("Failed to send command".into());
+        path: &str,
+        mode: LoadMode,
+    ) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+        let command = Command::Load {
+            path: path.to_string(),
+            mode,
+        };
+        let status = expect_status(self.request(&command).await?)?;
+        Ok((count(&status, "loaded")?, count(&status, "skipped")?))
+    }
+
+    /// Recent command lines the server rejected, as `TIMESTAMP PEER [error] LINE`
+    /// (admin command). The server clears its samples once they are read.
+    pub async fn bad_commands(&mut self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut line = self.send_command(&Command::BadCmds.to_line()).await?;
+        if let Some(msg) = line.strip_prefix("ERROR ") {
+            return Err(format!("ERROR {}", msg).into());
+        }
+        let mut samples = Vec::new();
+        while !line.is_empty() {
+            samples.push(line);
+            line = self.read_response_line().await?;
         }
+        Ok(samples)
+    }
 
-        let mut result = Vec::new();
-        let mut response = String::new();
+    /// Names of live keys matching `pattern`, a glob with `*` and `?`, sorted.
+    pub async fn keys(&mut self, pattern: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let command = Command::Keys {
+            pattern: pattern.to_string(),
+        };
+        self.request_names(&command).await
+    }
 
-        // Read all lines until empty line or error
-        loop {
-            response.clear();
-            match self.reader.read_line(&mut response).await {
-                Ok(0) => return Err("Connection closed by server".into()),
-                Ok(_) => {
-                    let resp = response.trim().to_string();
-                    // Ignore UPONG heartbeats
-                    if resp == "UPONG" {
-                        continue;
-                    } else if resp == "TERM" {
-                        return Err("Server shutting down".into());
-                    } else if resp.starts_with("ERROR") {
-                        return Err(resp.into());
-                    } else if resp.is_empty() {
-                        // Empty line indicates end of LIST response
-                        break;
-                    } else {
-                        // Parse the line
-                        let parts: Vec<&str> = resp.splitn(2, '=').collect();
-                        if parts.len() == 2 {
-                            let key = parts[0].to_string();
-                            let value_and_expiration: Vec<&str> = parts[1].split(',').collect();
-                            if value_and_expiration.len() == 2 {
-                                let value = value_and_expiration[0].to_string();
-                                let expiration = if value_and_expiration[1] == "no-expiration" {
-                                    None
-                                } else {
-                                    value_and_expiration[1].parse::<u64>().ok()
-                                };
-                                result.push((key, value, expiration));
-                            }
-                        }
-                    }
-                }
-                Err(_) => return Err("Error reading from server".into()),
-            }
+    /// Adds `tag` to `key`; false if the key is missing or expired.
+    pub async fn tag(&mut self, key: &str, tag: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let command = Command::Tag {
+            key: key.to_string(),
+            tag: tag.to_string(),
+        };
+        Ok(self.request(&command).await? == Response::Ok)
+    }
+
+    /// Names of live keys bearing `tag`, sorted.
+    pub async fn tagged(&mut self, tag: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let command = Command::Tagged {
+            tag: tag.to_string(),
+        };
+        self.request_names(&command).await
+    }
+
+    /// Gives every live key tagged `tag` the expiration `ttl` (e.g. `30min`),
+    /// returning how many keys it applied to.
+    pub async fn expire_tag(
+        &mut self,
+        tag: &str,
+        ttl: &str,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        let command = Command::ExpireTag {
+            tag: tag.to_string(),
+            ttl: parse_expiration(ttl).ok_or(ParseError::InvalidExpiration)?,
+        };
+        expect_integer(self.request(&command).await?)
+    }
+
+    // KEYS and TAGGED: one key name per line, ended by an empty line
+    async fn request_names(
+        &mut self,
+        command: &Command,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        command.validate_with(&self.limits)?;
+        let mut line = self.send_command(&command.to_line()).await?;
+        if let Some(msg) = line.strip_prefix("ERROR ") {
+            return Err(format!("ERROR {}", msg).into());
+        }
+        let mut names = Vec::new();
+        while !line.is_empty() {
+            names.push(line);
+            line = self.read_response_line().await?;
+        }
+        Ok(names)
+    }
+
+    /// One SCAN page: up to `count` entries after `cursor` (SCAN_START to begin), and
+    /// the cursor of the next page, which is SCAN_START once the scan is complete.
+    pub async fn scan_page(
+        &mut self,
+        cursor: &str,
+        count: usize,
+    ) -> Result<(String, Vec<ListEntry>), Box<dyn std::error::Error>> {
+        let command = Command::Scan {
+            after: scan_cursor_key(cursor)?,
+            count,
+        };
+        command.validate_with(&self.limits)?;
+        let next = self.send_command(&command.to_line()).await?;
+        if let Some(msg) = next.strip_prefix("ERROR ") {
+            return Err(format!("ERROR {}", msg).into());
+        }
+        let mut entries = Vec::new();
+        let mut line = self.read_response_line().await?;
+        while !line.is_empty() {
+            entries.push(
+                ListEntry::parse(&line).ok_or_else(|| format!("unexpected SCAN line: {}", line))?,
+            );
+            line = self.read_response_line().await?;
         }
+        Ok((next, entries))
+    }
+
+    /// Walks the store `count` entries at a time without holding the server's lock
+    /// between pages; see `Scan`.
+    pub fn scan(&mut self, count: usize) -> Scan<'_> {
+        Scan {
+            client: self,
+            cursor: Some(SCAN_START.to_string()),
+            count,
+        }
+    }
 
+    /// Number of live keys, without transferring them as LIST would.
+    pub async fn count(&mut self) -> Result<u64, Box<dyn std::error::Error>> {
+        let command = Command::Count { pattern: None };
+        Ok(expect_integer(self.request(&command).await?)? as u64)
+    }
+
+    /// Number of live keys whose names match `pattern`, a glob as KEYS takes.
+    pub async fn count_matching(
+        &mut self,
+        pattern: &str,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let command = Command::Count {
+            pattern: Some(pattern.to_string()),
+        };
+        Ok(expect_integer(self.request(&command).await?)? as u64)
+    }
+
+    /// LIST as (key, value, type, expiration in unix seconds), with the type as TYPE
+    /// reports it.
+    pub async fn list(
+        &mut self,
+    ) -> Result<Vec<(String, String, String, Option<u64>)>, Box<dyn std::error::Error>> {
+        let entries = self.list_entries(None).await?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| (entry.key, entry.value, entry.value_type, entry.expires_at))
+            .collect())
+    }
+
+    /// LIST of the keys whose names match `pattern`, a glob as KEYS takes, in the same
+    /// form as `list`.
+    pub async fn list_matching(
+        &mut self,
+        pattern: &str,
+    ) -> Result<Vec<(String, String, String, Option<u64>)>, Box<dyn std::error::Error>> {
+        let entries = self.list_entries(Some(pattern)).await?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| (entry.key, entry.value, entry.value_type, entry.expires_at))
+            .collect())
+    }
+
+    /// LIST as (key, value, type, expiration), with the expiration as a UTC date and
+    /// time.
+    pub async fn list_with_datetimes(
+        &mut self,
+    ) -> Result<Vec<(String, String, String, Option<DateTime<Utc>>)>, Box<dyn std::error::Error>>
+    {
+        let entries = self.list_entries(None).await?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| {
+                let expires_at = entry.expires_at_utc();
+                (entry.key, entry.value, entry.value_type, expires_at)
+            })
+            .collect())
+    }
+
+    /// LIST's entries sorted by expiration, soonest first and keys without one last,
+    /// at most `limit` of them.
+    pub async fn list_by_expiry(
+        &mut self,
+        limit: Option<usize>,
+    ) -> Result<Vec<ListEntry>, Box<dyn std::error::Error>> {
+        self.read_list(Command::ListByExpiry { limit }).await
+    }
+
+    async fn list_entries(
+        &mut self,
+        pattern: Option<&str>,
+    ) -> Result<Vec<ListEntry>, Box<dyn std::error::Error>> {
+        let command = Command::List {
+            pattern: pattern.map(str::to_string),
+        };
+        self.read_list(command).await
+    }
+
+    async fn read_list(
+        &mut self,
+        command: Command,
+    ) -> Result<Vec<ListEntry>, Box<dyn std::error::Error>> {
+        command.validate_with(&self.limits)?;
+        let mut line = self.send_command(&command.to_line()).await?;
+        let mut result = Vec::new();
+        // Read all lines until the empty line that terminates a LIST response
+        while !line.is_empty() {
+            if line.starts_with("ERROR") {
+                return Err(line.into());
+            }
+            if let Some(entry) = ListEntry::parse(&line) {
+                result.push(entry);
+            }
+            line = self.read_response_line().await?;
+        }
         Ok(result)
     }
 }
+
+fn expect_status(response: Response) -> Result<String, Box<dyn std::error::Error>> {
+    match response {
+        Response::Status(status) => Ok(status),
+        other => Err(format!("unexpected response: {:?}", other).into()),
+    }
+}
+
+// The `name=N` field of an `OK name=N ...` status line; absent fields count as 0
+fn count(status: &str, name: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    let prefix = format!("{}=", name);
+    match status
+        .split_whitespace()
+        .find_map(|f| f.strip_prefix(&prefix))
+    {
+        Some(n) => Ok(n.parse()?),
+        None => Ok(0),
+    }
+}
+
+fn expect_integer(response: Response) -> Result<i64, Box<dyn std::error::Error>> {
+    match response {
+        Response::Integer(i) => Ok(i),
+        other => Err(format!("unexpected response: {:?}", other).into()),
+    }
+}
+
+/// A SCAN in progress, from `KvClient::scan`. Entries come in name order. Keys stored
+/// for the whole scan are returned exactly once; keys added or removed meanwhile may
+/// or may not be.
+pub struct Scan<'a> {
+    client: &'a mut KvClient,
+    // None once the last page has been returned
+    cursor: Option<String>,
+    count: usize,
+}
+
+impl Scan<'_> {
+    /// The next page of entries, or None once the scan is complete.
+    pub async fn next_page(
+        &mut self,
+    ) -> Result<Option<Vec<ListEntry>>, Box<dyn std::error::Error>> {
+        let Some(cursor) = self.cursor.take() else {
+            return Ok(None);
+        };
+        let (next, entries) = self.client.scan_page(&cursor, self.count).await?;
+        if next != SCAN_START {
+            self.cursor = Some(next);
+        }
+        Ok(Some(entries))
+    }
+}
@@ -0,0 +1,69 @@
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+use chrono::{DateTime, Utc};
+
+// Pretty-prints a shrmpl-kv snapshot, or any EXPORT/DUMP document since they share the
+// format: one key per line sorted by name, then a summary. Entries are shown as stored,
+// without the server's validation, so a file the server rejects can still be inspected.
+fn main() {
+    println!("shrmpl-kv-dump version {}", VERSION);
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 2 {
+        eprintln!("Usage: {} <snapshot_file>", args[0]);
+        std::process::exit(1);
+    }
+    let path = &args[1];
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    let mut entries = match serde_json::from_str::<serde_json::Value>(&content) {
+        Ok(serde_json::Value::Array(entries)) => entries,
+        Ok(_) => {
+            eprintln!("{}: expected a JSON array of entries", path);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}: invalid JSON: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    entries.sort_by(|a, b| a["key"].as_str().cmp(&b["key"].as_str()));
+
+    let now = Utc::now().timestamp_millis();
+    let mut expiring = 0;
+    let mut expired = 0;
+    for entry in &entries {
+        let key = entry["key"].as_str().unwrap_or("?");
+        let value_type = entry["type"].as_str().unwrap_or("?");
+        let value = match &entry["value"] {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let expiration = match entry["expires_at"].as_i64() {
+            Some(ms) => {
+                expiring += 1;
+                let datetime = DateTime::<Utc>::from_timestamp_millis(ms)
+                    .map(|datetime| datetime.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+                    .unwrap_or_else(|| ms.to_string());
+                if ms <= now {
+                    expired += 1;
+                    format!("expired {}", datetime)
+                } else {
+                    format!("expires {}", datetime)
+                }
+            }
+            None => "no expiration".to_string(),
+        };
+        println!("{} [{}] = {} ({})", key, value_type, value, expiration);
+    }
+    println!(
+        "{} keys, {} with an expiration, {} already expired (skipped on load)",
+        entries.len(),
+        expiring,
+        expired
+    );
+}
@@ -1,223 +1,151 @@
 use clap::{Arg, Command};
 use std::collections::HashMap;
-use std::fs;
-use std::io::{self, BufRead};
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio::time::{timeout, Duration, Instant};
-
-mod shrmpl_kv_client;
-use shrmpl_kv_client::KvClient;
-
-#[derive(Clone)]
-struct TestConfig {
-    server_addr: String,
-    num_users: usize,
-    operations_per_user: usize,
-    shared_connection: bool,
-    full_test: bool,
-}
-
-#[derive(Debug, Clone)]
-struct TestResult {
-    duration: Duration,
-    success: bool,
-    error_type: Option<String>,
+use tokio::time::{Duration, Instant};
+
+use shrmpl::config::load_config;
+use shrmpl::loadtest::{
+    percentile, run_list_contention, run_lock_contention, run_log_throughput, run_test, summarize_by_server,
+    ListContentionConfig, LockContentionConfig, LogThroughputConfig, TestConfig,
+};
+use shrmpl::kv_protocol::ServerStatus;
+use shrmpl::loadtest_progress::{Progress, Snapshot};
+use shrmpl::loadtest_slow::{parse_threshold, slowest, SlowLog};
+use shrmpl::shrmpl_kv_client::KvClient;
+
+// Slow operations listed in the final report
+const SLOWEST_REPORTED: usize = 10;
+
+// Prints a progress line every second until aborted
+async fn report_progress(progress: Arc<Progress>) {
+    let interval_len = Duration::from_secs(1);
+    let mut interval = tokio::time::interval(interval_len);
+    interval.tick().await; // First tick completes immediately
+    let mut previous = Snapshot::default();
+    loop {
+        interval.tick().await;
+        let current = progress.snapshot();
+        println!("{}", current.progress_line(&previous, interval_len));
+        previous = current;
+    }
 }
 
-async fn run_test(config: TestConfig) -> Result<Vec<TestResult>, String> {
-    let mut results = Vec::new();
-
-    if config.shared_connection {
-        // Shared connection mode
-        let client = Arc::new(Mutex::new(
-            KvClient::connect(&config.server_addr)
-                .await
-                .map_err(|e| e.to_string())?,
-        ));
-
-        let mut handles = vec![];
-        for task_id in 0..config.num_users {
-            let client = Arc::clone(&client);
-            let config = config.clone();
-            let handle =
-                tokio::spawn(async move { run_task_operations(client, config, task_id).await });
-            handles.push(handle);
-        }
-
-        for handle in handles {
-            match handle.await {
-                Ok(Ok(task_results)) => results.extend(task_results),
-                Ok(Err(e)) => return Err(format!("Task error: {}", e)),
-                Err(e) => return Err(format!("Join error: {}", e)),
-            }
-        }
-    } else {
-        // Multi-connection mode
-        let mut handles = vec![];
-        for task_id in 0..config.num_users {
-            let config = config.clone();
-            let handle = tokio::spawn(async move {
-                let client = KvClient::connect(&config.server_addr)
-                    .await
-                    .map_err(|e| e.to_string())?;
-                run_task_operations(Arc::new(Mutex::new(client)), config, task_id).await
-            });
-            handles.push(handle);
-        }
-
-        for handle in handles {
-            match handle.await {
-                Ok(Ok(task_results)) => results.extend(task_results),
-                Ok(Err(e)) => return Err(format!("Task error: {}", e)),
-                Err(e) => return Err(format!("Join error: {}", e)),
-            }
-        }
+// Best-effort STATUS snapshot for the server growth report; older servers don't
+// support STATUS, so failures just skip the report
+async fn server_statuses(addrs: &[String]) -> Vec<Option<ServerStatus>> {
+    let mut statuses = Vec::new();
+    for addr in addrs {
+        let status = match KvClient::connect(addr).await {
+            Ok(mut client) => client.status().await.ok(),
+            Err(_) => None,
+        };
+        statuses.push(status);
     }
-
-    Ok(results)
+    statuses
 }
 
-async fn run_task_operations(
-    client: Arc<Mutex<KvClient>>,
-    config: TestConfig,
-    task_id: usize,
-) -> Result<Vec<TestResult>, String> {
-    let mut local_results = Vec::new();
-    let mut counter_value = 0i64;
-
-    for op_num in 0..config.operations_per_user {
-        let start = Instant::now();
-        let mut client_lock = client.lock().await;
-
-        let mut operation_success = true;
-        let mut operation_error = None;
-
-        if config.full_test {
-            // Comprehensive test operations
-            let set_key = format!("test_key_{}_{}", task_id, op_num);
-            let set_value = format!("{}", task_id);
-
-            // SET operation
-            if let Err(e) = client_lock.set(&set_key, &set_value).await {
-                operation_success = false;
-                operation_error = Some(format!("SET failed: {}", e));
-            }
-
-            // GET and verify
-            if operation_success {
-                match client_lock.get(&set_key).await {
-                    Ok(Some(val)) if val == set_value => {} // OK
-                    Ok(Some(val)) => {
-                        operation_success = false;
-                        operation_error = Some(format!(
-                            "GET verification failed: expected {}, got {}",
-                            set_value, val
-                        ));
-                    }
-                    Ok(None) => {
-                        operation_success = false;
-                        operation_error = Some("GET returned None".to_string());
-                    }
-                    Err(e) => {
-                        operation_success = false;
-                        operation_error = Some(format!("GET failed: {}", e));
-                    }
-                }
-            }
-
-            // INCR and verify
-            if operation_success {
-                let counter_key = format!("counter_{}", task_id);
-                match client_lock.incr(&counter_key).await {
-                    Ok(val) => {
-                        counter_value += 1;
-                        if val != counter_value {
-                            operation_success = false;
-                            operation_error = Some(format!(
-                                "INCR verification failed: expected {}, got {}",
-                                counter_value, val
-                            ));
-                        }
-                    }
-                    Err(e) => {
-                        operation_success = false;
-                        operation_error = Some(format!("INCR failed: {}", e));
-                    }
-                }
-            }
-
-            // SET with TTL
-            if operation_success {
-                let ttl_key = format!("ttl_key_{}_{}", task_id, op_num);
-                if let Err(e) = client_lock.set_with_ttl(&ttl_key, "ttl_value", "60s").await {
-                    operation_success = false;
-                    operation_error = Some(format!("SET with TTL failed: {}", e));
-                }
-            }
-        }
-
-        // Always do the batch GET (the original test)
-        let batch_result = timeout(
-            Duration::from_secs(3),
-            client_lock.batch(&["GET loginlock-ip-123", "GET loginlock-user-abc"]),
-        )
-        .await;
+// The loadtest reads the same env file as shrmpl-kv-srv and targets its BIND_ADDR
+fn server_addr_from_config(config_path: &str) -> Result<String, String> {
+    load_config(config_path)
+        .remove("BIND_ADDR")
+        .ok_or_else(|| "BIND_ADDR not found in config".to_string())
+}
 
-        drop(client_lock); // Release lock
+// SET p50/p99 idle versus while another connection runs LIST over `keys` keys
+async fn list_contention(addr: &str, keys: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let config = ListContentionConfig {
+        server_addr: addr.to_string(),
+        keys,
+        idle_sets: 2000,
+        lists: 5,
+    };
+    println!("LIST Contention Benchmark:");
+    println!("├── Keys: {}", config.keys);
+    println!("├── LISTs: {}", config.lists);
+    println!("└── Server: {}", config.server_addr);
+    println!();
 
-        let duration = start.elapsed();
+    let run = run_list_contention(&config).await?;
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    println!("Average LIST: {:.2}ms", ms(run.list_duration));
+    println!(
+        "SET idle:        {} ops, p50 {:.3}ms, p99 {:.3}ms, max {:.3}ms",
+        run.idle.len(),
+        ms(percentile(&run.idle, 50.0)),
+        ms(percentile(&run.idle, 99.0)),
+        ms(percentile(&run.idle, 100.0))
+    );
+    println!(
+        "SET during LIST: {} ops, p50 {:.3}ms, p99 {:.3}ms, max {:.3}ms",
+        run.during_list.len(),
+        ms(percentile(&run.during_list, 50.0)),
+        ms(percentile(&run.during_list, 99.0)),
+        ms(percentile(&run.during_list, 100.0))
+    );
+    Ok(())
+}
 
-        let final_success = match batch_result {
-            Ok(Ok(_)) => operation_success,
-            Ok(Err(e)) => {
-                operation_success = false;
-                operation_error = Some(format!("Batch GET failed: {}", e));
-                false
-            }
-            Err(_) => {
-                operation_success = false;
-                operation_error = Some("Batch GET timeout".to_string());
-                false
-            }
-        };
+// TASKS connections race for one SETNX lock per TTL window; each window needs one winner
+async fn lock_contention(addr: &str, tasks: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let config = LockContentionConfig {
+        server_addr: addr.to_string(),
+        tasks,
+        rounds: 5,
+        ttl: Duration::from_secs(1),
+    };
+    println!("SETNX Lock Contention:");
+    println!("├── Tasks: {}", config.tasks);
+    println!("├── Rounds: {} ({}s TTL each)", config.rounds, config.ttl.as_secs());
+    println!("└── Server: {}", config.server_addr);
+    println!();
 
-        local_results.push(TestResult {
-            duration,
-            success: final_success,
-            error_type: operation_error,
-        });
+    let run = run_lock_contention(&config).await?;
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    for (round, winners) in run.winners.iter().enumerate() {
+        let verdict = if *winners == 1 { "ok" } else { "VIOLATION" };
+        println!("Round {}: {} winner(s) {}", round + 1, winners, verdict);
     }
-
-    // Cleanup: delete test keys
-    if config.full_test {
-        let mut client_lock = client.lock().await;
-        for op_num in 0..config.operations_per_user {
-            let set_key = format!("test_key_{}_{}", task_id, op_num);
-            let ttl_key = format!("ttl_key_{}_{}", task_id, op_num);
-            let _ = client_lock.delete(&set_key).await; // Ignore errors
-            let _ = client_lock.delete(&ttl_key).await; // Ignore errors
-        }
-        let counter_key = format!("counter_{}", task_id);
-        let _ = client_lock.delete(&counter_key).await; // Ignore errors
+    println!(
+        "SETNX: {} ops, p50 {:.3}ms, p99 {:.3}ms, max {:.3}ms",
+        run.latencies.len(),
+        ms(percentile(&run.latencies, 50.0)),
+        ms(percentile(&run.latencies, 99.0)),
+        ms(percentile(&run.latencies, 100.0))
+    );
+    if let Some(first) = run.errors.first() {
+        println!("Errors: {} failed attempt(s), first: {}", run.errors.len(), first);
     }
-
-    Ok(local_results)
+    if run.violations() > 0 {
+        return Err(format!("{} round(s) without exactly one lock holder", run.violations()).into());
+    }
+    if !run.errors.is_empty() {
+        return Err(format!("{} SETNX attempt(s) failed", run.errors.len()).into());
+    }
+    Ok(())
 }
 
-fn load_config(config_path: &str) -> Result<String, String> {
-    let file = fs::File::open(config_path).map_err(|e| e.to_string())?;
-    let reader = io::BufReader::new(file);
+// Records/sec from Logger to a local sink, one connection per record versus batched
+async fn log_throughput(records: usize) -> Result<(), Box<dyn std::error::Error>> {
+    println!("SLOG Throughput Benchmark:");
+    println!("└── Records: {}", records);
+    println!();
 
-    for line in reader.lines() {
-        let line = line.map_err(|e| e.to_string())?;
-        let line = line.trim();
-        if line.starts_with("BIND_ADDR=") {
-            return Ok(line[10..].to_string());
-        }
+    for (label, batch_max) in [("unbatched", 1), ("SLOG_BATCH_MAX=64", 64)] {
+        let config = LogThroughputConfig {
+            records,
+            batch_max,
+            batch_delay: Duration::from_millis(5),
+        };
+        let run = run_log_throughput(&config).await?;
+        println!(
+            "{:<18} {} records in {:.2}s, {:.0} records/sec",
+            label,
+            run.received,
+            run.elapsed.as_secs_f64(),
+            run.records_per_sec()
+        );
     }
-
-    Err("BIND_ADDR not found in config".to_string())
+    Ok(())
 }
 
 #[tokio::main]
@@ -226,9 +154,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .arg(
             Arg::new("config")
                 .help("Path to config file")
-                .required(true)
+                .required_unless_present_any(["servers", "log-throughput"])
                 .index(1),
         )
+        .arg(
+            Arg::new("servers")
+                .long("servers")
+                .help("Comma-separated server addresses to spread connections across round-robin (overrides the config file)")
+                .value_delimiter(','),
+        )
         .arg(
             Arg::new("shared")
                 .long("shared")
@@ -238,22 +172,117 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .arg(
             Arg::new("full")
                 .long("full")
-                .help("Run full comprehensive test (SET/GET/INCR/DELETE) instead of batch GET only")
+                .help("Run full comprehensive test (SET/GET/INCR/MSET/DELETE) instead of batch GET only")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verify")
+                .long("verify")
+                .help("Also write increasing tokens to per-user keys and check for lost or stale writes at the end")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("think-ms")
+                .long("think-ms")
+                .help("Pause in milliseconds between each user's operations")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("think-jitter-ms")
+                .long("think-jitter-ms")
+                .help("Add a random 0..N ms to each think-time pause")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("list-contention")
+                .long("list-contention")
+                .help("Instead of the load test, write N keys and compare SET latency on an idle server with SET latency while LIST runs")
+                .value_name("KEYS")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("lock-contention")
+                .long("lock-contention")
+                .help("Instead of the load test, have N connections race for one SETNX lock in each of five 1s TTL windows and check each window has exactly one winner")
+                .value_name("TASKS")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("log-throughput")
+                .long("log-throughput")
+                .help("Instead of the load test, send N log records to a local SLOG sink with and without batching and compare records/sec")
+                .value_name("RECORDS")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("slow-threshold")
+                .long("slow-threshold")
+                .help("Log every operation taking at least this long (e.g. 250ms, 1.5s) as it happens and list the slowest in the report")
+                .value_name("DURATION"),
+        )
+        .arg(
+            Arg::new("slow-log")
+                .long("slow-log")
+                .help("Write slow operations to this file instead of stderr")
+                .value_name("PATH")
+                .requires("slow-threshold"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .long("quiet")
+                .help("Suppress the per-second progress line")
                 .action(clap::ArgAction::SetTrue),
         )
         .get_matches();
 
-    let config_path = matches.get_one::<String>("config").unwrap();
-    let server_addr = load_config(config_path)?;
+    if let Some(&records) = matches.get_one::<usize>("log-throughput") {
+        return log_throughput(records).await;
+    }
+    let server_addrs: Vec<String> = match matches.get_many::<String>("servers") {
+        Some(servers) => servers.cloned().collect(),
+        None => vec![server_addr_from_config(matches.get_one::<String>("config").unwrap())?],
+    };
+    if let Some(&keys) = matches.get_one::<usize>("list-contention") {
+        return list_contention(&server_addrs[0], keys).await;
+    }
+    if let Some(&tasks) = matches.get_one::<usize>("lock-contention") {
+        return lock_contention(&server_addrs[0], tasks).await;
+    }
     let shared_connection = matches.get_flag("shared");
     let full_test = matches.get_flag("full");
+    let verify = matches.get_flag("verify");
+    let quiet = matches.get_flag("quiet");
+    let think_ms = *matches.get_one::<u64>("think-ms").unwrap();
+    let think_jitter_ms = *matches.get_one::<u64>("think-jitter-ms").unwrap();
+    let progress = Arc::new(Progress::new());
+    let slow_threshold = match matches.get_one::<String>("slow-threshold") {
+        Some(text) => Some(parse_threshold(text).ok_or_else(|| format!("Invalid --slow-threshold: {}", text))?),
+        None => None,
+    };
+    let (slow_log, slow_writer) = match slow_threshold {
+        Some(threshold) => {
+            let (log, writer) = match matches.get_one::<String>("slow-log") {
+                Some(path) => SlowLog::start(threshold, tokio::fs::File::create(path).await?),
+                None => SlowLog::start(threshold, tokio::io::stderr()),
+            };
+            (Some(log), Some(writer))
+        }
+        None => (None, None),
+    };
 
     let config = TestConfig {
-        server_addr,
+        server_addrs,
         num_users: 5,
         operations_per_user: 10000,
         shared_connection,
         full_test,
+        verify,
+        think_time: Duration::from_millis(think_ms),
+        think_jitter: Duration::from_millis(think_jitter_ms),
+        progress: Arc::clone(&progress),
+        slow_log,
     };
 
     println!("Load Test Configuration:");
@@ -268,12 +297,43 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         "├── Test Mode: {}",
         if config.full_test { "full comprehensive" } else { "batch GET only" }
     );
-    println!("└── Server: {}", config.server_addr);
+    if config.verify {
+        println!("├── Write Verification: on");
+    }
+    if think_ms > 0 || think_jitter_ms > 0 {
+        println!("├── Think Time: {}ms + 0-{}ms jitter", think_ms, think_jitter_ms);
+    }
+    if let Some(threshold) = slow_threshold {
+        println!(
+            "├── Slow Log: >= {}ms to {}",
+            threshold.as_millis(),
+            matches.get_one::<String>("slow-log").map(String::as_str).unwrap_or("stderr")
+        );
+    }
+    println!("└── Server: {}", config.server_addrs.join(", "));
     println!();
     println!("Starting test execution...");
 
     let test_start = Instant::now();
-    let results = run_test(config).await?;
+    let reporter = if quiet {
+        None
+    } else {
+        Some(tokio::spawn(report_progress(progress)))
+    };
+    let server_addrs = config.server_addrs.clone();
+    let statuses_before = server_statuses(&server_addrs).await;
+    let results = run_test(config).await;
+    if let Some(reporter) = reporter {
+        reporter.abort();
+    }
+    let run = results?;
+    let results = run.results;
+    // Every task has finished, so the writer stops once it has drained the channel
+    let slow_ops = match slow_writer {
+        Some(writer) => writer.await?,
+        None => Vec::new(),
+    };
+    let statuses_after = server_statuses(&server_addrs).await;
     let total_duration = test_start.elapsed();
 
     let total = results.len();
@@ -306,6 +366,54 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    if server_addrs.len() > 1 {
+        println!("\nPer-Server Breakdown:");
+        let summaries = summarize_by_server(&results, server_addrs.len());
+        for (addr, summary) in server_addrs.iter().zip(summaries) {
+            println!(
+                "  {}: {} ops, {:.1}% success, avg {:.2}ms",
+                addr,
+                summary.operations,
+                (summary.successful as f64 / summary.operations.max(1) as f64) * 100.0,
+                summary.average_latency().as_secs_f64() * 1000.0
+            );
+        }
+    }
+
+    let growth: Vec<_> = server_addrs
+        .iter()
+        .zip(statuses_before.iter().zip(&statuses_after))
+        .filter_map(|(addr, pair)| match pair {
+            (Some(before), Some(after)) => Some((addr, before, after)),
+            _ => None,
+        })
+        .collect();
+    if !growth.is_empty() {
+        println!("\nServer Growth (STATUS before -> after):");
+        for (addr, before, after) in growth {
+            println!(
+                "  {}: keys {} -> {} ({:+}), mem ~{} -> ~{} bytes ({:+})",
+                addr,
+                before.keys,
+                after.keys,
+                after.keys as i64 - before.keys as i64,
+                before.mem_bytes,
+                after.mem_bytes,
+                after.mem_bytes as i64 - before.mem_bytes as i64
+            );
+        }
+    }
+
+    if verify {
+        println!("\nConsistency Violations: {}", run.violations.len());
+        for violation in run.violations.iter().take(10) {
+            println!("  {}", violation);
+        }
+        if run.violations.len() > 10 {
+            println!("  ... and {} more", run.violations.len() - 10);
+        }
+    }
+
     let mut buckets = [
         (10, 0),
         (50, 0),
@@ -364,6 +472,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         (buckets[6].1 as f64 / successful as f64) * 100.0
     );
 
+    if let Some(threshold) = slow_threshold {
+        println!(
+            "\nSlowest Operations (>= {}ms): {} total",
+            threshold.as_millis(),
+            slow_ops.len()
+        );
+        for op in slowest(&slow_ops, SLOWEST_REPORTED) {
+            println!("  {}", op);
+        }
+    }
+
     println!(
         "\nTotal Test Duration: {:.2}s",
         total_duration.as_secs_f64()
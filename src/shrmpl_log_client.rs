@@ -1,8 +1,12 @@
+use crate::config::ConfigKey;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
-use tokio::time::{timeout, Duration};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::time::{timeout, timeout_at, Duration, Instant};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum LogLevel {
     Debug,
     Info,
@@ -11,6 +15,7 @@ pub enum LogLevel {
 }
 
 impl LogLevel {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(level: &str) -> Self {
         match level.to_uppercase().as_str() {
             "DEBUG" => LogLevel::Debug,
@@ -20,18 +25,160 @@ impl LogLevel {
             _ => LogLevel::Info, // default
         }
     }
-    
+
     pub fn should_log(&self, message_level: &LogLevel) -> bool {
-        match (self, message_level) {
-            (LogLevel::Debug, _) => true,
-            (LogLevel::Info, LogLevel::Info | LogLevel::Warn | LogLevel::Error) => true,
-            (LogLevel::Warn, LogLevel::Warn | LogLevel::Error) => true,
-            (LogLevel::Error, LogLevel::Error) => true,
-            _ => false,
-        }
+        matches!(
+            (self, message_level),
+            (LogLevel::Debug, _)
+                | (
+                    LogLevel::Info,
+                    LogLevel::Info | LogLevel::Warn | LogLevel::Error
+                )
+                | (LogLevel::Warn, LogLevel::Warn | LogLevel::Error)
+                | (LogLevel::Error, LogLevel::Error)
+        )
+    }
+}
+
+/// SERVER_NAME value (and the behaviour when it is unset) that names log records
+/// after the OS hostname.
+pub const AUTO_HOST: &str = "auto";
+
+/// Logging keys read by `Logger::from_config` and their defaults, the same for every
+/// binary. Server schemas repeat these entries so their `--init` templates list them.
+/// SEND_LOG defaults to true because an empty SLOG_DEST already keeps records local.
+pub const CONFIG_SCHEMA: &[ConfigKey] = &[
+    ConfigKey {
+        name: "SLOG_DEST",
+        default: Some(""),
+        description: "host:port of the shrmpl-log server; empty disables remote logging",
+    },
+    ConfigKey {
+        name: "SERVER_NAME",
+        default: Some(AUTO_HOST),
+        description: "Host name reported in log records; auto uses the OS hostname",
+    },
+    ConfigKey {
+        name: "SEND_LOG",
+        default: Some("true"),
+        description: "Send log records to SLOG_DEST (true/false)",
+    },
+    ConfigKey {
+        name: "LOG_LEVEL",
+        default: Some("INFO"),
+        description: "Minimum level to log: DEBUG, INFO, WARN or ERROR",
+    },
+    ConfigKey {
+        name: "LOG_CONSOLE",
+        default: Some("true"),
+        description: "Print log messages to stdout (true/false)",
+    },
+    ConfigKey {
+        name: "SEND_ACTV",
+        default: Some("false"),
+        description: "Also send ACTV (activity) records to SLOG_DEST (true/false)",
+    },
+    ConfigKey {
+        name: "LOG_FILE",
+        default: Some(""),
+        description: "Also append records to PREFIX-YYYYMMDD.log locally; empty disables it",
+    },
+    ConfigKey {
+        name: "SLOG_BATCH_MAX",
+        default: Some("1"),
+        description: "Records sent to SLOG in one write over a kept-open connection; 1 sends each on its own",
+    },
+    ConfigKey {
+        name: "SLOG_BATCH_DELAY_MS",
+        default: Some("5"),
+        description: "Longest a queued record waits for a batch to fill when SLOG_BATCH_MAX is above 1",
+    },
+];
+
+// Used as the host when SERVER_NAME is auto and the OS hostname can't be read
+const FALLBACK_HOST: &str = "shrmpl";
+
+// The configured value, or the default from CONFIG_SCHEMA
+fn setting<'a>(config: &'a HashMap<String, String>, key: &str) -> &'a str {
+    match config.get(key) {
+        Some(value) => value,
+        None => CONFIG_SCHEMA
+            .iter()
+            .find(|k| k.name == key)
+            .and_then(|k| k.default)
+            .unwrap_or(""),
     }
 }
 
+// Anything other than true/false counts as unset
+fn flag(config: &HashMap<String, String>, key: &str) -> bool {
+    setting(config, key)
+        .parse()
+        .unwrap_or_else(|_| setting(&HashMap::new(), key) == "true")
+}
+
+/// Width of the HOST field in an SLOG record.
+const HOST_WIDTH: usize = 32;
+
+/// Host field for a configured SERVER_NAME: the name itself, or for `auto`/unset the
+/// OS hostname cut to 32 bytes. Falls back to `fallback` if the hostname can't be read.
+pub fn resolve_host(server_name: Option<&str>, fallback: &str) -> String {
+    match server_name {
+        Some(name) if name != AUTO_HOST => name.to_string(),
+        _ => os_hostname()
+            .map(|name| truncate(&name, HOST_WIDTH).to_string())
+            .unwrap_or_else(|| fallback.to_string()),
+    }
+}
+
+fn os_hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    let name = String::from_utf8_lossy(&buf[..len]).into_owned();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+// Cuts at a char boundary so a non-ASCII name can't split a character
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+// Records queued for the batching sender before `log` has to wait for room
+const BATCH_QUEUE_LEN: usize = 4096;
+
+// With SLOG_BATCH_MAX above 1, a background task owns the SLOG connection and sends
+// queued records several to a write
+struct Batcher {
+    max_records: usize,
+    max_delay: Duration,
+    // Started by the first record, so a Logger can be built outside a runtime
+    tx: OnceLock<mpsc::Sender<Queued>>,
+}
+
+enum Queued {
+    Line(String),
+    // Answered once every record queued before it has been written
+    Flush(oneshot::Sender<()>),
+}
+
+// Local copy of the records SLOG would get, in daily files named like shrmpl-log-srv's
+struct LogFile {
+    prefix: String,
+    // Date (YYYYMMDD) and handle of the file being appended to
+    current: Mutex<Option<(String, tokio::fs::File)>>,
+}
+
 #[derive(Clone)]
 pub struct Logger {
     pub dest: String,
@@ -40,11 +187,99 @@ pub struct Logger {
     pub log_console: bool,
     pub send_actv: bool,
     pub send_log: bool,
+    file: Option<Arc<LogFile>>,
+    batch: Option<Arc<Batcher>>,
 }
 
 impl Logger {
-    pub fn new(dest: String, host: String, log_level: LogLevel, log_console: bool, send_actv: bool, send_log: bool) -> Self {
-        Self { dest, host, log_level, log_console, send_actv, send_log }
+    pub fn new(
+        dest: String,
+        host: String,
+        log_level: LogLevel,
+        log_console: bool,
+        send_actv: bool,
+        send_log: bool,
+    ) -> Self {
+        Self {
+            dest,
+            host,
+            log_level,
+            log_console,
+            send_actv,
+            send_log,
+            file: None,
+            batch: None,
+        }
+    }
+
+    /// Also appends every record to `<prefix>-YYYYMMDD.log`, whether or not SLOG is
+    /// enabled or reachable. An empty prefix leaves the file off.
+    pub fn with_log_file(mut self, prefix: &str) -> Self {
+        self.file = if prefix.is_empty() {
+            None
+        } else {
+            Some(Arc::new(LogFile {
+                prefix: prefix.to_string(),
+                current: Mutex::new(None),
+            }))
+        };
+        self
+    }
+
+    /// Hands SLOG records to a background task that writes up to `max_records` of
+    /// them at once over a kept-open connection, waiting at most `max_delay` for a
+    /// batch to fill; `log` returns once its record is queued. With `max_records` of
+    /// 0 or 1 every record gets its own connection, as before.
+    pub fn with_batching(mut self, max_records: usize, max_delay: Duration) -> Self {
+        self.batch = (max_records > 1).then(|| {
+            Arc::new(Batcher {
+                max_records,
+                max_delay,
+                tx: OnceLock::new(),
+            })
+        });
+        self
+    }
+
+    /// Waits until every record queued for SLOG so far has been written or failed.
+    /// Returns at once without batching.
+    pub async fn flush(&self) {
+        let Some(tx) = self.batch.as_ref().and_then(|batch| batch.tx.get()) else {
+            return;
+        };
+        let (done_tx, done_rx) = oneshot::channel();
+        if tx.send(Queued::Flush(done_tx)).await.is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+
+    /// The LOG_FILE prefix, if records are also written locally.
+    pub fn log_file(&self) -> Option<&str> {
+        self.file.as_ref().map(|file| file.prefix.as_str())
+    }
+
+    /// Builds the logger from the keys in `CONFIG_SCHEMA`, using their defaults for
+    /// anything unset.
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        Self::from_config_with(config, None)
+    }
+
+    /// Like `from_config`, but `name_override` (when given) replaces SERVER_NAME.
+    pub fn from_config_with(config: &HashMap<String, String>, name_override: Option<&str>) -> Self {
+        let server_name = name_override.unwrap_or_else(|| setting(config, "SERVER_NAME"));
+        Self::new(
+            setting(config, "SLOG_DEST").to_string(),
+            resolve_host(Some(server_name), FALLBACK_HOST),
+            LogLevel::from_str(setting(config, "LOG_LEVEL")),
+            flag(config, "LOG_CONSOLE"),
+            flag(config, "SEND_ACTV"),
+            flag(config, "SEND_LOG"),
+        )
+        .with_log_file(setting(config, "LOG_FILE"))
+        .with_batching(
+            setting(config, "SLOG_BATCH_MAX").parse().unwrap_or(1),
+            Duration::from_millis(setting(config, "SLOG_BATCH_DELAY_MS").parse().unwrap_or(5)),
+        )
     }
 
     pub async fn log(&self, level: &str, code: &str, message: &str) {
@@ -56,16 +291,24 @@ impl Logger {
             "ACTV" => LogLevel::Info, // Treat ACTV as INFO level
             _ => LogLevel::Info,
         };
-        
+
         // Console output if enabled and level meets threshold
         if self.log_console && self.log_level.should_log(&message_level) {
             println!("{}", message);
         }
-        
-        // Send to SLOG if enabled and not ACTV (or ACTV is enabled)
-        let should_send = self.send_log && !self.dest.is_empty() && 
-                        (level != "ACTV" || self.send_actv);
-        
+
+        // The file and SLOG get every level; ACTV only when it is enabled
+        let is_record = level != "ACTV" || self.send_actv;
+
+        if let (Some(file), true) = (&self.file, is_record) {
+            if let Err(e) = self.write_file(file, level, code, message).await {
+                eprintln!("Failed to write log to LOG_FILE: {}", e);
+            }
+        }
+
+        // Send to SLOG if enabled
+        let should_send = self.send_log && !self.dest.is_empty() && is_record;
+
         if should_send {
             if let Err(e) = self.send_log(level, code, message).await {
                 eprintln!("Failed to send log to SLOG: {}", e);
@@ -93,21 +336,207 @@ impl Logger {
         self.log("DEBG", code, message).await;
     }
 
-
+    /// Like `log`, but runs in a spawned task so the caller never waits on a slow or
+    /// unreachable SLOG. For startup records that must not hold up serving; they may
+    /// reach SLOG after records logged later.
+    pub fn log_detached(&self, level: &str, code: &str, message: &str) {
+        let logger = self.clone();
+        let (level, code, message) = (level.to_string(), code.to_string(), message.to_string());
+        tokio::spawn(async move {
+            logger.log(&level, &code, &message).await;
+        });
+    }
 
     // Network logging uses proper error propagation to allow graceful degradation
     // when SLOG server is unavailable - errors are logged locally but don't crash
-    async fn send_log(&self, level: &str, code: &str, message: &str) -> Result<(), Box<dyn std::error::Error>> {
+    async fn send_log(
+        &self,
+        level: &str,
+        code: &str,
+        message: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         // Format per SLOG protocol: [LVL(4)] [HOST(32)] [CODE(12)] [LEN(5)]: [MSG]\n
-        let lvl = format!("{:<4}", &level[..level.len().min(4)]);
-        let host_padded = format!("{:<32}", &self.host[..self.host.len().min(32)]);
-        let code_padded = format!("{:<12}", &code[..code.len().min(12)]);
-        let len_str = format!("{:05}", message.len());
-        let line = format!("{} {} {} {}: {}\n", lvl, host_padded, code_padded, len_str, message);
-        
+        let line = format!(
+            "{} {:05}: {}\n",
+            self.header(level, code), message.len(), message
+        );
+
+        if let Some(batch) = &self.batch {
+            let tx = batch.tx.get_or_init(|| {
+                let (tx, rx) = mpsc::channel(BATCH_QUEUE_LEN);
+                tokio::spawn(send_batches(
+                    self.dest.clone(),
+                    rx,
+                    batch.max_records,
+                    batch.max_delay,
+                ));
+                tx
+            });
+            tx.send(Queued::Line(line)).await.map_err(|_| "batching sender stopped")?;
+            return Ok(());
+        }
+
         let stream = timeout(Duration::from_secs(5), TcpStream::connect(&self.dest)).await??;
         let mut stream = stream;
         timeout(Duration::from_secs(5), stream.write_all(line.as_bytes())).await??;
         Ok(())
     }
-}
\ No newline at end of file
+
+    // Padded [LVL(4)] [HOST(32)] [CODE(12)] fields shared by SLOG and LOG_FILE lines
+    fn header(&self, level: &str, code: &str) -> String {
+        format!(
+            "{:<4} {:<32} {:<12}",
+            &level[..level.len().min(4)],
+            truncate(&self.host, HOST_WIDTH),
+            &code[..code.len().min(12)]
+        )
+    }
+
+    // Same line shrmpl-log-srv writes: [TS(24)] [LVL] [HOST] [CODE] [LEN(4)]: [MSG]\n.
+    // Each line is a single unbuffered write so it survives a crash; after an error
+    // the file is reopened on the next call.
+    async fn write_file(
+        &self,
+        file: &LogFile,
+        level: &str,
+        code: &str,
+        message: &str,
+    ) -> std::io::Result<()> {
+        let now = chrono::Utc::now();
+        let date = now.format("%Y%m%d").to_string();
+        let line = format!(
+            "{} {} {:04}: {}\n",
+            now.format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            self.header(level, code),
+            message.len(),
+            message
+        );
+
+        let mut current = file.current.lock().await;
+        if current.as_ref().is_none_or(|(open_date, _)| *open_date != date) {
+            let path = format!("{}-{}.log", file.prefix, date);
+            let handle = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            *current = Some((date, handle));
+        }
+        let (_, handle) = current.as_mut().unwrap();
+        // tokio hands the write to a blocking thread; flush waits for it to land
+        let mut result = handle.write_all(line.as_bytes()).await;
+        if result.is_ok() {
+            result = handle.flush().await;
+        }
+        if result.is_err() {
+            *current = None;
+        }
+        result
+    }
+}
+
+// Drains the queue a batch at a time: the first record plus whatever arrives within
+// max_delay, up to max_records, concatenated into a single write. SLOG reads records
+// line by line, so batching needs no framing of its own.
+async fn send_batches(
+    dest: String,
+    mut rx: mpsc::Receiver<Queued>,
+    max_records: usize,
+    max_delay: Duration,
+) {
+    let mut stream: Option<TcpStream> = None;
+    while let Some(first) = rx.recv().await {
+        let deadline = Instant::now() + max_delay;
+        let mut buf = String::new();
+        let mut records = 0;
+        let mut flushes = Vec::new();
+        let mut next = Some(first);
+        while let Some(queued) = next.take() {
+            match queued {
+                Queued::Line(line) => {
+                    buf.push_str(&line);
+                    records += 1;
+                }
+                // Write what is queued straight away rather than waiting out the delay
+                Queued::Flush(done) => {
+                    flushes.push(done);
+                    break;
+                }
+            }
+            if records < max_records {
+                next = timeout_at(deadline, rx.recv()).await.ok().flatten();
+            }
+        }
+        if records > 0 {
+            if let Err(e) = write_batch(&dest, &mut stream, &buf).await {
+                stream = None;
+                eprintln!("Failed to send {} log records to SLOG: {}", records, e);
+            }
+        }
+        for done in flushes {
+            let _ = done.send(());
+        }
+    }
+}
+
+// A kept-open connection can still fail on write if SLOG went away after the probe;
+// the rest of the batch then gets one more try on a new connection. What the old
+// socket had already accepted is not sent again, so records written into a half-open
+// connection are lost; a record cut off part-way is resent whole.
+async fn write_batch(
+    dest: &str,
+    stream: &mut Option<TcpStream>,
+    buf: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if stream.as_ref().is_some_and(|open| !still_open(open)) {
+        *stream = None;
+    }
+    let reused = stream.is_some();
+    let mut written = 0;
+    match write_once(dest, stream, buf, &mut written).await {
+        Err(_) if reused => {
+            *stream = None;
+            let mut resume = buf[..written].rfind('\n').map_or(0, |end| end + 1);
+            Ok(write_once(dest, stream, buf, &mut resume).await?)
+        }
+        result => Ok(result?),
+    }
+}
+
+// SLOG writes back on a kept-open connection too (a UPONG every minute, and STAT
+// replies), so pending bytes are read and dropped; only end of stream or an error
+// means it closed the connection
+fn still_open(stream: &TcpStream) -> bool {
+    let mut pending = [0u8; 256];
+    loop {
+        match stream.try_read(&mut pending) {
+            Ok(0) => return false,
+            Ok(_) => continue,
+            Err(e) => return e.kind() == std::io::ErrorKind::WouldBlock,
+        }
+    }
+}
+
+// Writes buf from `written` on, advancing it as the socket takes bytes so a caller
+// knows how far a failed write got
+async fn write_once(
+    dest: &str,
+    stream: &mut Option<TcpStream>,
+    buf: &str,
+    written: &mut usize,
+) -> std::io::Result<()> {
+    if stream.is_none() {
+        *stream = Some(timeout(Duration::from_secs(5), TcpStream::connect(dest)).await??);
+    }
+    let open = stream.as_mut().unwrap();
+    timeout(Duration::from_secs(5), async {
+        while *written < buf.len() {
+            match open.write(&buf.as_bytes()[*written..]).await? {
+                0 => return Err(std::io::ErrorKind::WriteZero.into()),
+                n => *written += n,
+            }
+        }
+        Ok(())
+    })
+    .await?
+}
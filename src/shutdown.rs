@@ -0,0 +1,26 @@
+// Process signals handled by the servers. Each binary waits here and then sends on
+// the broadcast channel its server core listens to, or notifies it.
+
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+
+/// Resolves on the first SIGINT (Ctrl-C) or SIGTERM.
+pub async fn signal_received() {
+    // Installing a handler only fails if the runtime has no signal driver, which is a
+    // startup bug rather than something to recover from
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+}
+
+/// Notifies `notify` on every SIGUSR1, for as long as the process runs.
+pub async fn forward_sigusr1(notify: Arc<Notify>) {
+    let mut sigusr1 =
+        signal(SignalKind::user_defined1()).expect("Failed to install SIGUSR1 handler");
+    while sigusr1.recv().await.is_some() {
+        notify.notify_one();
+    }
+}
@@ -0,0 +1,190 @@
+// Access log for shrmpl-vault-srv: one line per request, kept apart from the
+// operational records that go through the Logger. Request handlers queue entries on
+// a channel and never wait on the disk; a single task owns the buffered file and
+// rotates it daily to `<ACCESS_LOG_PATH>-YYYYMMDD.log`, the shrmpl-log-srv scheme.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use ring::digest;
+use tokio::fs;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::error;
+
+// Entries queued beyond this are dropped and counted as a write failure
+const QUEUE_LEN: usize = 4096;
+
+/// One served request, written as
+/// `TIMESTAMP CLIENT_IP FINGERPRINT "METHOD PATH" STATUS BYTES DURATION_MS`.
+#[derive(Clone, Debug)]
+pub struct AccessEntry {
+    pub time: DateTime<Utc>,
+    pub client_ip: String,
+    /// From `fingerprint`, or `-` when the request carried no secret.
+    pub fingerprint: String,
+    pub method: String,
+    /// Request path without the query string, which holds the secret.
+    pub path: String,
+    pub status: u16,
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+impl AccessEntry {
+    pub fn line(&self) -> String {
+        format!(
+            "{} {} {} \"{} {}\" {} {} {}\n",
+            self.time.format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            self.client_ip,
+            self.fingerprint,
+            self.method,
+            self.path,
+            self.status,
+            self.bytes,
+            self.duration.as_millis()
+        )
+    }
+}
+
+/// First 8 bytes of the secret's SHA-256 in hex: enough to tell secrets apart in
+/// the log without writing them out.
+pub fn fingerprint(secret: &str) -> String {
+    digest::digest(&digest::SHA256, secret.as_bytes()).as_ref()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+struct Shared {
+    lines: AtomicU64,
+    failed: AtomicBool,
+}
+
+/// Handle for queueing entries; cheap to clone into every request.
+#[derive(Clone)]
+pub struct AccessLog {
+    tx: Option<mpsc::Sender<Option<AccessEntry>>>,
+    shared: Arc<Shared>,
+}
+
+impl AccessLog {
+    /// Logs nothing, for an empty ACCESS_LOG_PATH.
+    pub fn disabled() -> AccessLog {
+        AccessLog {
+            tx: None,
+            shared: Arc::new(Shared {
+                lines: AtomicU64::new(0),
+                failed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Starts the writer task for files named `<path>-YYYYMMDD.log`.
+    pub fn start(path: &str) -> (AccessLog, JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel(QUEUE_LEN);
+        let log = AccessLog {
+            tx: Some(tx),
+            ..AccessLog::disabled()
+        };
+        let writer = tokio::spawn(writer_loop(rx, path.to_string(), log.shared.clone()));
+        (log, writer)
+    }
+
+    /// Queues `entry` without waiting; a full queue counts as a failed write.
+    pub fn record(&self, entry: AccessEntry) {
+        if let Some(tx) = &self.tx {
+            if tx.try_send(Some(entry)).is_err() {
+                self.shared.fail("access log queue is full");
+            }
+        }
+    }
+
+    /// Lines written to disk so far.
+    pub fn lines(&self) -> u64 {
+        self.shared.lines.load(Ordering::Relaxed)
+    }
+
+    /// Set once any entry could not be written, and stays set.
+    pub fn write_failed(&self) -> bool {
+        self.shared.failed.load(Ordering::Relaxed)
+    }
+
+    /// Asks the writer to flush and stop once the entries queued so far are written.
+    pub async fn close(&self) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(None).await;
+        }
+    }
+}
+
+impl Shared {
+    // Reported through tracing only on the first failure, so a full disk can't flood
+    // the console
+    fn fail(&self, reason: &str) {
+        if !self.failed.swap(true, Ordering::Relaxed) {
+            error!("Access log write failed: {}", reason);
+        }
+    }
+}
+
+// Flushes whenever the queue runs dry, so the file trails the requests by at most
+// one batch
+async fn writer_loop(
+    mut rx: mpsc::Receiver<Option<AccessEntry>>,
+    path: String,
+    shared: Arc<Shared>,
+) {
+    let mut current_date = String::new();
+    let mut writer: Option<BufWriter<fs::File>> = None;
+    while let Some(Some(entry)) = rx.recv().await {
+        let date = entry.time.format("%Y%m%d").to_string();
+        if date != current_date || writer.is_none() {
+            if let Some(mut w) = writer.take() {
+                if let Err(e) = w.flush().await {
+                    shared.fail(&e.to_string());
+                }
+            }
+            writer = match open_file(&path, &date).await {
+                Ok(w) => Some(w),
+                Err(e) => {
+                    shared.fail(&format!("{}-{}.log: {}", path, date, e));
+                    None
+                }
+            };
+            current_date = date;
+        }
+        // Without a file the entry is dropped; the next entry retries the open
+        let Some(w) = writer.as_mut() else {
+            continue;
+        };
+        match w.write_all(entry.line().as_bytes()).await {
+            Ok(()) => {
+                shared.lines.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => shared.fail(&e.to_string()),
+        }
+        if rx.is_empty() {
+            if let Err(e) = w.flush().await {
+                shared.fail(&e.to_string());
+            }
+        }
+    }
+    if let Some(mut w) = writer {
+        if let Err(e) = w.flush().await {
+            shared.fail(&e.to_string());
+        }
+    }
+}
+
+async fn open_file(path: &str, date: &str) -> std::io::Result<BufWriter<fs::File>> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(format!("{}-{}.log", path, date))
+        .await?;
+    Ok(BufWriter::new(file))
+}
@@ -0,0 +1,164 @@
+// Read cache for files served by shrmpl-vault-srv. Each lookup stats the file and
+// only reads it (through tokio::fs, off the worker threads) when the cached copy is
+// missing or its mtime/length no longer match. The least recently used entries are
+// evicted once the entry count or total size goes over the configured limits.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug)]
+pub struct CachedFile {
+    pub bytes: Arc<[u8]>,
+    pub mtime: SystemTime,
+    /// Quoted strong validator built from the length and mtime.
+    pub etag: String,
+}
+
+/// What `stat` reports about a file without reading it.
+#[derive(Clone, Debug)]
+pub struct FileMeta {
+    pub len: u64,
+    pub mtime: SystemTime,
+    /// The ETag a GET of the unchanged file carries.
+    pub etag: String,
+}
+
+/// Size, mtime and ETag of the regular file at `path`, bypassing the cache.
+pub async fn stat(path: &Path) -> io::Result<FileMeta> {
+    let metadata = tokio::fs::metadata(path).await?;
+    if !metadata.is_file() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file"));
+    }
+    let mtime = metadata.modified()?;
+    Ok(FileMeta {
+        len: metadata.len(),
+        mtime,
+        etag: etag(metadata.len() as usize, mtime),
+    })
+}
+
+struct Entry {
+    file: CachedFile,
+    // Value of FileCache::clock when last served, for LRU eviction
+    last_used: AtomicU64,
+}
+
+pub struct FileCache {
+    enabled: bool,
+    max_bytes: usize,
+    max_entries: usize,
+    entries: RwLock<HashMap<PathBuf, Entry>>,
+    clock: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl FileCache {
+    /// A cache holding at most `max_entries` files and `max_bytes` of contents. Files
+    /// larger than `max_bytes` are served but never cached.
+    pub fn new(max_bytes: usize, max_entries: usize) -> FileCache {
+        FileCache {
+            enabled: true,
+            max_bytes,
+            max_entries,
+            entries: RwLock::new(HashMap::new()),
+            clock: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads every file from disk; only the miss counter is kept.
+    pub fn disabled() -> FileCache {
+        FileCache {
+            enabled: false,
+            ..FileCache::new(0, 0)
+        }
+    }
+
+    /// Contents of `path`, from the cache when the file is unchanged since it was read.
+    pub async fn read(&self, path: &Path) -> io::Result<CachedFile> {
+        let metadata = tokio::fs::metadata(path).await?;
+        let mtime = metadata.modified()?;
+        if let Some(file) = self.lookup(path, mtime, metadata.len()) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(file);
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let bytes: Arc<[u8]> = tokio::fs::read(path).await?.into();
+        // Stamped with the mtime seen before reading, so a write racing the read
+        // shows up as a changed mtime on the next lookup
+        let file = CachedFile {
+            etag: etag(bytes.len(), mtime),
+            bytes,
+            mtime,
+        };
+        if self.enabled && file.bytes.len() <= self.max_bytes {
+            self.insert(path, file.clone());
+        }
+        Ok(file)
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Number of cached files and their total size in bytes.
+    pub fn usage(&self) -> (usize, usize) {
+        let entries = self.entries.read().unwrap();
+        let bytes = entries.values().map(|e| e.file.bytes.len()).sum();
+        (entries.len(), bytes)
+    }
+
+    fn lookup(&self, path: &Path, mtime: SystemTime, len: u64) -> Option<CachedFile> {
+        let entries = self.entries.read().unwrap();
+        let entry = entries.get(path)?;
+        if entry.file.mtime != mtime || entry.file.bytes.len() as u64 != len {
+            return None;
+        }
+        entry.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(entry.file.clone())
+    }
+
+    fn insert(&self, path: &Path, file: CachedFile) {
+        let mut entries = self.entries.write().unwrap();
+        let entry = Entry {
+            file,
+            last_used: AtomicU64::new(self.tick()),
+        };
+        entries.insert(path.to_path_buf(), entry);
+
+        let mut total: usize = entries.values().map(|e| e.file.bytes.len()).sum();
+        while entries.len() > self.max_entries || total > self.max_bytes {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used.load(Ordering::Relaxed))
+                .map(|(path, _)| path.clone());
+            match oldest.and_then(|path| entries.remove(&path)) {
+                Some(evicted) => total -= evicted.file.bytes.len(),
+                None => break,
+            }
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+fn etag(len: usize, mtime: SystemTime) -> String {
+    let nanos = mtime
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", len, nanos)
+}
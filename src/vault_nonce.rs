@@ -0,0 +1,81 @@
+// Replay protection for shrmpl-vault-srv: each nonce is accepted once within the
+// window. The number of nonces remembered is capped, evicting the oldest, so a flood
+// of requests can't grow the map without bound; an evicted nonce could be replayed,
+// so the cap should cover a full window of traffic.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Longest nonce accepted; longer ones are rejected rather than stored.
+pub const MAX_NONCE_LEN: usize = 128;
+
+#[derive(Clone)]
+pub struct NonceStore {
+    seen: Arc<Mutex<Seen>>,
+    window: Duration,
+    max_tracked: usize,
+}
+
+#[derive(Default)]
+struct Seen {
+    // When each nonce was first seen
+    first: HashMap<String, Instant>,
+    // The same nonces in the order they arrived, so the oldest is always at the front
+    // and expiring or evicting it takes constant time
+    order: VecDeque<(String, Instant)>,
+}
+
+impl Seen {
+    // False once there is nothing left to drop
+    fn pop_oldest(&mut self) -> bool {
+        match self.order.pop_front() {
+            Some((nonce, _)) => {
+                self.first.remove(&nonce);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl NonceStore {
+    /// Remembers nonces for `window`, tracking at most `max_tracked` (at least one).
+    pub fn new(window: Duration, max_tracked: usize) -> NonceStore {
+        NonceStore {
+            seen: Arc::new(Mutex::new(Seen::default())),
+            window,
+            max_tracked: max_tracked.max(1),
+        }
+    }
+
+    /// Records `nonce` and returns whether it is new: false for a replay within the window.
+    pub fn check(&self, nonce: &str) -> bool {
+        self.check_at(nonce, Instant::now())
+    }
+
+    /// `check` with the clock passed in, for tests.
+    pub fn check_at(&self, nonce: &str, now: Instant) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        // Those past the window are all at the front
+        while seen
+            .order
+            .front()
+            .is_some_and(|(_, first)| now.duration_since(*first) >= self.window)
+        {
+            seen.pop_oldest();
+        }
+        if seen.first.contains_key(nonce) {
+            return false;
+        }
+        while seen.first.len() >= self.max_tracked && seen.pop_oldest() {}
+        seen.first.insert(nonce.to_string(), now);
+        seen.order.push_back((nonce.to_string(), now));
+        true
+    }
+
+    /// Number of nonces currently remembered.
+    pub fn tracked(&self) -> usize {
+        self.seen.lock().unwrap().first.len()
+    }
+}
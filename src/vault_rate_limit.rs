@@ -0,0 +1,83 @@
+// Per-secret rate limiting for shrmpl-vault-srv: a sliding one-minute window of
+// request times for each key. The number of keys tracked is capped, evicting the
+// least recently seen, so a flood of distinct keys can't grow the map without bound.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+struct Window {
+    // Allowed requests within the last minute, oldest first
+    hits: Vec<Instant>,
+    // Last check for this key, allowed or not, for LRU eviction
+    last_seen: Instant,
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    windows: Arc<Mutex<HashMap<String, Window>>>,
+    max_requests_per_minute: u32,
+    max_tracked: usize,
+}
+
+impl RateLimiter {
+    /// Allows `max_requests_per_minute` per key, tracking at most `max_tracked` keys
+    /// (at least one). An evicted key starts over with an empty window.
+    pub fn new(max_requests_per_minute: u32, max_tracked: usize) -> RateLimiter {
+        RateLimiter {
+            windows: Arc::new(Mutex::new(HashMap::new())),
+            max_requests_per_minute,
+            max_tracked: max_tracked.max(1),
+        }
+    }
+
+    /// Records a request for `key` and returns whether it is within the limit.
+    pub fn check(&self, key: &str) -> bool {
+        let mut windows = self.windows.lock().unwrap();
+        let now = Instant::now();
+        if !windows.contains_key(key) && windows.len() >= self.max_tracked {
+            evict(&mut windows, now, self.max_tracked);
+        }
+
+        let window = windows.entry(key.to_string()).or_insert_with(|| Window {
+            hits: Vec::new(),
+            last_seen: now,
+        });
+        window.last_seen = now;
+        window.hits.retain(|&hit| now.duration_since(hit) < WINDOW);
+        if window.hits.len() < self.max_requests_per_minute as usize {
+            window.hits.push(now);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Number of keys currently tracked.
+    pub fn tracked(&self) -> usize {
+        self.windows.lock().unwrap().len()
+    }
+}
+
+// Makes room for one more key: drops keys with no requests left in the window, then
+// the least recently seen ones
+fn evict(windows: &mut HashMap<String, Window>, now: Instant, max_tracked: usize) {
+    windows.retain(|_, window| {
+        window
+            .hits
+            .last()
+            .is_some_and(|&hit| now.duration_since(hit) < WINDOW)
+    });
+    while windows.len() >= max_tracked {
+        let oldest = windows
+            .iter()
+            .min_by_key(|(_, window)| window.last_seen)
+            .map(|(key, _)| key.clone());
+        match oldest {
+            Some(key) => windows.remove(&key),
+            None => break,
+        };
+    }
+}
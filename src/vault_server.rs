@@ -0,0 +1,911 @@
+// shrmpl-vault server core: TLS/mTLS setup, the hyper service and request handling.
+// The shrmpl-vault-srv binary loads config and sets up logging; tests run the same
+// code in-process against an ephemeral port.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hyper::body::HttpBody;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode, Uri};
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{RootCertStore, ServerConfig};
+use rustls_pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::{error, info, warn};
+use x509_parser::prelude::*;
+
+use crate::accept_backoff::{self, AcceptBackoff};
+use crate::config::ConfigKey;
+use crate::proxy_protocol;
+use crate::sd_notify::{self, Notifier};
+use crate::shrmpl_log_client::Logger;
+use crate::vault_access_log::{self, AccessEntry, AccessLog};
+use crate::vault_cache::{self, FileCache};
+use crate::vault_nonce::{self, NonceStore};
+use crate::vault_rate_limit::RateLimiter;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub const CONFIG_SCHEMA: &[ConfigKey] = &[
+    ConfigKey {
+        name: "BIND_ADDR",
+        default: Some("0.0.0.0:7474"),
+        description: "Address and port to listen on",
+    },
+    ConfigKey {
+        name: "LOG_LEVEL",
+        default: Some("INFO"),
+        description: "Minimum level to log: DEBUG, INFO, WARN or ERROR",
+    },
+    ConfigKey {
+        name: "TLS_CERTIFICATE_PRIVKEY_PATH",
+        default: None,
+        description: "Server private key PEM, e.g. /etc/shrmpl/crt/shrmpl_vault_server_privkey.pem",
+    },
+    ConfigKey {
+        name: "TLS_CERTIFICATE_FULLCHAIN_PATH",
+        default: None,
+        description:
+            "Server certificate chain PEM, e.g. /etc/shrmpl/crt/shrmpl_vault_server_fullchain.pem",
+    },
+    ConfigKey {
+        name: "MTLS_CLIENT_CA_CERT_PATH",
+        default: None,
+        description:
+            "CA used to verify client certificates, e.g. /etc/shrmpl/crt/shrmpl_vault_mtls_ca.pem",
+    },
+    ConfigKey {
+        name: "CONFIG_DIR",
+        default: None,
+        description: "Directory holding the files served by the vault",
+    },
+    ConfigKey {
+        name: "ALLOWED_SECRETS",
+        default: None,
+        description: "Comma-separated secrets accepted in the ?secret= query parameter",
+    },
+    ConfigKey {
+        name: "RATE_LIMIT_REQUESTS_PER_MINUTE",
+        default: Some("60"),
+        description: "Requests allowed per secret per minute",
+    },
+    ConfigKey {
+        name: "RATE_LIMIT_MAX_TRACKED",
+        default: Some("10000"),
+        description:
+            "Secrets the rate limiter keeps windows for; the least recently seen are dropped",
+    },
+    ConfigKey {
+        name: "NONCE_WINDOW_SECS",
+        default: Some("0"),
+        description:
+            "Require a single-use nonce per request, remembered this long; 0 disables replay protection",
+    },
+    ConfigKey {
+        name: "NONCE_MAX_TRACKED",
+        default: Some("100000"),
+        description: "Nonces remembered for replay checks; the oldest are dropped first",
+    },
+    ConfigKey {
+        name: "SLOG_DEST",
+        default: Some(""),
+        description: "host:port of the shrmpl-log server; empty disables remote logging",
+    },
+    ConfigKey {
+        name: "SERVER_NAME",
+        default: Some("auto"),
+        description: "Host name reported in log records; auto uses the OS hostname",
+    },
+    ConfigKey {
+        name: "SEND_LOG",
+        default: Some("true"),
+        description: "Send log records to SLOG_DEST (true/false)",
+    },
+    ConfigKey {
+        name: "LOG_CONSOLE",
+        default: Some("true"),
+        description: "Print log messages to stdout (true/false)",
+    },
+    ConfigKey {
+        name: "SEND_ACTV",
+        default: Some("false"),
+        description: "Also send ACTV (activity) records to SLOG_DEST (true/false)",
+    },
+    ConfigKey {
+        name: "LOG_FILE",
+        default: Some(""),
+        description: "Also append records to PREFIX-YYYYMMDD.log locally; empty disables it",
+    },
+    ConfigKey {
+        name: "SLOG_BATCH_MAX",
+        default: Some("1"),
+        description:
+            "Records sent to SLOG in one write over a kept-open connection; 1 sends each on its own",
+    },
+    ConfigKey {
+        name: "SLOG_BATCH_DELAY_MS",
+        default: Some("5"),
+        description:
+            "Longest a queued record waits for a batch to fill when SLOG_BATCH_MAX is above 1",
+    },
+    ConfigKey {
+        name: "CACHE_DISABLED",
+        default: Some("false"),
+        description: "Read every file from disk instead of caching contents in memory (true/false)",
+    },
+    ConfigKey {
+        name: "CACHE_MAX_BYTES",
+        default: Some("1048576"),
+        description: "Total size of file contents kept in the read cache",
+    },
+    ConfigKey {
+        name: "CACHE_MAX_ENTRIES",
+        default: Some("256"),
+        description: "Number of files kept in the read cache",
+    },
+    ConfigKey {
+        name: "ACCESS_LOG_PATH",
+        default: Some(""),
+        description:
+            "Access log path prefix, written daily to PREFIX-YYYYMMDD.log; empty disables it",
+    },
+    ConfigKey {
+        name: "LOG_TLS_HANDSHAKE_FAILURES",
+        default: Some("true"),
+        description: "Send a TLSHANDSK warning to SLOG for each failed TLS handshake (true/false)",
+    },
+    accept_backoff::INITIAL_KEY,
+    accept_backoff::MAX_KEY,
+    proxy_protocol::KEY,
+];
+
+// Startup settings read from the config file
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub bind_addr: String,
+    pub cert_privkey_path: String,
+    pub cert_fullchain_path: String,
+    pub mtls_client_ca_cert_path: String,
+    pub config_dir: String,
+    pub allowed_secrets: Vec<String>,
+    pub rate_limit: u32,
+    pub rate_limit_max_tracked: usize,
+    /// How long a nonce stays used; zero means requests need no nonce.
+    pub nonce_window: Duration,
+    pub nonce_max_tracked: usize,
+    pub cache_disabled: bool,
+    pub cache_max_bytes: usize,
+    pub cache_max_entries: usize,
+    /// Prefix of the daily access log files; empty for no access log.
+    pub access_log_path: String,
+    /// Whether failed TLS handshakes are logged to SLOG as well as the console.
+    pub log_handshake_failures: bool,
+    pub accept_backoff: AcceptBackoff,
+    /// Whether connections must open with a PROXY protocol header naming the client.
+    pub proxy_protocol: bool,
+    /// systemd notifications; from_config leaves them off and the binary reads them from the environment.
+    pub notify: Notifier,
+}
+
+impl Settings {
+    pub fn from_config(config: &HashMap<String, String>) -> Settings {
+        let get = |key: &str| config.get(key).cloned().unwrap_or_default();
+        Settings {
+            bind_addr: config
+                .get("BIND_ADDR")
+                .cloned()
+                .unwrap_or_else(|| "0.0.0.0:7474".to_string()),
+            cert_privkey_path: get("TLS_CERTIFICATE_PRIVKEY_PATH"),
+            cert_fullchain_path: get("TLS_CERTIFICATE_FULLCHAIN_PATH"),
+            mtls_client_ca_cert_path: get("MTLS_CLIENT_CA_CERT_PATH"),
+            config_dir: get("CONFIG_DIR"),
+            allowed_secrets: get("ALLOWED_SECRETS")
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .collect(),
+            rate_limit: config
+                .get("RATE_LIMIT_REQUESTS_PER_MINUTE")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(60),
+            rate_limit_max_tracked: config
+                .get("RATE_LIMIT_MAX_TRACKED")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(10000),
+            nonce_window: Duration::from_secs(
+                config
+                    .get("NONCE_WINDOW_SECS")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+            ),
+            nonce_max_tracked: config
+                .get("NONCE_MAX_TRACKED")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(100000),
+            cache_disabled: config
+                .get("CACHE_DISABLED")
+                .map(|s| s == "true")
+                .unwrap_or(false),
+            cache_max_bytes: config
+                .get("CACHE_MAX_BYTES")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1024 * 1024),
+            cache_max_entries: config
+                .get("CACHE_MAX_ENTRIES")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(256),
+            access_log_path: get("ACCESS_LOG_PATH"),
+            log_handshake_failures: config
+                .get("LOG_TLS_HANDSHAKE_FAILURES")
+                .map(|s| s != "false")
+                .unwrap_or(true),
+            accept_backoff: AcceptBackoff::from_config(config),
+            proxy_protocol: proxy_protocol::enabled(config),
+            notify: Notifier::disabled(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct VaultState {
+    config_dir: String,
+    allowed_secrets: Vec<String>,
+    rate_limiter: RateLimiter,
+    /// None unless NONCE_WINDOW_SECS is set.
+    nonces: Option<NonceStore>,
+    cache: Arc<FileCache>,
+    access_log: AccessLog,
+    logger: Logger,
+}
+
+// What a new connection goes through before hyper gets it: the PROXY header when
+// enabled, then the TLS handshake
+struct Handshake {
+    tls_acceptor: TlsAcceptor,
+    use_proxy_protocol: bool,
+    log_handshake_failures: bool,
+    logger: Logger,
+}
+
+impl Handshake {
+    // None when the connection was dropped; the reason has been logged
+    async fn run(self, mut stream: TcpStream, peer: SocketAddr) -> Option<ClientStream> {
+        // The header comes before the TLS handshake, in plain text
+        let client = if self.use_proxy_protocol {
+            match proxy_protocol::read_header(&mut stream).await {
+                Ok(client) => client,
+                Err(e) => {
+                    let msg = format!("Dropped connection from {}: {}", peer, e);
+                    warn!("{}", msg);
+                    self.logger.log_detached("WARN", "VAULTPROXY", &msg);
+                    return None;
+                }
+            }
+        } else {
+            None
+        };
+        match self.tls_acceptor.accept(stream).await {
+            Ok(inner) => Some(ClientStream { inner, client }),
+            Err(e) => {
+                let msg = format!(
+                    "TLS handshake failed from {}: {}",
+                    client.unwrap_or(peer),
+                    e
+                );
+                error!("{}", msg);
+                if self.log_handshake_failures {
+                    self.logger.log_detached("WARN", "TLSHANDSK", &msg);
+                }
+                None
+            }
+        }
+    }
+}
+
+// An accepted connection and the client named by its PROXY header, if any
+struct ClientStream {
+    inner: TlsStream<TcpStream>,
+    client: Option<SocketAddr>,
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// Serves the request and records it in the access log, whatever the outcome. A
+// client named by a PROXY header takes precedence over forwarding headers.
+async fn handle_request(
+    req: Request<Body>,
+    state: VaultState,
+    client: Option<SocketAddr>,
+) -> Result<Response<Body>, hyper::Error> {
+    let started = Instant::now();
+    let time = chrono::Utc::now();
+    let client_ip = match client {
+        Some(addr) => addr.ip().to_string(),
+        None => get_client_ip(&req),
+    };
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let fingerprint = parse_query_params(req.uri().query())
+        .get("secret")
+        .map(|secret| vault_access_log::fingerprint(secret))
+        .unwrap_or_else(|| "-".to_string());
+
+    let response = respond(req, &state, &client_ip).await?;
+
+    state.access_log.record(AccessEntry {
+        time,
+        client_ip,
+        fingerprint,
+        method,
+        path,
+        status: response.status().as_u16(),
+        bytes: response.body().size_hint().exact().unwrap_or(0),
+        duration: started.elapsed(),
+    });
+    Ok(response)
+}
+
+async fn respond(
+    req: Request<Body>,
+    state: &VaultState,
+    client_ip: &str,
+) -> Result<Response<Body>, hyper::Error> {
+    let method = req.method();
+    let uri = req.uri();
+
+    if method != Method::GET {
+        let msg = format!("{} {} - Method not allowed: {}", client_ip, method, uri);
+        warn!("{}", msg);
+        state.logger.warn("HTTPERROR", &msg).await;
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::from("Method not allowed"))
+            .unwrap());
+    }
+
+    let path = uri.path();
+    let query_params = parse_query_params(uri.query());
+
+    // Check for secret key in query params
+    let secret_key = match query_params.get("secret") {
+        Some(key) => key,
+        None => {
+            let msg = format!("{} {} - Missing secret key", client_ip, uri);
+            warn!("{}", msg);
+            state.logger.warn("AUTHFAIL", &msg).await;
+            return Ok(Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .body(Body::from("Missing secret key"))
+                .unwrap());
+        }
+    };
+
+    // Validate secret key
+    if !state.allowed_secrets.contains(secret_key) {
+        let msg = format!("{} {} - Invalid secret key: {}", client_ip, uri, secret_key);
+        warn!("{}", msg);
+        state.logger.warn("AUTH", &msg).await;
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body(Body::from("Invalid secret key"))
+            .unwrap());
+    }
+
+    // Check rate limit
+    if !state.rate_limiter.check(secret_key) {
+        let msg = format!(
+            "{} {} - Rate limit exceeded for secret: {}",
+            client_ip, uri, secret_key
+        );
+        warn!("{}", msg);
+        state.logger.warn("RATELIMIT", &msg).await;
+        return Ok(Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header("Retry-After", "60")
+            .body(Body::from("Rate limit exceeded"))
+            .unwrap());
+    }
+
+    // Reject replays of a request that already carried this nonce
+    if let Some(nonces) = &state.nonces {
+        let nonce = query_params.get("nonce").map(String::as_str).or_else(|| {
+            req.headers()
+                .get(NONCE_HEADER)
+                .and_then(|h| h.to_str().ok())
+        });
+        let rejection = match nonce {
+            None | Some("") => Some((StatusCode::UNAUTHORIZED, "Missing nonce")),
+            Some(nonce) if nonce.len() > vault_nonce::MAX_NONCE_LEN => {
+                Some((StatusCode::BAD_REQUEST, "Nonce too long"))
+            }
+            Some(nonce) if !nonces.check(nonce) => {
+                Some((StatusCode::CONFLICT, "Nonce already used"))
+            }
+            Some(_) => None,
+        };
+        if let Some((status, reason)) = rejection {
+            let msg = format!("{} {} - {}", client_ip, uri, reason);
+            warn!("{}", msg);
+            state.logger.warn("NONCE", &msg).await;
+            return Ok(Response::builder()
+                .status(status)
+                .body(Body::from(reason))
+                .unwrap());
+        }
+    }
+
+    // Extract filename from path (remove leading slash)
+    let filename = match path.strip_prefix("/") {
+        Some(name) => name,
+        None => {
+            let msg = format!("{} {} - Invalid path format", client_ip, uri);
+            warn!("{}", msg);
+            state.logger.warn("HTTPERROR", &msg).await;
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("Invalid path"))
+                .unwrap());
+        }
+    };
+
+    // Construct full file path
+    let file_path = format!("{}/{}", state.config_dir, filename);
+
+    if query_params.get("meta").map(String::as_str) == Some("1") {
+        return Ok(respond_meta(state, client_ip, uri, filename, &file_path).await);
+    }
+
+    // Read and return file
+    match state.cache.read(Path::new(&file_path)).await {
+        Ok(file) => {
+            let msg = format!(
+                "{} {} - Successfully retrieved file: {}",
+                client_ip, uri, filename
+            );
+            info!("{}", msg);
+            state.logger.activity("VAULTACCESS", &msg).await;
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "text/plain")
+                .header("Content-Length", file.bytes.len().to_string())
+                .header("ETag", file.etag)
+                .body(Body::from(file.bytes.to_vec()))
+                .unwrap())
+        }
+        Err(_) => {
+            let msg = format!("{} {} - File not found: {}", client_ip, uri, filename);
+            warn!("{}", msg);
+            state.logger.warn("FILENOTFND", &msg).await;
+            Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("File not found"))
+                .unwrap())
+        }
+    }
+}
+
+// `?meta=1`: the file's size and mtime as JSON, with the GET's ETag and a
+// Last-Modified header, so a client can check for changes without the contents
+async fn respond_meta(
+    state: &VaultState,
+    client_ip: &str,
+    uri: &Uri,
+    filename: &str,
+    file_path: &str,
+) -> Response<Body> {
+    match vault_cache::stat(Path::new(file_path)).await {
+        Ok(meta) => {
+            let msg = format!(
+                "{} {} - Successfully retrieved metadata: {}",
+                client_ip, uri, filename
+            );
+            info!("{}", msg);
+            state.logger.activity("VAULTACCESS", &msg).await;
+            let mtime = chrono::DateTime::<chrono::Utc>::from(meta.mtime);
+            let body = serde_json::json!({
+                "file": filename,
+                "size": meta.len,
+                "mtime": mtime.timestamp(),
+            });
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .header(
+                    "Last-Modified",
+                    mtime.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+                )
+                .header("ETag", meta.etag)
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        }
+        Err(_) => {
+            let msg = format!("{} {} - File not found: {}", client_ip, uri, filename);
+            warn!("{}", msg);
+            state.logger.warn("FILENOTFND", &msg).await;
+            Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("File not found"))
+                .unwrap()
+        }
+    }
+}
+
+// Where a client may send its nonce instead of the `nonce` query parameter
+const NONCE_HEADER: &str = "x-vault-nonce";
+
+fn get_client_ip(req: &Request<Body>) -> String {
+    req.headers()
+        .get("x-forwarded-for")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.split(',').next())
+        .map(|s| s.trim().to_string())
+        .or_else(|| {
+            req.headers()
+                .get("x-real-ip")
+                .and_then(|h| h.to_str().ok())
+                .map(|s| s.to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn parse_query_params(query: Option<&str>) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    if let Some(query_str) = query {
+        for pair in query_str.split('&') {
+            if let Some((key, value)) = pair.split_once('=') {
+                params.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+    params
+}
+
+/// Logs through `tracing` how long the first certificate in `cert_path` remains valid.
+pub fn check_certificate_expiration(cert_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let cert_pem = fs::read(cert_path)?;
+
+    // Parse PEM to extract DER certificate
+    let mut cert_reader = BufReader::new(&cert_pem[..]);
+    let certs = rustls_pemfile::certs(&mut cert_reader)?;
+
+    if certs.is_empty() {
+        return Err("No certificates found in PEM file".into());
+    }
+
+    // Parse the first certificate as DER
+    match parse_x509_certificate(&certs[0]) {
+        Ok((_, cert)) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64;
+
+            let not_after = cert.validity().not_after.timestamp();
+            let days_until_expiry = (not_after - now) / 86400;
+
+            if days_until_expiry < 0 {
+                error!("Certificate has expired!");
+            } else if days_until_expiry < 30 {
+                warn!("Certificate expires in {} days", days_until_expiry);
+            } else {
+                info!("Certificate expires in {} days", days_until_expiry);
+            }
+
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to parse certificate: {}", e);
+            if !certs.is_empty() {
+                let first_bytes = &certs[0][..std::cmp::min(20, certs[0].len())];
+                error!("First 20 bytes of certificate DER: {:?}", first_bytes);
+            }
+            Err(Box::new(e))
+        }
+    }
+}
+
+/// Loads the TLS configuration, binds `settings.bind_addr` and serves requests until a
+/// value arrives on `shutdown_rx`. Returns the bound address and a handle that finishes
+/// once in-flight requests have completed.
+pub async fn run(
+    settings: Settings,
+    logger: Logger,
+    shutdown_rx: broadcast::Receiver<()>,
+) -> Result<(SocketAddr, JoinHandle<()>), Box<dyn std::error::Error>> {
+    // Load TLS certificates
+    let tls_config = match load_server_config(
+        &settings.cert_privkey_path,
+        &settings.cert_fullchain_path,
+        &settings.mtls_client_ca_cert_path,
+    ) {
+        Ok(config) => config,
+        Err(e) => {
+            let msg = format!("Failed to load TLS configuration: {}", e);
+            error!("{}", msg);
+            return Err(e);
+        }
+    };
+
+    // Create TLS acceptor
+    let tls_acceptor = TlsAcceptor::from(Arc::new(tls_config));
+
+    // Parse bind address
+    let addr: SocketAddr = settings.bind_addr.parse()?;
+
+    // Create TCP listener
+    let listener = TcpListener::bind(&addr).await?;
+    let local_addr = listener.local_addr()?;
+    let start_msg = format!(
+        "shrmpl-vault-srv version {} listening on {}",
+        VERSION, local_addr
+    );
+    info!("{}", start_msg);
+    logger.log_detached("INFO", "VAULTLISTEN", &start_msg);
+
+    // Create vault state
+    let cache = Arc::new(if settings.cache_disabled {
+        FileCache::disabled()
+    } else {
+        FileCache::new(settings.cache_max_bytes, settings.cache_max_entries)
+    });
+    let (access_log, access_writer) = if settings.access_log_path.is_empty() {
+        (AccessLog::disabled(), None)
+    } else {
+        let (log, writer) = AccessLog::start(&settings.access_log_path);
+        (log, Some(writer))
+    };
+    let state = VaultState {
+        config_dir: settings.config_dir,
+        allowed_secrets: settings.allowed_secrets,
+        rate_limiter: RateLimiter::new(settings.rate_limit, settings.rate_limit_max_tracked),
+        nonces: (!settings.nonce_window.is_zero())
+            .then(|| NonceStore::new(settings.nonce_window, settings.nonce_max_tracked)),
+        cache: cache.clone(),
+        access_log: access_log.clone(),
+        logger: logger.clone(),
+    };
+
+    // Create service
+    let make_svc = make_service_fn(move |conn: &ClientStream| {
+        let state = state.clone();
+        let client = conn.client;
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req| {
+                handle_request(req, state.clone(), client)
+            }))
+        }
+    });
+
+    // Create server. On shutdown hyper stops polling the accept stream, closes idle
+    // connections and waits for requests already being handled to finish.
+    let mut shutdown_rx = shutdown_rx;
+    let mut backoff = settings.accept_backoff;
+    let notify = settings.notify;
+    let stopping_notify = notify.clone();
+    let log_handshake_failures = settings.log_handshake_failures;
+    let use_proxy_protocol = settings.proxy_protocol;
+    // Detached so an unreachable SLOG doesn't hold up the next accept
+    let accept_logger = logger.clone();
+    // The PROXY header and the TLS handshake run in a task per connection, so a client
+    // that connects and stays silent holds up only itself; finished connections come
+    // back to the accept stream through this channel
+    let (ready_tx, mut ready_rx) = mpsc::channel::<ClientStream>(64);
+    let server = Server::builder(hyper::server::accept::from_stream(async_stream::stream! {
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        backoff.reset();
+                        let handshake = Handshake {
+                            tls_acceptor: tls_acceptor.clone(),
+                            use_proxy_protocol,
+                            log_handshake_failures,
+                            logger: accept_logger.clone(),
+                        };
+                        let ready_tx = ready_tx.clone();
+                        tokio::spawn(async move {
+                            if let Some(conn) = handshake.run(stream, peer).await {
+                                let _ = ready_tx.send(conn).await;
+                            }
+                        });
+                    }
+                    // Ending the stream stops the server the same way a shutdown does
+                    Err(e) if accept_backoff::is_fatal(&e) => {
+                        error!("Listener failed, stopping shrmpl-vault server: {}", e);
+                        break;
+                    }
+                    Err(e) => {
+                        let delay = backoff.next_delay();
+                        let msg = format!("Failed to accept connection: {}; retrying in {}ms", e, delay.as_millis());
+                        warn!("{}", msg);
+                        accept_logger.log_detached("WARN", "VAULTACCEPTERR", &msg);
+                        tokio::time::sleep(delay).await;
+                    }
+                },
+                Some(conn) = ready_rx.recv() => yield Ok::<_, hyper::Error>(conn),
+            }
+        }
+    }))
+    .serve(make_svc)
+    .with_graceful_shutdown(async move {
+        let _ = shutdown_rx.recv().await;
+        stopping_notify.stopping();
+    });
+
+    let success_msg = "shrmpl-vault server started successfully";
+    info!("{}", success_msg);
+    logger.log_detached("INFO", "SRVU", success_msg);
+
+    notify.ready();
+
+    let handle = tokio::spawn(async move {
+        // The watchdog pings for as long as the runtime keeps polling this task
+        tokio::pin!(server);
+        let mut watchdog = notify.watchdog_timer();
+        let result = loop {
+            tokio::select! {
+                result = &mut server => break result,
+                _ = sd_notify::next_watchdog(&mut watchdog) => notify.watchdog(),
+            }
+        };
+        if let Err(e) = result {
+            let msg = format!("Server error: {}", e);
+            error!("{}", msg);
+            logger.error("SRVU", &msg).await;
+        }
+        let (entries, bytes) = cache.usage();
+        let msg = format!(
+            "File cache: hits={} misses={} entries={} bytes={}",
+            cache.hits(),
+            cache.misses(),
+            entries,
+            bytes
+        );
+        info!("{}", msg);
+        logger.info("VAULTCACHE", &msg).await;
+        if let Some(writer) = access_writer {
+            access_log.close().await;
+            let _ = writer.await;
+            let msg = format!(
+                "Access log: lines={} write_failed={}",
+                access_log.lines(),
+                access_log.write_failed()
+            );
+            if access_log.write_failed() {
+                warn!("{}", msg);
+                logger.warn("VAULTACCLOG", &msg).await;
+            } else {
+                info!("{}", msg);
+                logger.info("VAULTACCLOG", &msg).await;
+            }
+        }
+        let msg = "shrmpl-vault-srv stopped after draining in-flight requests";
+        info!("{}", msg);
+        logger.info("VAULTDOWN", msg).await;
+        logger.flush().await;
+    });
+
+    Ok((local_addr, handle))
+}
+
+/// Loads the TLS configuration and reads the first file in CONFIG_DIR, by name,
+/// through the file cache, for `--selftest`. Nothing is bound.
+pub async fn selftest(settings: &Settings) -> Result<String, String> {
+    load_server_config(
+        &settings.cert_privkey_path,
+        &settings.cert_fullchain_path,
+        &settings.mtls_client_ca_cert_path,
+    )
+    .map_err(|e| format!("cannot load TLS configuration: {}", e))?;
+
+    let mut names: Vec<String> = fs::read_dir(&settings.config_dir)
+        .map_err(|e| format!("cannot list {}: {}", settings.config_dir, e))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    let name = names
+        .first()
+        .ok_or_else(|| format!("no files to serve in {}", settings.config_dir))?;
+    let path = format!("{}/{}", settings.config_dir, name);
+    let file = FileCache::new(settings.cache_max_bytes, settings.cache_max_entries)
+        .read(Path::new(&path))
+        .await
+        .map_err(|e| format!("cannot read {}: {}", path, e))?;
+    Ok(format!(
+        "TLS configuration loaded, read {} ({} bytes)",
+        name,
+        file.bytes.len()
+    ))
+}
+
+fn load_server_config(
+    privkey_path: &str,
+    fullchain_path: &str,
+    client_ca_path: &str,
+) -> Result<ServerConfig, Box<dyn std::error::Error>> {
+    // Load and parse certificate
+    let cert_file = fs::File::open(fullchain_path)?;
+    let mut cert_reader = BufReader::new(cert_file);
+    let server_certs: Vec<_> = certs(&mut cert_reader)?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+
+    // Load and parse private key
+    let key_file = fs::File::open(privkey_path)?;
+    let mut key_reader = BufReader::new(key_file);
+    // Try PKCS8 first, then RSA
+    let keys = pkcs8_private_keys(&mut key_reader)?;
+    let key = if !keys.is_empty() {
+        rustls::PrivateKey(keys[0].clone())
+    } else {
+        // Reset reader and try RSA keys
+        let mut key_reader = BufReader::new(fs::File::open(privkey_path)?);
+        let rsa_keys = rsa_private_keys(&mut key_reader)?;
+        if rsa_keys.is_empty() {
+            return Err("No valid private key found".into());
+        }
+        rustls::PrivateKey(rsa_keys[0].clone())
+    };
+
+    // Load and parse client CA certificate
+    let client_ca_file = fs::File::open(client_ca_path)?;
+    let mut client_ca_reader = BufReader::new(client_ca_file);
+    let client_ca_certs: Vec<_> = certs(&mut client_ca_reader)?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+
+    let mut root_cert_store = RootCertStore::empty();
+    for cert in client_ca_certs {
+        root_cert_store.add(&cert)?;
+    }
+
+    let client_verifier = Arc::new(AllowAnyAuthenticatedClient::new(root_cert_store));
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(client_verifier)
+        .with_single_cert(server_certs, key)?;
+
+    Ok(config)
+}
@@ -0,0 +1,113 @@
+// Lowers RLIMIT_NOFILE for the whole process, so these tests live in their own binary
+// rather than alongside tests that open sockets concurrently.
+
+mod common;
+
+use common::KvServer;
+use shrmpl::accept_backoff::{self, AcceptBackoff};
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+fn nofile_limit() -> libc::rlimit {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    assert_eq!(
+        unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) },
+        0
+    );
+    limit
+}
+
+fn set_nofile_limit(limit: &libc::rlimit) {
+    assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, limit) }, 0);
+}
+
+// New descriptors take the lowest free number, and EMFILE means that number is
+// not below the soft limit
+fn lowest_free_fd() -> libc::rlim_t {
+    let fd = unsafe { libc::dup(0) };
+    assert!(fd >= 0);
+    unsafe { libc::close(fd) };
+    fd as libc::rlim_t
+}
+
+fn cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    assert_eq!(unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) }, 0);
+    let total = |t: libc::timeval| {
+        Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+    };
+    total(usage.ru_utime) + total(usage.ru_stime)
+}
+
+#[test]
+fn delay_doubles_up_to_cap_and_resets() {
+    let mut backoff = AcceptBackoff::new(Duration::from_millis(10), Duration::from_millis(50));
+    let delays: Vec<u128> = (0..5).map(|_| backoff.next_delay().as_millis()).collect();
+    assert_eq!(delays, vec![10, 20, 40, 50, 50]);
+
+    backoff.reset();
+    assert_eq!(backoff.next_delay(), Duration::from_millis(10));
+
+    assert!(accept_backoff::is_fatal(&io::Error::from_raw_os_error(
+        libc::EBADF
+    )));
+    assert!(!accept_backoff::is_fatal(&io::Error::from_raw_os_error(
+        libc::EMFILE
+    )));
+    assert!(!accept_backoff::is_fatal(&io::Error::from_raw_os_error(
+        libc::ECONNABORTED
+    )));
+}
+
+// The connection stays in the listen backlog while accept fails with EMFILE, so
+// every retry fails again until descriptors free up
+#[tokio::test]
+async fn kv_server_backs_off_while_out_of_file_descriptors() {
+    let server = KvServer::start_with(&[
+        ("ACCEPT_BACKOFF_MS", "10"),
+        ("ACCEPT_BACKOFF_MAX_MS", "100"),
+    ])
+    .await;
+
+    // The single-threaded test runtime doesn't run the accept loop until we await,
+    // so the connection is still pending when the limit drops
+    let pending = std::net::TcpStream::connect(server.addr).unwrap();
+    let original = nofile_limit();
+    set_nofile_limit(&libc::rlimit {
+        rlim_cur: lowest_free_fd(),
+        rlim_max: original.rlim_max,
+    });
+
+    let cpu_before = cpu_time();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let cpu_used = cpu_time() - cpu_before;
+    set_nofile_limit(&original);
+
+    assert!(
+        cpu_used < Duration::from_millis(300),
+        "accept loop spun: {:?} of CPU in 1s",
+        cpu_used
+    );
+    assert!(!server.handle.is_finished(), "server stopped on EMFILE");
+
+    // Once descriptors are available the pending client is served
+    pending.set_nonblocking(true).unwrap();
+    let mut stream = TcpStream::from_std(pending).unwrap();
+    stream.write_all(b"PING\n").await.unwrap();
+    let mut reply = String::new();
+    tokio::time::timeout(
+        Duration::from_secs(2),
+        BufReader::new(&mut stream).read_line(&mut reply),
+    )
+    .await
+    .expect("pending connection was never accepted")
+    .unwrap();
+    assert_eq!(reply, "PONG\n");
+
+    server.shutdown().await;
+}
@@ -0,0 +1,528 @@
+// In-process harness shared by the integration tests: starts servers on ephemeral
+// ports and captures Logger output through a fake SLOG listener.
+#![allow(dead_code)]
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use shrmpl::kv_server::{self, Settings};
+use shrmpl::sd_notify::Notifier;
+use shrmpl::shrmpl_log_client::{LogLevel, Logger};
+use shrmpl::{log_server, vault_server};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls;
+use tokio_rustls::TlsConnector;
+
+/// Accepts SLOG connections and keeps every received line.
+pub struct FakeSlog {
+    pub addr: SocketAddr,
+    lines: Arc<Mutex<Vec<String>>>,
+    // The accept loop and every connection it took, for `restart` to abort
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl FakeSlog {
+    pub async fn start() -> FakeSlog {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let slog = FakeSlog {
+            addr: listener.local_addr().unwrap(),
+            lines: Arc::new(Mutex::new(Vec::new())),
+            tasks: Arc::new(Mutex::new(Vec::new())),
+        };
+        slog.serve(listener);
+        slog
+    }
+
+    fn serve(&self, listener: TcpListener) {
+        let captured = Arc::clone(&self.lines);
+        let tasks = Arc::clone(&self.tasks);
+        let accept = tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let captured = Arc::clone(&captured);
+                let connection = tokio::spawn(async move {
+                    let mut reader = BufReader::new(socket).lines();
+                    while let Ok(Some(line)) = reader.next_line().await {
+                        captured.lock().unwrap().push(line);
+                    }
+                });
+                tasks.lock().unwrap().push(connection);
+            }
+        });
+        self.tasks.lock().unwrap().push(accept);
+    }
+
+    /// Closes the listener and every connection, as a SLOG restart would, then listens
+    /// again on the same address. Lines received so far are kept.
+    pub async fn restart(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        // Aborted tasks drop their sockets once the runtime gets to them
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        self.serve(TcpListener::bind(self.addr).await.unwrap());
+    }
+
+    /// Logger that sends everything at `level` and above to this listener only.
+    pub fn logger(&self, level: LogLevel) -> Logger {
+        Logger::new(
+            self.addr.to_string(),
+            "test-host".to_string(),
+            level,
+            false,
+            true,
+            true,
+        )
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().clone()
+    }
+
+    /// Waits up to two seconds for a record with the given code.
+    pub async fn wait_for_code(&self, code: &str) -> String {
+        for _ in 0..200 {
+            if let Some(line) = self.lines().into_iter().find(|l| record_code(l) == code) {
+                return line;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no {} record in {:?}", code, self.lines());
+    }
+}
+
+/// SLOG destination whose connects hang: the listener never accepts and its one-slot
+/// accept queue is already taken, so the kernel drops further SYNs unanswered.
+pub struct HungSlog {
+    pub addr: SocketAddr,
+    _listener: socket2::Socket,
+    _queued: std::net::TcpStream,
+}
+
+impl HungSlog {
+    pub fn start() -> HungSlog {
+        use socket2::{Domain, Socket, Type};
+        let listener = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        let any: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        listener.bind(&any.into()).unwrap();
+        listener.listen(0).unwrap();
+        let addr = listener.local_addr().unwrap().as_socket().unwrap();
+        let queued = std::net::TcpStream::connect(addr).unwrap();
+        HungSlog {
+            addr,
+            _listener: listener,
+            _queued: queued,
+        }
+    }
+
+    pub fn logger(&self) -> Logger {
+        Logger::new(
+            self.addr.to_string(),
+            "test-host".to_string(),
+            LogLevel::Debug,
+            false,
+            true,
+            true,
+        )
+    }
+}
+
+/// Extracts the code field of an SLOG line: `LVL HOST(32) CODE(12) LEN: MSG`.
+pub fn record_code(line: &str) -> &str {
+    line.get(38..50).unwrap_or("").trim_end()
+}
+
+pub struct KvServer {
+    pub addr: SocketAddr,
+    pub slog: FakeSlog,
+    pub shutdown_tx: broadcast::Sender<()>,
+    pub handle: JoinHandle<()>,
+}
+
+impl KvServer {
+    pub async fn start(admin_commands: bool) -> KvServer {
+        let admin = if admin_commands { "true" } else { "false" };
+        KvServer::start_with(&[("ADMIN_COMMANDS", admin)]).await
+    }
+
+    /// Starts a server from config entries layered over an ephemeral BIND_ADDR.
+    pub async fn start_with(entries: &[(&str, &str)]) -> KvServer {
+        let slog = FakeSlog::start().await;
+        let mut config = HashMap::new();
+        config.insert("BIND_ADDR".to_string(), "127.0.0.1:0".to_string());
+        for (key, value) in entries {
+            config.insert(key.to_string(), value.to_string());
+        }
+        KvServer::launch(Settings::from_config(&config), slog).await
+    }
+
+    /// Starts a default server that sends systemd notifications through `notify`.
+    pub async fn start_notifying(notify: Notifier) -> KvServer {
+        let mut config = HashMap::new();
+        config.insert("BIND_ADDR".to_string(), "127.0.0.1:0".to_string());
+        let mut settings = Settings::from_config(&config);
+        settings.notify = notify;
+        KvServer::launch(settings, FakeSlog::start().await).await
+    }
+
+    /// Starts a server from settings the test built, e.g. to keep `snapshot_now`.
+    pub async fn start_with_settings(settings: Settings) -> KvServer {
+        KvServer::launch(settings, FakeSlog::start().await).await
+    }
+
+    async fn launch(settings: Settings, slog: FakeSlog) -> KvServer {
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let (addr, handle) = kv_server::run(settings, slog.logger(LogLevel::Info), shutdown_rx)
+            .await
+            .unwrap();
+        KvServer {
+            addr,
+            slog,
+            shutdown_tx,
+            handle,
+        }
+    }
+
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// Signals shutdown and waits for the accept loop to finish.
+    pub async fn shutdown(self) -> FakeSlog {
+        self.shutdown_tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), self.handle)
+            .await
+            .expect("server did not stop")
+            .unwrap();
+        self.slog
+    }
+}
+
+/// Creates an empty directory under the system temp dir, unique per call.
+pub fn temp_dir(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "shrmpl-{}-{}-{}",
+        name,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub struct LogServer {
+    pub addr: SocketAddr,
+    pub data_dir: PathBuf,
+    pub shutdown_tx: broadcast::Sender<()>,
+    pub handle: JoinHandle<()>,
+}
+
+impl LogServer {
+    pub async fn start() -> LogServer {
+        LogServer::start_with(&[]).await
+    }
+
+    /// Starts a server from config entries layered over an ephemeral BIND_ADDR and
+    /// a fresh DATA_DIR.
+    pub async fn start_with(entries: &[(&str, &str)]) -> LogServer {
+        let data_dir = temp_dir("log");
+        let settings = log_server::Settings::from_config(&log_config(&data_dir, entries));
+        LogServer::launch(settings, data_dir).await
+    }
+
+    pub async fn start_notifying(notify: Notifier) -> LogServer {
+        let data_dir = temp_dir("log");
+        let mut settings = log_server::Settings::from_config(&log_config(&data_dir, &[]));
+        settings.notify = notify;
+        LogServer::launch(settings, data_dir).await
+    }
+
+    async fn launch(settings: log_server::Settings, data_dir: PathBuf) -> LogServer {
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let (addr, handle) = log_server::run(settings, shutdown_rx).await.unwrap();
+        LogServer {
+            addr,
+            data_dir,
+            shutdown_tx,
+            handle,
+        }
+    }
+
+    /// Logger that sends every level, including ACTV, to this server.
+    pub fn logger(&self) -> Logger {
+        Logger::new(
+            self.addr.to_string(),
+            "test-host".to_string(),
+            LogLevel::Debug,
+            false,
+            true,
+            true,
+        )
+    }
+
+    /// Signals shutdown and waits until the writers have flushed.
+    pub async fn shutdown(self) -> PathBuf {
+        self.shutdown_tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), self.handle)
+            .await
+            .expect("server did not stop")
+            .unwrap();
+        self.data_dir
+    }
+}
+
+/// Runs `binary --init path [extra]` and returns whether it succeeded.
+pub fn run_init(binary: &str, path: &Path, extra: &[&str]) -> bool {
+    std::process::Command::new(binary)
+        .arg("--init")
+        .arg(path)
+        .args(extra)
+        .output()
+        .unwrap()
+        .status
+        .success()
+}
+
+/// Sets `key=value` on the template line that names `key`.
+pub fn fill_template(template: &str, key: &str, value: &str) -> String {
+    let prefix = format!("{}=", key);
+    assert!(
+        template.lines().any(|line| line.starts_with(&prefix)),
+        "{}",
+        key
+    );
+    template
+        .lines()
+        .map(|line| {
+            if line.starts_with(&prefix) {
+                format!("{}{}\n", prefix, value)
+            } else {
+                format!("{}\n", line)
+            }
+        })
+        .collect()
+}
+
+/// Writes `entries` to a config file and runs `binary <config> --selftest`, returning
+/// whether it exited 0 along with its stdout and stderr.
+pub fn run_selftest(binary: &str, entries: &[(&str, &str)]) -> (bool, String) {
+    let config = temp_dir("selftest").join("selftest.env");
+    let content: String = entries
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect();
+    std::fs::write(&config, content).unwrap();
+    run_selftest_file(binary, &config)
+}
+
+/// `run_selftest` of a config file already on disk.
+pub fn run_selftest_file(binary: &str, config: &Path) -> (bool, String) {
+    let output = std::process::Command::new(binary)
+        .arg(config)
+        .arg("--selftest")
+        .output()
+        .unwrap();
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    (output.status.success(), text)
+}
+
+fn log_config(data_dir: &Path, entries: &[(&str, &str)]) -> HashMap<String, String> {
+    let mut config = HashMap::new();
+    config.insert("BIND_ADDR".to_string(), "127.0.0.1:0".to_string());
+    config.insert("DATA_DIR".to_string(), data_dir.display().to_string());
+    for (key, value) in entries {
+        config.insert(key.to_string(), value.to_string());
+    }
+    config
+}
+
+/// Contents of the `<prefix>-YYYYMMDD.log` files in `dir`, concatenated.
+pub fn read_log_files(dir: &Path, prefix: &str) -> String {
+    let mut contents = String::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        if name.starts_with(&format!("{}-", prefix)) {
+            contents.push_str(&std::fs::read_to_string(&path).unwrap());
+        }
+    }
+    contents
+}
+
+/// A throwaway CA with one server and one client certificate, written as PEM files.
+pub struct TestPki {
+    pub dir: PathBuf,
+    client_cert: Vec<u8>,
+    client_key: Vec<u8>,
+    ca_cert: Vec<u8>,
+}
+
+impl TestPki {
+    pub fn generate() -> TestPki {
+        let dir = temp_dir("pki");
+        let mut ca_params = CertificateParams::new(vec![]);
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "shrmpl test ca");
+        let ca = Certificate::from_params(ca_params).unwrap();
+        let server =
+            Certificate::from_params(CertificateParams::new(vec!["localhost".to_string()]))
+                .unwrap();
+        let client =
+            Certificate::from_params(CertificateParams::new(vec!["client".to_string()])).unwrap();
+
+        std::fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+        std::fs::write(
+            dir.join("server_fullchain.pem"),
+            server.serialize_pem_with_signer(&ca).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("server_privkey.pem"),
+            server.serialize_private_key_pem(),
+        )
+        .unwrap();
+        TestPki {
+            client_cert: client.serialize_der_with_signer(&ca).unwrap(),
+            client_key: client.serialize_private_key_der(),
+            ca_cert: ca.serialize_der().unwrap(),
+            dir,
+        }
+    }
+
+    /// Connector presenting the client certificate and trusting only the test CA.
+    pub fn connector(&self) -> TlsConnector {
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add(&rustls::Certificate(self.ca_cert.clone()))
+            .unwrap();
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(
+                vec![rustls::Certificate(self.client_cert.clone())],
+                rustls::PrivateKey(self.client_key.clone()),
+            )
+            .unwrap();
+        TlsConnector::from(Arc::new(config))
+    }
+}
+
+pub struct VaultServer {
+    pub addr: SocketAddr,
+    pub pki: TestPki,
+    /// Directory the vault serves files from.
+    pub files: PathBuf,
+    pub slog: FakeSlog,
+    pub shutdown_tx: broadcast::Sender<()>,
+    pub handle: JoinHandle<()>,
+}
+
+impl VaultServer {
+    /// Starts a vault accepting the secret `test-secret` with fresh certificates.
+    pub async fn start() -> VaultServer {
+        VaultServer::start_notifying(Notifier::disabled()).await
+    }
+
+    pub async fn start_notifying(notify: Notifier) -> VaultServer {
+        VaultServer::launch(&[], notify).await
+    }
+
+    /// Starts a vault with config entries layered over the test defaults.
+    pub async fn start_with(entries: &[(&str, &str)]) -> VaultServer {
+        VaultServer::launch(entries, Notifier::disabled()).await
+    }
+
+    async fn launch(entries: &[(&str, &str)], notify: Notifier) -> VaultServer {
+        let pki = TestPki::generate();
+        let files = temp_dir("vault");
+        let slog = FakeSlog::start().await;
+        let path = |name: &str| pki.dir.join(name).display().to_string();
+        let mut config = HashMap::new();
+        config.insert("BIND_ADDR".to_string(), "127.0.0.1:0".to_string());
+        config.insert(
+            "TLS_CERTIFICATE_PRIVKEY_PATH".to_string(),
+            path("server_privkey.pem"),
+        );
+        config.insert(
+            "TLS_CERTIFICATE_FULLCHAIN_PATH".to_string(),
+            path("server_fullchain.pem"),
+        );
+        config.insert("MTLS_CLIENT_CA_CERT_PATH".to_string(), path("ca.pem"));
+        config.insert("CONFIG_DIR".to_string(), files.display().to_string());
+        config.insert("ALLOWED_SECRETS".to_string(), "test-secret".to_string());
+        for (key, value) in entries {
+            config.insert(key.to_string(), value.to_string());
+        }
+        let mut settings = vault_server::Settings::from_config(&config);
+        settings.notify = notify;
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let (addr, handle) = vault_server::run(settings, slog.logger(LogLevel::Info), shutdown_rx)
+            .await
+            .unwrap();
+        VaultServer {
+            addr,
+            pki,
+            files,
+            slog,
+            shutdown_tx,
+            handle,
+        }
+    }
+
+    /// Sends a GET for `path` over a fresh mTLS connection and returns the raw response.
+    pub async fn get(&self, path: &str) -> String {
+        vault_get(self.addr, self.pki.connector(), path).await
+    }
+
+    /// Signals shutdown and waits for in-flight requests to drain.
+    pub async fn shutdown(self) -> FakeSlog {
+        self.shutdown_tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), self.handle)
+            .await
+            .expect("server did not stop")
+            .unwrap();
+        self.slog
+    }
+}
+
+/// Sends one GET request over mTLS and reads the response until the server closes.
+pub async fn vault_get(addr: SocketAddr, connector: TlsConnector, path: &str) -> String {
+    vault_get_after(addr, connector, b"", path).await
+}
+
+/// `vault_get` with `preamble`, such as a PROXY header, sent before the TLS handshake.
+pub async fn vault_get_after(
+    addr: SocketAddr,
+    connector: TlsConnector,
+    preamble: &[u8],
+    path: &str,
+) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let mut tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+    tcp.write_all(preamble).await.unwrap();
+    let server_name = rustls::ServerName::try_from("localhost").unwrap();
+    let mut tls = connector.connect(server_name, tcp).await.unwrap();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    );
+    tls.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    // A peer that closes without close_notify still leaves the full response read
+    let _ = tls.read_to_end(&mut response).await;
+    String::from_utf8_lossy(&response).to_string()
+}
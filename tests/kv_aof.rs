@@ -0,0 +1,130 @@
+mod common;
+
+use common::temp_dir;
+use shrmpl::kv_aof::{self, AofSettings, AofSync};
+use shrmpl::kv_protocol::{Command, ParseError};
+use shrmpl::kv_store::{StoredValue, Value};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn settings(entries: &[(&str, &str)]) -> Option<AofSettings> {
+    let config: HashMap<String, String> = entries
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    AofSettings::from_config(&config)
+}
+
+#[test]
+fn settings_need_a_path_and_default_to_everysec() {
+    assert!(settings(&[("AOF_SYNC", "always")]).is_none());
+    assert!(settings(&[("AOF_PATH", "")]).is_none());
+    let aof = settings(&[("AOF_PATH", "kv.aof")]).unwrap();
+    assert_eq!((aof.path.as_str(), aof.sync), ("kv.aof", AofSync::EverySec));
+    let aof = settings(&[("AOF_PATH", "kv.aof"), ("AOF_SYNC", "ALWAYS")]).unwrap();
+    assert_eq!(aof.sync, AofSync::Always);
+    let aof = settings(&[("AOF_PATH", "kv.aof"), ("AOF_SYNC", "sometimes")]).unwrap();
+    assert_eq!(aof.sync, AofSync::EverySec);
+}
+
+#[test]
+fn lines_replay_as_the_state_they_record() {
+    let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let plain = StoredValue {
+        value: Value::Int(42),
+        expires_at: None,
+    };
+    let line = kv_aof::entry_line("count", &plain);
+    assert_eq!(line, "SET count 42");
+    assert_eq!(
+        kv_aof::replay_command(&line, now),
+        Ok(Command::Set {
+            key: "count".to_string(),
+            value: "42".to_string(),
+            ttl: None,
+            raw: false,
+        })
+    );
+
+    // A string that reads as an integer keeps RAW so it replays as the same string
+    let padded = StoredValue {
+        value: Value::Str("007".to_string()),
+        expires_at: None,
+    };
+    assert_eq!(kv_aof::entry_line("code", &padded), "SET code 007 RAW");
+    let whole = StoredValue {
+        value: Value::Float(2.0),
+        expires_at: None,
+    };
+    assert_eq!(kv_aof::entry_line("rate", &whole), "SET rate 2.0");
+    let text = StoredValue {
+        value: Value::Str("alice".to_string()),
+        expires_at: None,
+    };
+    assert_eq!(kv_aof::entry_line("user", &text), "SET user alice");
+
+    // The expiration is absolute, so the replayed TTL is whatever is left of it
+    let expiring = StoredValue {
+        value: Value::Bytes(vec![0, 255]),
+        expires_at: Some(now + Duration::from_secs(60)),
+    };
+    let line = kv_aof::entry_line("blob", &expiring);
+    assert_eq!(line, "@1000060000 SETB blob AP8=");
+    assert_eq!(
+        kv_aof::replay_command(&line, now + Duration::from_secs(20)),
+        Ok(Command::SetB {
+            key: "blob".to_string(),
+            data: vec![0, 255],
+            ttl: Some(Duration::from_secs(40)),
+        })
+    );
+    assert_eq!(
+        kv_aof::replay_command(&line, now + Duration::from_secs(60)),
+        Ok(Command::Del {
+            key: "blob".to_string()
+        })
+    );
+
+    assert_eq!(
+        kv_aof::replay_command(&kv_aof::del_line("count"), now),
+        Ok(Command::Del {
+            key: "count".to_string()
+        })
+    );
+    assert_eq!(
+        kv_aof::replay_command(kv_aof::FLUSH_LINE, now),
+        Ok(Command::FlushAll)
+    );
+}
+
+#[test]
+fn lines_the_journal_never_writes_are_rejected() {
+    let now = SystemTime::now();
+    assert_eq!(
+        kv_aof::replay_command("INCR count", now),
+        Err(ParseError::UnknownCommand)
+    );
+    assert_eq!(
+        kv_aof::replay_command("@soon SET a b", now),
+        Err(ParseError::InvalidExpiration)
+    );
+    assert_eq!(
+        kv_aof::replay_command("@99999999999999 DEL a", now),
+        Err(ParseError::UnknownCommand)
+    );
+    assert!(kv_aof::replay_command("@1", now).is_err());
+    assert!(kv_aof::replay_command("SET a", now).is_err());
+}
+
+#[test]
+fn read_leaves_out_a_torn_last_line() {
+    let dir = temp_dir("aof");
+    let path = dir.join("kv.aof");
+    let path = path.to_str().unwrap();
+    assert_eq!(kv_aof::read(path).unwrap(), (Vec::new(), 0));
+
+    std::fs::write(path, "SET a 1\nDEL a\nSET b 2").unwrap();
+    let (lines, len) = kv_aof::read(path).unwrap();
+    assert_eq!(lines, vec!["SET a 1", "DEL a"]);
+    assert_eq!(len, 14);
+}
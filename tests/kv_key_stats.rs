@@ -0,0 +1,52 @@
+use shrmpl::kv_key_stats::KeyStats;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn top_orders_by_accesses_then_recency() {
+    let start = SystemTime::now();
+    let at = |secs| start + Duration::from_secs(secs);
+    let mut stats = KeyStats::new(10);
+    for _ in 0..3 {
+        stats.hit("hot", at(1));
+    }
+    stats.hit("warm", at(2));
+    stats.miss("warm", at(3));
+    stats.miss("ghost", at(4));
+    stats.miss("ghost", at(5));
+    stats.set("written", at(6));
+    stats.set("written", at(7));
+
+    // Ties go to the most recently accessed
+    let top: Vec<String> = stats.top(3).into_iter().map(|c| c.key).collect();
+    assert_eq!(top, vec!["hot", "written", "ghost"]);
+    let warm = stats.get("warm").unwrap();
+    assert_eq!((warm.hits, warm.misses, warm.sets), (1, 1, 0));
+    assert_eq!(stats.get("written").unwrap().sets, 2);
+    assert!(stats.get("never").is_none());
+
+    // A deleted key keeps its misses but not its hits
+    stats.remove("warm");
+    assert_eq!(stats.get("warm").unwrap().hits, 1);
+    stats.remove("hot");
+    assert!(stats.get("hot").is_none());
+    stats.retain_present(|key| key != "written");
+    assert!(stats.get("written").is_none());
+}
+
+#[test]
+fn flood_of_missing_keys_stays_bounded() {
+    let now = SystemTime::now();
+    let mut stats = KeyStats::new(100);
+    for round in 0..1000 {
+        stats.miss("probe", now);
+        for i in 0..10 {
+            stats.miss(&format!("random-{}-{}", round, i), now);
+        }
+        assert!(stats.missing_tracked() <= 100);
+    }
+    assert_eq!(stats.missing_tracked(), 100);
+    // The key missed on every round is never the least missed, so it stays on top
+    let top = stats.top(1);
+    assert_eq!(top[0].key, "probe");
+    assert_eq!(top[0].misses, 1000);
+}
@@ -0,0 +1,897 @@
+use chrono::TimeZone;
+use shrmpl::kv_protocol::{
+    frame_reply, inflate_frame, parse_expiration, parse_time, scan_cursor, scan_cursor_key,
+    split_batch, time_line, Command, ExpiryChange, HelloReply, KeyCounters, Limits, ListEntry,
+    LoadMode, ParseError, Response, ServerStatus, COMPRESS_MIN_BYTES, FRAME_DEFLATE, KEY_NOT_FOUND,
+    MAX_DEBUG_SLEEP_MS, MAX_KEY_LEN, MAX_MGET_KEYS, MAX_MSET_PAIRS, MAX_SCAN_COUNT, MAX_VALUE_LEN,
+    MGET_END, NIL, SCAN_DEFAULT_COUNT, SCAN_START,
+};
+use std::time::Duration;
+
+// Small deterministic xorshift so failures are reproducible without a rand dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn token(&mut self, max_len: usize) -> String {
+        const CHARS: &[u8] =
+            b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-_:.,=";
+        let len = 1 + self.below(max_len as u64) as usize;
+        (0..len)
+            .map(|_| CHARS[self.below(CHARS.len() as u64) as usize] as char)
+            .collect()
+    }
+
+    fn ttl(&mut self) -> Option<Duration> {
+        match self.below(3) {
+            0 => None,
+            _ => Some(Duration::from_secs(self.below(100_000))),
+        }
+    }
+
+    fn command(&mut self) -> Command {
+        match self.below(36) {
+            0 => Command::Ping,
+            1 => Command::Get {
+                key: self.token(MAX_KEY_LEN),
+            },
+            2 => Command::Set {
+                key: self.token(MAX_KEY_LEN),
+                value: self.token(MAX_VALUE_LEN),
+                ttl: self.ttl(),
+                raw: self.below(2) == 0,
+            },
+            3 => Command::Incr {
+                key: self.token(MAX_KEY_LEN),
+                ttl: self.ttl(),
+            },
+            4 => Command::Del {
+                key: self.token(MAX_KEY_LEN),
+            },
+            5 => Command::Peek {
+                key: self.token(MAX_KEY_LEN),
+            },
+            6 => Command::KeyStats {
+                key: self.token(MAX_KEY_LEN),
+            },
+            7 => Command::TopKeys {
+                n: self.below(1000) as usize,
+            },
+            8 => Command::GetEx {
+                key: self.token(MAX_KEY_LEN),
+                expiry: match self.ttl() {
+                    Some(ttl) => ExpiryChange::Ttl(ttl),
+                    None if self.below(2) == 0 => ExpiryChange::Persist,
+                    None => ExpiryChange::Keep,
+                },
+            },
+            9 => Command::Decr {
+                key: self.token(MAX_KEY_LEN),
+                ttl: self.ttl(),
+            },
+            10 => Command::Expire {
+                key: self.token(MAX_KEY_LEN),
+                ttl: Duration::from_secs(self.below(100_000)),
+            },
+            11 => Command::MGet {
+                keys: (0..1 + self.below(MAX_MGET_KEYS as u64))
+                    .map(|_| self.token(MAX_KEY_LEN))
+                    .collect(),
+            },
+            12 => Command::Ttl {
+                key: self.token(MAX_KEY_LEN),
+            },
+            13 => Command::MSet {
+                pairs: (0..1 + self.below(MAX_MSET_PAIRS as u64))
+                    .map(|_| (self.token(MAX_KEY_LEN), self.token(MAX_VALUE_LEN)))
+                    .collect(),
+                ttl: self.ttl(),
+            },
+            14 => Command::Persist {
+                key: self.token(MAX_KEY_LEN),
+            },
+            15 => Command::SetNx {
+                key: self.token(MAX_KEY_LEN),
+                value: self.token(MAX_VALUE_LEN),
+                ttl: self.ttl(),
+            },
+            16 => Command::DebugSleep {
+                ms: self.below(MAX_DEBUG_SLEEP_MS + 1),
+            },
+            17 => Command::IncrBy {
+                key: self.token(MAX_KEY_LEN),
+                amount: (self.next() as i64).max(1),
+                ttl: self.ttl(),
+            },
+            18 => Command::DecrBy {
+                key: self.token(MAX_KEY_LEN),
+                amount: (self.next() as i64).min(-1),
+                ttl: self.ttl(),
+            },
+            19 => Command::GetSet {
+                key: self.token(MAX_KEY_LEN),
+                value: self.token(MAX_VALUE_LEN),
+                ttl: self.ttl(),
+            },
+            20 => Command::Exists {
+                key: self.token(MAX_KEY_LEN),
+            },
+            21 => Command::Rename {
+                src: self.token(MAX_KEY_LEN),
+                dst: self.token(MAX_KEY_LEN),
+            },
+            22 => Command::RenameNx {
+                src: self.token(MAX_KEY_LEN),
+                dst: self.token(MAX_KEY_LEN),
+            },
+            23 => Command::Keys {
+                pattern: self.token(MAX_KEY_LEN),
+            },
+            24 => Command::PTtl {
+                key: self.token(MAX_KEY_LEN),
+            },
+            25 => Command::Tag {
+                key: self.token(MAX_KEY_LEN),
+                tag: self.token(MAX_KEY_LEN),
+            },
+            26 => Command::Tagged {
+                tag: self.token(MAX_KEY_LEN),
+            },
+            27 => Command::Scan {
+                after: match self.below(2) {
+                    0 => None,
+                    _ => Some(self.token(MAX_KEY_LEN)),
+                },
+                count: 1 + self.below(MAX_SCAN_COUNT as u64) as usize,
+            },
+            28 => Command::ExpireTag {
+                tag: self.token(MAX_KEY_LEN),
+                ttl: Duration::from_secs(self.below(100_000)),
+            },
+            29 => Command::Cas {
+                key: self.token(MAX_KEY_LEN),
+                expected: self.token(MAX_VALUE_LEN),
+                value: self.token(MAX_VALUE_LEN),
+                ttl: self.ttl(),
+            },
+            30 => Command::Count {
+                pattern: match self.below(2) {
+                    0 => None,
+                    _ => Some(self.token(MAX_KEY_LEN)),
+                },
+            },
+            31 => Command::ListByExpiry {
+                limit: match self.below(2) {
+                    0 => None,
+                    _ => Some(1 + self.below(10_000) as usize),
+                },
+            },
+            32 => Command::Type {
+                key: self.token(MAX_KEY_LEN),
+            },
+            33 => Command::IncrByFloat {
+                key: self.token(MAX_KEY_LEN),
+                delta: (self.next() as i64 | 1) as f64 / (1 + self.below(1000)) as f64,
+                ttl: self.ttl(),
+            },
+            34 => Command::GetDel {
+                key: self.token(MAX_KEY_LEN),
+            },
+            _ => Command::List {
+                pattern: match self.below(2) {
+                    0 => None,
+                    _ => Some(self.token(MAX_KEY_LEN)),
+                },
+            },
+        }
+    }
+}
+
+#[test]
+fn random_valid_commands_round_trip() {
+    let mut rng = Rng(0x5eed_1234_abcd_0001);
+    for _ in 0..5000 {
+        let command = rng.command();
+        assert_eq!(command.validate(), Ok(()));
+        assert_eq!(Command::parse(&command.to_line()), Ok(command));
+    }
+}
+
+#[test]
+fn malformed_commands_rejected_identically() {
+    let long = "k".repeat(MAX_KEY_LEN + 1);
+    let cases = [
+        ("", ParseError::UnknownCommand),
+        ("FLY away", ParseError::UnknownCommand),
+        ("GET", ParseError::InvalidArguments),
+        ("GET a b", ParseError::InvalidArguments),
+        ("TYPE", ParseError::InvalidArguments),
+        ("INCRBY a 0", ParseError::InvalidArguments),
+        ("DECRBY a 1 1s extra", ParseError::InvalidArguments),
+        ("INCRBY a 1 soon", ParseError::InvalidExpiration),
+        ("TYPE a b", ParseError::InvalidArguments),
+        ("SET a", ParseError::InvalidArguments),
+        ("SET a b 1s extra", ParseError::InvalidArguments),
+        ("PING now", ParseError::InvalidArguments),
+        ("SET a b 10parsecs", ParseError::InvalidExpiration),
+        ("INCR a soon", ParseError::InvalidExpiration),
+        ("DECR a soon", ParseError::InvalidExpiration),
+        ("DECR", ParseError::InvalidArguments),
+        ("EXPIRE a", ParseError::InvalidArguments),
+        ("EXPIRE a later", ParseError::InvalidExpiration),
+        ("GETEX a forever", ParseError::InvalidExpiration),
+        ("GETEX a 1s 2s", ParseError::InvalidArguments),
+        ("TTL", ParseError::InvalidArguments),
+        ("TTL a b", ParseError::InvalidArguments),
+        ("PTTL", ParseError::InvalidArguments),
+        ("PTTL a b", ParseError::InvalidArguments),
+        ("MSET", ParseError::InvalidArguments),
+        ("MSET a", ParseError::InvalidArguments),
+        ("MSET a 1 b", ParseError::InvalidArguments),
+        ("PERSIST", ParseError::InvalidArguments),
+        ("PERSIST a 1h", ParseError::InvalidArguments),
+        ("SETNX a", ParseError::InvalidArguments),
+        ("SETNX a b 1s extra", ParseError::InvalidArguments),
+        ("SETNX a b soon", ParseError::InvalidExpiration),
+        ("DEBUG SLEEP", ParseError::InvalidArguments),
+        ("DEBUG NAP 10", ParseError::InvalidArguments),
+        ("DEBUG SLEEP soon", ParseError::InvalidArguments),
+        ("INCRBY a", ParseError::InvalidArguments),
+        ("GETSET a", ParseError::InvalidArguments),
+        ("GETSET a b 1s extra", ParseError::InvalidArguments),
+        ("GETSET a b soon", ParseError::InvalidExpiration),
+        ("EXISTS", ParseError::InvalidArguments),
+        ("EXISTS a b", ParseError::InvalidArguments),
+        ("RENAME a", ParseError::InvalidArguments),
+        ("RENAMENX a b c", ParseError::InvalidArguments),
+        ("KEYS", ParseError::InvalidArguments),
+        ("KEYS a* b*", ParseError::InvalidArguments),
+        ("LIST a* b*", ParseError::InvalidArguments),
+        ("COUNT a* b*", ParseError::InvalidArguments),
+        ("LIST BYEXPIRY 5", ParseError::InvalidArguments),
+        ("LIST BYEXPIRY LIMIT", ParseError::InvalidArguments),
+        ("LIST BYEXPIRY LIMIT 0", ParseError::InvalidArguments),
+        ("LIST BYEXPIRY LIMIT five", ParseError::InvalidArguments),
+        ("FLUSHALL now", ParseError::InvalidArguments),
+        ("TAG a", ParseError::InvalidArguments),
+        ("TAGGED", ParseError::InvalidArguments),
+        ("EXPIRETAG a", ParseError::InvalidArguments),
+        ("EXPIRETAG a soon", ParseError::InvalidExpiration),
+        ("CAS a 1", ParseError::InvalidArguments),
+        ("CAS a 1 2 1s extra", ParseError::InvalidArguments),
+        ("CAS a 1 2 soon", ParseError::InvalidExpiration),
+        ("SCAN", ParseError::InvalidArguments),
+        ("SCAN 0 COUNT", ParseError::InvalidArguments),
+        ("SCAN 0 COUNT 0", ParseError::InvalidArguments),
+        ("SCAN 0 COUNT 1001", ParseError::InvalidArguments),
+        ("SCAN 0 LIMIT 5", ParseError::InvalidArguments),
+        ("SCAN abc", ParseError::InvalidArguments),
+        ("SCAN zz", ParseError::InvalidArguments),
+        ("INCRBY a 1 1s 2s", ParseError::InvalidArguments),
+        ("DECRBY a 9223372036854775808", ParseError::InvalidArguments),
+    ];
+    for (line, expected) in cases {
+        assert_eq!(Command::parse(line), Err(expected), "line {:?}", line);
+    }
+
+    // Client-built commands hit the same validation the server parser applies
+    let built = Command::Get { key: long.clone() };
+    assert_eq!(built.validate(), Err(ParseError::InvalidLength));
+    assert_eq!(
+        Command::parse(&format!("GET {}", long)),
+        Err(ParseError::InvalidLength)
+    );
+    let built = Command::Set {
+        key: "a".to_string(),
+        value: "two words".to_string(),
+        ttl: None,
+        raw: false,
+    };
+    assert_eq!(built.validate(), Err(ParseError::InvalidLength));
+}
+
+#[test]
+fn verbs_are_case_insensitive_and_ttls_normalize() {
+    assert_eq!(
+        Command::parse("set k v 5min"),
+        Ok(Command::Set {
+            key: "k".to_string(),
+            value: "v".to_string(),
+            ttl: Some(Duration::from_secs(300)),
+            raw: false,
+        })
+    );
+    assert_eq!(parse_expiration("2h"), Some(Duration::from_secs(7200)));
+    assert_eq!(parse_expiration("30"), None);
+}
+
+#[test]
+fn responses_parse_by_command_shape() {
+    let get = Command::Get {
+        key: "k".to_string(),
+    };
+    // A stored value that happens to read "OK" is still a value for GET
+    assert_eq!(
+        Response::parse(&get, "OK"),
+        Response::Value("OK".to_string())
+    );
+    assert_eq!(Response::parse(&get, KEY_NOT_FOUND), Response::NotFound);
+    assert_eq!(
+        Response::parse(&get, "ERROR invalid length"),
+        Response::Error("invalid length".to_string())
+    );
+
+    let incr = Command::Incr {
+        key: "k".to_string(),
+        ttl: None,
+    };
+    assert_eq!(Response::parse(&incr, "-7"), Response::Integer(-7));
+    assert!(matches!(
+        Response::parse(&incr, "seven"),
+        Response::Error(_)
+    ));
+
+    for response in [Response::Ok, Response::NotFound, Response::Integer(42)] {
+        let wire = response.to_wire();
+        let command = match response {
+            Response::Integer(_) => &incr,
+            _ => &Command::Del {
+                key: "k".to_string(),
+            },
+        };
+        assert_eq!(Response::parse(command, wire.trim_end()), response);
+    }
+}
+
+#[test]
+fn scan_cursors_resume_after_any_name() {
+    assert_eq!(scan_cursor(None), SCAN_START);
+    assert_eq!(scan_cursor_key(SCAN_START), Ok(None));
+    // A key named like the start cursor still resumes after itself
+    for name in ["0", "user:42", "a=b,c", "é"] {
+        let cursor = scan_cursor(Some(name));
+        assert_ne!(cursor, SCAN_START);
+        assert_eq!(scan_cursor_key(&cursor), Ok(Some(name.to_string())));
+    }
+    assert_eq!(
+        Command::parse("scan 0 count 5"),
+        Ok(Command::Scan {
+            after: None,
+            count: 5
+        })
+    );
+    assert_eq!(
+        Command::parse("SCAN 61"),
+        Ok(Command::Scan {
+            after: Some("a".to_string()),
+            count: SCAN_DEFAULT_COUNT
+        })
+    );
+}
+
+#[test]
+fn list_entries_and_batches() {
+    let entry = ListEntry {
+        key: "a".to_string(),
+        value: "x,y".to_string(),
+        value_type: "str".to_string(),
+        expires_at: Some(1_700_000_000),
+    };
+    assert_eq!(entry.to_line(), "a=x,y,str,1700000000");
+    assert_eq!(ListEntry::parse(&entry.to_line()), Some(entry));
+    assert_eq!(
+        ListEntry::parse("b=1,int,no-expiration").map(|e| (e.value_type, e.expires_at)),
+        Some(("int".to_string(), None))
+    );
+    assert_eq!(ListEntry::parse("b=1,no-expiration"), None);
+    assert_eq!(
+        split_batch("BATCH GET a; ;GET b"),
+        Some(vec!["GET a", "GET b"])
+    );
+    assert_eq!(split_batch("GET a"), None);
+}
+
+#[test]
+fn binary_commands_carry_base64() {
+    let command = Command::SetB {
+        key: "blob".to_string(),
+        data: vec![0, b'\n', 0xff],
+        ttl: Some(Duration::from_secs(30)),
+    };
+    assert_eq!(command.to_line(), "SETB blob AAr/ 30s");
+    assert_eq!(Command::parse(&command.to_line()), Ok(command));
+    assert_eq!(
+        Command::parse("SETB blob not*base64"),
+        Err(ParseError::InvalidBase64)
+    );
+}
+
+#[test]
+fn status_line_round_trips_and_tolerates_new_fields() {
+    let status = ServerStatus {
+        uptime: Duration::from_secs(3600),
+        keys: 1523,
+        expiring: 410,
+        mem_bytes: 204800,
+        version: "0.3.1".to_string(),
+        connections: 12,
+        saved_bytes: 96,
+        bytes_read: 5210,
+        bytes_written: 88104,
+        commands: 310,
+    };
+    let line = status.to_line();
+    assert_eq!(
+        line,
+        "uptime=3600s keys=1523 expiring=410 mem_bytes~=204800 version=0.3.1 connections=12 saved_bytes~=96 bytes_read=5210 bytes_written=88104 commands=310"
+    );
+    assert_eq!(ServerStatus::parse(&line), Some(status.clone()));
+    assert_eq!(
+        ServerStatus::parse(&format!("{} shiny=yes", line)),
+        Some(status)
+    );
+    assert_eq!(ServerStatus::parse("uptime=5s keys=1"), None);
+    // Servers without compression don't report saved_bytes
+    let older = ServerStatus::parse(
+        "uptime=5s keys=1 expiring=0 mem_bytes~=70 version=0.1.1 connections=1",
+    )
+    .unwrap();
+    assert_eq!(older.saved_bytes, 0);
+    assert_eq!(
+        (older.bytes_read, older.bytes_written, older.commands),
+        (0, 0, 0)
+    );
+}
+
+#[test]
+fn hello_options_and_reply() {
+    let quiet = Command::Hello {
+        heartbeat: false,
+        proto: 1,
+    };
+    assert_eq!(
+        Command::parse("hello noheartbeat proto=1"),
+        Ok(quiet.clone())
+    );
+    assert_eq!(Command::parse(&quiet.to_line()), Ok(quiet));
+    assert_eq!(
+        Command::parse("HELLO"),
+        Ok(Command::Hello {
+            heartbeat: true,
+            proto: 1
+        })
+    );
+    // A newer client's revision is accepted; the server answers with its own
+    assert_eq!(
+        Command::parse("HELLO proto=2"),
+        Ok(Command::Hello {
+            heartbeat: true,
+            proto: 2
+        })
+    );
+    assert_eq!(
+        Command::parse("HELLO proto=0"),
+        Err(ParseError::InvalidArguments)
+    );
+    assert_eq!(
+        Command::parse("HELLO loud"),
+        Err(ParseError::InvalidArguments)
+    );
+
+    let reply = HelloReply {
+        version: "0.1.1".to_string(),
+        proto: 1,
+        heartbeat: Some(Duration::from_secs(120)),
+        features: vec![],
+    };
+    let line = reply.to_line();
+    assert_eq!(line, "HELLO version=0.1.1 proto=1 heartbeat=120s");
+    assert_eq!(HelloReply::parse(&line), Some(reply));
+
+    let reply = HelloReply {
+        version: "0.1.1".to_string(),
+        proto: 1,
+        heartbeat: None,
+        features: vec!["batch".to_string(), "admin".to_string()],
+    };
+    let line = reply.to_line();
+    assert_eq!(
+        line,
+        "HELLO version=0.1.1 proto=1 heartbeat=off features=batch,admin"
+    );
+    assert_eq!(HelloReply::parse(&line), Some(reply.clone()));
+    assert!(reply.supports("admin"));
+    assert!(!reply.supports("binary"));
+    assert_eq!(
+        HelloReply::parse("HELLO version=0.1.1 proto=1 heartbeat=off compress=no")
+            .unwrap()
+            .heartbeat,
+        None
+    );
+    assert_eq!(HelloReply::parse("ERROR unknown command"), None);
+}
+
+#[test]
+fn select_parses_and_validates_namespace() {
+    assert_eq!(
+        Command::parse("SELECT billing"),
+        Ok(Command::Select {
+            namespace: Some("billing".to_string())
+        })
+    );
+    assert_eq!(
+        Command::parse("select"),
+        Ok(Command::Select { namespace: None })
+    );
+    assert_eq!(
+        Command::parse("SELECT a:b"),
+        Err(ParseError::InvalidNamespace)
+    );
+    assert_eq!(
+        Command::parse("SELECT a b"),
+        Err(ParseError::InvalidArguments)
+    );
+    let select = Command::Select {
+        namespace: Some("search".to_string()),
+    };
+    assert_eq!(Command::parse(&select.to_line()), Ok(select));
+}
+
+#[test]
+fn dump_and_load_parse_with_merge_by_default() {
+    assert_eq!(
+        Command::parse("load /tmp/kv.json"),
+        Ok(Command::Load {
+            path: "/tmp/kv.json".to_string(),
+            mode: LoadMode::Merge,
+        })
+    );
+    let load = Command::Load {
+        path: "/tmp/kv.json".to_string(),
+        mode: LoadMode::Replace,
+    };
+    assert_eq!(Command::parse(&load.to_line()), Ok(load.clone()));
+    assert_eq!(
+        Command::parse("LOAD /tmp/kv.json overwrite"),
+        Err(ParseError::InvalidArguments)
+    );
+    assert_eq!(Command::parse("DUMP"), Err(ParseError::InvalidArguments));
+    assert_eq!(Command::parse("bgrewriteaof"), Ok(Command::BgRewriteAof));
+    assert_eq!(
+        Command::parse("BGREWRITEAOF now"),
+        Err(ParseError::InvalidArguments)
+    );
+    assert_eq!(
+        Response::parse(&Command::BgRewriteAof, "OK rewritten=2"),
+        Response::Status("OK rewritten=2".to_string())
+    );
+    assert_eq!(
+        Response::parse(&load, "OK loaded=3 skipped=1"),
+        Response::Status("OK loaded=3 skipped=1".to_string())
+    );
+}
+
+#[test]
+fn key_stats_commands_and_counter_lines() {
+    assert_eq!(Command::parse("stats top 5"), Ok(Command::TopKeys { n: 5 }));
+    assert_eq!(
+        Command::parse("STATS TOP many"),
+        Err(ParseError::InvalidArguments)
+    );
+    assert_eq!(
+        Command::parse("STATS KEY"),
+        Err(ParseError::InvalidArguments)
+    );
+    let command = Command::KeyStats {
+        key: "user:1".to_string(),
+    };
+    let line = "key=user:1 hits=12 misses=3 sets=2 last_access=1760605923481";
+    assert_eq!(
+        Response::parse(&command, line),
+        Response::Status(line.to_string())
+    );
+    let counters = KeyCounters::parse(line).unwrap();
+    assert_eq!((counters.hits, counters.misses, counters.sets), (12, 3, 2));
+    assert_eq!(counters.accesses(), 17);
+    assert_eq!(counters.to_line(), line);
+}
+
+#[test]
+fn list_expirations_convert_to_utc_datetimes() {
+    let entry = ListEntry::parse("session=abc,str,1760605923").unwrap();
+    let expected = chrono::Utc
+        .with_ymd_and_hms(2025, 10, 16, 9, 12, 3)
+        .single()
+        .unwrap();
+    assert_eq!(entry.expires_at_utc(), Some(expected));
+    let forever = ListEntry::parse("motd=hello,str,no-expiration").unwrap();
+    assert_eq!(forever.expires_at_utc(), None);
+}
+
+#[test]
+fn compress_parses_and_frames_replies() {
+    assert_eq!(
+        Command::parse("compress on"),
+        Ok(Command::Compress { on: true })
+    );
+    let off = Command::Compress { on: false };
+    assert_eq!(Command::parse(&off.to_line()), Ok(off));
+    assert_eq!(
+        Command::parse("COMPRESS maybe"),
+        Err(ParseError::InvalidArguments)
+    );
+
+    // Short replies keep their lines, each behind the plain flag
+    assert_eq!(
+        frame_reply("a=1,no-expiration\n\n"),
+        b"=a=1,no-expiration\n=\n"
+    );
+    let long = "x".repeat(COMPRESS_MIN_BYTES) + "\n";
+    let frame = frame_reply(&long);
+    let (header, data) = frame.split_at(frame.iter().position(|&b| b == b'\n').unwrap() + 1);
+    assert_eq!(header[0], FRAME_DEFLATE);
+    let len: usize = std::str::from_utf8(&header[1..])
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    assert_eq!(len, data.len());
+    assert_eq!(inflate_frame(data).unwrap(), long.as_bytes());
+}
+
+#[test]
+fn time_replies_carry_seconds_and_nanoseconds() {
+    let time = std::time::UNIX_EPOCH + Duration::new(1_760_605_923, 481_000_007);
+    assert_eq!(time_line(time), "1760605923 481000007");
+    assert_eq!(parse_time("1760605923 481000007"), Some(time));
+    assert_eq!(parse_time("1760605923"), None);
+    assert_eq!(parse_time("1760605923 1000000000"), None);
+    assert_eq!(parse_time("soon 0"), None);
+    assert_eq!(Command::parse("time"), Ok(Command::Time));
+    assert_eq!(
+        Command::parse("TIME now"),
+        Err(ParseError::InvalidArguments)
+    );
+    assert_eq!(
+        Response::parse(&Command::Time, "1760605923 481000007"),
+        Response::Status("1760605923 481000007".to_string())
+    );
+}
+
+#[test]
+fn set_raw_modifier_follows_the_value_and_ttl() {
+    let set = |value: &str, ttl: Option<u64>, raw: bool| Command::Set {
+        key: "k".to_string(),
+        value: value.to_string(),
+        ttl: ttl.map(Duration::from_secs),
+        raw,
+    };
+    assert_eq!(Command::parse("SET k 007 raw"), Ok(set("007", None, true)));
+    assert_eq!(
+        Command::parse("SET k 007 10s RAW"),
+        Ok(set("007", Some(10), true))
+    );
+    assert_eq!(set("007", Some(10), true).to_line(), "SET k 007 10s RAW");
+    // With only a key before it, RAW is the value
+    assert_eq!(Command::parse("SET k RAW"), Ok(set("RAW", None, false)));
+    assert_eq!(Command::parse("SET k RAW RAW"), Ok(set("RAW", None, true)));
+    assert_eq!(
+        Command::parse("SET k 007 RAW 10s"),
+        Err(ParseError::InvalidArguments)
+    );
+    assert_eq!(
+        Command::parse("SET k 007 10s RAW RAW"),
+        Err(ParseError::InvalidArguments)
+    );
+}
+
+#[test]
+fn getdel_takes_one_key_and_answers_like_get() {
+    let getdel = Command::parse("getdel token").unwrap();
+    assert_eq!(
+        getdel,
+        Command::GetDel {
+            key: "token".to_string()
+        }
+    );
+    assert_eq!(getdel.to_line(), "GETDEL token");
+    assert_eq!(
+        Command::parse("GETDEL a b"),
+        Err(ParseError::InvalidArguments)
+    );
+    assert_eq!(
+        Command::parse(&format!("GETDEL {}", "k".repeat(MAX_KEY_LEN + 1))),
+        Err(ParseError::InvalidLength)
+    );
+    assert_eq!(
+        Response::parse(&getdel, "code-123"),
+        Response::Value("code-123".to_string())
+    );
+    assert_eq!(Response::parse(&getdel, KEY_NOT_FOUND), Response::NotFound);
+}
+
+#[test]
+fn incrbyfloat_takes_a_finite_non_zero_delta() {
+    assert_eq!(
+        Command::parse("incrbyfloat rate 0.5 1h"),
+        Ok(Command::IncrByFloat {
+            key: "rate".to_string(),
+            delta: 0.5,
+            ttl: Some(Duration::from_secs(3600)),
+        })
+    );
+    let built = Command::IncrByFloat {
+        key: "rate".to_string(),
+        delta: -1e-7,
+        ttl: None,
+    };
+    assert_eq!(built.to_line(), "INCRBYFLOAT rate -0.0000001");
+    assert_eq!(Command::parse(&built.to_line()), Ok(built.clone()));
+    for delta in ["inf", "-inf", "NaN", "1e400"] {
+        assert_eq!(
+            Command::parse(&format!("INCRBYFLOAT rate {}", delta)),
+            Err(ParseError::NotANumber),
+            "{}",
+            delta
+        );
+    }
+    for line in [
+        "INCRBYFLOAT rate 0",
+        "INCRBYFLOAT rate half",
+        "INCRBYFLOAT rate",
+    ] {
+        assert_eq!(
+            Command::parse(line),
+            Err(ParseError::InvalidArguments),
+            "{}",
+            line
+        );
+    }
+    assert_eq!(
+        Response::parse(&built, "2.5"),
+        Response::Value("2.5".to_string())
+    );
+    assert_eq!(
+        Response::parse(&Command::parse("INCR rate").unwrap(), "ERROR wrong type"),
+        Response::Error("wrong type".to_string())
+    );
+}
+
+#[test]
+fn limits_bound_keys_and_values() {
+    let limits = Limits::default();
+    assert_eq!(
+        limits.to_line(),
+        "max_key_len=100 max_value_len=100 max_bytes_len=100"
+    );
+    assert_eq!(Limits::parse(&limits.to_line()), Some(limits));
+    let raised = Limits::parse("max_key_len=200 max_value_len=500 since=0.2").unwrap();
+    assert_eq!(
+        raised,
+        Limits {
+            max_key_len: 200,
+            max_value_len: 500,
+            max_bytes_len: 100,
+        }
+    );
+    assert_eq!(Limits::parse("max_key_len=lots"), None);
+
+    assert_eq!(Command::parse("limits"), Ok(Command::Limits));
+    assert_eq!(
+        Command::parse("LIMITS keys"),
+        Err(ParseError::InvalidArguments)
+    );
+    assert_eq!(
+        Response::parse(&Command::Limits, &raised.to_line()),
+        Response::Status(raised.to_line())
+    );
+
+    let line = format!("SET {} {}", "k".repeat(150), "v".repeat(400));
+    assert_eq!(Command::parse(&line), Err(ParseError::InvalidLength));
+    let command = Command::parse_with(&line, &raised).unwrap();
+    assert_eq!(command.validate(), Err(ParseError::InvalidLength));
+    assert_eq!(command.validate_with(&raised), Ok(()));
+    let line = format!("GET {}", "k".repeat(201));
+    assert_eq!(
+        Command::parse_with(&line, &raised),
+        Err(ParseError::InvalidLength)
+    );
+}
+
+#[test]
+fn mset_takes_whole_pairs() {
+    assert_eq!(
+        Command::parse("mset a 1 b two"),
+        Ok(Command::MSet {
+            pairs: vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "two".to_string())
+            ],
+            ttl: None,
+        })
+    );
+    // Only an odd trailing argument is read as the TTL
+    assert_eq!(
+        Command::parse("MSET a 1 b 2 TTL=5min"),
+        Ok(Command::MSet {
+            pairs: vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "2".to_string())
+            ],
+            ttl: Some(Duration::from_secs(300)),
+        })
+    );
+    assert_eq!(
+        Command::parse("MSET a TTL=5s"),
+        Ok(Command::MSet {
+            pairs: vec![("a".to_string(), "TTL=5s".to_string())],
+            ttl: None,
+        })
+    );
+    assert_eq!(
+        Command::parse("MSET a 1 TTL=soon"),
+        Err(ParseError::InvalidExpiration)
+    );
+    assert_eq!(
+        Command::parse("MSET TTL=5s"),
+        Err(ParseError::InvalidArguments)
+    );
+    // One bad pair rejects the whole command
+    let long = "v".repeat(MAX_VALUE_LEN + 1);
+    assert_eq!(
+        Command::parse(&format!("MSET a 1 b {}", long)),
+        Err(ParseError::InvalidLength)
+    );
+    let most: Vec<String> = (0..MAX_MSET_PAIRS).map(|i| format!("k{} v", i)).collect();
+    assert!(Command::parse(&format!("MSET {}", most.join(" "))).is_ok());
+    assert!(Command::parse(&format!("MSET {} TTL=1h", most.join(" "))).is_ok());
+    assert_eq!(
+        Command::parse(&format!("MSET {} extra v", most.join(" "))),
+        Err(ParseError::InvalidArguments)
+    );
+    let built = Command::MSet {
+        pairs: vec![],
+        ttl: None,
+    };
+    assert_eq!(built.validate(), Err(ParseError::InvalidArguments));
+    assert_eq!(
+        Response::parse(&Command::parse("MSET a 1").unwrap(), "OK"),
+        Response::Ok
+    );
+}
+
+#[test]
+fn mget_replies_end_with_a_sentinel() {
+    assert_eq!(
+        Command::parse("mget a b"),
+        Ok(Command::MGet {
+            keys: vec!["a".to_string(), "b".to_string()]
+        })
+    );
+    assert_eq!(Command::parse("MGET"), Err(ParseError::InvalidArguments));
+    // Over-long keys parse; the server fails them one by one
+    let long = "k".repeat(MAX_KEY_LEN + 1);
+    assert!(Command::parse(&format!("MGET a {}", long)).is_ok());
+
+    let reply = Response::Values(vec![
+        Response::Value("1".to_string()),
+        Response::NotFound,
+        Response::error(ParseError::InvalidLength),
+    ]);
+    assert_eq!(reply.to_wire(), "1\n*NIL*\nERROR invalid length\nEND\n");
+    assert_eq!(
+        Response::Values(vec![]).to_wire(),
+        format!("{}\n", MGET_END)
+    );
+    assert_eq!(NIL, "*NIL*");
+}
@@ -0,0 +1,3196 @@
+mod common;
+
+use common::{
+    fill_template, record_code, run_init, run_selftest, run_selftest_file, temp_dir, HungSlog,
+    KvServer,
+};
+use shrmpl::kv_protocol::{self, ExpiryChange, HelloReply, Limits, LoadMode, MAX_KEY_LEN};
+use shrmpl::kv_server;
+use shrmpl::shrmpl_kv_client::{KvClient, KvError};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+#[tokio::test]
+async fn set_get_and_delete() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    client.set("greeting", "hello").await.unwrap();
+    assert_eq!(
+        client.get("greeting").await.unwrap(),
+        Some("hello".to_string())
+    );
+    assert_eq!(client.incr("hits").await.unwrap(), 1);
+    assert_eq!(client.incr("hits").await.unwrap(), 2);
+    client.delete("greeting").await.unwrap();
+    assert_eq!(client.get("greeting").await.unwrap(), None);
+    assert!(client.ping().await.is_ok());
+}
+
+#[tokio::test]
+async fn ttl_expiry() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    client.set_with_ttl("session", "abc", "1s").await.unwrap();
+    assert_eq!(
+        client.get("session").await.unwrap(),
+        Some("abc".to_string())
+    );
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.get("session").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn peek_reads_without_removing_expired_keys() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set("motd", "hello").await.unwrap();
+    client.set_with_ttl("session", "abc", "1s").await.unwrap();
+
+    assert_eq!(
+        client.peek("motd").await.unwrap(),
+        Some("hello".to_string())
+    );
+    assert_eq!(client.peek("missing").await.unwrap(), None);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    // PEEK reports the expired key missing but leaves it in place; GET removes it
+    assert_eq!(client.peek("session").await.unwrap(), None);
+    assert_eq!(client.status().await.unwrap().keys, 2);
+    assert_eq!(client.get("session").await.unwrap(), None);
+    assert_eq!(client.status().await.unwrap().keys, 1);
+
+    client.select(Some("billing")).await.unwrap();
+    client.set("motd", "scoped").await.unwrap();
+    assert_eq!(
+        client.peek("motd").await.unwrap(),
+        Some("scoped".to_string())
+    );
+}
+
+#[tokio::test]
+async fn getex_renews_or_clears_the_expiration_of_existing_keys() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set_with_ttl("session", "abc", "1s").await.unwrap();
+    client.set_with_ttl("token", "xyz", "1s").await.unwrap();
+
+    let renewed = client
+        .get_ex("session", ExpiryChange::Ttl(Duration::from_secs(60)))
+        .await
+        .unwrap();
+    assert_eq!(renewed, Some("abc".to_string()));
+    let persisted = client.get_ex("token", ExpiryChange::Persist).await.unwrap();
+    assert_eq!(persisted, Some("xyz".to_string()));
+    assert_eq!(client.status().await.unwrap().expiring, 1);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    // Both outlived their original second
+    assert_eq!(
+        client.get("session").await.unwrap(),
+        Some("abc".to_string())
+    );
+    assert_eq!(client.get("token").await.unwrap(), Some("xyz".to_string()));
+    let expires_at = client
+        .list()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|(key, _, _, _)| key == "session")
+        .and_then(|(_, _, _, expires_at)| expires_at)
+        .unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    assert!(expires_at > now + 50, "{} vs {}", expires_at, now);
+
+    // Without an expiration argument it reads like GET and changes nothing
+    let kept = client.get_ex("session", ExpiryChange::Keep).await.unwrap();
+    assert_eq!(kept, Some("abc".to_string()));
+    assert_eq!(client.status().await.unwrap().expiring, 1);
+}
+
+#[tokio::test]
+async fn getex_of_a_missing_or_expired_key_creates_nothing() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let ttl = ExpiryChange::Ttl(Duration::from_secs(60));
+    assert_eq!(client.get_ex("missing", ttl).await.unwrap(), None);
+    assert_eq!(client.status().await.unwrap().keys, 0);
+
+    client.set_with_ttl("session", "abc", "1s").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let persist = ExpiryChange::Persist;
+    assert_eq!(client.get_ex("session", persist).await.unwrap(), None);
+    assert_eq!(client.status().await.unwrap().keys, 0);
+
+    client.select(Some("billing")).await.unwrap();
+    client.set("session", "scoped").await.unwrap();
+    assert_eq!(
+        client.get_ex("session", persist).await.unwrap(),
+        Some("scoped".to_string())
+    );
+}
+
+#[tokio::test]
+async fn key_stats_count_hits_misses_and_sets() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let err = client.top_keys(5).await.unwrap_err();
+    assert_eq!(err.to_string(), "ERROR key stats disabled");
+
+    let server = KvServer::start_with(&[("KEY_STATS", "true")]).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set("user", "1").await.unwrap();
+    client.set("user", "2").await.unwrap();
+    for _ in 0..3 {
+        client.get("user").await.unwrap();
+    }
+    client.get("cart").await.unwrap();
+    client.peek("user").await.unwrap();
+
+    let user = client.key_stats("user").await.unwrap().unwrap();
+    assert_eq!((user.hits, user.misses, user.sets), (3, 0, 2));
+    assert!(user.last_access > 0);
+    assert_eq!(client.key_stats("cart").await.unwrap().unwrap().misses, 1);
+    assert!(client.key_stats("other").await.unwrap().is_none());
+    let top: Vec<String> = client
+        .top_keys(5)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|c| c.key)
+        .collect();
+    assert_eq!(top, vec!["user", "cart"]);
+
+    client.select(Some("billing")).await.unwrap();
+    client.get("user").await.unwrap();
+    assert_eq!(client.key_stats("user").await.unwrap().unwrap().misses, 1);
+}
+
+#[tokio::test]
+async fn access_log_writes_one_structured_line_per_command() {
+    let server = KvServer::start_with(&[("ACCESS_LOG", "true")]).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.get("user").await.unwrap();
+    client.set("user", "alice").await.unwrap();
+    client.select(Some("billing")).await.unwrap();
+    client.get("user").await.unwrap();
+
+    let mut access = Vec::new();
+    for _ in 0..200 {
+        access = server
+            .slog
+            .lines()
+            .into_iter()
+            .filter(|l| record_code(l) == "KVACCESS")
+            .map(|l| l.split_once(": ").unwrap().1.to_string())
+            .collect();
+        if access.len() == 5 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    // TIMESTAMP PEER VERB KEY RESULT BYTES DURATION_US
+    let fields: Vec<Vec<&str>> = access.iter().map(|l| l.split(' ').collect()).collect();
+    assert_eq!(fields.len(), 5, "{:?}", access);
+    for entry in &fields {
+        assert_eq!(entry.len(), 7, "{:?}", entry);
+        assert!(chrono::DateTime::parse_from_rfc3339(entry[0]).is_ok());
+        assert!(entry[1].starts_with("127.0.0.1:"));
+        assert!(entry[6].parse::<u64>().is_ok());
+    }
+    let summary: Vec<&[&str]> = fields.iter().map(|entry| &entry[2..6]).collect();
+    // The client asks for LIMITS on connect
+    assert_eq!(summary[0], ["LIMITS", "-", "ok", "52"]);
+    assert_eq!(summary[1], ["GET", "user", "miss", "16"]);
+    assert_eq!(summary[2], ["SET", "user", "ok", "3"]);
+    assert_eq!(summary[3], ["SELECT", "-", "ok", "3"]);
+    assert_eq!(summary[4], ["GET", "billing:user", "miss", "16"]);
+}
+
+#[tokio::test]
+async fn disabled_commands_are_rejected_whatever_their_arguments() {
+    let server = KvServer::start_with(&[
+        ("ADMIN_COMMANDS", "true"),
+        ("DISABLED_COMMANDS", "list, Export,FLUSHALL"),
+    ])
+    .await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set("a", "1").await.unwrap();
+    assert_eq!(client.get("a").await.unwrap(), Some("1".to_string()));
+
+    let err = client.list().await.unwrap_err();
+    assert_eq!(err.to_string(), "ERROR command disabled");
+    assert!(client.export().await.is_err());
+    let responses = client.batch(&["GET a", "list", "INCR a"]).await.unwrap();
+    assert_eq!(responses, vec!["1", "ERROR command disabled", "2"]);
+    // Names the server doesn't have are accepted and change nothing
+    assert!(client.status().await.is_ok());
+}
+
+#[tokio::test]
+async fn compression_shrinks_large_replies_without_changing_them() {
+    let server =
+        KvServer::start_with(&[("MAX_BYTES_LEN", "65536"), ("ADMIN_COMMANDS", "true")]).await;
+    let data: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+    let mut plain = KvClient::connect(&server.addr()).await.unwrap();
+    plain.set_bytes("blob", &data).await.unwrap();
+    for i in 0..50 {
+        plain.set(&format!("k{}", i), "value").await.unwrap();
+    }
+
+    let mut compressed = KvClient::connect(&server.addr()).await.unwrap();
+    assert!(compressed.supports(kv_protocol::FEATURE_COMPRESS));
+    compressed.set_compression(true).await.unwrap();
+
+    let before = (plain.bytes_read(), compressed.bytes_read());
+    let plain_value = plain.get_bytes("blob").await.unwrap();
+    let compressed_value = compressed.get_bytes("blob").await.unwrap();
+    let plain_bytes = plain.bytes_read() - before.0;
+    let compressed_bytes = compressed.bytes_read() - before.1;
+    assert_eq!(compressed_value, plain_value);
+    assert_eq!(compressed_value.unwrap(), data);
+    assert!(
+        compressed_bytes * 4 < plain_bytes,
+        "{} vs {}",
+        compressed_bytes,
+        plain_bytes
+    );
+
+    // Multi-line, streamed and small replies come through the framing intact
+    assert_eq!(
+        compressed.list().await.unwrap(),
+        plain.list().await.unwrap()
+    );
+    assert_eq!(
+        compressed.export().await.unwrap(),
+        plain.export().await.unwrap()
+    );
+    assert_eq!(
+        compressed.get("k1").await.unwrap(),
+        Some("value".to_string())
+    );
+    assert_eq!(compressed.get("missing").await.unwrap(), None);
+    let responses = compressed.batch(&["GET k1", "COMPRESS OFF"]).await.unwrap();
+    assert_eq!(
+        responses,
+        vec!["value", "ERROR COMPRESS must be sent on its own"]
+    );
+
+    compressed.set_compression(false).await.unwrap();
+    assert_eq!(
+        compressed.get("k2").await.unwrap(),
+        Some("value".to_string())
+    );
+}
+
+#[tokio::test]
+async fn batch_and_admin_gating() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    client.set("a", "1").await.unwrap();
+    let responses = client
+        .batch(&["GET a", "GET missing", "INCR a"])
+        .await
+        .unwrap();
+    assert_eq!(responses, vec!["1", "*KEY NOT FOUND*", "2"]);
+    assert!(client
+        .batch(&["PING", "PING", "PING", "PING"])
+        .await
+        .is_err());
+
+    let err = client.export().await.unwrap_err().to_string();
+    assert!(err.contains("admin commands disabled"), "{}", err);
+}
+
+#[tokio::test]
+async fn shutdown_sends_term_and_logs() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set("k", "v").await.unwrap();
+
+    let listening = server.slog.wait_for_code("KVSERVERLIST").await;
+    assert!(listening.starts_with("INFO test-host"), "{}", listening);
+    assert!(listening.ends_with(&server.addr()), "{}", listening);
+
+    let slog = server.shutdown().await;
+    let down = slog.wait_for_code("KVSERVERDOWN").await;
+    assert!(down.ends_with("Shutting down server..."), "{}", down);
+
+    // The connection task writes TERM and closes, which surfaces as an error
+    let err = client.get("k").await.unwrap_err().to_string();
+    assert!(
+        err == "Server shutting down" || err == "Connection closed by server",
+        "{}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn invalid_utf8_is_rejected_without_dropping_connection() {
+    let server = KvServer::start(false).await;
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(b"SET k caf\xe9\nPING\n").await.unwrap();
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "ERROR invalid encoding\n");
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "PONG\n");
+
+    let warning = server.slog.wait_for_code("KVBADENCODE").await;
+    assert!(warning.starts_with("WARN"), "{}", warning);
+}
+
+#[tokio::test]
+async fn pipelined_commands_get_their_replies_in_one_write() {
+    let server = KvServer::start(false).await;
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    let mut burst = String::from("HELLO noheartbeat\n");
+    let mut expected = String::new();
+    for i in 0..40 {
+        burst.push_str(&format!("SET k{} v{}\nGET k{}\nINCR n\n", i, i, i));
+        expected.push_str(&format!("OK\nv{}\n{}\n", i, i + 1));
+    }
+    burst.push_str("DEL nothing\nPING\n");
+    expected.push_str(&format!("{}\nPONG\n", kv_protocol::KEY_NOT_FOUND));
+    stream.write_all(burst.as_bytes()).await.unwrap();
+
+    // Only the HELLO reply may come separately: everything after it arrives at once
+    let mut received = Vec::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    while received.iter().filter(|&&b| b == b'\n').count() < 2 {
+        let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(n > 0, "connection closed");
+        received.extend_from_slice(&buf[..n]);
+    }
+    let received = String::from_utf8(received).unwrap();
+    let (hello, rest) = received.split_once('\n').unwrap();
+    assert!(hello.starts_with("HELLO "), "{}", hello);
+    assert_eq!(rest, expected);
+
+    // A lone command is still answered straight away
+    stream.write_all(b"PING\n").await.unwrap();
+    let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf[..n], b"PONG\n");
+}
+
+#[tokio::test]
+async fn client_reports_corrupted_response_and_recovers() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let (read_half, mut write_half) = socket.split();
+        let mut reader = BufReader::new(read_half);
+        let mut line = String::new();
+        // Answer the client's HELLO the way a server without HELLO support would
+        reader.read_line(&mut line).await.unwrap();
+        write_half
+            .write_all(b"ERROR unknown command\n")
+            .await
+            .unwrap();
+        reader.read_line(&mut line).await.unwrap();
+        write_half.write_all(b"\xff\xfe\n").await.unwrap();
+        reader.read_line(&mut line).await.unwrap();
+        write_half.write_all(b"value\n").await.unwrap();
+    });
+
+    let mut client = KvClient::connect(&addr.to_string()).await.unwrap();
+    assert!(client.negotiated().is_none());
+    let err = client.get("a").await.unwrap_err().to_string();
+    assert_eq!(err, "Invalid UTF-8 in server response");
+    assert_eq!(client.get("b").await.unwrap(), Some("value".to_string()));
+}
+
+#[tokio::test]
+async fn binary_values_round_trip() {
+    let server = KvServer::start_with(&[("MAX_BYTES_LEN", "64")]).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    let max: Vec<u8> = (0..64).map(|i| (i * 37) as u8).collect();
+    let cases: [&[u8]; 4] = [b"\0\0\0", b"line\nbreak\r\n", &[0xff, 0x00, 0x80], &max];
+    for (i, data) in cases.iter().enumerate() {
+        let key = format!("blob{}", i);
+        client.set_bytes(&key, data).await.unwrap();
+        assert_eq!(
+            client.get_bytes(&key).await.unwrap().as_deref(),
+            Some(*data)
+        );
+    }
+
+    // The limit applies to the decoded length, and GET returns the base64 form
+    let err = client.set_bytes("big", &[0u8; 65]).await.unwrap_err();
+    assert_eq!(err.to_string(), "ERROR invalid length");
+    assert_eq!(client.get("blob0").await.unwrap(), Some("AAAA".to_string()));
+    assert_eq!(client.get_bytes("missing").await.unwrap(), None);
+    client.set("text", "hi").await.unwrap();
+    assert_eq!(
+        client.get_bytes("text").await.unwrap(),
+        Some(b"hi".to_vec())
+    );
+}
+
+#[tokio::test]
+async fn key_and_value_limits_come_from_config() {
+    let server = KvServer::start_with(&[("MAX_KEY_LEN", "200"), ("MAX_VALUE_LEN", "500")]).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let limits = client.limits();
+    assert_eq!((limits.max_key_len, limits.max_value_len), (200, 500));
+
+    // The client checks against what LIMITS reported, not the defaults
+    let (key, value) = ("k".repeat(200), "v".repeat(500));
+    client.set(&key, &value).await.unwrap();
+    assert_eq!(client.get(&key).await.unwrap(), Some(value));
+    assert!(client.set("a", &"v".repeat(501)).await.is_err());
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    let request = format!(
+        "LIMITS\nSET a {}\nGET {}\n",
+        "v".repeat(501),
+        "k".repeat(201)
+    );
+    stream
+        .get_mut()
+        .write_all(request.as_bytes())
+        .await
+        .unwrap();
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    assert_eq!(
+        line,
+        "max_key_len=200 max_value_len=500 max_bytes_len=100\n"
+    );
+    for _ in 0..2 {
+        line.clear();
+        stream.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "ERROR invalid length\n");
+    }
+
+    let default = KvServer::start(false).await;
+    let mut client = KvClient::connect(&default.addr()).await.unwrap();
+    assert_eq!(client.limits(), Limits::default());
+    let mut stream = BufReader::new(TcpStream::connect(default.addr).await.unwrap());
+    let request = format!("SET a {}\n", "v".repeat(101));
+    stream
+        .get_mut()
+        .write_all(request.as_bytes())
+        .await
+        .unwrap();
+    line.clear();
+    stream.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "ERROR invalid length\n");
+    assert!(client.set("a", &"v".repeat(101)).await.is_err());
+}
+
+#[tokio::test]
+async fn status_tracks_keys_memory_and_connections() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let empty = client.status().await.unwrap();
+    assert_eq!((empty.keys, empty.expiring, empty.mem_bytes), (0, 0, 0));
+    assert_eq!(empty.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(empty.connections, 1);
+
+    let mut second = KvClient::connect(&server.addr()).await.unwrap();
+    second.ping().await.unwrap(); // Make sure the server has accepted it
+    client.set("a", "12345").await.unwrap();
+    client.set_with_ttl("b", "x", "1h").await.unwrap();
+    let grown = client.status().await.unwrap();
+    assert_eq!((grown.keys, grown.expiring), (2, 1));
+    assert_eq!(grown.connections, 2);
+
+    // Overwrite with a shorter value and delete the other key
+    client.set("a", "1").await.unwrap();
+    client.delete("b").await.unwrap();
+    let shrunk = client.status().await.unwrap();
+    assert_eq!((shrunk.keys, shrunk.expiring), (1, 0));
+    assert!(shrunk.mem_bytes < grown.mem_bytes);
+
+    client.delete("a").await.unwrap();
+    assert_eq!(client.status().await.unwrap().mem_bytes, 0);
+}
+
+#[tokio::test]
+async fn incr_rejects_overflow_and_non_integers() {
+    let server = KvServer::start(true).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let kv_error = |err: Box<dyn std::error::Error>| *err.downcast::<KvError>().unwrap();
+
+    client.set("max", &i64::MAX.to_string()).await.unwrap();
+    let err = client.incr("max").await.unwrap_err();
+    assert_eq!(kv_error(err), KvError::Overflow);
+    assert_eq!(client.get("max").await.unwrap(), Some(i64::MAX.to_string()));
+
+    client.set("min", &i64::MIN.to_string()).await.unwrap();
+    assert_eq!(client.incr("min").await.unwrap(), i64::MIN + 1);
+
+    // Only IMPORT can store a numeric value as a string
+    client
+        .import(r#"[{"key":"numstr","value":"41","type":"str","expires_at":null},{"key":"maxstr","value":"9223372036854775807","type":"str","expires_at":null}]"#)
+        .await
+        .unwrap();
+    assert_eq!(client.incr("numstr").await.unwrap(), 42);
+    let err = client.incr("maxstr").await.unwrap_err();
+    assert_eq!(kv_error(err), KvError::Overflow);
+
+    client.set("word", "hello").await.unwrap();
+    let err = client.incr("word").await.unwrap_err();
+    assert_eq!(kv_error(err), KvError::NotAnInteger);
+    assert_eq!(client.get("word").await.unwrap(), Some("hello".to_string()));
+    client.set_bytes("blob", &[0, 1, 2]).await.unwrap();
+    let err = client.incr("blob").await.unwrap_err();
+    assert_eq!(kv_error(err), KvError::NotAnInteger);
+}
+
+#[tokio::test]
+async fn getset_returns_the_value_it_replaces() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    assert_eq!(client.getset("counter", "5").await.unwrap(), None);
+    assert_eq!(
+        client.getset("counter", "0").await.unwrap(),
+        Some("5".to_string())
+    );
+    assert_eq!(client.incr("counter").await.unwrap(), 1);
+    assert_eq!(client.ttl("counter").await.unwrap(), -1);
+
+    // A live key keeps its expiration; an expired one is treated as absent
+    client.set_with_ttl("lease", "a", "1h").await.unwrap();
+    assert_eq!(
+        client.getset("lease", "b").await.unwrap(),
+        Some("a".to_string())
+    );
+    assert!(client.ttl("lease").await.unwrap() > 3500);
+    client.set_with_ttl("brief", "a", "1s").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.getset("brief", "b").await.unwrap(), None);
+    assert_eq!(client.get("brief").await.unwrap(), Some("b".to_string()));
+    assert_eq!(client.ttl("brief").await.unwrap(), -1);
+
+    // An expiration replaces the old one, on a live key or a fresh one
+    assert_eq!(
+        client.getset_with_ttl("lease", "c", "10s").await.unwrap(),
+        Some("b".to_string())
+    );
+    assert!((1..=10).contains(&client.ttl("lease").await.unwrap()));
+    assert_eq!(
+        client
+            .getset_with_ttl("handover", "a", "1min")
+            .await
+            .unwrap(),
+        None
+    );
+    assert!(client.ttl("handover").await.unwrap() > 50);
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    for (line, reply) in [
+        ("GETSET fresh 1\n", "*NIL*\n"),
+        ("GETSET fresh 2\n", "1\n"),
+        ("GETSET fresh 3 soon\n", "ERROR invalid expiration\n"),
+        ("GETSET fresh\n", "ERROR invalid arguments\n"),
+    ] {
+        stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply, "{}", line);
+    }
+}
+
+// A key that never existed: nothing is returned and the key is created as SET would
+#[tokio::test]
+async fn getset_creates_a_fresh_key() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    assert_eq!(client.getset("plain", "hello").await.unwrap(), None);
+    assert_eq!(client.get("plain").await.unwrap().as_deref(), Some("hello"));
+    assert_eq!(client.ttl("plain").await.unwrap(), -1);
+
+    assert_eq!(client.getset("number", "41").await.unwrap(), None);
+    assert_eq!(
+        client.key_type("number").await.unwrap().as_deref(),
+        Some("int")
+    );
+    assert_eq!(client.incr("number").await.unwrap(), 42);
+
+    assert_eq!(
+        client
+            .getset_with_ttl("lock", "owner-a", "30s")
+            .await
+            .unwrap(),
+        None
+    );
+    let pttl = client.pttl("lock").await.unwrap();
+    assert!(pttl > 25_000 && pttl <= 30_000, "{}", pttl);
+    assert_eq!(
+        client.getset("lock", "owner-b").await.unwrap().as_deref(),
+        Some("owner-a")
+    );
+}
+
+// A key whose expiration has passed but that the sweep hasn't removed yet reads as
+// missing, and its old expiration does not carry over to the new value
+#[tokio::test]
+async fn getset_treats_an_expired_key_as_missing() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    client.set_with_ttl("plain", "stale", "1s").await.unwrap();
+    client.set_with_ttl("lock", "owner-a", "1s").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    assert_eq!(client.getset("plain", "fresh").await.unwrap(), None);
+    assert_eq!(client.get("plain").await.unwrap().as_deref(), Some("fresh"));
+    assert_eq!(client.ttl("plain").await.unwrap(), -1);
+
+    assert_eq!(
+        client
+            .getset_with_ttl("lock", "owner-b", "1h")
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        client.get("lock").await.unwrap().as_deref(),
+        Some("owner-b")
+    );
+    assert!(client.ttl("lock").await.unwrap() > 3500);
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    client.set_with_ttl("wire", "stale", "1s").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    stream
+        .get_mut()
+        .write_all(b"GETSET wire fresh\n")
+        .await
+        .unwrap();
+    let mut reply = String::new();
+    stream.read_line(&mut reply).await.unwrap();
+    assert_eq!(reply, "*NIL*\n");
+}
+
+#[tokio::test]
+async fn proxy_protocol_header_names_the_client() {
+    let server = KvServer::start_with(&[("PROXY_PROTOCOL", "true")]).await;
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 7171\r\nPING\n")
+        .await
+        .unwrap();
+    let mut reply = String::new();
+    stream.read_line(&mut reply).await.unwrap();
+    assert_eq!(reply, "PONG\n");
+    drop(stream);
+    let closed = server.slog.wait_for_code("KVCONNCLOSE").await;
+    assert!(closed.contains("203.0.113.7:51234 "), "{}", closed);
+
+    // Without the header the connection is dropped unanswered
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(b"PING\nPING\n").await.unwrap();
+    // Closing with input unread may reset the connection rather than end it
+    let mut rest = Vec::new();
+    let _ = stream.read_to_end(&mut rest).await;
+    assert!(rest.is_empty(), "{:?}", rest);
+    let dropped = server.slog.wait_for_code("KVPROXYERR").await;
+    assert!(dropped.contains("missing PROXY header"), "{}", dropped);
+}
+
+#[tokio::test]
+async fn rename_moves_the_value_and_expiration() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    client.set_with_ttl("old", "v1", "1h").await.unwrap();
+    client.set("taken", "v2").await.unwrap();
+    client.rename("old", "taken").await.unwrap();
+    assert_eq!(client.get("old").await.unwrap(), None);
+    assert_eq!(client.get("taken").await.unwrap(), Some("v1".to_string()));
+    assert!(client.ttl("taken").await.unwrap() > 3500);
+    client.rename("taken", "taken").await.unwrap();
+    assert_eq!(client.get("taken").await.unwrap(), Some("v1".to_string()));
+    let err = client.rename("old", "new").await.unwrap_err();
+    assert_eq!(err.to_string(), "ERROR key not found");
+
+    // RENAMENX leaves an existing destination alone
+    client.set("other", "v3").await.unwrap();
+    assert!(!client.renamenx("taken", "other").await.unwrap());
+    assert!(!client.renamenx("taken", "taken").await.unwrap());
+    assert_eq!(client.get("other").await.unwrap(), Some("v3".to_string()));
+    assert!(client.renamenx("taken", "fresh").await.unwrap());
+    assert_eq!(client.get("fresh").await.unwrap(), Some("v1".to_string()));
+    assert_eq!(client.get("taken").await.unwrap(), None);
+
+    // An expired source is missing and an expired destination is free
+    client.set_with_ttl("brief", "a", "1s").await.unwrap();
+    client.set_with_ttl("lapsed", "b", "1s").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let err = client.rename("brief", "x").await.unwrap_err();
+    assert_eq!(err.to_string(), "ERROR key not found");
+    assert!(client.renamenx("fresh", "lapsed").await.unwrap());
+
+    let long_key = "k".repeat(MAX_KEY_LEN + 1);
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    for (line, reply) in [
+        ("RENAME lapsed moved\n".to_string(), "OK\n"),
+        ("RENAMENX moved other\n".to_string(), "0\n"),
+        ("RENAME gone x\n".to_string(), "ERROR key not found\n"),
+        (
+            format!("RENAME moved {}\n", long_key),
+            "ERROR invalid length\n",
+        ),
+        ("RENAME moved\n".to_string(), "ERROR invalid arguments\n"),
+    ] {
+        stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply, "{}", line);
+    }
+    assert_eq!(client.get("moved").await.unwrap(), Some("v1".to_string()));
+}
+
+// A match larger than KEYS_REPLY_LIMIT returns the first names in sorted order
+#[tokio::test]
+async fn keys_reply_is_capped() {
+    let server = KvServer::start_with(&[("KEYS_REPLY_LIMIT", "3")]).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client
+        .mset(&[
+            ("k5", "1"),
+            ("k2", "1"),
+            ("k4", "1"),
+            ("k1", "1"),
+            ("k3", "1"),
+        ])
+        .await
+        .unwrap();
+
+    assert_eq!(client.keys("k*").await.unwrap(), vec!["k1", "k2", "k3"]);
+    assert_eq!(client.keys("k[45]").await.unwrap(), vec!["k4", "k5"]);
+    assert_eq!(client.count_matching("k*").await.unwrap(), 5);
+}
+
+#[tokio::test]
+async fn keys_lists_live_names_matching_a_glob() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client
+        .mset(&[
+            ("loginlock-ip-1", "1"),
+            ("loginlock-ip-22", "1"),
+            ("loginlock-user-abc", "1"),
+            ("abc", "1"),
+            ("axc", "1"),
+            ("ac", "1"),
+            ("a*c", "1"),
+        ])
+        .await
+        .unwrap();
+
+    for (pattern, expected) in [
+        (
+            "*",
+            vec![
+                "a*c",
+                "abc",
+                "ac",
+                "axc",
+                "loginlock-ip-1",
+                "loginlock-ip-22",
+                "loginlock-user-abc",
+            ],
+        ),
+        ("a?c", vec!["a*c", "abc", "axc"]),
+        ("loginlock-ip-*", vec!["loginlock-ip-1", "loginlock-ip-22"]),
+        ("*abc", vec!["abc", "loginlock-user-abc"]),
+        ("*ip*", vec!["loginlock-ip-1", "loginlock-ip-22"]),
+        ("a*c", vec!["a*c", "abc", "ac", "axc"]),
+        ("loginlock-ip-?", vec!["loginlock-ip-1"]),
+        ("abc", vec!["abc"]),
+        ("ab", vec![]),
+        ("**b**", vec!["abc", "loginlock-user-abc"]),
+    ] {
+        assert_eq!(client.keys(pattern).await.unwrap(), expected, "{}", pattern);
+    }
+
+    // Expired keys are left out; SELECT matches names without the prefix
+    client.set_with_ttl("brief", "a", "1s").await.unwrap();
+    client.select(Some("billing")).await.unwrap();
+    client.set("abc", "2").await.unwrap();
+    assert_eq!(client.keys("a*").await.unwrap(), vec!["abc"]);
+    client.select(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.keys("b*").await.unwrap(), vec!["billing:abc"]);
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"KEYS loginlock-user-*\nKEYS nothing*\nKEYS\n")
+        .await
+        .unwrap();
+    for reply in [
+        "loginlock-user-abc\n",
+        "\n",
+        "\n",
+        "ERROR invalid arguments\n",
+    ] {
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply);
+    }
+}
+
+#[tokio::test]
+async fn list_by_expiry_sorts_soonest_first() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client
+        .mset(&[("never", "a"), ("forever", "b")])
+        .await
+        .unwrap();
+    client.set_with_ttl("later", "c", "2h").await.unwrap();
+    client.set_with_ttl("soon", "d", "30s").await.unwrap();
+    client.set_with_ttl("middle", "e", "1h").await.unwrap();
+    client.set_with_ttl("gone", "f", "1s").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let names = |entries: Vec<kv_protocol::ListEntry>| -> Vec<String> {
+        entries.into_iter().map(|entry| entry.key).collect()
+    };
+    // Keys without an expiration come last, by name
+    assert_eq!(
+        names(client.list_by_expiry(None).await.unwrap()),
+        ["soon", "middle", "later", "forever", "never"]
+    );
+    let limited = client.list_by_expiry(Some(2)).await.unwrap();
+    assert_eq!(names(limited.clone()), ["soon", "middle"]);
+    assert_eq!(limited[0].value, "d");
+    assert!(limited[0].expires_at.is_some());
+    assert_eq!(client.list_by_expiry(Some(100)).await.unwrap().len(), 5);
+
+    // The keywords are case-insensitive; the body ends with an empty line as LIST's does
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"LIST byexpiry limit 1\nLIST BYEXPIRY LIMIT 0\n")
+        .await
+        .unwrap();
+    let mut lines = Vec::new();
+    for _ in 0..3 {
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        lines.push(got);
+    }
+    assert!(lines[0].starts_with("soon=d,"), "{:?}", lines);
+    assert_eq!(lines[1..], ["\n", "ERROR invalid arguments\n"]);
+
+    // Under SELECT only the namespace's keys are sorted
+    client.select(Some("app")).await.unwrap();
+    client.set_with_ttl("token", "g", "10s").await.unwrap();
+    assert_eq!(names(client.list_by_expiry(None).await.unwrap()), ["token"]);
+}
+
+#[tokio::test]
+async fn list_with_a_pattern_filters_live_keys() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client
+        .mset(&[
+            ("user:1", "a"),
+            ("user:2", "b"),
+            ("user:3", "c"),
+            ("user:x", "d"),
+            ("order:1", "e"),
+            ("[draft]", "f"),
+        ])
+        .await
+        .unwrap();
+    client.set_with_ttl("user:4", "brief", "1s").await.unwrap();
+
+    // LIST follows the store's order, so names are compared sorted
+    let names = |items: Vec<(String, String, String, Option<u64>)>| -> Vec<String> {
+        let mut names: Vec<String> = items.into_iter().map(|(key, _, _, _)| key).collect();
+        names.sort();
+        names
+    };
+    for (pattern, expected) in [
+        (
+            "user:*",
+            vec!["user:1", "user:2", "user:3", "user:4", "user:x"],
+        ),
+        ("user:[12]", vec!["user:1", "user:2"]),
+        ("user:[2-4]", vec!["user:2", "user:3", "user:4"]),
+        ("*:[a-z]", vec!["user:x"]),
+        ("?????:1", vec!["order:1"]),
+        ("[draft", vec![]),
+        ("[[]draft]", vec!["[draft]"]),
+        ("nothing*", vec![]),
+    ] {
+        let listed = client.list_matching(pattern).await.unwrap();
+        assert_eq!(names(listed), expected, "{}", pattern);
+    }
+    // KEYS takes the same sets and ranges
+    assert_eq!(
+        client.keys("user:[13x]").await.unwrap(),
+        vec!["user:1", "user:3", "user:x"]
+    );
+    let listed = client.list_matching("order:*").await.unwrap();
+    assert_eq!(
+        listed,
+        vec![(
+            "order:1".to_string(),
+            "e".to_string(),
+            "str".to_string(),
+            None
+        )]
+    );
+
+    // Expired keys are left out, with a pattern or without
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(
+        names(client.list_matching("user:[2-4]").await.unwrap()),
+        vec!["user:2", "user:3"]
+    );
+    assert_eq!(client.list().await.unwrap().len(), 6);
+
+    // Under SELECT the pattern is matched without the prefix
+    client.select(Some("billing")).await.unwrap();
+    client.set("user:9", "g").await.unwrap();
+    assert_eq!(
+        names(client.list_matching("user:*").await.unwrap()),
+        vec!["user:9"]
+    );
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"LIST order:*\nLIST a b\n")
+        .await
+        .unwrap();
+    for reply in [
+        "order:1=e,str,no-expiration\n",
+        "\n",
+        "ERROR invalid arguments\n",
+    ] {
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply);
+    }
+}
+
+#[tokio::test]
+async fn count_reports_live_keys_without_listing_them() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    assert_eq!(client.count().await.unwrap(), 0);
+    client
+        .mset(&[("user:1", "a"), ("user:2", "b"), ("order:1", "c")])
+        .await
+        .unwrap();
+    client.set_with_ttl("user:3", "brief", "1s").await.unwrap();
+
+    assert_eq!(client.count().await.unwrap(), 4);
+    assert_eq!(client.count_matching("user:*").await.unwrap(), 3);
+    assert_eq!(client.count_matching("user:[12]").await.unwrap(), 2);
+    assert_eq!(client.count_matching("nothing*").await.unwrap(), 0);
+
+    // Expired keys aren't counted though STATUS still includes them until swept
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.count().await.unwrap(), 3);
+    assert_eq!(client.count_matching("user:*").await.unwrap(), 2);
+
+    // Under SELECT only the namespace's keys count, matched without the prefix
+    client.select(Some("billing")).await.unwrap();
+    assert_eq!(client.count().await.unwrap(), 0);
+    client.set("user:9", "d").await.unwrap();
+    assert_eq!(client.count_matching("user:*").await.unwrap(), 1);
+    client.select(None).await.unwrap();
+    assert_eq!(client.count().await.unwrap(), 4);
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"COUNT\nCOUNT order:*\nCOUNT a b\n")
+        .await
+        .unwrap();
+    for reply in ["4\n", "1\n", "ERROR invalid arguments\n"] {
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply);
+    }
+}
+
+#[tokio::test]
+async fn scan_pages_through_every_key_once() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let names: Vec<String> = (0..10_000).map(|i| format!("key-{}", i)).collect();
+    for batch in names.chunks(32) {
+        let pairs: Vec<(&str, &str)> = batch.iter().map(|name| (name.as_str(), "v")).collect();
+        client.mset(&pairs).await.unwrap();
+    }
+
+    let mut seen = Vec::new();
+    let mut pages = 0;
+    let mut scan = client.scan(300);
+    while let Some(page) = scan.next_page().await.unwrap() {
+        assert!(page.len() <= 300);
+        seen.extend(page.into_iter().map(|entry| entry.key));
+        pages += 1;
+    }
+    assert_eq!(pages, 34);
+    // Pages come in name order, so every key shows up exactly once
+    let mut expected = names.clone();
+    expected.sort();
+    assert_eq!(seen, expected);
+
+    // Expired keys are skipped; SELECT scans the namespace without the prefix
+    client.set_with_ttl("brief", "a", "1s").await.unwrap();
+    client.select(Some("billing")).await.unwrap();
+    client.mset(&[("a", "1"), ("b", "2")]).await.unwrap();
+    let (next, page) = client.scan_page(kv_protocol::SCAN_START, 1).await.unwrap();
+    assert_eq!(page[0].key, "a");
+    let (next, page) = client.scan_page(&next, 1).await.unwrap();
+    assert_eq!(page[0].key, "b");
+    assert_eq!(next, kv_protocol::SCAN_START);
+    client.select(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let (_, page) = client
+        .scan_page(&kv_protocol::scan_cursor(Some("billing:b")), 10)
+        .await
+        .unwrap();
+    assert_eq!(page[0].key, "key-0");
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"SCAN 0 COUNT 1\nSCAN 6b65792d39393939\nSCAN 0 COUNT 0\n")
+        .await
+        .unwrap();
+    for reply in [
+        "62696c6c696e673a61\n",
+        "billing:a=1,int,no-expiration\n",
+        "\n",
+        "0\n",
+        "\n",
+        "ERROR invalid arguments\n",
+    ] {
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply);
+    }
+}
+
+#[tokio::test]
+async fn tags_group_keys_until_they_are_removed() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client
+        .mset(&[("cart", "3"), ("prefs", "dark"), ("other", "x")])
+        .await
+        .unwrap();
+
+    assert!(client.tag("cart", "session-42").await.unwrap());
+    assert!(client.tag("prefs", "session-42").await.unwrap());
+    assert!(client.tag("prefs", "user-7").await.unwrap());
+    assert!(!client.tag("missing", "session-42").await.unwrap());
+    assert_eq!(
+        client.tagged("session-42").await.unwrap(),
+        vec!["cart", "prefs"]
+    );
+    assert_eq!(client.tagged("user-7").await.unwrap(), vec!["prefs"]);
+    assert!(client.tagged("nobody").await.unwrap().is_empty());
+
+    // Overwriting keeps the tags; deleting drops them
+    client.set("cart", "4").await.unwrap();
+    assert_eq!(
+        client.tagged("session-42").await.unwrap(),
+        vec!["cart", "prefs"]
+    );
+    client.delete("prefs").await.unwrap();
+    client.set("prefs", "light").await.unwrap();
+    assert_eq!(client.tagged("session-42").await.unwrap(), vec!["cart"]);
+    assert!(client.tagged("user-7").await.unwrap().is_empty());
+
+    // RENAME carries the tags; expiry drops them
+    client.rename("cart", "basket").await.unwrap();
+    assert_eq!(client.tagged("session-42").await.unwrap(), vec!["basket"]);
+    client.set_with_ttl("brief", "a", "1s").await.unwrap();
+    assert!(client.tag("brief", "session-42").await.unwrap());
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.tagged("session-42").await.unwrap(), vec!["basket"]);
+    assert!(!client.tag("brief", "session-42").await.unwrap());
+
+    // Under SELECT only the namespace's keys are listed, without the prefix
+    client.select(Some("billing")).await.unwrap();
+    client.set("invoice", "1").await.unwrap();
+    assert!(client.tag("invoice", "session-42").await.unwrap());
+    assert_eq!(client.tagged("session-42").await.unwrap(), vec!["invoice"]);
+    client.select(None).await.unwrap();
+    assert_eq!(
+        client.tagged("session-42").await.unwrap(),
+        vec!["basket", "billing:invoice"]
+    );
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"TAG other group\nTAG gone group\nTAGGED group\nTAG other\n")
+        .await
+        .unwrap();
+    for reply in [
+        "OK\n",
+        "*KEY NOT FOUND*\n",
+        "other\n",
+        "\n",
+        "ERROR invalid arguments\n",
+    ] {
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply);
+    }
+}
+
+#[tokio::test]
+async fn expiretag_expires_only_the_tagged_keys() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client
+        .mset(&[("cart", "3"), ("prefs", "dark"), ("other", "x")])
+        .await
+        .unwrap();
+    client.set_with_ttl("lease", "1", "1h").await.unwrap();
+    for key in ["cart", "prefs", "lease"] {
+        assert!(client.tag(key, "session-42").await.unwrap());
+    }
+    assert!(client.tag("other", "user-7").await.unwrap());
+
+    assert_eq!(client.expire_tag("session-42", "5min").await.unwrap(), 3);
+    for key in ["cart", "prefs", "lease"] {
+        let ttl = client.ttl(key).await.unwrap();
+        assert!(ttl > 290 && ttl <= 300, "{} {}", key, ttl);
+    }
+    assert_eq!(client.ttl("other").await.unwrap(), -1);
+    assert_eq!(client.expire_tag("nobody", "5min").await.unwrap(), 0);
+
+    // Under SELECT only the namespace's tagged keys are touched
+    client.select(Some("billing")).await.unwrap();
+    client.set("invoice", "1").await.unwrap();
+    assert!(client.tag("invoice", "user-7").await.unwrap());
+    assert_eq!(client.expire_tag("user-7", "1s").await.unwrap(), 1);
+    client.select(None).await.unwrap();
+    assert_eq!(client.ttl("other").await.unwrap(), -1);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.tagged("user-7").await.unwrap(), vec!["other"]);
+    assert_eq!(client.expire_tag("user-7", "1h").await.unwrap(), 1);
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"EXPIRETAG session-42 10s\nEXPIRETAG session-42\nEXPIRETAG a soon\n")
+        .await
+        .unwrap();
+    for reply in [
+        "3\n",
+        "ERROR invalid arguments\n",
+        "ERROR invalid expiration\n",
+    ] {
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply);
+    }
+}
+
+#[tokio::test]
+async fn blank_lines_get_a_reply_like_any_other() {
+    let server = KvServer::start(false).await;
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    // Pipelined, so each reply has to line up with the line that caused it
+    stream
+        .get_mut()
+        .write_all(b"\n   \nSET a 1\n\t \r\n  GET a  \n\r\n")
+        .await
+        .unwrap();
+    for reply in [
+        "ERROR unknown command\n",
+        "ERROR unknown command\n",
+        "OK\n",
+        "ERROR unknown command\n",
+        "1\n",
+        "ERROR unknown command\n",
+    ] {
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply);
+    }
+}
+
+#[tokio::test]
+async fn incrbyfloat_adds_fractions() {
+    let path = temp_dir("aof-float").join("kv.aof");
+    let server = KvServer::start_with_settings(aof_settings(&path, "always")).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    assert_eq!(client.incr_float("rate", 0.5).await.unwrap(), 0.5);
+    assert_eq!(client.incr_float("rate", 1.25).await.unwrap(), 1.75);
+    assert_eq!(client.incr_float("rate", 0.25).await.unwrap(), 2.0);
+    // Whole results keep their fractional part and stay floats
+    assert_eq!(client.get("rate").await.unwrap().as_deref(), Some("2.0"));
+    assert_eq!(
+        client.key_type("rate").await.unwrap().as_deref(),
+        Some("float")
+    );
+    assert_eq!(client.get_float("rate").await.unwrap(), Some(2.0));
+
+    // Integers and numeric strings are promoted
+    client.set("hits", "10").await.unwrap();
+    assert_eq!(client.incr_float("hits", -0.1).await.unwrap(), 9.9);
+    client.set_raw("padded", "007", None).await.unwrap();
+    assert_eq!(client.incr_float("padded", 0.5).await.unwrap(), 7.5);
+    client.set("score", "4.2").await.unwrap();
+    client.set_float("whole", 3.0).await.unwrap();
+    assert_eq!(
+        client.key_type("whole").await.unwrap().as_deref(),
+        Some("float")
+    );
+    assert_eq!(client.get_float("missing").await.unwrap(), None);
+
+    client.set("name", "shrmpl").await.unwrap();
+    let err = client.incr("score").await.unwrap_err();
+    assert_eq!(err.to_string(), "ERROR wrong type");
+    let err = client.incrby("whole", 2).await.unwrap_err();
+    assert_eq!(err.to_string(), "ERROR wrong type");
+    for delta in [f64::NAN, f64::INFINITY] {
+        let err = client.incr_float("score", delta).await.unwrap_err();
+        assert_eq!(err.to_string(), "ERROR not a number");
+    }
+    let err = client.incr_float("name", 1.5).await.unwrap_err();
+    assert_eq!(err.to_string(), "ERROR not a number");
+    assert_eq!(client.incr_float("big", 1.7e308).await.unwrap(), 1.7e308);
+    let err = client.incr_float("big", 1.7e308).await.unwrap_err();
+    assert_eq!(err.to_string(), "ERROR overflow");
+    assert!(client.get_float("name").await.is_err());
+    assert!(client.set_float("name", f64::NAN).await.is_err());
+    server.shutdown().await;
+
+    let server = KvServer::start_with_settings(aof_settings(&path, "always")).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    for (key, value) in [("rate", 2.0), ("hits", 9.9), ("score", 4.2), ("whole", 3.0)] {
+        assert_eq!(client.get_float(key).await.unwrap(), Some(value));
+        assert_eq!(
+            client.key_type(key).await.unwrap().as_deref(),
+            Some("float"),
+            "{}",
+            key
+        );
+    }
+    let export = client.export().await.unwrap();
+    assert!(export.contains(r#""type":"float""#), "{}", export);
+}
+
+// A float reads back in the spelling it was set with, so GET, LIST and CAS agree with SET
+#[tokio::test]
+async fn floats_read_back_as_written() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    for text in ["1.0", "1e-7", "1e300", "-2.5"] {
+        client.set("vk", text).await.unwrap();
+        assert_eq!(
+            client.key_type("vk").await.unwrap().as_deref(),
+            Some("float")
+        );
+        assert_eq!(client.get("vk").await.unwrap().as_deref(), Some(text));
+        let listed = client.list().await.unwrap();
+        assert_eq!(listed[0].1, text);
+        // A version string used as the expected value still matches
+        assert!(client.cas("vk", text, "next").await.unwrap(), "{}", text);
+        assert!(!client.cas("vk", text, "again").await.unwrap(), "{}", text);
+    }
+
+    // Spellings {:?} doesn't produce stay strings, and so read back as written too
+    for text in ["0.0000001", "4.20", "1e5"] {
+        client.set("vk", text).await.unwrap();
+        assert_eq!(client.key_type("vk").await.unwrap().as_deref(), Some("str"));
+        assert_eq!(client.get("vk").await.unwrap().as_deref(), Some(text));
+        assert!(client.cas("vk", text, "next").await.unwrap(), "{}", text);
+    }
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    for (line, reply) in [
+        ("SET tiny 1e-7\n", "OK\n"),
+        ("INCRBYFLOAT tiny 1e-7\n", "2e-7\n"),
+        ("SET half 1.5\n", "OK\n"),
+        ("INCRBYFLOAT half 0.5\n", "2.0\n"),
+        ("GET half\n", "2.0\n"),
+    ] {
+        stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply, "{}", line);
+    }
+}
+
+#[tokio::test]
+async fn set_raw_keeps_the_exact_string() {
+    let path = temp_dir("aof-raw").join("kv.aof");
+    let server = KvServer::start_with_settings(aof_settings(&path, "always")).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    // By default anything that parses as i64 is stored as an integer
+    client.set("zip", "007").await.unwrap();
+    client.set("big", "12345678901234567890").await.unwrap();
+    client.set_raw("code", "007", None).await.unwrap();
+    client.set_raw("lease", "-0", Some("1h")).await.unwrap();
+    let expected = [
+        ("zip", "7", "int"),
+        ("big", "12345678901234567890", "str"),
+        ("code", "007", "str"),
+        ("lease", "-0", "str"),
+    ];
+    for (key, value, value_type) in expected {
+        assert_eq!(client.get(key).await.unwrap().as_deref(), Some(value));
+        assert_eq!(
+            client.key_type(key).await.unwrap().as_deref(),
+            Some(value_type)
+        );
+    }
+    assert!(client.ttl("lease").await.unwrap() > 3500);
+
+    // A raw string still counts as a number for INCR, which stores the result as one
+    let responses = client
+        .batch(&["SET code 007 RAW", "INCR code"])
+        .await
+        .unwrap();
+    assert_eq!(responses, vec!["OK", "8"]);
+    client.set_raw("code", "007", None).await.unwrap();
+    server.shutdown().await;
+
+    let server = KvServer::start_with_settings(aof_settings(&path, "always")).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    for (key, value, value_type) in expected {
+        assert_eq!(client.get(key).await.unwrap().as_deref(), Some(value));
+        assert_eq!(
+            client.key_type(key).await.unwrap().as_deref(),
+            Some(value_type)
+        );
+    }
+}
+
+#[tokio::test]
+async fn type_reports_how_values_are_stored() {
+    let server = KvServer::start_with(&[("COMPRESS_THRESHOLD_BYTES", "64")]).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    client.set("count", "42").await.unwrap();
+    client.set("decimal", "4.2").await.unwrap();
+    client.set("name", "shrmpl").await.unwrap();
+    client.set("long", &"x".repeat(90)).await.unwrap();
+    client.set_bytes("blob", &[0, 1, 2]).await.unwrap();
+    client.set_with_ttl("brief", "1", "1s").await.unwrap();
+    for (key, expected) in [
+        ("count", Some("int")),
+        ("decimal", Some("float")),
+        ("name", Some("str")),
+        ("long", Some("str")),
+        ("blob", Some("bytes")),
+        ("brief", Some("int")),
+        ("missing", None),
+    ] {
+        assert_eq!(
+            client.key_type(key).await.unwrap().as_deref(),
+            expected,
+            "{}",
+            key
+        );
+    }
+    let mut listed: Vec<(String, String)> = client
+        .list()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(key, _, value_type, _)| (key, value_type))
+        .collect();
+    listed.sort();
+    assert_eq!(
+        listed,
+        [
+            ("blob", "bytes"),
+            ("brief", "int"),
+            ("count", "int"),
+            ("decimal", "float"),
+            ("long", "str"),
+            ("name", "str"),
+        ]
+        .map(|(k, t)| (k.to_string(), t.to_string()))
+    );
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.key_type("brief").await.unwrap(), None);
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    for (line, reply) in [
+        ("TYPE count\n", "int\n"),
+        ("TYPE missing\n", "*KEY NOT FOUND*\n"),
+        ("TYPE\n", "ERROR invalid arguments\n"),
+    ] {
+        stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply, "{}", line);
+    }
+}
+
+#[tokio::test]
+async fn exists_reports_live_keys_only() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    assert!(!client.exists("user").await.unwrap());
+    client.set("user", "alice").await.unwrap();
+    assert!(client.exists("user").await.unwrap());
+    client.delete("user").await.unwrap();
+    assert!(!client.exists("user").await.unwrap());
+
+    // An expired key is gone even before the sweep runs
+    client.set_with_ttl("brief", "a", "1s").await.unwrap();
+    assert!(client.exists("brief").await.unwrap());
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(!client.exists("brief").await.unwrap());
+    assert_eq!(client.ttl("brief").await.unwrap(), -2);
+
+    let long_key = "k".repeat(101);
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    for (line, reply) in [
+        ("SET here 1\n".to_string(), "OK\n"),
+        ("EXISTS here\n".to_string(), "1\n"),
+        ("EXISTS gone\n".to_string(), "0\n"),
+        (format!("EXISTS {}\n", long_key), "ERROR invalid length\n"),
+        ("EXISTS\n".to_string(), "ERROR invalid arguments\n"),
+    ] {
+        stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply, "{}", line);
+    }
+}
+
+#[tokio::test]
+async fn setnx_only_sets_an_absent_key() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    assert!(client.setnx("lock", "worker-1").await.unwrap());
+    assert!(!client.setnx("lock", "worker-2").await.unwrap());
+    assert_eq!(
+        client.get("lock").await.unwrap(),
+        Some("worker-1".to_string())
+    );
+
+    // An expired key counts as absent
+    assert!(client.setnx_with_ttl("lease", "a", "1s").await.unwrap());
+    assert!(!client.setnx_with_ttl("lease", "b", "1s").await.unwrap());
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(client.setnx("lease", "c").await.unwrap());
+    assert_eq!(client.ttl("lease").await.unwrap(), -1);
+
+    // Of many clients racing for one key, exactly one wins
+    let mut racers = Vec::new();
+    for i in 0..8 {
+        let addr = server.addr();
+        racers.push(tokio::spawn(async move {
+            let mut client = KvClient::connect(&addr).await.unwrap();
+            client.setnx("race", &format!("racer-{}", i)).await.unwrap()
+        }));
+    }
+    let mut winners = 0;
+    for racer in racers {
+        if racer.await.unwrap() {
+            winners += 1;
+        }
+    }
+    assert_eq!(winners, 1);
+
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(b"SETNX lock worker-3\n").await.unwrap();
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).await.unwrap();
+    assert_eq!(line, "0\n");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn getdel_hands_a_token_to_exactly_one_client() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set("reset", "code-123").await.unwrap();
+    assert_eq!(
+        client.getdel("reset").await.unwrap().as_deref(),
+        Some("code-123")
+    );
+    assert_eq!(client.get("reset").await.unwrap(), None);
+    assert_eq!(client.getdel("reset").await.unwrap(), None);
+    client.set_with_ttl("brief", "x", "1s").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.getdel("brief").await.unwrap(), None);
+
+    // Two clients asking at the same moment: one gets the value, the other nothing
+    for round in 0..50 {
+        client
+            .set(&format!("token{}", round), &format!("code-{}", round))
+            .await
+            .unwrap();
+    }
+    let barrier = std::sync::Arc::new(tokio::sync::Barrier::new(2));
+    let mut racers = Vec::new();
+    for _ in 0..2 {
+        let mut racer = KvClient::connect(&server.addr()).await.unwrap();
+        let barrier = barrier.clone();
+        racers.push(tokio::spawn(async move {
+            let mut got = Vec::new();
+            for round in 0..50 {
+                barrier.wait().await;
+                let token = racer.getdel(&format!("token{}", round)).await.unwrap();
+                got.extend(token);
+            }
+            got
+        }));
+    }
+    let mut got: Vec<String> = Vec::new();
+    for racer in racers {
+        got.extend(racer.await.unwrap());
+    }
+    got.sort();
+    let mut expected: Vec<String> = (0..50).map(|round| format!("code-{}", round)).collect();
+    expected.sort();
+    assert_eq!(got, expected);
+
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(b"GETDEL token0\n").await.unwrap();
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).await.unwrap();
+    assert_eq!(line, "*KEY NOT FOUND*\n");
+}
+
+#[tokio::test]
+async fn cas_swaps_only_a_matching_value() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    client.set("version", "7").await.unwrap();
+    assert!(client.cas("version", "7", "8").await.unwrap());
+    assert!(!client.cas("version", "7", "9").await.unwrap());
+    assert_eq!(client.get("version").await.unwrap(), Some("8".to_string()));
+    assert!(!client.cas("missing", "7", "8").await.unwrap());
+    assert_eq!(client.get("missing").await.unwrap(), None);
+
+    // Without a ttl the key keeps its expiration; an expired key never matches
+    client.set_with_ttl("lease", "a", "1h").await.unwrap();
+    assert!(client.cas("lease", "a", "b").await.unwrap());
+    assert!(client.ttl("lease").await.unwrap() > 3500);
+    assert!(client.cas_with_ttl("lease", "b", "c", "1s").await.unwrap());
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(!client.cas("lease", "c", "d").await.unwrap());
+    assert_eq!(client.get("lease").await.unwrap(), None);
+
+    // Of many clients swapping from the same value, exactly one wins
+    let mut racers = Vec::new();
+    for i in 0..8 {
+        let addr = server.addr();
+        racers.push(tokio::spawn(async move {
+            let mut client = KvClient::connect(&addr).await.unwrap();
+            client
+                .cas("version", "8", &format!("racer-{}", i))
+                .await
+                .unwrap()
+        }));
+    }
+    let mut winners = 0;
+    for racer in racers {
+        if racer.await.unwrap() {
+            winners += 1;
+        }
+    }
+    assert_eq!(winners, 1);
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"SET n 5\nCAS n 5 6 10s\nCAS n 5 7\nCAS n 6\n")
+        .await
+        .unwrap();
+    for reply in ["OK\n", "1\n", "0\n", "ERROR invalid arguments\n"] {
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply);
+    }
+}
+
+#[tokio::test]
+async fn mset_writes_every_pair_or_none() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client
+        .mset(&[("a", "1"), ("b", "two"), ("a", "3")])
+        .await
+        .unwrap();
+    // Later pairs win, and integers are stored as SET stores them
+    assert_eq!(client.incr("a").await.unwrap(), 4);
+    assert_eq!(client.get("b").await.unwrap(), Some("two".to_string()));
+
+    let long = "v".repeat(kv_protocol::MAX_VALUE_LEN + 1);
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    for (line, reply) in [
+        (format!("MSET c 1 d {}\n", long), "ERROR invalid length\n"),
+        ("MSET c 1 d\n".to_string(), "ERROR invalid arguments\n"),
+    ] {
+        stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply);
+    }
+    assert_eq!(client.get("c").await.unwrap(), None);
+
+    // A namespace that pushes one key over the limit fails the whole command
+    client.select(Some("billing")).await.unwrap();
+    let near_limit = "k".repeat(MAX_KEY_LEN - 4);
+    let err = client
+        .mset(&[("c", "1"), (&near_limit, "2")])
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "ERROR invalid length");
+    client.mset(&[("c", "1")]).await.unwrap();
+    client.select(None).await.unwrap();
+    assert_eq!(client.get("c").await.unwrap(), None);
+    assert_eq!(
+        client.get("billing:c").await.unwrap(),
+        Some("1".to_string())
+    );
+}
+
+#[tokio::test]
+async fn mset_ttl_applies_to_every_pair() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client
+        .mset_with_ttl(&[("a", "1"), ("b", "2")], "1h")
+        .await
+        .unwrap();
+    for key in ["a", "b"] {
+        let ttl = client.ttl(key).await.unwrap();
+        assert!((3500..=3600).contains(&ttl), "{}: {}", key, ttl);
+    }
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    for (line, reply) in [
+        ("MSET c 1 d 2 TTL=1s\n", "OK\n"),
+        ("MSET e 1 TTL=never\n", "ERROR invalid expiration\n"),
+        ("MSET e 1 f\n", "ERROR invalid arguments\n"),
+    ] {
+        stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply, "{}", line);
+    }
+    assert_eq!(client.get("e").await.unwrap(), None);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.get("c").await.unwrap(), None);
+    assert_eq!(client.get("d").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn mget_returns_one_slot_per_key() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set("a", "1").await.unwrap();
+    client.set("b", "2").await.unwrap();
+    client.set_with_ttl("gone", "3", "1s").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let long = "k".repeat(MAX_KEY_LEN + 1);
+    let values = client
+        .mget(&["b", "missing", "gone", &long, "a"])
+        .await
+        .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            Some("2".to_string()),
+            None,
+            None,
+            None,
+            Some("1".to_string())
+        ]
+    );
+
+    // On the wire the oversized key fails only its own line
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    let line = format!("MGET {} a missing\n", long);
+    stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+    let mut reply = Vec::new();
+    for _ in 0..4 {
+        let mut line = String::new();
+        stream.read_line(&mut line).await.unwrap();
+        reply.push(line);
+    }
+    assert_eq!(
+        reply,
+        vec!["ERROR invalid length\n", "1\n", "*NIL*\n", "END\n"]
+    );
+
+    let too_many: Vec<String> = (0..=kv_protocol::MAX_MGET_KEYS)
+        .map(|i| format!("k{}", i))
+        .collect();
+    let too_many: Vec<&str> = too_many.iter().map(String::as_str).collect();
+    let err = client.mget(&too_many).await.unwrap_err();
+    assert_eq!(err.to_string(), "invalid arguments");
+    stream
+        .get_mut()
+        .write_all(format!("MGET {}\n", too_many.join(" ")).as_bytes())
+        .await
+        .unwrap();
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "ERROR invalid arguments\n");
+
+    client.select(Some("billing")).await.unwrap();
+    client.set("a", "scoped").await.unwrap();
+    assert_eq!(
+        client.mget(&["a", "b"]).await.unwrap(),
+        vec![Some("scoped".to_string()), None]
+    );
+}
+
+#[tokio::test]
+async fn time_matches_the_local_clock() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let before = std::time::SystemTime::now();
+    let server_time = client.time().await.unwrap();
+    let after = std::time::SystemTime::now();
+    // Same host, so the server's clock reading falls between the two local ones
+    let slack = Duration::from_millis(50);
+    assert!(
+        server_time + slack >= before,
+        "{:?} < {:?}",
+        server_time,
+        before
+    );
+    assert!(
+        server_time <= after + slack,
+        "{:?} > {:?}",
+        server_time,
+        after
+    );
+}
+
+#[tokio::test]
+async fn expire_sets_a_ttl_on_an_existing_key() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set("session", "abc").await.unwrap();
+    client.set_with_ttl("lease", "xyz", "1h").await.unwrap();
+
+    assert!(client.expire("session", "1s").await.unwrap());
+    // Shortening an existing expiration works too
+    assert!(client.expire("lease", "1s").await.unwrap());
+    assert_eq!(client.status().await.unwrap().expiring, 2);
+    assert_eq!(
+        client.get("session").await.unwrap(),
+        Some("abc".to_string())
+    );
+    assert!(!client.expire("missing", "1s").await.unwrap());
+    assert_eq!(client.status().await.unwrap().keys, 2);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(!client.expire("session", "1h").await.unwrap());
+    assert_eq!(client.get("lease").await.unwrap(), None);
+    assert_eq!(client.status().await.unwrap().keys, 0);
+
+    let err = client.expire("session", "later").await.unwrap_err();
+    assert_eq!(err.to_string(), "invalid expiration");
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(b"EXPIRE session later\n").await.unwrap();
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).await.unwrap();
+    assert_eq!(line, "ERROR invalid expiration\n");
+}
+
+#[tokio::test]
+async fn ttl_reports_seconds_left() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set("forever", "x").await.unwrap();
+    client.set_with_ttl("lease", "y", "1h").await.unwrap();
+    client.set_with_ttl("brief", "z", "1s").await.unwrap();
+
+    assert_eq!(client.ttl("forever").await.unwrap(), -1);
+    let left = client.ttl("lease").await.unwrap();
+    assert!((3598..=3600).contains(&left), "{}", left);
+    assert_eq!(client.ttl("missing").await.unwrap(), -2);
+
+    // An expired key is removed on the spot, as GET would
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.status().await.unwrap().keys, 3);
+    assert_eq!(client.ttl("brief").await.unwrap(), -2);
+    assert_eq!(client.status().await.unwrap().keys, 2);
+
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(b"TTL forever\n").await.unwrap();
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).await.unwrap();
+    assert_eq!(line, "-1\n");
+}
+
+#[tokio::test]
+async fn pttl_reports_milliseconds_left() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set("forever", "x").await.unwrap();
+    client.set_with_ttl("brief", "z", "1s").await.unwrap();
+
+    assert_eq!(client.pttl("forever").await.unwrap(), -1);
+    assert_eq!(client.pttl("missing").await.unwrap(), -2);
+    let left = client.pttl("brief").await.unwrap();
+    assert!((1..=1000).contains(&left), "{}", left);
+    // Sub-second accuracy: TTL has already rounded down to 0 while PTTL still counts
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(client.ttl("brief").await.unwrap(), 0);
+    let later = client.pttl("brief").await.unwrap();
+    assert!((1..=500).contains(&later), "{}", later);
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(client.pttl("brief").await.unwrap(), -2);
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"PTTL forever\nPTTL\n")
+        .await
+        .unwrap();
+    for reply in ["-1\n", "ERROR invalid arguments\n"] {
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply);
+    }
+}
+
+#[tokio::test]
+async fn persist_removes_an_expiration() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set_with_ttl("session", "abc", "1s").await.unwrap();
+    client.set("forever", "x").await.unwrap();
+    client.set_with_ttl("brief", "y", "1s").await.unwrap();
+
+    assert!(client.persist("session").await.unwrap());
+    assert_eq!(client.ttl("session").await.unwrap(), -1);
+    // Already without an expiration is still OK
+    assert!(client.persist("forever").await.unwrap());
+    assert!(!client.persist("missing").await.unwrap());
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(
+        client.get("session").await.unwrap(),
+        Some("abc".to_string())
+    );
+    assert!(!client.persist("brief").await.unwrap());
+    assert_eq!(client.status().await.unwrap().keys, 2);
+    assert_eq!(client.status().await.unwrap().expiring, 0);
+
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(b"PERSIST missing\n").await.unwrap();
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).await.unwrap();
+    assert_eq!(line, "*KEY NOT FOUND*\n");
+}
+
+#[tokio::test]
+async fn decr_mirrors_incr() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let kv_error = |err: Box<dyn std::error::Error>| *err.downcast::<KvError>().unwrap();
+
+    assert_eq!(client.decr("stock").await.unwrap(), -1);
+    client.set("stock", "10").await.unwrap();
+    assert_eq!(client.decr("stock").await.unwrap(), 9);
+    assert_eq!(client.incr("stock").await.unwrap(), 10);
+
+    // A ttl only applies to a key DECR creates
+    assert_eq!(client.decr_with_ttl("lease", "1s").await.unwrap(), -1);
+    client.set("kept", "5").await.unwrap();
+    assert_eq!(client.decr_with_ttl("kept", "1s").await.unwrap(), 4);
+    assert_eq!(client.status().await.unwrap().expiring, 1);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.get("lease").await.unwrap(), None);
+    assert_eq!(client.decr("kept").await.unwrap(), 3);
+
+    client.set("min", &i64::MIN.to_string()).await.unwrap();
+    let err = client.decr("min").await.unwrap_err();
+    assert_eq!(kv_error(err), KvError::Overflow);
+    assert_eq!(client.get("min").await.unwrap(), Some(i64::MIN.to_string()));
+
+    client.set("word", "hello").await.unwrap();
+    let err = client.decr("word").await.unwrap_err();
+    assert_eq!(kv_error(err), KvError::NotAnInteger);
+    assert_eq!(client.get("word").await.unwrap(), Some("hello".to_string()));
+}
+
+#[tokio::test]
+async fn incrby_and_decrby_step_by_an_amount() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let kv_error = |err: Box<dyn std::error::Error>| *err.downcast::<KvError>().unwrap();
+
+    assert_eq!(client.incrby("quota", 100).await.unwrap(), 100);
+    assert_eq!(client.decrby("quota", 30).await.unwrap(), 70);
+    assert_eq!(client.decrby("quota", -5).await.unwrap(), 75);
+    assert_eq!(client.decrby("fresh", 3).await.unwrap(), -3);
+
+    // The expiration set when the key was created is kept
+    client.set_with_ttl("lease", "10", "1h").await.unwrap();
+    assert_eq!(client.incrby("lease", 5).await.unwrap(), 15);
+    assert!(client.ttl("lease").await.unwrap() > 3500);
+    assert_eq!(client.incrby_with_ttl("lease", 5, "1s").await.unwrap(), 20);
+    assert!(client.ttl("lease").await.unwrap() > 3500);
+    // A TTL only applies to a key the call creates
+    assert_eq!(client.decrby_with_ttl("bucket", 4, "1h").await.unwrap(), -4);
+    assert!(client.ttl("bucket").await.unwrap() > 3500);
+    assert_eq!(client.incrby_with_ttl("quota", 1, "1h").await.unwrap(), 76);
+    assert_eq!(client.ttl("quota").await.unwrap(), -1);
+    assert_eq!(client.decrby("quota", 1).await.unwrap(), 75);
+
+    client.set("low", "-1").await.unwrap();
+    assert_eq!(client.decrby("low", i64::MIN).await.unwrap(), i64::MAX);
+    let err = client.decrby("low", -1).await.unwrap_err();
+    assert_eq!(kv_error(err), KvError::Overflow);
+    client.set("word", "hello").await.unwrap();
+    let err = client.incrby("word", 1).await.unwrap_err();
+    assert_eq!(kv_error(err), KvError::NotAnInteger);
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    for line in [
+        "INCRBY quota 1.5\n",
+        "DECRBY quota\n",
+        "INCRBY quota ten\n",
+        "INCRBY quota 0\n",
+        "DECRBY quota 1 1h more\n",
+    ] {
+        stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+        let mut reply = String::new();
+        stream.read_line(&mut reply).await.unwrap();
+        assert_eq!(reply, "ERROR invalid arguments\n", "{}", line);
+    }
+    assert_eq!(client.get("quota").await.unwrap(), Some("75".to_string()));
+    assert!(client.incrby("quota", 0).await.is_err());
+    assert!(client.decrby("quota", 0).await.is_err());
+    assert!(client.incrby("untouched", 0).await.is_err());
+    assert_eq!(client.get("untouched").await.unwrap(), None);
+
+    // The TTL argument on the wire gives the key it creates that expiration
+    for (line, reply, key, ttl_ms) in [
+        ("INCRBY window 2 1h\n", "2\n", "window", 3_600_000),
+        ("DECRBY credit 3 90s\n", "-3\n", "credit", 90_000),
+    ] {
+        stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply, "{}", line);
+        let pttl = client.pttl(key).await.unwrap();
+        assert!(pttl > ttl_ms - 5_000 && pttl <= ttl_ms, "{}: {}", key, pttl);
+    }
+}
+
+#[tokio::test]
+async fn seed_file_populates_store_before_first_client() {
+    let seed = temp_dir("seed").join("seed.txt");
+    std::fs::write(
+        &seed,
+        "# warm the login cache\nSET motd hello\nSET hits 41\n\nSET session abc 1h\nSET broken\nDEL motd\n",
+    )
+    .unwrap();
+    let server = KvServer::start_with(&[("SEED_FILE", seed.to_str().unwrap())]).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    assert_eq!(client.get("motd").await.unwrap(), Some("hello".to_string()));
+    assert_eq!(client.incr("hits").await.unwrap(), 42);
+    let status = client.status().await.unwrap();
+    assert_eq!((status.keys, status.expiring), (3, 1));
+
+    let loaded = server.slog.wait_for_code("KVSEEDLOAD").await;
+    assert!(loaded.contains("Loaded 3 keys"), "{}", loaded);
+    assert!(loaded.contains("2 lines skipped"), "{}", loaded);
+    server.slog.wait_for_code("KVSEEDSKIP").await;
+}
+
+#[tokio::test]
+async fn unreachable_slog_does_not_delay_startup() {
+    let slog = HungSlog::start();
+    // A connect to it must really hang, or the test proves nothing
+    let probe =
+        tokio::time::timeout(Duration::from_millis(300), TcpStream::connect(slog.addr)).await;
+    assert!(probe.is_err(), "SLOG connect did not hang");
+
+    let seed = temp_dir("seed").join("seed.txt");
+    std::fs::write(&seed, "SET motd hello\nSET broken\n").unwrap();
+    let mut config = std::collections::HashMap::new();
+    config.insert("BIND_ADDR".to_string(), "127.0.0.1:0".to_string());
+    config.insert("SEED_FILE".to_string(), seed.display().to_string());
+    let settings = kv_server::Settings::from_config(&config);
+    let (shutdown_tx, shutdown_rx) = tokio::sync::broadcast::channel(1);
+
+    // Only startup is checked: per-command records still wait on SLOG
+    let started = std::time::Instant::now();
+    let (addr, handle) = kv_server::run(settings, slog.logger(), shutdown_rx)
+        .await
+        .unwrap();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    assert!(
+        started.elapsed() < Duration::from_secs(1),
+        "accepting took {:?}",
+        started.elapsed()
+    );
+
+    drop(stream);
+    // Not awaited: the shutdown record waits out the SLOG connect timeout
+    shutdown_tx.send(()).unwrap();
+    drop(handle);
+}
+
+#[tokio::test]
+async fn dump_and_load_move_a_store_between_servers() {
+    let path = temp_dir("dump").join("store.json");
+    let path = path.to_str().unwrap();
+    let source = KvServer::start(true).await;
+    let mut client = KvClient::connect(&source.addr()).await.unwrap();
+    client.set("motd", "hello").await.unwrap();
+    client.incr("hits").await.unwrap();
+    client.set_with_ttl("session", "abc", "1h").await.unwrap();
+    client.set_with_ttl("brief", "gone", "1s").await.unwrap();
+    assert_eq!(client.dump(path).await.unwrap(), 4);
+    let mut expected = client.list().await.unwrap();
+    expected.retain(|(key, _, _, _)| key != "brief");
+    expected.sort();
+
+    let target = KvServer::start(true).await;
+    let mut client = KvClient::connect(&target.addr()).await.unwrap();
+    client.set("local", "kept").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.load(path, LoadMode::Merge).await.unwrap(), (3, 1));
+    // Expirations are absolute, so the loaded TTLs end where the source's do
+    let mut loaded = client.list().await.unwrap();
+    loaded.retain(|(key, _, _, _)| key != "local");
+    loaded.sort();
+    assert_eq!(loaded, expected);
+    assert_eq!(client.get("local").await.unwrap(), Some("kept".to_string()));
+    assert_eq!(client.incr("hits").await.unwrap(), 2);
+
+    client.set("local", "dropped").await.unwrap();
+    assert_eq!(client.load(path, LoadMode::Replace).await.unwrap(), (3, 1));
+    assert_eq!(client.get("local").await.unwrap(), None);
+    assert_eq!(client.status().await.unwrap().keys, 3);
+
+    let err = client
+        .load("/nonexistent/store.json", LoadMode::Merge)
+        .await;
+    assert!(err.unwrap_err().to_string().contains("load failed"));
+    let loaded = target.slog.wait_for_code("KVLOAD").await;
+    assert!(loaded.starts_with("ACTV"), "{}", loaded);
+    assert!(loaded.contains("OK loaded=3 skipped=1"), "{}", loaded);
+    source.slog.wait_for_code("KVDUMP").await;
+
+    let gated = KvServer::start(false).await;
+    let mut client = KvClient::connect(&gated.addr()).await.unwrap();
+    let err = client.dump(path).await.unwrap_err().to_string();
+    assert!(err.contains("admin commands disabled"), "{}", err);
+}
+
+#[tokio::test]
+async fn flushall_empties_the_store_only_when_allowed() {
+    let server = KvServer::start_with(&[("ALLOW_FLUSH", "true")]).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.mset(&[("a", "1"), ("b", "2")]).await.unwrap();
+    client.set_with_ttl("c", "3", "1h").await.unwrap();
+    assert!(client.tag("a", "group").await.unwrap());
+    // Even under SELECT the whole store goes
+    client.select(Some("billing")).await.unwrap();
+    client.set("invoice", "4").await.unwrap();
+
+    client.flushall().await.unwrap();
+    assert_eq!(client.count().await.unwrap(), 0);
+    client.select(None).await.unwrap();
+    assert_eq!(client.count().await.unwrap(), 0);
+    assert!(client.tagged("group").await.unwrap().is_empty());
+    assert_eq!(client.status().await.unwrap().mem_bytes, 0);
+    let flushed = server.slog.wait_for_code("KVFLUSH").await;
+    assert!(flushed.starts_with("ACTV"), "{}", flushed);
+    assert!(flushed.contains("removed 4 keys"), "{}", flushed);
+
+    // The store keeps working afterwards
+    client.set("a", "5").await.unwrap();
+    assert_eq!(client.get("a").await.unwrap(), Some("5".to_string()));
+
+    // Admin commands don't turn it on
+    let gated = KvServer::start(true).await;
+    let mut client = KvClient::connect(&gated.addr()).await.unwrap();
+    client.set("a", "1").await.unwrap();
+    let err = client.flushall().await.unwrap_err().to_string();
+    assert!(err.contains("command disabled"), "{}", err);
+    assert_eq!(client.count().await.unwrap(), 1);
+    let mut stream = BufReader::new(TcpStream::connect(gated.addr).await.unwrap());
+    stream.get_mut().write_all(b"FLUSHALL\n").await.unwrap();
+    let mut reply = String::new();
+    stream.read_line(&mut reply).await.unwrap();
+    assert_eq!(reply, "ERROR command disabled\n");
+}
+
+#[tokio::test]
+async fn seed_file_accepts_export_document() {
+    let seed = temp_dir("seed").join("seed.json");
+    std::fs::write(
+        &seed,
+        r#"[{"key":"count","value":7,"type":"int","expires_at":null},{"key":"name","value":"shrmpl","type":"str","expires_at":null}]"#,
+    )
+    .unwrap();
+    let server = KvServer::start_with(&[("SEED_FILE", seed.to_str().unwrap())]).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    assert_eq!(client.incr("count").await.unwrap(), 8);
+    assert_eq!(
+        client.get("name").await.unwrap(),
+        Some("shrmpl".to_string())
+    );
+}
+
+// Settings for a server snapshotting to `path`, over an ephemeral BIND_ADDR
+fn snapshot_settings(path: &std::path::Path, interval_secs: &str) -> kv_server::Settings {
+    let config = [
+        ("BIND_ADDR", "127.0.0.1:0"),
+        ("SNAPSHOT_PATH", path.to_str().unwrap()),
+        ("SNAPSHOT_INTERVAL_SECS", interval_secs),
+    ]
+    .iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+    kv_server::Settings::from_config(&config)
+}
+
+#[tokio::test]
+async fn snapshot_survives_a_restart() {
+    let path = temp_dir("snapshot").join("kv.snapshot");
+    let server = KvServer::start_with_settings(snapshot_settings(&path, "0")).await;
+    // No snapshot yet is a first start, not an error
+    assert!(server
+        .slog
+        .wait_for_code("KVSNAPLOAD")
+        .await
+        .contains("starting without it"));
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client
+        .mset(&[("name", "shrmpl"), ("count", "7")])
+        .await
+        .unwrap();
+    client.set_with_ttl("lease", "a", "1h").await.unwrap();
+    client.set_bytes("blob", &[0, 1, 2]).await.unwrap();
+    let slog = server.shutdown().await;
+    assert!(slog
+        .wait_for_code("KVSNAPSAVE")
+        .await
+        .contains("Saved 4 keys"));
+
+    // Expirations are saved in unix milliseconds
+    let mut doc: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let lease = doc
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["key"] == "lease")
+        .unwrap();
+    let expires_at = lease["expires_at"].as_u64().unwrap();
+    assert!(
+        expires_at > now_ms + 3_500_000 && expires_at <= now_ms + 3_600_000,
+        "{} vs {}",
+        expires_at,
+        now_ms
+    );
+
+    // An entry that expired while the server was down is skipped on load
+    doc.as_array_mut().unwrap().push(serde_json::json!(
+        {"key": "stale", "value": "x", "type": "str", "expires_at": 1}
+    ));
+    std::fs::write(&path, doc.to_string()).unwrap();
+
+    let server = KvServer::start_with_settings(snapshot_settings(&path, "0")).await;
+    assert!(server
+        .slog
+        .wait_for_code("KVSNAPLOAD")
+        .await
+        .contains("Loaded 4 keys from snapshot"));
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    assert_eq!(
+        client.get("name").await.unwrap(),
+        Some("shrmpl".to_string())
+    );
+    assert_eq!(client.incr("count").await.unwrap(), 8);
+    assert!(client.ttl("lease").await.unwrap() > 3500);
+    assert_eq!(client.get_bytes("blob").await.unwrap(), Some(vec![0, 1, 2]));
+    assert_eq!(client.get("stale").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn snapshot_is_written_on_request_and_on_interval() {
+    let path = temp_dir("snapshot").join("kv.snapshot");
+    let settings = snapshot_settings(&path, "1");
+    let snapshot_now = settings.snapshot_now.clone();
+    let server = KvServer::start_with_settings(settings).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let saved_keys = || -> Vec<String> {
+        let doc: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let mut keys: Vec<String> = doc
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["key"].as_str().unwrap().to_string())
+            .collect();
+        keys.sort();
+        keys
+    };
+
+    // What the binary does on SIGUSR1
+    client.set("first", "1").await.unwrap();
+    snapshot_now.notify_one();
+    assert!(server
+        .slog
+        .wait_for_code("KVSNAPSAVE")
+        .await
+        .contains("(requested)"));
+    assert_eq!(saved_keys(), ["first"]);
+
+    client.set("second", "2").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+    assert_eq!(saved_keys(), ["first", "second"]);
+}
+
+// Entries saved under higher limits than today's still load
+#[tokio::test]
+async fn snapshot_loads_entries_beyond_the_current_limits() {
+    let path = temp_dir("snapshot").join("kv.snapshot");
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let doc = serde_json::json!([{
+        "key": "k".repeat(300),
+        "value": "v".repeat(2000),
+        "type": "str",
+        "expires_at": now_ms + 3_600_000,
+    }]);
+    std::fs::write(&path, doc.to_string()).unwrap();
+
+    let server = KvServer::start_with_settings(snapshot_settings(&path, "0")).await;
+    assert!(server
+        .slog
+        .wait_for_code("KVSNAPLOAD")
+        .await
+        .contains("Loaded 1 keys from snapshot"));
+    let slog = server.shutdown().await;
+    assert!(slog
+        .wait_for_code("KVSNAPSAVE")
+        .await
+        .contains("Saved 1 keys"));
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(saved, doc);
+}
+
+// Starting over an unreadable snapshot would overwrite it at the next save
+#[tokio::test]
+async fn corrupt_snapshot_stops_startup() {
+    let path = temp_dir("snapshot").join("kv.snapshot");
+    std::fs::write(&path, "not json").unwrap();
+    let slog = common::FakeSlog::start().await;
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::broadcast::channel(1);
+    let result = kv_server::run(
+        snapshot_settings(&path, "0"),
+        slog.logger(shrmpl::shrmpl_log_client::LogLevel::Info),
+        shutdown_rx,
+    )
+    .await;
+    assert!(result.is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "not json");
+}
+
+// Settings for a server journaling to `path`, over an ephemeral BIND_ADDR
+fn aof_settings(path: &std::path::Path, sync: &str) -> kv_server::Settings {
+    let config = [
+        ("BIND_ADDR", "127.0.0.1:0"),
+        ("ADMIN_COMMANDS", "true"),
+        ("ALLOW_FLUSH", "true"),
+        ("AOF_PATH", path.to_str().unwrap()),
+        ("AOF_SYNC", sync),
+    ]
+    .iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+    kv_server::Settings::from_config(&config)
+}
+
+#[tokio::test]
+async fn aof_replays_writes_after_a_restart() {
+    let path = temp_dir("aof").join("kv.aof");
+    let server = KvServer::start_with_settings(aof_settings(&path, "everysec")).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set("gone", "x").await.unwrap();
+    client.flushall().await.unwrap();
+    client.set("name", "shrmpl").await.unwrap();
+    client.rename("name", "title").await.unwrap();
+    client.incr("count").await.unwrap();
+    client.incrby("count", 9).await.unwrap();
+    assert!(client.setnx("lock", "me").await.unwrap());
+    client.delete("lock").await.unwrap();
+    client.set_with_ttl("lease", "a", "1h").await.unwrap();
+    client.set_with_ttl("kept", "b", "1h").await.unwrap();
+    client.persist("kept").await.unwrap();
+    client.set("short", "c").await.unwrap();
+    client.expire("short", "1s").await.unwrap();
+    client.set_bytes("blob", &[0, 1, 2]).await.unwrap();
+    server.shutdown().await;
+
+    // A crash mid-write leaves a line without its newline
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    std::io::Write::write_all(&mut file, b"SET torn val").unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let server = KvServer::start_with_settings(aof_settings(&path, "everysec")).await;
+    assert!(server
+        .slog
+        .wait_for_code("KVAOFTORN")
+        .await
+        .contains("incomplete last line"));
+    assert!(server
+        .slog
+        .wait_for_code("KVAOFLOAD")
+        .await
+        .contains("Replayed 15 lines"));
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    assert_eq!(
+        client.get("title").await.unwrap(),
+        Some("shrmpl".to_string())
+    );
+    for key in ["gone", "name", "lock", "short", "torn"] {
+        assert_eq!(client.get(key).await.unwrap(), None, "{}", key);
+    }
+    assert_eq!(client.incr("count").await.unwrap(), 11);
+    assert!(client.ttl("lease").await.unwrap() > 3500);
+    assert_eq!(client.ttl("kept").await.unwrap(), -1);
+    assert_eq!(client.get_bytes("blob").await.unwrap(), Some(vec![0, 1, 2]));
+    server.shutdown().await;
+
+    // The torn line was cut off before anything new was appended after it
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert!(!content.contains("torn"));
+    assert!(content.ends_with("\nSET count 11\n"), "{}", content);
+}
+
+// Every value type, with and without an expiration, comes through EXPORT and IMPORT
+// unchanged, expiration instants included
+#[tokio::test]
+async fn export_imports_into_a_fresh_server_unchanged() {
+    let source = KvServer::start(true).await;
+    let mut client = KvClient::connect(&source.addr()).await.unwrap();
+    client.set("count", "7").await.unwrap();
+    client.set_with_ttl("hits", "-12", "1h").await.unwrap();
+    client.set("name", "shrmpl").await.unwrap();
+    client.set_with_ttl("lease", "abc", "90s").await.unwrap();
+    client.set_raw("zip", "007", Some("10min")).await.unwrap();
+    client.set_float("rate", 2.5).await.unwrap();
+    client.set_float("ratio", 0.125).await.unwrap();
+    client.set_bytes("blob", &[0, 1, 2, 255]).await.unwrap();
+    client.set_bytes("image", b"\x89PNG").await.unwrap();
+    for (key, ttl) in [("ratio", "2h"), ("image", "30min")] {
+        assert!(client.expire(key, ttl).await.unwrap());
+    }
+    let export = client.export().await.unwrap();
+
+    let target = KvServer::start(true).await;
+    let mut imported = KvClient::connect(&target.addr()).await.unwrap();
+    assert_eq!(imported.import(&export).await.unwrap(), (9, 0));
+
+    let sorted = |json: &str| {
+        let mut entries: Vec<serde_json::Value> = serde_json::from_str(json).unwrap();
+        entries.sort_by(|a, b| a["key"].as_str().cmp(&b["key"].as_str()));
+        entries
+    };
+    let entries = sorted(&export);
+    assert_eq!(sorted(&imported.export().await.unwrap()), entries);
+    let types: Vec<&str> = entries
+        .iter()
+        .map(|entry| entry["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        types,
+        ["bytes", "int", "int", "bytes", "str", "str", "float", "float", "str"]
+    );
+    let expiring = entries
+        .iter()
+        .filter(|entry| entry["expires_at"].is_u64())
+        .count();
+    assert_eq!(expiring, 5);
+
+    let mut listed = client.list().await.unwrap();
+    let mut imported_listed = imported.list().await.unwrap();
+    listed.sort();
+    imported_listed.sort();
+    assert_eq!(imported_listed, listed);
+    assert_eq!(
+        imported.get_bytes("blob").await.unwrap(),
+        Some(vec![0, 1, 2, 255])
+    );
+    assert_eq!(imported.get_float("rate").await.unwrap(), Some(2.5));
+    assert_eq!(imported.get("zip").await.unwrap().as_deref(), Some("007"));
+    assert!(imported.ttl("hits").await.unwrap() > 3500);
+}
+
+#[tokio::test]
+async fn aof_restart_survives_imports_and_tags() {
+    let path = temp_dir("aof-import").join("kv.aof");
+    let server = KvServer::start_with_settings(aof_settings(&path, "always")).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    // An empty value has no SET line to be journaled as, so IMPORT refuses it
+    for empty in [
+        r#"[{"key":"blank","value":"","type":"str","expires_at":null}]"#,
+        r#"[{"key":"blank","value":"","type":"bytes","expires_at":null}]"#,
+    ] {
+        assert!(client.import(empty).await.is_err());
+    }
+    let doc = r#"[{"key":"name","value":"shrmpl","type":"str","expires_at":null}]"#;
+    assert_eq!(client.import(doc).await.unwrap(), (1, 0));
+    assert!(client.tag("name", "team").await.unwrap());
+    server.shutdown().await;
+
+    let server = KvServer::start_with_settings(aof_settings(&path, "always")).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    assert_eq!(client.get("name").await.unwrap().as_deref(), Some("shrmpl"));
+    assert_eq!(client.get("blank").await.unwrap(), None);
+    // Tags are not journaled
+    assert!(client.tagged("team").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn bgrewriteaof_compacts_the_file() {
+    let path = temp_dir("aof").join("kv.aof");
+    let server = KvServer::start_with_settings(aof_settings(&path, "always")).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    for _ in 0..50 {
+        client.incr("hits").await.unwrap();
+    }
+    client.set("other", "x").await.unwrap();
+    client.delete("other").await.unwrap();
+    // With AOF_SYNC=always a write is answered once its line is in the file
+    assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 52);
+
+    assert_eq!(client.bgrewriteaof().await.unwrap(), 1);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "SET hits 50\n");
+    // Later writes go to the new file
+    client.incr("hits").await.unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "SET hits 50\nSET hits 51\n"
+    );
+    server.shutdown().await;
+
+    let server = KvServer::start_with_settings(aof_settings(&path, "always")).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    assert_eq!(client.incr("hits").await.unwrap(), 52);
+
+    let plain = KvServer::start(true).await;
+    let mut client = KvClient::connect(&plain.addr()).await.unwrap();
+    let err = client.bgrewriteaof().await.unwrap_err();
+    assert!(err.to_string().contains("aof disabled"), "{}", err);
+}
+
+#[tokio::test]
+async fn hello_noheartbeat_suppresses_upong() {
+    let server = KvServer::start_with(&[("HEARTBEAT_SECS", "1")]).await;
+
+    let mut quiet = KvClient::connect_without_heartbeat(&server.addr())
+        .await
+        .unwrap();
+    let hello = quiet.negotiated().unwrap();
+    assert_eq!((hello.proto, hello.heartbeat), (1, None));
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    assert_eq!(
+        client.negotiated().unwrap().heartbeat,
+        Some(Duration::from_secs(1))
+    );
+
+    // Raw connections see exactly what a netcat-style script would
+    let mut opted_out = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    opted_out
+        .get_mut()
+        .write_all(b"HELLO noheartbeat proto=1\n")
+        .await
+        .unwrap();
+    let mut legacy = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    let mut line = String::new();
+    opted_out.read_line(&mut line).await.unwrap();
+    let reply = HelloReply::parse(line.trim_end()).unwrap();
+    assert_eq!(reply.heartbeat, None, "{}", line);
+
+    // Three heartbeat intervals: the legacy connection gets UPONGs, the other nothing
+    tokio::time::sleep(Duration::from_millis(3200)).await;
+    for conn in [&mut opted_out, &mut legacy] {
+        conn.get_mut().write_all(b"PING\n").await.unwrap();
+    }
+    line.clear();
+    opted_out.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "PONG\n");
+    let mut upongs = 0;
+    loop {
+        line.clear();
+        legacy.read_line(&mut line).await.unwrap();
+        if line != "UPONG\n" {
+            break;
+        }
+        upongs += 1;
+    }
+    assert_eq!(line, "PONG\n");
+    assert!(upongs >= 2, "{} heartbeats", upongs);
+
+    // HELLO is only honoured as the first line, and the client skips UPONGs
+    line.clear();
+    legacy
+        .get_mut()
+        .write_all(b"HELLO noheartbeat\n")
+        .await
+        .unwrap();
+    legacy.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "ERROR HELLO must be the first command\n");
+    assert!(quiet.ping().await.is_ok());
+    assert!(client.ping().await.is_ok());
+}
+
+#[tokio::test]
+async fn large_strings_are_compressed_transparently() {
+    let server = KvServer::start_with(&[
+        ("COMPRESS_THRESHOLD_BYTES", "32"),
+        ("ADMIN_COMMANDS", "true"),
+    ])
+    .await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let large = "shrmpl-".repeat(14);
+
+    client.set("small", "short-value").await.unwrap();
+    let status = client.status().await.unwrap();
+    assert_eq!(status.saved_bytes, 0);
+    assert_eq!(status.mem_bytes, 64 + "small".len() as u64 + 11);
+
+    client.set("large", &large).await.unwrap();
+    assert_eq!(client.get("large").await.unwrap(), Some(large.clone()));
+    let listed = client.list().await.unwrap();
+    assert!(listed
+        .iter()
+        .any(|(k, v, t, _)| k == "large" && *v == large && t == "str"));
+    assert!(client.export().await.unwrap().contains(&large));
+    let status = client.status().await.unwrap();
+    assert!(status.saved_bytes > 0);
+    assert_eq!(
+        status.mem_bytes + status.saved_bytes,
+        2 * 64 + ("small".len() + 11 + "large".len() + large.len()) as u64
+    );
+
+    client.delete("large").await.unwrap();
+    assert_eq!(client.status().await.unwrap().saved_bytes, 0);
+}
+
+#[tokio::test]
+async fn traffic_counts_match_the_bytes_on_the_wire() {
+    let server = KvServer::start(false).await;
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    for (command, reply) in [
+        ("SET a 1\n", "OK\n"),
+        ("GET a\n", "1\n"),
+        ("LIST\n", "a=1,int,no-expiration\n"),
+    ] {
+        stream
+            .get_mut()
+            .write_all(command.as_bytes())
+            .await
+            .unwrap();
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply);
+    }
+    let mut end = String::new();
+    stream.read_line(&mut end).await.unwrap();
+    assert_eq!(end, "\n");
+
+    // STATUS is counted as read before it runs; its own reply isn't written yet
+    stream.get_mut().write_all(b"STATUS\n").await.unwrap();
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    let status = kv_protocol::ServerStatus::parse(line.trim_end()).unwrap();
+    assert_eq!(status.bytes_read, (8 + 6 + 5 + 7) as u64);
+    assert_eq!(status.bytes_written, (3 + 2 + 22 + 1) as u64);
+    assert_eq!(status.commands, 4);
+
+    drop(stream);
+    let record = server.slog.wait_for_code("KVCONNCLOSE").await;
+    assert!(
+        record.ends_with(&format!(
+            " bytes_read=26 bytes_written={} commands=4",
+            28 + line.len()
+        )),
+        "{}",
+        record
+    );
+}
+
+#[tokio::test]
+async fn list_streams_every_key_across_chunks() {
+    let seed = temp_dir("seed").join("seed.txt");
+    let lines: String = (0..2500)
+        .map(|i| format!("SET key{} value{}\n", i, i))
+        .collect();
+    std::fs::write(&seed, lines).unwrap();
+    let server = KvServer::start_with(&[
+        ("SEED_FILE", seed.to_str().unwrap()),
+        ("ADMIN_COMMANDS", "true"),
+    ])
+    .await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    // EXPORT is serialized in the same chunks
+    let doc: serde_json::Value = serde_json::from_str(&client.export().await.unwrap()).unwrap();
+    assert_eq!(doc.as_array().unwrap().len(), 2500);
+
+    let mut listed = client.list().await.unwrap();
+    assert_eq!(listed.len(), 2500);
+    listed.sort();
+    listed.dedup();
+    assert_eq!(listed.len(), 2500);
+    assert!(listed
+        .iter()
+        .all(|(k, v, _, exp)| k[3..] == v[5..] && exp.is_none()));
+
+    // The terminating empty line leaves the connection in step
+    client.set("after", "list").await.unwrap();
+    assert_eq!(client.get("after").await.unwrap(), Some("list".to_string()));
+}
+
+// A LIST far larger than the socket buffers, to a client that stops reading, leaves
+// the server blocked mid-reply; writers must still get the lock meanwhile
+#[tokio::test]
+async fn stalled_list_reader_does_not_block_writers() {
+    let seed = temp_dir("seed").join("seed.txt");
+    let value = "v".repeat(90);
+    let lines: String = (0..200_000)
+        .map(|i| format!("SET key{} {}\n", i, value))
+        .collect();
+    std::fs::write(&seed, lines).unwrap();
+    let server = KvServer::start_with(&[("SEED_FILE", seed.to_str().unwrap())]).await;
+
+    let mut stalled = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stalled.get_mut().write_all(b"LIST\n").await.unwrap();
+    let mut line = String::new();
+    stalled.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("key"), "{}", line);
+
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(2), client.set("after", "list"))
+        .await
+        .expect("SET waited on the stalled LIST")
+        .unwrap();
+
+    // The rest of the reply is still all there
+    let mut entries = 1;
+    loop {
+        line.clear();
+        stalled.read_line(&mut line).await.unwrap();
+        if line == "\n" {
+            break;
+        }
+        entries += 1;
+    }
+    assert!(entries >= 200_000, "{} entries", entries);
+}
+
+#[tokio::test]
+async fn hello_lists_compiled_in_features() {
+    let server = KvServer::start(false).await;
+    let client = KvClient::connect(&server.addr()).await.unwrap();
+    let hello = client.negotiated().unwrap();
+    assert_eq!(hello.features, kv_protocol::FEATURES);
+    assert!(client.supports(kv_protocol::FEATURE_BATCH));
+    assert!(!client.supports(kv_protocol::FEATURE_ADMIN));
+
+    let admin = KvServer::start(true).await;
+    let client = KvClient::connect(&admin.addr()).await.unwrap();
+    let mut expected = kv_protocol::FEATURES.to_vec();
+    expected.push(kv_protocol::FEATURE_ADMIN);
+    assert_eq!(client.negotiated().unwrap().features, expected);
+
+    // A client asking for a newer revision gets this server's
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"HELLO proto=7\n")
+        .await
+        .unwrap();
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    let reply = HelloReply::parse(line.trim_end()).unwrap();
+    assert_eq!(reply.proto, kv_protocol::PROTOCOL_VERSION);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn large_values_do_not_starve_other_connections() {
+    let server = KvServer::start_with(&[("MAX_BYTES_LEN", "8388608")]).await;
+    let data: Vec<u8> = (0..6 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set_bytes("blob", &data).await.unwrap();
+
+    let addr = server.addr();
+    let len = data.len();
+    let reader = tokio::spawn(async move {
+        let mut client = KvClient::connect(&addr).await.unwrap();
+        for _ in 0..3 {
+            let value = client.get_bytes("blob").await.unwrap().unwrap();
+            assert_eq!(value.len(), len);
+        }
+    });
+
+    // PINGs keep being answered while the large reads are in flight
+    let mut pings = 0;
+    let mut slowest = Duration::ZERO;
+    while !reader.is_finished() {
+        let start = std::time::Instant::now();
+        client.ping().await.unwrap();
+        slowest = slowest.max(start.elapsed());
+        pings += 1;
+    }
+    reader.await.unwrap();
+    assert!(pings > 1, "only {} PINGs during the transfer", pings);
+    assert!(
+        slowest < Duration::from_millis(500),
+        "slowest PING {:?}",
+        slowest
+    );
+}
+
+#[tokio::test]
+async fn select_isolates_namespaces_per_connection() {
+    let server = KvServer::start(false).await;
+    let mut billing = KvClient::connect(&server.addr()).await.unwrap();
+    let mut search = KvClient::connect(&server.addr()).await.unwrap();
+    let mut global = KvClient::connect(&server.addr()).await.unwrap();
+    billing.select(Some("billing")).await.unwrap();
+    search.select(Some("search")).await.unwrap();
+
+    billing.set("config", "monthly").await.unwrap();
+    search.set("config", "fuzzy").await.unwrap();
+    assert_eq!(billing.incr("runs").await.unwrap(), 1);
+    global.set("config", "shared").await.unwrap();
+
+    assert_eq!(
+        billing.get("config").await.unwrap(),
+        Some("monthly".to_string())
+    );
+    assert_eq!(
+        search.get("config").await.unwrap(),
+        Some("fuzzy".to_string())
+    );
+    assert_eq!(search.get("runs").await.unwrap(), None);
+    assert_eq!(
+        global.get("billing:config").await.unwrap(),
+        Some("monthly".to_string())
+    );
+
+    // LIST in a namespace shows only its keys, without the prefix
+    let mut listed: Vec<String> = billing
+        .list()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(k, v, _, _)| format!("{}={}", k, v))
+        .collect();
+    listed.sort();
+    assert_eq!(listed, vec!["config=monthly", "runs=1"]);
+    assert_eq!(global.list().await.unwrap().len(), 4);
+
+    assert!(search.delete("config").await.unwrap());
+    assert_eq!(
+        billing.get("config").await.unwrap(),
+        Some("monthly".to_string())
+    );
+
+    // SELECT alone goes back to the global namespace
+    billing.select(None).await.unwrap();
+    assert_eq!(
+        billing.get("config").await.unwrap(),
+        Some("shared".to_string())
+    );
+}
+
+#[tokio::test]
+async fn select_validates_namespace_and_key_length() {
+    let server = KvServer::start(false).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+
+    for bad in ["a:b", &"n".repeat(99)] {
+        let err = client.select(Some(bad)).await.unwrap_err().to_string();
+        assert!(err.contains("invalid namespace"), "{}", err);
+    }
+
+    // The prefixed key counts against the 100-byte limit
+    client.select(Some("tenant")).await.unwrap();
+    let fits = "k".repeat(100 - "tenant:".len());
+    client.set(&fits, "v").await.unwrap();
+    let err = client.set(&format!("{}k", fits), "v").await.unwrap_err();
+    assert!(err.to_string().contains("invalid length"), "{}", err);
+}
+
+// Answers the client's first lines with `replies`, then reads everything after
+// without a word, holding the connection open the way a frozen host would
+async fn silent_server(replies: &'static [&'static str]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let (read_half, mut write_half) = socket.into_split();
+        let mut reader = BufReader::new(read_half);
+        let mut line = String::new();
+        for reply in replies {
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+            write_half.write_all(reply.as_bytes()).await.unwrap();
+        }
+        while reader.read_line(&mut line).await.unwrap_or(0) > 0 {}
+        drop(write_half);
+    });
+    addr.to_string()
+}
+
+const HELLO_1S: &str = "HELLO version=0.1.1 proto=1 heartbeat=1s features=heartbeat\n";
+
+fn is_unresponsive(err: &(dyn std::error::Error + 'static)) -> bool {
+    err.downcast_ref::<KvError>() == Some(&KvError::ServerUnresponsive)
+}
+
+#[tokio::test]
+async fn client_declares_a_silent_server_dead() {
+    let addr = silent_server(&[HELLO_1S, "PONG\n"]).await;
+    let mut client = KvClient::connect(&addr).await.unwrap();
+    client.set_dead_after_heartbeats(2);
+    client.ping().await.unwrap();
+
+    // The GET goes out but nothing comes back, not even a heartbeat
+    let started = std::time::Instant::now();
+    let err = client.get("a").await.unwrap_err();
+    let waited = started.elapsed();
+    assert!(is_unresponsive(err.as_ref()), "{}", err);
+    assert!(
+        waited > Duration::from_millis(1500) && waited < Duration::from_secs(3),
+        "detected after {:?}",
+        waited
+    );
+    assert!(client.is_unresponsive());
+    assert!(client.last_server_contact().elapsed() >= Duration::from_secs(2));
+
+    // Later commands fail straight away
+    let started = std::time::Instant::now();
+    let err = client.ping().await.unwrap_err();
+    assert!(is_unresponsive(err.as_ref()), "{}", err);
+    assert!(started.elapsed() < Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn idle_client_fails_fast_once_the_server_went_silent() {
+    let addr = silent_server(&[HELLO_1S]).await;
+    let mut client = KvClient::connect(&addr).await.unwrap();
+    client.set_dead_after_heartbeats(2);
+
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    let started = std::time::Instant::now();
+    let err = client.ping().await.unwrap_err();
+    assert!(is_unresponsive(err.as_ref()), "{}", err);
+    assert!(started.elapsed() < Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn debug_sleep_trips_the_client_timeout() {
+    let server =
+        KvServer::start_with(&[("HEARTBEAT_SECS", "1"), ("DEBUG_COMMANDS_ENABLED", "true")]).await;
+    let warning = server.slog.wait_for_code("KVDEBUGCMDS").await;
+    assert!(warning.starts_with("WARN"), "{}", warning);
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.debug_sleep(10).await.unwrap();
+
+    // Heartbeats stop while the connection sleeps, so the client gives up
+    client.set_dead_after_heartbeats(2);
+    let started = std::time::Instant::now();
+    let err = client.debug_sleep(5000).await.unwrap_err();
+    assert!(is_unresponsive(err.as_ref()), "{}", err);
+    assert!(started.elapsed() < Duration::from_secs(4));
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"DEBUG SLEEP 60001\n")
+        .await
+        .unwrap();
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "ERROR invalid arguments\n");
+}
+
+#[tokio::test]
+async fn debug_commands_are_off_by_default() {
+    let server = KvServer::start(true).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let started = std::time::Instant::now();
+    let err = client.debug_sleep(2000).await.unwrap_err();
+    assert_eq!(err.to_string(), "ERROR debug commands disabled");
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+// Heartbeats pile up unread while the client is idle and count once drained
+#[tokio::test]
+async fn heartbeats_keep_an_idle_connection_alive() {
+    let server = KvServer::start_with(&[("HEARTBEAT_SECS", "1")]).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.set_dead_after_heartbeats(2);
+
+    tokio::time::sleep(Duration::from_millis(3000)).await;
+    assert!(client.last_server_contact().elapsed() >= Duration::from_secs(3));
+    client.ping().await.unwrap();
+    assert!(client.last_server_contact().elapsed() < Duration::from_millis(100));
+    assert!(!client.is_unresponsive());
+
+    // Without negotiated heartbeats silence is normal and never fatal
+    let mut quiet = KvClient::connect_without_heartbeat(&server.addr())
+        .await
+        .unwrap();
+    quiet.set_dead_after_heartbeats(1);
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    quiet.ping().await.unwrap();
+}
+
+#[tokio::test]
+async fn badcmds_returns_and_clears_rejected_lines() {
+    let server =
+        KvServer::start_with(&[("ADMIN_COMMANDS", "true"), ("BAD_COMMAND_SAMPLES", "3")]).await;
+
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    let peer = stream.local_addr().unwrap().to_string();
+    let long = format!("FROB {}", "x".repeat(300));
+    let lines = [
+        "BOGUS one".to_string(),
+        "GET".to_string(),
+        "BATCH GET a;SET b".to_string(),
+        long.clone(),
+        "PING".to_string(),
+    ];
+    for line in &lines {
+        stream
+            .write_all(format!("{}\n", line).as_bytes())
+            .await
+            .unwrap();
+    }
+    stream.write_all(b"\xff\xfe\n").await.unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut reply = String::new();
+    for _ in 0..lines.len() + 1 {
+        reply.clear();
+        reader.read_line(&mut reply).await.unwrap();
+    }
+    assert_eq!(reply, "ERROR invalid encoding\n");
+
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let samples = client.bad_commands().await.unwrap();
+    // Only the three most recent are kept: the batch's SET, the long line and the bad bytes
+    assert_eq!(samples.len(), 3, "{:?}", samples);
+    for sample in &samples {
+        let mut fields = sample.splitn(3, ' ');
+        assert!(chrono::DateTime::parse_from_rfc3339(fields.next().unwrap()).is_ok());
+        assert_eq!(fields.next(), Some(peer.as_str()), "{}", sample);
+    }
+    assert!(
+        samples[0].ends_with(" [invalid arguments] SET b"),
+        "{}",
+        samples[0]
+    );
+    assert!(
+        samples[1].ends_with(&format!(" [unknown command] {}", &long[..200])),
+        "{}",
+        samples[1]
+    );
+    assert!(
+        samples[2].ends_with(" [invalid encoding] \u{fffd}\u{fffd}"),
+        "{}",
+        samples[2]
+    );
+
+    // Reading clears them
+    assert!(client.bad_commands().await.unwrap().is_empty());
+
+    let plain = KvServer::start(false).await;
+    let mut client = KvClient::connect(&plain.addr()).await.unwrap();
+    let err = client.bad_commands().await.unwrap_err().to_string();
+    assert_eq!(err, "ERROR admin commands disabled");
+}
+
+#[test]
+fn selftest_passes_on_a_good_config() {
+    // The configured address is in use, so only the ephemeral selftest port can work
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let bind_addr = taken.local_addr().unwrap().to_string();
+    let (passed, output) = run_selftest(
+        env!("CARGO_BIN_EXE_shrmpl-kv-srv"),
+        &[("BIND_ADDR", &bind_addr), ("DISABLED_COMMANDS", "DEL")],
+    );
+    assert!(passed, "{}", output);
+    assert!(output.contains("SELFTEST PASS"), "{}", output);
+}
+
+#[test]
+fn init_template_boots_once_bind_addr_is_filled() {
+    let binary = env!("CARGO_BIN_EXE_shrmpl-kv-srv");
+    let config = temp_dir("kv-init").join("kv.env");
+    assert!(run_init(binary, &config, &[]));
+    let template = std::fs::read_to_string(&config).unwrap();
+    assert!(template.contains("# REQUIRED: Address and port to listen on"));
+    assert!(template.contains("\nMAX_KEY_LEN=100\n"), "{}", template);
+    assert!(!run_init(binary, &config, &[]));
+
+    let (passed, output) = run_selftest_file(binary, &config);
+    assert!(!passed, "{}", output);
+    assert!(output.contains("BIND_ADDR"), "{}", output);
+
+    let filled = fill_template(&template, "BIND_ADDR", "127.0.0.1:0");
+    std::fs::write(&config, filled).unwrap();
+    let (passed, output) = run_selftest_file(binary, &config);
+    assert!(passed, "{}", output);
+    assert!(output.contains("SELFTEST PASS"), "{}", output);
+}
+
+// The metrics of the next StatsD packet, sorted
+async fn statsd_packet(daemon: &UdpSocket) -> Vec<String> {
+    let mut buf = [0u8; 1500];
+    let n = tokio::time::timeout(Duration::from_secs(3), daemon.recv(&mut buf))
+        .await
+        .expect("no StatsD packet")
+        .unwrap();
+    let mut metrics: Vec<String> = std::str::from_utf8(&buf[..n])
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    metrics.sort();
+    metrics
+}
+
+// Each push carries every metric; counters cover only what happened since the last one
+#[tokio::test]
+async fn statsd_pushes_counters_and_gauges() {
+    let daemon = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let statsd_addr = daemon.local_addr().unwrap().to_string();
+    let server =
+        KvServer::start_with(&[("STATSD_ADDR", &statsd_addr), ("STATSD_INTERVAL_SECS", "1")]).await;
+    // The first push goes out at startup, before any client
+    assert_eq!(
+        statsd_packet(&daemon).await,
+        [
+            "shrmpl.kv.commands:0|c",
+            "shrmpl.kv.connections:0|g",
+            "shrmpl.kv.hits:0|c",
+            "shrmpl.kv.keys:0|g",
+            "shrmpl.kv.misses:0|c",
+        ]
+    );
+
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    client.mset(&[("a", "1"), ("b", "2")]).await.unwrap();
+    client.get("a").await.unwrap();
+    client.get("b").await.unwrap();
+    client.get("missing").await.unwrap();
+    // The client's HELLO and LIMITS make six commands
+    assert_eq!(
+        statsd_packet(&daemon).await,
+        [
+            "shrmpl.kv.commands:6|c",
+            "shrmpl.kv.connections:1|g",
+            "shrmpl.kv.hits:2|c",
+            "shrmpl.kv.keys:2|g",
+            "shrmpl.kv.misses:1|c",
+        ]
+    );
+    assert!(statsd_packet(&daemon)
+        .await
+        .contains(&"shrmpl.kv.commands:0|c".to_string()));
+}
+
+// Overwrites always go through; only writes that would add a key are refused, and keys
+// that have expired free their place before the sweep removes them
+#[tokio::test]
+async fn max_keys_refuses_new_keys_once_full() {
+    let server = KvServer::start_with(&[("MAX_KEYS", "3")]).await;
+    let mut client = KvClient::connect(&server.addr()).await.unwrap();
+    let kv_error = |err: Box<dyn std::error::Error>| *err.downcast::<KvError>().unwrap();
+
+    client.mset(&[("a", "1"), ("b", "2")]).await.unwrap();
+    client.set_with_ttl("brief", "x", "1s").await.unwrap();
+    let err = client.set("c", "3").await.unwrap_err();
+    assert_eq!(kv_error(err), KvError::StoreFull);
+    let err = client.incr("counter").await.unwrap_err();
+    assert_eq!(kv_error(err), KvError::StoreFull);
+    client.set("a", "10").await.unwrap();
+    assert_eq!(
+        client.getset("b", "20").await.unwrap(),
+        Some("2".to_string())
+    );
+    assert!(!client.setnx("a", "11").await.unwrap());
+    assert_eq!(client.count().await.unwrap(), 3);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    client.set("c", "3").await.unwrap();
+    let err = client.mset(&[("d", "4"), ("a", "1")]).await.unwrap_err();
+    assert_eq!(kv_error(err), KvError::StoreFull);
+    client.delete("a").await.unwrap();
+    client.mset(&[("d", "4"), ("b", "2")]).await.unwrap();
+
+    let mut stream = BufReader::new(TcpStream::connect(server.addr).await.unwrap());
+    for (line, reply) in [
+        ("SETNX e 5\n", "ERROR store full\n"),
+        ("GETSET e 5\n", "ERROR store full\n"),
+        ("SETB e AA==\n", "ERROR store full\n"),
+        ("DECRBY e 1\n", "ERROR store full\n"),
+        ("INCR b\n", "3\n"),
+    ] {
+        stream.get_mut().write_all(line.as_bytes()).await.unwrap();
+        let mut got = String::new();
+        stream.read_line(&mut got).await.unwrap();
+        assert_eq!(got, reply, "{}", line);
+    }
+}
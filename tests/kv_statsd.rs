@@ -0,0 +1,57 @@
+use shrmpl::kv_statsd::{Sample, StatsdSettings};
+use std::collections::HashMap;
+use std::time::Duration;
+
+fn settings(entries: &[(&str, &str)]) -> Option<StatsdSettings> {
+    let config: HashMap<String, String> = entries
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    StatsdSettings::from_config(&config)
+}
+
+#[test]
+fn counters_report_the_change_and_gauges_the_level() {
+    let previous = Sample {
+        commands: 100,
+        hits: 40,
+        misses: 10,
+        keys: 7,
+        connections: 2,
+    };
+    let sample = Sample {
+        commands: 130,
+        hits: 45,
+        misses: 10,
+        keys: 9,
+        connections: 1,
+    };
+    assert_eq!(
+        sample.packet(&previous),
+        "shrmpl.kv.commands:30|c\n\
+         shrmpl.kv.hits:5|c\n\
+         shrmpl.kv.misses:0|c\n\
+         shrmpl.kv.keys:9|g\n\
+         shrmpl.kv.connections:1|g"
+    );
+    // The first push counts everything since startup
+    assert!(sample
+        .packet(&Sample::default())
+        .starts_with("shrmpl.kv.commands:130|c\n"));
+}
+
+#[test]
+fn statsd_is_off_without_an_address() {
+    assert!(settings(&[]).is_none());
+    assert!(settings(&[("STATSD_ADDR", ""), ("STATSD_INTERVAL_SECS", "5")]).is_none());
+
+    let statsd = settings(&[("STATSD_ADDR", "127.0.0.1:8125")]).unwrap();
+    assert_eq!(statsd.addr, "127.0.0.1:8125");
+    assert_eq!(statsd.interval, Duration::from_secs(10));
+    let statsd = settings(&[
+        ("STATSD_ADDR", "127.0.0.1:8125"),
+        ("STATSD_INTERVAL_SECS", "0"),
+    ])
+    .unwrap();
+    assert_eq!(statsd.interval, Duration::from_secs(1));
+}
@@ -0,0 +1,200 @@
+use shrmpl::kv_store::{compress, decompress, entry_size, format_float, Store, StoredValue, Value};
+use std::time::{Duration, SystemTime};
+
+fn text(s: &str, expires_at: Option<SystemTime>) -> StoredValue {
+    StoredValue {
+        value: Value::Str(s.to_string()),
+        expires_at,
+    }
+}
+
+// Recomputes the estimate from scratch to compare with the incremental one
+fn walked(store: &Store) -> (usize, usize) {
+    let mem = store.iter().map(|(k, v)| entry_size(k, v)).sum();
+    let expiring = store.iter().filter(|(_, v)| v.expires_at.is_some()).count();
+    (mem, expiring)
+}
+
+fn assert_consistent(store: &Store) {
+    assert_eq!((store.mem_bytes(), store.expiring()), walked(store));
+}
+
+#[test]
+fn overwrite_adjusts_by_the_size_difference() {
+    let mut store = Store::new();
+    let later = SystemTime::now() + Duration::from_secs(60);
+
+    store.insert("k".to_string(), text("short", None));
+    let small = store.mem_bytes();
+    store.insert("k".to_string(), text("a-much-longer-value", Some(later)));
+    assert_eq!(
+        store.mem_bytes(),
+        small + "a-much-longer-value".len() - "short".len()
+    );
+    assert_eq!(store.expiring(), 1);
+    assert_consistent(&store);
+
+    // Overwriting with a non-expiring int drops both the text and the TTL
+    store.insert(
+        "k".to_string(),
+        StoredValue {
+            value: Value::Int(7),
+            expires_at: None,
+        },
+    );
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.expiring(), 0);
+    assert_consistent(&store);
+}
+
+#[test]
+fn set_expiration_updates_the_expiring_count_in_place() {
+    let mut store = Store::new();
+    let later = SystemTime::now() + Duration::from_secs(60);
+    store.insert("k".to_string(), text("value", None));
+    let mem = store.mem_bytes();
+
+    assert!(store.set_expiration("k", Some(later)));
+    assert_eq!(store.get("k").unwrap().expires_at, Some(later));
+    assert_eq!(store.expiring(), 1);
+    assert!(store.set_expiration("k", Some(later + Duration::from_secs(1))));
+    assert_eq!(store.expiring(), 1);
+    assert!(store.set_expiration("k", None));
+    assert_eq!(store.expiring(), 0);
+    assert_eq!(store.mem_bytes(), mem);
+    assert_consistent(&store);
+
+    assert!(!store.set_expiration("missing", Some(later)));
+    assert_eq!(store.len(), 1);
+}
+
+#[test]
+fn delete_returns_to_zero() {
+    let mut store = Store::new();
+    let later = SystemTime::now() + Duration::from_secs(60);
+    for i in 0..20 {
+        let ttl = if i % 2 == 0 { Some(later) } else { None };
+        store.insert(format!("key{}", i), text(&"x".repeat(i), ttl));
+    }
+    assert_eq!(store.expiring(), 10);
+    assert_consistent(&store);
+
+    for i in 0..20 {
+        assert!(store.remove(&format!("key{}", i)).is_some());
+    }
+    assert!(store.remove("key0").is_none());
+    assert_eq!(
+        (store.mem_bytes(), store.expiring(), store.len()),
+        (0, 0, 0)
+    );
+}
+
+#[test]
+fn expiry_sweep_releases_only_expired_entries() {
+    let mut store = Store::new();
+    let now = SystemTime::now();
+    store.insert(
+        "gone1".to_string(),
+        text("a", Some(now - Duration::from_secs(1))),
+    );
+    store.insert("gone2".to_string(), text("bb", Some(now)));
+    store.insert(
+        "kept".to_string(),
+        text("ccc", Some(now + Duration::from_secs(60))),
+    );
+    store.insert(
+        "forever".to_string(),
+        StoredValue {
+            value: Value::Bytes(vec![0; 10]),
+            expires_at: None,
+        },
+    );
+
+    assert_eq!(store.remove_expired(now), 2);
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.expiring(), 1);
+    assert_consistent(&store);
+    assert_eq!(store.remove_expired(now), 0);
+}
+
+#[test]
+fn compressed_values_track_saved_bytes() {
+    let long = "abcd".repeat(25);
+    let payload = compress(&long);
+    assert!(payload.len() < long.len());
+    assert_eq!(decompress(&payload), long);
+
+    let mut store = Store::new();
+    store.insert(
+        "big".to_string(),
+        StoredValue {
+            value: Value::Compressed(payload.clone()),
+            expires_at: None,
+        },
+    );
+    assert_eq!(store.saved_bytes(), long.len() - payload.len());
+    assert_consistent(&store);
+    store.insert("big".to_string(), text("plain", None));
+    assert_eq!(store.saved_bytes(), 0);
+    assert_consistent(&store);
+}
+
+#[test]
+fn tags_follow_their_key_out_of_the_store() {
+    let mut store = Store::new();
+    let now = SystemTime::now();
+    store.insert("a".to_string(), text("1", None));
+    store.insert(
+        "b".to_string(),
+        text("2", Some(now - Duration::from_secs(1))),
+    );
+    store.insert("c".to_string(), text("3", None));
+    let tagged = |store: &Store, tag: &str| {
+        let mut keys: Vec<String> = store.tagged(tag).cloned().collect();
+        keys.sort();
+        keys
+    };
+
+    assert!(store.tag("a", "t"));
+    assert!(store.tag("b", "t"));
+    assert!(store.tag("c", "u"));
+    assert!(!store.tag("missing", "t"));
+    assert_eq!(tagged(&store, "t"), vec!["a", "b"]);
+
+    store.remove_expired(now);
+    assert_eq!(tagged(&store, "t"), vec!["a"]);
+    assert!(store.rename("a", "c"));
+    assert_eq!(tagged(&store, "t"), vec!["c"]);
+    assert!(tagged(&store, "u").is_empty());
+    assert!(!store.rename("a", "d"));
+    store.remove("c");
+    assert!(tagged(&store, "t").is_empty());
+    assert_consistent(&store);
+}
+
+#[test]
+fn numbers_are_recognized_only_in_their_shortest_spelling() {
+    for (text, expected) in [
+        ("42", Some(Value::Int(42))),
+        ("007", Some(Value::Int(7))),
+        ("4.2", Some(Value::Float(4.2))),
+        ("2.0", Some(Value::Float(2.0))),
+        ("-0.5", Some(Value::Float(-0.5))),
+        ("1e-7", Some(Value::Float(1e-7))),
+        ("1e300", Some(Value::Float(1e300))),
+        ("4.20", None),
+        ("1e5", None),
+        ("0.0000001", None),
+        ("2", Some(Value::Int(2))),
+        ("12345678901234567890", None),
+        ("inf", None),
+        ("NaN", None),
+        ("shrmpl", None),
+    ] {
+        assert_eq!(Value::number(text), expected, "{}", text);
+    }
+    assert_eq!(Value::Float(0.5).type_name(), "float");
+    for f in [2.0, 1e-7, 1e300, -0.5] {
+        assert_eq!(Value::number(&format_float(f)), Some(Value::Float(f)));
+    }
+}
@@ -0,0 +1,252 @@
+mod common;
+
+use common::KvServer;
+use shrmpl::loadtest::{
+    percentile, run_list_contention, run_lock_contention, run_log_throughput, run_test,
+    summarize_by_server, ListContentionConfig, LockContentionConfig, LogThroughputConfig,
+    TestConfig,
+};
+use shrmpl::loadtest_progress::Progress;
+use shrmpl::loadtest_slow::{parse_threshold, slowest, SlowLog};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+fn config(server: &KvServer, think_time: Duration) -> TestConfig {
+    TestConfig {
+        server_addrs: vec![server.addr()],
+        num_users: 2,
+        operations_per_user: 5,
+        shared_connection: false,
+        full_test: true,
+        verify: false,
+        think_time,
+        think_jitter: Duration::ZERO,
+        progress: Arc::new(Progress::new()),
+        slow_log: None,
+    }
+}
+
+#[tokio::test]
+async fn think_time_stretches_run_but_not_latency() {
+    let server = KvServer::start(false).await;
+    let think = Duration::from_millis(60);
+
+    let start = Instant::now();
+    let results = run_test(config(&server, think)).await.unwrap().results;
+    let elapsed = start.elapsed();
+
+    assert_eq!(results.len(), 10);
+    assert!(results.iter().all(|r| r.success));
+    // Four pauses between each user's five operations
+    assert!(elapsed >= think * 4, "run took {:?}", elapsed);
+    let slowest = results.iter().map(|r| r.duration).max().unwrap();
+    assert!(slowest < think, "slowest op {:?}", slowest);
+}
+
+#[tokio::test]
+async fn operations_spread_across_servers() {
+    let first = KvServer::start(false).await;
+    let second = KvServer::start(false).await;
+    for shared_connection in [false, true] {
+        let mut config = config(&first, Duration::ZERO);
+        config.server_addrs.push(second.addr());
+        config.num_users = 4;
+        config.shared_connection = shared_connection;
+
+        let results = run_test(config).await.unwrap().results;
+        let summaries = summarize_by_server(&results, 2);
+        for summary in &summaries {
+            assert_eq!(summary.operations, 10);
+            assert_eq!(summary.successful, 10);
+        }
+    }
+}
+
+#[tokio::test]
+async fn verify_mode_finds_no_violations() {
+    let server = KvServer::start(false).await;
+    let mut config = config(&server, Duration::ZERO);
+    config.num_users = 3;
+    config.operations_per_user = 120;
+    config.full_test = false;
+    config.verify = true;
+    config.shared_connection = true;
+
+    let run = run_test(config).await.unwrap();
+    assert_eq!(run.results.len(), 360);
+    assert!(run.results.iter().all(|r| r.success));
+    assert!(run.violations.is_empty(), "{:?}", run.violations);
+
+    // Verify keys are cleaned up afterwards
+    let mut client = shrmpl::shrmpl_kv_client::KvClient::connect(&server.addr())
+        .await
+        .unwrap();
+    assert!(client.list().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn list_contention_times_sets_and_cleans_up() {
+    let server = KvServer::start(false).await;
+    let config = ListContentionConfig {
+        server_addr: server.addr(),
+        keys: 100,
+        idle_sets: 20,
+        lists: 2,
+    };
+
+    let run = run_list_contention(&config).await.unwrap();
+    assert_eq!(run.idle.len(), 20);
+    assert!(run.list_duration > Duration::ZERO);
+    assert!(percentile(&run.idle, 50.0) <= percentile(&run.idle, 99.0));
+
+    let mut client = shrmpl::shrmpl_kv_client::KvClient::connect(&server.addr())
+        .await
+        .unwrap();
+    assert!(client.list().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn lock_contention_has_one_winner_per_window() {
+    let server = KvServer::start(false).await;
+    let config = LockContentionConfig {
+        server_addr: server.addr(),
+        tasks: 16,
+        rounds: 2,
+        ttl: Duration::from_secs(1),
+    };
+
+    let run = run_lock_contention(&config).await.unwrap();
+    assert_eq!(run.winners, [1, 1]);
+    assert_eq!(run.violations(), 0);
+    assert_eq!(run.latencies.len(), 32);
+
+    let mut client = shrmpl::shrmpl_kv_client::KvClient::connect(&server.addr())
+        .await
+        .unwrap();
+    assert_eq!(client.get("lockbench:lock").await.unwrap(), None);
+}
+
+// Failed SETNXs are lost attempts; the other tasks don't wait for the failed ones
+#[tokio::test]
+async fn lock_contention_reports_failed_attempts() {
+    let server = KvServer::start_with(&[("DISABLED_COMMANDS", "SETNX")]).await;
+    let config = LockContentionConfig {
+        server_addr: server.addr(),
+        tasks: 4,
+        rounds: 2,
+        ttl: Duration::from_secs(1),
+    };
+    let run = tokio::time::timeout(Duration::from_secs(10), run_lock_contention(&config))
+        .await
+        .expect("lock contention hung on a failed SETNX")
+        .unwrap();
+    assert_eq!(run.winners, [0, 0]);
+    assert_eq!(run.violations(), 2);
+    assert_eq!(run.errors.len(), 8);
+    assert!(
+        run.errors[0].contains("command disabled"),
+        "{}",
+        run.errors[0]
+    );
+
+    let config = LockContentionConfig { tasks: 0, ..config };
+    assert!(run_lock_contention(&config).await.is_err());
+}
+
+// Forwards client lines to `upstream`, holding back every `every`th BATCH line (counted
+// across all connections) for `delay` before passing it on
+async fn slow_proxy(upstream: String, every: usize, delay: Duration) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let batches = Arc::new(AtomicUsize::new(0));
+    tokio::spawn(async move {
+        while let Ok((client, _)) = listener.accept().await {
+            let server = TcpStream::connect(&upstream).await.unwrap();
+            let batches = Arc::clone(&batches);
+            tokio::spawn(async move {
+                let (client_read, mut client_write) = client.into_split();
+                let (mut server_read, mut server_write) = server.into_split();
+                tokio::spawn(async move {
+                    let _ = tokio::io::copy(&mut server_read, &mut client_write).await;
+                });
+                let mut lines = BufReader::new(client_read).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line.starts_with("BATCH")
+                        && (batches.fetch_add(1, Ordering::Relaxed) + 1).is_multiple_of(every)
+                    {
+                        tokio::time::sleep(delay).await;
+                    }
+                    let line = format!("{}\n", line);
+                    if server_write.write_all(line.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn slow_operations_are_logged_with_details() {
+    let server = KvServer::start(false).await;
+    let proxy = slow_proxy(server.addr(), 4, Duration::from_millis(300)).await;
+    let path = common::temp_dir("slowlog").join("slow.log");
+    let file = tokio::fs::File::create(&path).await.unwrap();
+    let (slow_log, writer) = SlowLog::start(parse_threshold("250ms").unwrap(), file);
+
+    let mut config = config(&server, Duration::ZERO);
+    config.server_addrs = vec![proxy];
+    config.full_test = false;
+    config.operations_per_user = 8;
+    config.slow_log = Some(slow_log);
+    let results = run_test(config).await.unwrap().results;
+    let slow_ops = writer.await.unwrap();
+
+    // Every fourth of the 16 batch GETs was held back
+    assert_eq!(results.len(), 16);
+    assert_eq!(slow_ops.len(), 4, "{:?}", slow_ops);
+    for op in &slow_ops {
+        assert!(op.duration >= Duration::from_millis(300), "{}", op);
+        assert_eq!(op.op_type, "batch");
+        assert_eq!(op.key, "loginlock-ip-123");
+        assert!(op.task_id < 2);
+        assert_eq!(op.error, None);
+    }
+    let slowest = slowest(&slow_ops, 2);
+    assert_eq!(slowest.len(), 2);
+    assert!(slowest[0].duration >= slowest[1].duration);
+
+    let logged = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = logged.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", logged);
+    for (line, op) in lines.iter().zip(&slow_ops) {
+        assert_eq!(*line, format!("SLOW {}", op));
+        assert!(
+            line.contains(" op=batch key=loginlock-ip-123 duration="),
+            "{}",
+            line
+        );
+    }
+
+    assert_eq!(parse_threshold("1.5s"), Some(Duration::from_millis(1500)));
+    assert_eq!(parse_threshold("40"), Some(Duration::from_millis(40)));
+    assert_eq!(parse_threshold("soon"), None);
+}
+
+#[tokio::test]
+async fn log_throughput_receives_every_record_whole() {
+    for batch_max in [1, 64] {
+        let config = LogThroughputConfig {
+            records: 300,
+            batch_max,
+            batch_delay: Duration::from_millis(5),
+        };
+        let run = run_log_throughput(&config).await.unwrap();
+        assert_eq!(run.received, 300);
+        assert!(run.records_per_sec() > 0.0);
+    }
+}
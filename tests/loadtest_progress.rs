@@ -0,0 +1,37 @@
+use shrmpl::loadtest_progress::{Progress, Snapshot};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn counter_increments_as_operations_complete() {
+    let progress = Arc::new(Progress::new());
+    assert_eq!(progress.snapshot(), Snapshot::default());
+
+    let handles: Vec<_> = (0..4)
+        .map(|task| {
+            let progress = Arc::clone(&progress);
+            std::thread::spawn(move || {
+                for op in 0..250 {
+                    // Task 0 fails every tenth operation
+                    progress.record(!(task == 0 && op % 10 == 0));
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let snapshot = progress.snapshot();
+    assert_eq!(snapshot.completed, 1000);
+    assert_eq!(snapshot.succeeded, 975);
+
+    let previous = Snapshot {
+        completed: 500,
+        succeeded: 500,
+    };
+    assert_eq!(
+        snapshot.progress_line(&previous, Duration::from_secs(1)),
+        "Progress: 1000 ops, 97.5% success, ~500 ops/s"
+    );
+}
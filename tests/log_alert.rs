@@ -0,0 +1,124 @@
+use shrmpl::log_alert::{AlertEvent, AlertSettings, ErrorRates};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+fn settings(entries: &[(&str, &str)]) -> AlertSettings {
+    let config: HashMap<String, String> = entries
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    AlertSettings::from_config(&config)
+}
+
+// Feeds `count` ERRO records from `host` one second apart, returning the events
+fn burst(
+    rates: &mut ErrorRates,
+    host: &str,
+    count: usize,
+    start: Instant,
+) -> Vec<Option<AlertEvent>> {
+    (0..count)
+        .map(|i| {
+            let at = start + Duration::from_secs(i as u64);
+            rates.record(host, &format!("failure {}", i), at)
+        })
+        .collect()
+}
+
+#[test]
+fn alerts_once_when_a_host_reaches_its_threshold() {
+    let mut rates = ErrorRates::new(settings(&[("ALERT_ERRO_PER_MIN", "3")]));
+    let t0 = Instant::now();
+
+    let events = burst(&mut rates, "web1", 5, t0);
+    assert_eq!(events[..2], [None, None]);
+    assert_eq!(
+        events[2],
+        Some(AlertEvent::Alert {
+            host: "web1".to_string(),
+            erro_per_min: 3,
+            threshold: 3,
+            samples: vec![
+                "failure 0".to_string(),
+                "failure 1".to_string(),
+                "failure 2".to_string()
+            ],
+        })
+    );
+    // Still over the threshold, but already alerting
+    assert_eq!(events[3..], [None, None]);
+    assert!(burst(&mut rates, "db1", 2, t0).iter().all(Option::is_none));
+}
+
+#[test]
+fn clears_when_the_rate_drops_and_respects_the_cooldown() {
+    let mut rates = ErrorRates::new(settings(&[
+        ("ALERT_ERRO_PER_MIN", "3"),
+        ("ALERT_COOLDOWN_SECS", "300"),
+    ]));
+    let t0 = Instant::now();
+    assert!(burst(&mut rates, "web1", 3, t0)[2].is_some());
+    assert_eq!(rates.sweep(t0 + Duration::from_secs(30)), vec![]);
+
+    // The first error leaves the window 60s after it arrived
+    let cleared = rates.sweep(t0 + Duration::from_secs(60));
+    assert_eq!(
+        cleared,
+        vec![AlertEvent::Clear {
+            host: "web1".to_string(),
+            erro_per_min: 2,
+            threshold: 3,
+        }]
+    );
+    assert_eq!(cleared[0].code(), "ALRTCLR");
+
+    // A second burst inside the cooldown is counted but not alerted
+    let again = t0 + Duration::from_secs(120);
+    assert!(burst(&mut rates, "web1", 3, again)
+        .iter()
+        .all(Option::is_none));
+    assert_eq!(rates.sweep(again + Duration::from_secs(200)), vec![]);
+
+    let later = t0 + Duration::from_secs(400);
+    let events = burst(&mut rates, "web1", 3, later);
+    assert!(matches!(events[2], Some(AlertEvent::Alert { .. })));
+}
+
+#[test]
+fn host_overrides_and_idle_hosts_are_forgotten() {
+    let mut rates = ErrorRates::new(settings(&[
+        ("ALERT_ERRO_PER_MIN", "3"),
+        ("ALERT_ERRO_PER_MIN_HOSTS", "batch:0, web1:2"),
+        ("ALERT_COOLDOWN_SECS", "60"),
+    ]));
+    let t0 = Instant::now();
+    assert!(burst(&mut rates, "batch", 10, t0)
+        .iter()
+        .all(Option::is_none));
+    assert!(burst(&mut rates, "web1", 2, t0)[1].is_some());
+    assert!(burst(&mut rates, "db1", 1, t0)[0].is_none());
+    assert_eq!(rates.tracked(), 2);
+
+    assert_eq!(rates.sweep(t0 + Duration::from_secs(61)).len(), 1);
+    assert_eq!(rates.sweep(t0 + Duration::from_secs(62)), vec![]);
+    assert_eq!(rates.tracked(), 0);
+}
+
+#[test]
+fn alerts_are_off_by_default() {
+    let defaults = settings(&[]);
+    assert!(!defaults.enabled());
+    assert!(settings(&[("ALERT_ERRO_PER_MIN_HOSTS", "web1:5")]).enabled());
+
+    let event = AlertEvent::Alert {
+        host: "web1".to_string(),
+        erro_per_min: 4,
+        threshold: 3,
+        samples: vec!["disk full".to_string()],
+    };
+    let json: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(json["event"], "alert");
+    assert_eq!(json["host"], "web1");
+    assert_eq!(json["erro_per_min"], 4);
+    assert_eq!(json["samples"][0], "disk full");
+}
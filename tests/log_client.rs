@@ -0,0 +1,290 @@
+mod common;
+
+use common::{record_code, temp_dir, FakeSlog};
+use shrmpl::shrmpl_log_client::{resolve_host, LogLevel, Logger, AUTO_HOST, CONFIG_SCHEMA};
+use shrmpl::{kv_server, vault_server};
+use std::collections::HashMap;
+use std::time::Duration;
+
+fn os_hostname() -> String {
+    let output = std::process::Command::new("hostname").output().unwrap();
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+#[test]
+fn auto_server_name_resolves_to_os_hostname() {
+    let mut expected = os_hostname();
+    expected.truncate(32);
+    assert_eq!(resolve_host(Some(AUTO_HOST), "fallback"), expected);
+    assert_eq!(resolve_host(None, "fallback"), expected);
+}
+
+#[test]
+fn explicit_server_name_is_used_verbatim() {
+    assert_eq!(
+        resolve_host(Some("skv-srv-east"), "fallback"),
+        "skv-srv-east"
+    );
+    assert_eq!(resolve_host(Some("Auto"), "fallback"), "Auto");
+}
+
+#[test]
+fn logger_defaults_are_pinned() {
+    let defaults: Vec<(&str, &str)> = CONFIG_SCHEMA
+        .iter()
+        .map(|key| (key.name, key.default.unwrap()))
+        .collect();
+    assert_eq!(
+        defaults,
+        vec![
+            ("SLOG_DEST", ""),
+            ("SERVER_NAME", "auto"),
+            ("SEND_LOG", "true"),
+            ("LOG_LEVEL", "INFO"),
+            ("LOG_CONSOLE", "true"),
+            ("SEND_ACTV", "false"),
+            ("LOG_FILE", ""),
+            ("SLOG_BATCH_MAX", "1"),
+            ("SLOG_BATCH_DELAY_MS", "5"),
+        ]
+    );
+
+    let logger = Logger::from_config(&HashMap::new());
+    assert_eq!(logger.dest, "");
+    assert_eq!(logger.host, resolve_host(None, "fallback"));
+    assert_eq!(logger.log_level, LogLevel::Info);
+    assert!(logger.log_console);
+    assert!(!logger.send_actv);
+    assert!(logger.send_log);
+    assert_eq!(logger.log_file(), None);
+}
+
+// The servers repeat the logging keys in their own schemas for --init templates
+#[test]
+fn server_schemas_share_the_logger_defaults() {
+    for schema in [kv_server::CONFIG_SCHEMA, vault_server::CONFIG_SCHEMA] {
+        for key in CONFIG_SCHEMA {
+            let server_key = schema.iter().find(|k| k.name == key.name).unwrap();
+            assert_eq!(server_key.default, key.default, "{}", key.name);
+        }
+    }
+}
+
+#[test]
+fn logger_reads_config_and_name_override() {
+    let config: HashMap<String, String> = [
+        ("SLOG_DEST", "127.0.0.1:7379"),
+        ("SERVER_NAME", "skv-srv-east"),
+        ("SEND_LOG", "false"),
+        ("LOG_LEVEL", "warn"),
+        ("LOG_CONSOLE", "bogus"),
+        ("SEND_ACTV", "true"),
+    ]
+    .iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+
+    let logger = Logger::from_config(&config);
+    assert_eq!(logger.dest, "127.0.0.1:7379");
+    assert_eq!(logger.host, "skv-srv-east");
+    assert_eq!(logger.log_level, LogLevel::Warn);
+    // Unparseable flags fall back to their default
+    assert!(logger.log_console);
+    assert!(logger.send_actv);
+    assert!(!logger.send_log);
+
+    let forced = Logger::from_config_with(&config, Some("kv-canary"));
+    assert_eq!(forced.host, "kv-canary");
+}
+
+// SLOG_DEST points at a closed port, so the file is the only place records land
+#[tokio::test]
+async fn log_calls_append_to_the_daily_log_file() {
+    let prefix = temp_dir("log-file").join("kv");
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let dest = closed.local_addr().unwrap().to_string();
+    drop(closed);
+    let config: HashMap<String, String> = [
+        ("SLOG_DEST", dest.as_str()),
+        ("SERVER_NAME", "skv-srv-east"),
+        ("LOG_CONSOLE", "false"),
+        ("LOG_FILE", prefix.to_str().unwrap()),
+    ]
+    .iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+    let logger = Logger::from_config(&config);
+    assert_eq!(logger.log_file(), prefix.to_str());
+
+    logger.info("KVSTART", "listening on 0.0.0.0:7171").await;
+    logger.activity("KVACTV", "skipped without SEND_ACTV").await;
+    logger
+        .debug("KVDEBUG", "below LOG_LEVEL but still recorded")
+        .await;
+
+    let today = chrono::Utc::now().format("%Y%m%d").to_string();
+    let path = format!("{}-{}.log", prefix.display(), today);
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    assert!(chrono::DateTime::parse_from_rfc3339(&lines[0][..24]).is_ok());
+    assert_eq!(
+        &lines[0][24..],
+        format!(
+            " INFO {:<32} {:<12} 0025: listening on 0.0.0.0:7171",
+            "skv-srv-east", "KVSTART"
+        )
+    );
+    assert!(lines[1].contains(" DEBG skv-srv-east "), "{}", lines[1]);
+    assert!(lines[1].ends_with(": below LOG_LEVEL but still recorded"));
+
+    // A second logger for the same prefix appends rather than truncating
+    Logger::from_config(&config)
+        .warn("KVWARN", "appended")
+        .await;
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(contents.lines().count(), 3);
+}
+
+// Several tasks log concurrently through one batching Logger; every line SLOG gets
+// must be exactly one record, and each task's records must stay in order
+#[tokio::test]
+async fn batched_records_keep_their_boundaries() {
+    let slog = FakeSlog::start().await;
+    let logger = slog
+        .logger(LogLevel::Info)
+        .with_batching(16, Duration::from_millis(5));
+    let mut tasks = Vec::new();
+    for task in 0..4 {
+        let logger = logger.clone();
+        tasks.push(tokio::spawn(async move {
+            for i in 0..250 {
+                // Lengths vary so a split or merged record can't pass the length check
+                let message = format!("task={} seq={} {}", task, i, "x".repeat(i % 37));
+                logger.info("BATCHTEST", &message).await;
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    logger.flush().await;
+
+    let mut lines = Vec::new();
+    for _ in 0..200 {
+        lines = slog.lines();
+        if lines.len() >= 1000 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(lines.len(), 1000);
+    let mut next_seq = [0usize; 4];
+    for line in &lines {
+        assert_eq!(record_code(line), "BATCHTEST");
+        let (len, message) = line[50..].trim_start().split_once(": ").unwrap();
+        assert_eq!(len.parse::<usize>().unwrap(), message.len(), "{}", line);
+        let fields: Vec<usize> = message
+            .split(' ')
+            .take(2)
+            .map(|f| f.split_once('=').unwrap().1.parse().unwrap())
+            .collect();
+        assert_eq!(fields[1], next_seq[fields[0]], "{}", line);
+        next_seq[fields[0]] += 1;
+    }
+}
+
+// SLOG restarting between batches costs no records: the dropped connection is noticed
+// and the next batch goes out over a new one
+#[tokio::test]
+async fn batched_records_survive_a_slog_restart() {
+    let slog = FakeSlog::start().await;
+    let logger = slog
+        .logger(LogLevel::Info)
+        .with_batching(16, Duration::from_millis(5));
+    let wait_for = |n: usize| {
+        let slog = &slog;
+        async move {
+            for _ in 0..200 {
+                if slog.lines().len() >= n {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            slog.lines()
+        }
+    };
+    for round in 0..3 {
+        for i in 0..40 {
+            let message = format!("seq={}", round * 40 + i);
+            logger.info("RESTARTTEST", &message).await;
+        }
+        logger.flush().await;
+        assert_eq!(wait_for(40 * (round + 1)).await.len(), 40 * (round + 1));
+        slog.restart().await;
+    }
+
+    let lines = slog.lines();
+    for (seq, line) in lines.iter().enumerate() {
+        assert!(line.ends_with(&format!("seq={}", seq)), "{}", line);
+    }
+}
+
+// A lone record in a quiet period goes out after the batching delay, without a flush
+#[tokio::test]
+async fn single_batched_record_is_not_held_back() {
+    let slog = FakeSlog::start().await;
+    let logger = slog
+        .logger(LogLevel::Info)
+        .with_batching(64, Duration::from_millis(5));
+    logger.info("QUIET", "only record").await;
+    slog.wait_for_code("QUIET").await;
+}
+
+// SLOG writes back over a kept-open connection (UPONG every minute, STAT replies); that
+// doesn't make the batching sender take it for closed and reconnect
+#[tokio::test]
+async fn batched_connection_is_kept_when_slog_writes_back() {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (lines_tx, mut lines_rx) = tokio::sync::mpsc::unbounded_channel();
+    let accepted = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = accepted.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let lines_tx = lines_tx.clone();
+            tokio::spawn(async move {
+                let (read, mut write) = socket.split();
+                let mut reader = BufReader::new(read).lines();
+                while let Ok(Some(line)) = reader.next_line().await {
+                    let _ = lines_tx.send(line);
+                    // A heartbeat lands between every two batches
+                    let _ = write.write_all(b"UPONG 60000\n").await;
+                }
+            });
+        }
+    });
+
+    let logger = Logger::new(
+        addr.to_string(),
+        "test-host".to_string(),
+        LogLevel::Info,
+        false,
+        true,
+        true,
+    )
+    .with_batching(16, Duration::from_millis(5));
+    for i in 0..3 {
+        logger.info("WRITEBACK", &format!("seq={}", i)).await;
+        logger.flush().await;
+        let line = tokio::time::timeout(Duration::from_secs(5), lines_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(line.ends_with(&format!("seq={}", i)), "{}", line);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 1);
+}
@@ -0,0 +1,288 @@
+mod common;
+
+use common::{
+    fill_template, read_log_files, run_init, run_selftest, run_selftest_file, temp_dir, LogServer,
+};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+#[tokio::test]
+async fn shutdown_flushes_queued_records() {
+    let server = LogServer::start().await;
+    let logger = server.logger();
+    logger.error("TESTERR", "disk full on /var").await;
+    logger.activity("TESTACTV", "user alice logged in").await;
+    logger.info("TESTINFO", "cache warmed").await;
+
+    // Well under the writers' two-second flush interval, so the records are still
+    // buffered when shutdown starts
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let data_dir = server.shutdown().await;
+
+    let error = read_log_files(&data_dir, "error");
+    assert!(error.contains("TESTERR"), "{}", error);
+    assert!(error.contains("disk full on /var"), "{}", error);
+    let activity = read_log_files(&data_dir, "activity");
+    assert!(activity.contains("user alice logged in"), "{}", activity);
+    let misc = read_log_files(&data_dir, "misc");
+    assert!(misc.contains("cache warmed"), "{}", misc);
+}
+
+#[tokio::test]
+async fn stops_accepting_after_shutdown() {
+    let server = LogServer::start().await;
+    let addr = server.addr;
+    server.shutdown().await;
+    assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+}
+
+// Sends a STAT query and returns the reply's key=value fields
+async fn stat(
+    reader: &mut BufReader<tokio::net::TcpStream>,
+) -> std::collections::HashMap<String, String> {
+    reader.get_mut().write_all(b"STAT\n").await.unwrap();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        // The stats task sends an UPONG keepalive right after startup
+        if let Some(fields) = line.trim_end().strip_prefix("STAT ") {
+            return fields
+                .split(' ')
+                .filter_map(|field| field.split_once('='))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+        }
+    }
+}
+
+#[tokio::test]
+async fn stat_query_reports_start_time_and_uptime() {
+    let server = LogServer::start().await;
+    let socket = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    let mut reader = BufReader::new(socket);
+
+    let first = stat(&mut reader).await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let second = stat(&mut reader).await;
+
+    let start_time = &first["start_time"];
+    assert!(
+        chrono::DateTime::parse_from_rfc3339(start_time).is_ok(),
+        "{}",
+        start_time
+    );
+    assert_eq!(start_time, &second["start_time"]);
+    let uptime = |fields: &std::collections::HashMap<String, String>| {
+        fields["uptime_seconds"].parse::<u64>().unwrap()
+    };
+    assert!(
+        uptime(&second) > uptime(&first),
+        "{:?} then {:?}",
+        first,
+        second
+    );
+    // A query is not a record
+    assert_eq!(second["recv"], "0");
+    assert_eq!(second["protocol_errors"], "0");
+
+    server.shutdown().await;
+}
+
+// A raw protocol line, padded to the fixed-width header fields
+fn record_line(lvl: &str, code: &str, msg: &str) -> String {
+    format!(
+        "{} {:<32} {:<12} {:05}: {}\n",
+        lvl,
+        "test-host",
+        code,
+        msg.len(),
+        msg
+    )
+}
+
+#[tokio::test]
+async fn queue_max_bytes_drops_records_past_the_byte_cap() {
+    let server = LogServer::start_with(&[("QUEUE_MAX_BYTES", "2000")]).await;
+    let socket = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    let mut reader = BufReader::new(socket);
+
+    // Well under QUEUE_CAPACITY, but each large record alone is over the byte cap
+    let large = "x".repeat(4000);
+    for _ in 0..5 {
+        let line = record_line("ERRO", "TESTBIG", &large);
+        reader.get_mut().write_all(line.as_bytes()).await.unwrap();
+    }
+    let small = record_line("ERRO", "TESTSMALL", "disk 85% full");
+    reader.get_mut().write_all(small.as_bytes()).await.unwrap();
+
+    let stats = stat(&mut reader).await;
+    assert_eq!(stats["recv"], "6");
+    assert_eq!(stats["dropped"], "5");
+    let queued: u64 = stats["queued_bytes"].parse().unwrap();
+    assert!(queued <= 2000, "{:?}", stats);
+    drop(reader);
+
+    let data_dir = server.shutdown().await;
+    let error = read_log_files(&data_dir, "error");
+    assert!(error.contains("disk 85% full"), "{}", error);
+    assert!(!error.contains("TESTBIG"), "{}", error);
+}
+
+#[tokio::test]
+async fn warn_and_debug_records_get_their_own_files() {
+    let server = LogServer::start().await;
+    let logger = server.logger();
+    logger.warn("TESTWARN", "disk 85% full").await;
+    logger.debug("TESTDEBG", "cache lookup took 3ms").await;
+    logger.info("TESTINFO", "cache warmed").await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let data_dir = server.shutdown().await;
+
+    let warn = read_log_files(&data_dir, "warn");
+    assert!(warn.contains("disk 85% full"), "{}", warn);
+    let debug = read_log_files(&data_dir, "debug");
+    assert!(debug.contains("cache lookup took 3ms"), "{}", debug);
+    let misc = read_log_files(&data_dir, "misc");
+    assert!(misc.contains("cache warmed"), "{}", misc);
+    assert!(!misc.contains("TESTWARN"), "{}", misc);
+    assert!(!misc.contains("TESTDEBG"), "{}", misc);
+}
+
+#[tokio::test]
+async fn level_files_can_restore_the_three_file_layout() {
+    let server = LogServer::start_with(&[("LEVEL_FILES", "ACTV:activity,ERRO:error")]).await;
+    server.logger().warn("TESTWARN", "disk 85% full").await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let data_dir = server.shutdown().await;
+
+    let misc = read_log_files(&data_dir, "misc");
+    assert!(misc.contains("disk 85% full"), "{}", misc);
+    assert_eq!(read_log_files(&data_dir, "warn"), "");
+}
+
+#[tokio::test]
+async fn stats_records_carry_the_configured_server_name() {
+    let server = LogServer::start_with(&[("SERVER_NAME", "log-east-1")]).await;
+    // The stats task writes its first LOGSTATS record at startup
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let data_dir = server.shutdown().await;
+
+    let misc = read_log_files(&data_dir, "misc");
+    let stats: Vec<&str> = misc.lines().filter(|l| l.contains("LOGSTATS")).collect();
+    assert!(!stats.is_empty(), "{}", misc);
+    for line in stats {
+        // recv_ts, LVL, then the 32-byte host field
+        assert_eq!(&line[25..29], "INFO", "{}", line);
+        assert_eq!(&line[30..62], format!("{:<32}", "log-east-1"), "{}", line);
+    }
+}
+
+// Answers one HTTP request with 200 and returns its body
+async fn accept_webhook(listener: tokio::net::TcpListener) -> String {
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut reader = BufReader::new(socket);
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        if let Some((name, value)) = line.trim_end().split_once(": ") {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.parse().unwrap();
+            }
+        }
+        if line == "\r\n" {
+            break;
+        }
+    }
+    let mut body = vec![0u8; length];
+    tokio::io::AsyncReadExt::read_exact(&mut reader, &mut body)
+        .await
+        .unwrap();
+    reader
+        .get_mut()
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+        .await
+        .unwrap();
+    String::from_utf8(body).unwrap()
+}
+
+#[tokio::test]
+async fn erro_burst_raises_an_alert_and_posts_the_webhook() {
+    let webhook = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hooks/shrmpl", webhook.local_addr().unwrap());
+    let server = LogServer::start_with(&[
+        ("SERVER_NAME", "log-east-1"),
+        ("ALERT_ERRO_PER_MIN", "3"),
+        ("ALERT_WEBHOOK_URL", &url),
+    ])
+    .await;
+    let logger = server.logger();
+    for i in 0..4 {
+        logger.error("TESTERR", &format!("disk full #{}", i)).await;
+    }
+    let body = tokio::time::timeout(Duration::from_secs(5), accept_webhook(webhook))
+        .await
+        .expect("no webhook request");
+    let data_dir = server.shutdown().await;
+
+    let payload: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(payload["event"], "alert");
+    assert_eq!(payload["host"], "test-host");
+    assert_eq!(payload["erro_per_min"], 3);
+    assert_eq!(payload["samples"][2], "disk full #2");
+
+    let activity = read_log_files(&data_dir, "activity");
+    let alerts: Vec<&str> = activity.lines().filter(|l| l.contains("ALRT")).collect();
+    assert_eq!(alerts.len(), 1, "{}", activity);
+    assert_eq!(&alerts[0][30..62], format!("{:<32}", "log-east-1"));
+    assert!(alerts[0].contains("host=test-host erro_per_min=3 threshold=3"));
+    assert_eq!(read_log_files(&data_dir, "error").lines().count(), 4);
+}
+
+#[test]
+fn server_name_defaults_to_the_os_hostname() {
+    let settings = shrmpl::log_server::Settings::from_config(&Default::default());
+    let hostname = std::process::Command::new("hostname").output().unwrap();
+    let hostname = String::from_utf8(hostname.stdout).unwrap();
+    assert_eq!(settings.server_name, hostname.trim());
+}
+
+#[test]
+fn selftest_writes_and_reads_back_a_record() {
+    let data_dir = temp_dir("log-selftest");
+    let (passed, output) = run_selftest(
+        env!("CARGO_BIN_EXE_shrmpl-log-srv"),
+        &[
+            ("BIND_ADDR", "127.0.0.1:0"),
+            ("DATA_DIR", &data_dir.display().to_string()),
+        ],
+    );
+    assert!(passed, "{}", output);
+    assert!(output.contains("SELFTEST PASS"), "{}", output);
+    // The scratch directory is gone again
+    assert_eq!(std::fs::read_dir(&data_dir).unwrap().count(), 0);
+}
+
+#[test]
+fn init_template_boots_once_the_required_keys_are_filled() {
+    let binary = env!("CARGO_BIN_EXE_shrmpl-log-srv");
+    let dir = temp_dir("log-init");
+    let config = dir.join("slog.env");
+    assert!(run_init(binary, &config, &[]));
+    let template = std::fs::read_to_string(&config).unwrap();
+    assert!(!run_init(binary, &config, &[]));
+    assert!(run_init(binary, &config, &["--force"]));
+
+    let (passed, output) = run_selftest_file(binary, &config);
+    assert!(!passed, "{}", output);
+    assert!(output.contains("DATA_DIR"), "{}", output);
+
+    let data_dir = dir.join("data");
+    std::fs::create_dir_all(&data_dir).unwrap();
+    let filled = fill_template(&template, "BIND_ADDR", "127.0.0.1:0");
+    let filled = fill_template(&filled, "DATA_DIR", &data_dir.display().to_string());
+    std::fs::write(&config, filled).unwrap();
+    let (passed, output) = run_selftest_file(binary, &config);
+    assert!(passed, "{}", output);
+    assert!(output.contains("SELFTEST PASS"), "{}", output);
+}
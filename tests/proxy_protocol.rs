@@ -0,0 +1,87 @@
+use shrmpl::proxy_protocol::read_header;
+use std::net::SocketAddr;
+
+// Parses the header at the start of `bytes`, returning what follows it unread
+async fn parse(bytes: &[u8]) -> (std::io::Result<Option<SocketAddr>>, Vec<u8>) {
+    let mut rest = bytes;
+    let result = read_header(&mut rest).await;
+    (result, rest.to_vec())
+}
+
+fn v2(command: u8, family: u8, block: &[u8]) -> Vec<u8> {
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    header.push(0x20 | command);
+    header.push(family);
+    header.extend_from_slice(&(block.len() as u16).to_be_bytes());
+    header.extend_from_slice(block);
+    header
+}
+
+#[tokio::test]
+async fn v1_headers_name_the_client() {
+    let (client, rest) = parse(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 7171\r\nPING\n").await;
+    assert_eq!(client.unwrap(), Some("203.0.113.7:51234".parse().unwrap()));
+    assert_eq!(rest, b"PING\n");
+
+    let (client, _) = parse(b"PROXY TCP6 2001:db8::7 2001:db8::1 40000 7171\r\n").await;
+    assert_eq!(
+        client.unwrap(),
+        Some("[2001:db8::7]:40000".parse().unwrap())
+    );
+
+    let (client, rest) = parse(b"PROXY UNKNOWN\r\nPING\n").await;
+    assert_eq!(client.unwrap(), None);
+    assert_eq!(rest, b"PING\n");
+}
+
+#[tokio::test]
+async fn v2_headers_name_the_client() {
+    let mut block = vec![198, 51, 100, 9, 10, 0, 0, 1];
+    block.extend_from_slice(&40000u16.to_be_bytes());
+    block.extend_from_slice(&7474u16.to_be_bytes());
+    let mut bytes = v2(1, 0x11, &block);
+    bytes.extend_from_slice(b"rest");
+    let (client, rest) = parse(&bytes).await;
+    assert_eq!(client.unwrap(), Some("198.51.100.9:40000".parse().unwrap()));
+    assert_eq!(rest, b"rest");
+
+    let mut block = "2001:db8::7"
+        .parse::<std::net::Ipv6Addr>()
+        .unwrap()
+        .octets()
+        .to_vec();
+    block.extend_from_slice(&[0; 16]);
+    block.extend_from_slice(&443u16.to_be_bytes());
+    block.extend_from_slice(&7474u16.to_be_bytes());
+    // Trailing TLVs are skipped with the rest of the block
+    block.extend_from_slice(&[0x04, 0x00, 0x01, 0xff]);
+    let (client, rest) = parse(&v2(1, 0x21, &block)).await;
+    assert_eq!(client.unwrap(), Some("[2001:db8::7]:443".parse().unwrap()));
+    assert!(rest.is_empty());
+
+    // A balancer's health check
+    let (client, _) = parse(&v2(0, 0x00, &[])).await;
+    assert_eq!(client.unwrap(), None);
+}
+
+#[tokio::test]
+async fn bad_headers_are_rejected() {
+    let too_long = format!("PROXY TCP4 {}\r\n", "1".repeat(100));
+    let short_v2 = v2(1, 0x11, &[198, 51, 100, 9]);
+    let cases: [&[u8]; 9] = [
+        b"PING\nPING\nPING\n",
+        b"PROXY\r\n\r\n",
+        b"PROXY TCP4 203.0.113.7 10.0.0.1 51234\r\n",
+        b"PROXY TCP4 2001:db8::7 10.0.0.1 51234 7171\r\n",
+        b"PROXY TCP4 203.0.113.7 10.0.0.1 65536 7171\r\n",
+        b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 7171\n",
+        too_long.as_bytes(),
+        &short_v2,
+        b"PROXY TCP4 203.0.113.7",
+    ];
+    for bytes in cases {
+        let (client, _) = parse(bytes).await;
+        let err = client.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "{:?}", bytes);
+    }
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use common::{temp_dir, KvServer, LogServer, VaultServer};
+use shrmpl::sd_notify::Notifier;
+use std::time::Duration;
+use tokio::net::UnixDatagram;
+
+// Stands in for systemd's notify socket
+struct NotifySocket {
+    socket: UnixDatagram,
+    path: String,
+}
+
+impl NotifySocket {
+    fn bind() -> NotifySocket {
+        let path = temp_dir("notify").join("notify.sock");
+        NotifySocket {
+            socket: UnixDatagram::bind(&path).unwrap(),
+            path: path.display().to_string(),
+        }
+    }
+
+    /// Notifier with a watchdog timeout short enough to ping every 100ms.
+    fn notifier(&self) -> Notifier {
+        Notifier::new(&self.path, Some(Duration::from_millis(200)))
+    }
+
+    async fn recv_within(&self, wait: Duration) -> Option<String> {
+        let mut buf = [0u8; 256];
+        let len = tokio::time::timeout(wait, self.socket.recv(&mut buf))
+            .await
+            .ok()?
+            .unwrap();
+        Some(String::from_utf8_lossy(&buf[..len]).to_string())
+    }
+
+    async fn recv(&self) -> String {
+        self.recv_within(Duration::from_secs(5))
+            .await
+            .expect("no notification")
+    }
+
+    // Skips watchdog pings, which arrive throughout
+    async fn recv_state(&self) -> String {
+        loop {
+            let message = self.recv().await;
+            if message != "WATCHDOG=1" {
+                return message;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn log_server_notifies_ready_watchdog_and_stopping() {
+    let notify = NotifySocket::bind();
+    let server = LogServer::start_notifying(notify.notifier()).await;
+
+    assert_eq!(notify.recv().await, "READY=1");
+    assert_eq!(notify.recv().await, "WATCHDOG=1");
+
+    server.shutdown_tx.send(()).unwrap();
+    assert_eq!(notify.recv_state().await, "STOPPING=1");
+    server.handle.await.unwrap();
+}
+
+// The activity log file is a FIFO with no reader, so the activity writer blocks
+// opening it the way a hung disk would wedge it. The writer thread stays blocked
+// until the test process exits; a FIFO can't be fsynced, so there is no clean way
+// to let it finish.
+#[tokio::test]
+async fn log_server_withholds_watchdog_while_a_writer_is_stuck() {
+    let notify = NotifySocket::bind();
+    let server = LogServer::start_notifying(notify.notifier()).await;
+    assert_eq!(notify.recv().await, "READY=1");
+    assert_eq!(notify.recv().await, "WATCHDOG=1");
+
+    let today = chrono::Utc::now().format("%Y%m%d").to_string();
+    let fifo = server.data_dir.join(format!("activity-{}.log", today));
+    let status = std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    server
+        .logger()
+        .activity("TESTACTV", "blocks the activity writer")
+        .await;
+
+    // Pings keep coming every 100ms until the stuck writer's heartbeat goes stale
+    let mut stopped = false;
+    for _ in 0..60 {
+        if notify
+            .recv_within(Duration::from_millis(500))
+            .await
+            .is_none()
+        {
+            stopped = true;
+            break;
+        }
+    }
+    assert!(stopped, "watchdog kept pinging with a stuck writer");
+    assert!(!server.handle.is_finished());
+}
+
+#[tokio::test]
+async fn kv_server_notifies_ready_watchdog_and_stopping() {
+    let notify = NotifySocket::bind();
+    let server = KvServer::start_notifying(notify.notifier()).await;
+
+    assert_eq!(notify.recv().await, "READY=1");
+    assert_eq!(notify.recv().await, "WATCHDOG=1");
+
+    server.shutdown().await;
+    assert_eq!(notify.recv_state().await, "STOPPING=1");
+}
+
+#[tokio::test]
+async fn vault_server_notifies_ready_watchdog_and_stopping() {
+    let notify = NotifySocket::bind();
+    let server = VaultServer::start_notifying(notify.notifier()).await;
+
+    assert_eq!(notify.recv().await, "READY=1");
+    assert_eq!(notify.recv().await, "WATCHDOG=1");
+
+    server.shutdown().await;
+    assert_eq!(notify.recv_state().await, "STOPPING=1");
+}
+
+#[test]
+fn notifications_are_off_without_notify_socket() {
+    // Skipped when the test run itself is a systemd notify service
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        let notifier = Notifier::from_env();
+        assert!(!notifier.is_enabled());
+        assert!(notifier.watchdog_timer().is_none());
+    }
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::temp_dir;
+use shrmpl::vault_cache::FileCache;
+use std::path::PathBuf;
+
+fn write_files(names: &[&str], contents: &str) -> Vec<PathBuf> {
+    let dir = temp_dir("cache");
+    names
+        .iter()
+        .map(|name| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn evicts_least_recently_used_over_size_cap() {
+    let files = write_files(&["a", "b", "c"], "1234");
+    let cache = FileCache::new(10, 100);
+
+    cache.read(&files[0]).await.unwrap();
+    cache.read(&files[1]).await.unwrap();
+    // Touching a makes b the least recently used
+    cache.read(&files[0]).await.unwrap();
+    cache.read(&files[2]).await.unwrap();
+    assert_eq!(cache.usage(), (2, 8));
+    assert_eq!((cache.hits(), cache.misses()), (1, 3));
+
+    cache.read(&files[0]).await.unwrap();
+    cache.read(&files[2]).await.unwrap();
+    assert_eq!((cache.hits(), cache.misses()), (3, 3));
+    cache.read(&files[1]).await.unwrap();
+    assert_eq!((cache.hits(), cache.misses()), (3, 4));
+}
+
+#[tokio::test]
+async fn evicts_over_entry_cap_and_skips_oversized_files() {
+    let files = write_files(&["a", "b"], "1234");
+    let cache = FileCache::new(1000, 1);
+    cache.read(&files[0]).await.unwrap();
+    cache.read(&files[1]).await.unwrap();
+    assert_eq!(cache.usage(), (1, 4));
+
+    let big = write_files(&["big"], &"x".repeat(2000));
+    let file = cache.read(&big[0]).await.unwrap();
+    assert_eq!(file.bytes.len(), 2000);
+    assert_eq!(cache.usage(), (1, 4));
+}
+
+#[tokio::test]
+async fn disabled_cache_always_reads_disk() {
+    let files = write_files(&["a"], "1234");
+    let cache = FileCache::disabled();
+    cache.read(&files[0]).await.unwrap();
+    cache.read(&files[0]).await.unwrap();
+    assert_eq!((cache.hits(), cache.misses()), (0, 2));
+    assert_eq!(cache.usage(), (0, 0));
+}
@@ -0,0 +1,28 @@
+use shrmpl::vault_nonce::NonceStore;
+use std::time::{Duration, Instant};
+
+#[test]
+fn a_nonce_is_accepted_once_per_window() {
+    let nonces = NonceStore::new(Duration::from_secs(60), 100);
+    let start = Instant::now();
+    assert!(nonces.check_at("n-1", start));
+    assert!(!nonces.check_at("n-1", start + Duration::from_secs(59)));
+    assert!(nonces.check_at("n-2", start));
+    // Past the window it is forgotten and accepted again
+    assert!(nonces.check_at("n-1", start + Duration::from_secs(60)));
+    assert!(!nonces.check_at("n-1", start + Duration::from_secs(61)));
+}
+
+#[test]
+fn flood_of_nonces_stays_bounded() {
+    let nonces = NonceStore::new(Duration::from_secs(60), 100);
+    let start = Instant::now();
+    for i in 0..10_000 {
+        assert!(nonces.check_at(&format!("flood-{}", i), start + Duration::from_millis(i)));
+        assert!(nonces.tracked() <= 100);
+    }
+    // The newest are the ones still remembered
+    let end = start + Duration::from_secs(10);
+    assert!(!nonces.check_at("flood-9999", end));
+    assert!(nonces.check_at("flood-0", end));
+}
@@ -0,0 +1,26 @@
+use shrmpl::vault_rate_limit::RateLimiter;
+
+#[test]
+fn flood_of_distinct_keys_stays_bounded() {
+    let limiter = RateLimiter::new(60, 100);
+    for i in 0..10_000 {
+        assert!(limiter.check(&format!("flood-{}", i)));
+        assert!(limiter.tracked() <= 100);
+    }
+    assert_eq!(limiter.tracked(), 100);
+}
+
+#[test]
+fn evicts_the_least_recently_seen_key() {
+    let limiter = RateLimiter::new(1, 2);
+    assert!(limiter.check("a"));
+    assert!(limiter.check("b"));
+    // A rejected request still counts as seen, leaving b the oldest
+    assert!(!limiter.check("a"));
+    assert!(limiter.check("c"));
+
+    assert!(!limiter.check("a"));
+    // b lost its window when it was evicted
+    assert!(limiter.check("b"));
+    assert_eq!(limiter.tracked(), 2);
+}
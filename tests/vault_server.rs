@@ -0,0 +1,463 @@
+mod common;
+
+use common::{
+    fill_template, run_init, run_selftest, run_selftest_file, temp_dir, TestPki, VaultServer,
+};
+use std::io::Write;
+use std::time::Duration;
+
+#[tokio::test]
+async fn serves_files_over_mtls() {
+    let server = VaultServer::start().await;
+    std::fs::write(server.files.join("db.env"), "DB_PASSWORD=hunter2\n").unwrap();
+
+    let response = server.get("/db.env?secret=test-secret").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("DB_PASSWORD=hunter2\n"), "{}", response);
+
+    let response = server.get("/db.env?secret=wrong").await;
+    assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn nonce_replays_are_rejected() {
+    let server = VaultServer::start_with(&[("NONCE_WINDOW_SECS", "60")]).await;
+    std::fs::write(server.files.join("db.env"), "DB_PASSWORD=hunter2\n").unwrap();
+
+    let response = server.get("/db.env?secret=test-secret&nonce=a1b2c3").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let response = server.get("/db.env?secret=test-secret&nonce=a1b2c3").await;
+    assert!(response.starts_with("HTTP/1.1 409"), "{}", response);
+    let warning = server.slog.wait_for_code("NONCE").await;
+    assert!(warning.contains("Nonce already used"), "{}", warning);
+
+    let response = server.get("/db.env?secret=test-secret&nonce=d4e5f6").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let response = server.get("/db.env?secret=test-secret").await;
+    assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+    server.shutdown().await;
+}
+
+// Plain HTTP on the TLS port, as a scanner would send
+async fn send_plaintext(addr: std::net::SocketAddr) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /db.env HTTP/1.1\r\nHost: vault\r\n\r\n")
+        .await
+        .unwrap();
+    let mut rest = Vec::new();
+    let _ = stream.read_to_end(&mut rest).await;
+}
+
+#[tokio::test]
+async fn failed_tls_handshakes_are_logged_to_slog() {
+    let server = VaultServer::start().await;
+    send_plaintext(server.addr).await;
+    let warning = server.slog.wait_for_code("TLSHANDSK").await;
+    assert!(warning.starts_with("WARN"), "{}", warning);
+    assert!(warning.contains("from 127.0.0.1:"), "{}", warning);
+
+    // The server still serves proper clients
+    std::fs::write(server.files.join("db.env"), "DB_PASSWORD=hunter2\n").unwrap();
+    let response = server.get("/db.env?secret=test-secret").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    let quiet = VaultServer::start_with(&[("LOG_TLS_HANDSHAKE_FAILURES", "false")]).await;
+    send_plaintext(quiet.addr).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let lines = quiet.slog.lines();
+    assert!(
+        !lines
+            .iter()
+            .any(|line| common::record_code(line) == "TLSHANDSK"),
+        "{:?}",
+        lines
+    );
+}
+
+// The requested file is a FIFO, so the handler blocks reading it until the test
+// writes the contents. That holds a request in flight across the shutdown signal.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn shutdown_drains_in_flight_requests() {
+    let server = VaultServer::start().await;
+    let fifo = server.files.join("slow.env");
+    let status = std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let request = {
+        let addr = server.addr;
+        let connector = server.pki.connector();
+        tokio::spawn(async move {
+            common::vault_get(addr, connector, "/slow.env?secret=test-secret").await
+        })
+    };
+
+    // Opening the write end blocks until the handler has opened the read end
+    let writer = tokio::task::spawn_blocking(move || {
+        std::fs::OpenOptions::new().write(true).open(fifo).unwrap()
+    })
+    .await
+    .unwrap();
+    server.shutdown_tx.send(()).unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!server.handle.is_finished());
+
+    tokio::task::spawn_blocking(move || {
+        let mut writer = writer;
+        writer.write_all(b"API_KEY=late\n").unwrap();
+    })
+    .await
+    .unwrap();
+
+    let response = request.await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("API_KEY=late\n"), "{}", response);
+
+    tokio::time::timeout(Duration::from_secs(5), server.handle)
+        .await
+        .expect("server did not stop")
+        .unwrap();
+    server.slog.wait_for_code("VAULTDOWN").await;
+    assert!(tokio::net::TcpStream::connect(server.addr).await.is_err());
+}
+
+// Value of `name` in a raw HTTP response, matched case-insensitively
+fn header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    response
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+#[tokio::test]
+async fn cached_files_are_refreshed_after_a_change() {
+    let server = VaultServer::start().await;
+    let path = server.files.join("api.env");
+    std::fs::write(&path, "API_KEY=one\n").unwrap();
+
+    let first = server.get("/api.env?secret=test-secret").await;
+    let second = server.get("/api.env?secret=test-secret").await;
+    assert!(second.ends_with("API_KEY=one\n"), "{}", second);
+    let etag = header(&first, "etag").expect("no ETag").to_string();
+    assert_eq!(header(&second, "etag"), Some(etag.as_str()));
+    assert_eq!(header(&second, "content-length"), Some("12"));
+
+    std::fs::write(&path, "API_KEY=rotated\n").unwrap();
+    let third = server.get("/api.env?secret=test-secret").await;
+    assert!(third.ends_with("API_KEY=rotated\n"), "{}", third);
+    assert_ne!(header(&third, "etag"), Some(etag.as_str()));
+    assert_eq!(header(&third, "content-length"), Some("16"));
+
+    let slog = server.shutdown().await;
+    let stats = slog.wait_for_code("VAULTCACHE").await;
+    assert!(stats.contains("hits=1 misses=2"), "{}", stats);
+}
+
+#[tokio::test]
+async fn meta_reports_size_and_mtime_without_the_contents() {
+    let server = VaultServer::start().await;
+    let path = server.files.join("api.env");
+    std::fs::write(&path, "API_KEY=one\n").unwrap();
+    let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+    let mtime = chrono::DateTime::<chrono::Utc>::from(mtime);
+
+    let response = server.get("/api.env?secret=test-secret&meta=1").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(!response.contains("API_KEY"), "{}", response);
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    let meta: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(meta["file"], "api.env");
+    assert_eq!(meta["size"], 12);
+    assert_eq!(meta["mtime"], mtime.timestamp());
+    assert_eq!(
+        header(&response, "last-modified"),
+        Some(
+            mtime
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string()
+                .as_str()
+        )
+    );
+    let full = server.get("/api.env?secret=test-secret").await;
+    assert_eq!(header(&response, "etag"), header(&full, "etag"));
+
+    for (path, status) in [
+        ("/api.env?meta=1", "401"),
+        ("/api.env?secret=wrong&meta=1", "401"),
+        ("/missing.env?secret=test-secret&meta=1", "404"),
+    ] {
+        let response = server.get(path).await;
+        assert!(
+            response.starts_with(&format!("HTTP/1.1 {}", status)),
+            "{}: {}",
+            path,
+            response
+        );
+    }
+}
+
+// The access log shows the client named by the PROXY header, not the balancer
+#[tokio::test]
+async fn proxy_protocol_header_names_the_client() {
+    let prefix = temp_dir("vault-access").join("access");
+    let server = VaultServer::start_with(&[
+        ("PROXY_PROTOCOL", "true"),
+        ("ACCESS_LOG_PATH", prefix.to_str().unwrap()),
+    ])
+    .await;
+    std::fs::write(server.files.join("db.env"), "DB_PASSWORD=hunter2\n").unwrap();
+    for (client, status) in [
+        ("203.0.113.7", 200),
+        ("2001:db8::8", 200),
+        ("203.0.113.7", 200),
+    ] {
+        let family = if client.contains(':') { "TCP6" } else { "TCP4" };
+        let header = format!("PROXY {} {} {} 51234 7474\r\n", family, client, client);
+        let response = common::vault_get_after(
+            server.addr,
+            server.pki.connector(),
+            header.as_bytes(),
+            "/db.env?secret=test-secret",
+        )
+        .await;
+        assert!(
+            response.starts_with(&format!("HTTP/1.1 {}", status)),
+            "{}: {}",
+            client,
+            response
+        );
+    }
+
+    // A connection without the header never reaches the TLS handshake
+    send_plaintext(server.addr).await;
+    let dropped = server.slog.wait_for_code("VAULTPROXY").await;
+    assert!(dropped.contains("missing PROXY header"), "{}", dropped);
+    server.shutdown().await;
+
+    let clients: Vec<String> = access_lines(&prefix)
+        .iter()
+        .map(|line| line.split(' ').nth(1).unwrap().to_string())
+        .collect();
+    assert_eq!(clients, ["203.0.113.7", "2001:db8::8", "203.0.113.7"]);
+}
+
+// A connection that never sends its PROXY header holds up only itself
+#[tokio::test]
+async fn silent_proxy_connection_does_not_block_other_clients() {
+    let server = VaultServer::start_with(&[("PROXY_PROTOCOL", "true")]).await;
+    std::fs::write(server.files.join("db.env"), "DB_PASSWORD=hunter2\n").unwrap();
+    let _silent = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+
+    let response = tokio::time::timeout(
+        Duration::from_secs(2),
+        common::vault_get_after(
+            server.addr,
+            server.pki.connector(),
+            b"PROXY TCP4 203.0.113.7 203.0.113.7 51234 7474\r\n",
+            "/db.env?secret=test-secret",
+        ),
+    )
+    .await
+    .expect("request waited behind the silent connection");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    server.shutdown().await;
+}
+
+// Every line of the daily access files under `prefix`, oldest file first
+fn access_lines(prefix: &std::path::Path) -> Vec<String> {
+    let dir = prefix.parent().unwrap();
+    let name = format!("{}-", prefix.file_name().unwrap().to_str().unwrap());
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .starts_with(&name)
+        })
+        .collect();
+    files.sort();
+    files
+        .iter()
+        .flat_map(|path| {
+            std::fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn access_log_records_one_line_per_request() {
+    let prefix = temp_dir("vault-access").join("access");
+    let server = VaultServer::start_with(&[
+        ("ACCESS_LOG_PATH", prefix.to_str().unwrap()),
+        ("RATE_LIMIT_REQUESTS_PER_MINUTE", "3"),
+    ])
+    .await;
+    std::fs::write(server.files.join("db.env"), "DB_PASSWORD=hunter2\n").unwrap();
+
+    let requests = [
+        ("/db.env?secret=test-secret", 200),
+        ("/missing.env?secret=test-secret", 404),
+        ("/db.env", 401),
+        ("/db.env?secret=wrong", 401),
+        ("/db.env?secret=test-secret", 200),
+        ("/db.env?secret=test-secret", 429),
+    ];
+    for (path, status) in requests {
+        let response = server.get(path).await;
+        assert!(
+            response.starts_with(&format!("HTTP/1.1 {}", status)),
+            "{}: {}",
+            path,
+            response
+        );
+    }
+    let slog = server.shutdown().await;
+
+    let lines = access_lines(&prefix);
+    assert_eq!(lines.len(), requests.len(), "{:?}", lines);
+    let test_secret = shrmpl::vault_access_log::fingerprint("test-secret");
+    let wrong_secret = shrmpl::vault_access_log::fingerprint("wrong");
+    assert_eq!(test_secret.len(), 16);
+    assert_ne!(test_secret, wrong_secret);
+    let expected = [
+        (test_secret.as_str(), "/db.env", 200, "20"),
+        (test_secret.as_str(), "/missing.env", 404, "14"),
+        ("-", "/db.env", 401, "18"),
+        (wrong_secret.as_str(), "/db.env", 401, "18"),
+        (test_secret.as_str(), "/db.env", 200, "20"),
+        (test_secret.as_str(), "/db.env", 429, "19"),
+    ];
+    for (line, (fingerprint, path, status, bytes)) in lines.iter().zip(expected) {
+        let fields: Vec<&str> = line.split(' ').collect();
+        assert_eq!(fields.len(), 8, "{}", line);
+        assert!(
+            chrono::DateTime::parse_from_rfc3339(fields[0]).is_ok(),
+            "{}",
+            line
+        );
+        assert_eq!(fields[1], "unknown", "{}", line);
+        assert_eq!(fields[2], fingerprint, "{}", line);
+        assert_eq!(fields[3], "\"GET", "{}", line);
+        assert_eq!(fields[4], format!("{}\"", path), "{}", line);
+        assert_eq!(fields[5], status.to_string(), "{}", line);
+        assert_eq!(fields[6], bytes, "{}", line);
+        assert!(fields[7].parse::<u64>().is_ok(), "{}", line);
+        assert!(!line.contains("secret"), "{}", line);
+    }
+
+    let stats = slog.wait_for_code("VAULTACCLOG").await;
+    assert!(stats.contains("lines=6 write_failed=false"), "{}", stats);
+}
+
+#[tokio::test]
+async fn access_log_failures_do_not_affect_serving() {
+    let prefix = temp_dir("vault-access").join("missing-dir").join("access");
+    let server = VaultServer::start_with(&[("ACCESS_LOG_PATH", prefix.to_str().unwrap())]).await;
+    std::fs::write(server.files.join("db.env"), "DB_PASSWORD=hunter2\n").unwrap();
+
+    for _ in 0..2 {
+        let response = server.get("/db.env?secret=test-secret").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+
+    let slog = server.shutdown().await;
+    let stats = slog.wait_for_code("VAULTACCLOG").await;
+    assert!(stats.contains("lines=0 write_failed=true"), "{}", stats);
+}
+
+#[test]
+fn selftest_reads_a_file_with_the_configured_certificates() {
+    let pki = TestPki::generate();
+    let files = temp_dir("vault-selftest");
+    let path = |name: &str| pki.dir.join(name).display().to_string();
+    let files_dir = files.display().to_string();
+    let entries = [
+        ("TLS_CERTIFICATE_PRIVKEY_PATH", path("server_privkey.pem")),
+        (
+            "TLS_CERTIFICATE_FULLCHAIN_PATH",
+            path("server_fullchain.pem"),
+        ),
+        ("MTLS_CLIENT_CA_CERT_PATH", path("ca.pem")),
+        ("CONFIG_DIR", files_dir),
+        ("ALLOWED_SECRETS", "test-secret".to_string()),
+    ];
+    let entries: Vec<(&str, &str)> = entries.iter().map(|(k, v)| (*k, v.as_str())).collect();
+    let binary = env!("CARGO_BIN_EXE_shrmpl-vault-srv");
+
+    // Nothing to serve yet
+    let (passed, output) = run_selftest(binary, &entries);
+    assert!(!passed, "{}", output);
+    assert!(output.contains("SELFTEST FAIL"), "{}", output);
+
+    std::fs::write(files.join("db.env"), "DB_PASSWORD=hunter2\n").unwrap();
+    let (passed, output) = run_selftest(binary, &entries);
+    assert!(passed, "{}", output);
+    assert!(output.contains("read db.env (20 bytes)"), "{}", output);
+}
+
+#[test]
+fn init_template_boots_once_the_required_keys_are_filled() {
+    let binary = env!("CARGO_BIN_EXE_shrmpl-vault-srv");
+    let dir = temp_dir("vault-init");
+    let config = dir.join("vault.env");
+    assert!(run_init(binary, &config, &[]));
+    let template = std::fs::read_to_string(&config).unwrap();
+    let secret = |template: &str| {
+        let line = template
+            .lines()
+            .find(|line| line.starts_with("ALLOWED_SECRETS="))
+            .unwrap();
+        line["ALLOWED_SECRETS=".len()..].to_string()
+    };
+    let first = secret(&template);
+    assert_eq!(first.len(), 32, "{}", first);
+    assert!(first.chars().all(|c| c.is_ascii_hexdigit()), "{}", first);
+
+    // An existing file is only replaced with --force, and each run draws a new secret
+    assert!(!run_init(binary, &config, &[]));
+    assert_eq!(std::fs::read_to_string(&config).unwrap(), template);
+    assert!(run_init(binary, &config, &["--force"]));
+    let template = std::fs::read_to_string(&config).unwrap();
+    assert_ne!(secret(&template), first);
+
+    // As generated the required paths are empty, so the server refuses to start
+    let (passed, output) = run_selftest_file(binary, &config);
+    assert!(!passed, "{}", output);
+    assert!(output.contains("CONFIG_DIR"), "{}", output);
+
+    let pki = TestPki::generate();
+    let files = dir.join("files");
+    std::fs::create_dir_all(&files).unwrap();
+    std::fs::write(files.join("db.env"), "DB_PASSWORD=hunter2\n").unwrap();
+    let path = |name: &str| pki.dir.join(name).display().to_string();
+    let mut filled = template;
+    for (key, value) in [
+        ("TLS_CERTIFICATE_PRIVKEY_PATH", path("server_privkey.pem")),
+        (
+            "TLS_CERTIFICATE_FULLCHAIN_PATH",
+            path("server_fullchain.pem"),
+        ),
+        ("MTLS_CLIENT_CA_CERT_PATH", path("ca.pem")),
+        ("CONFIG_DIR", files.display().to_string()),
+    ] {
+        filled = fill_template(&filled, key, &value);
+    }
+    std::fs::write(&config, filled).unwrap();
+    let (passed, output) = run_selftest_file(binary, &config);
+    assert!(passed, "{}", output);
+    assert!(output.contains("read db.env (20 bytes)"), "{}", output);
+}